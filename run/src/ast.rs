@@ -10,6 +10,19 @@ pub struct Program {
     pub statements: Vec<Statement>,
 }
 
+/// What `--show-script` does with the generated script
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShowScript {
+    /// Run the function normally
+    #[default]
+    Off,
+    /// Print the script instead of running it
+    Plain,
+    /// Print the script with a comment before each part naming the Runfile function
+    /// and line it came from (`--show-script --annotate`)
+    Annotated,
+}
+
 /// Output capture mode for command execution
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum OutputMode {
@@ -41,11 +54,138 @@ pub struct CommandOutput {
     /// Process exit code (None if killed by signal)
     pub exit_code: Option<i32>,
 
+    /// Signal that killed the process, if it was killed by one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+
     /// Execution duration in milliseconds
     pub duration_ms: u128,
 
     /// Timestamp when execution started (Unix epoch ms)
     pub started_at: u128,
+
+    /// stdout and stderr line by line, in the order they were written. Only the end of
+    /// the output when it was too long to keep whole (see `stdout` and `stderr`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<OutputEvent>,
+
+    /// Top-level statements of the function body as they ran, with `@trace` or `--trace`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<TraceStep>,
+
+    /// Operations the sandbox denied, with `@sandbox` or `--sandbox`: stderr lines
+    /// reporting "Permission denied" on a path the sandbox blocks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sandbox_denied: Vec<String>,
+}
+
+/// A top-level statement of a traced function body, and what it did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// Source text of the statement
+    pub command: String,
+
+    /// Runfile (or sourced file) the statement belongs to
+    pub file: String,
+
+    /// Line number of the statement within that file (1-indexed)
+    pub line: usize,
+
+    /// How long the statement ran, in milliseconds
+    pub duration_ms: u128,
+
+    /// Exit status of the statement (None if the function was killed by a signal)
+    pub exit_code: Option<i32>,
+
+    /// stdout written while the statement ran
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stdout: String,
+
+    /// stderr written while the statement ran
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+}
+
+impl TraceStep {
+    /// `ok`, `exit N`, or `killed` when the function was killed by a signal
+    #[must_use]
+    pub fn status(&self) -> String {
+        match self.exit_code {
+            Some(0) => "ok".to_string(),
+            Some(code) => format!("exit {code}"),
+            None => "killed".to_string(),
+        }
+    }
+}
+
+/// A line of output, with its newline if it had one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputEvent {
+    /// Which stream the line was written to
+    pub stream: OutputStream,
+
+    /// Milliseconds after the command started when the line began
+    pub at_ms: u128,
+
+    /// The text written
+    pub text: String,
+}
+
+/// The output streams of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+}
+
+/// How stdout and stderr are laid out in Markdown results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamLayout {
+    /// Output, then stderr when the function failed
+    #[default]
+    Separate,
+    /// Both streams in one block, in the order they were written, with stderr lines marked
+    Merged,
+}
+
+impl CommandOutput {
+    /// Both streams in the order they were written, with stderr lines marked, or `None`
+    /// if no timeline was recorded. Starts with where to find the full output when
+    /// only its end was kept.
+    #[must_use]
+    pub fn merged_output(&self) -> Option<String> {
+        if self.events.is_empty() {
+            return None;
+        }
+        let mut text: String = [&self.stdout, &self.stderr]
+            .into_iter()
+            .filter_map(|stream| crate::output_file::truncation_header(stream))
+            .collect();
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        for event in &self.events {
+            if event.stream == OutputStream::Stderr {
+                text.push_str("[stderr] ");
+            }
+            text.push_str(&event.text);
+            if !event.text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        Some(text)
+    }
 }
 
 /// Context information about command execution
@@ -84,6 +224,52 @@ pub struct StructuredResult {
 
     /// Human-readable summary
     pub summary: String,
+
+    /// Runfile location of the line that failed, when it could be determined
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureLocation>,
+
+    /// The code `run` exits with: the failing command's own, or a reserved one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+
+    /// Why the call failed, which decides `exit_code`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_reason: Option<crate::exit::ExitReason>,
+
+    /// Signal that killed the failing command, if it was killed by one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+
+    /// Resource limit from `@limit` the failing command ran into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit_exceeded: Option<crate::limits::LimitViolation>,
+}
+
+/// Runfile location of a failing line, mapped back from the generated script
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FailureLocation {
+    /// Runfile (or sourced file) the failing line belongs to
+    pub file: String,
+
+    /// Line number within that file (1-indexed)
+    pub line: usize,
+
+    /// Runfile function containing the failing line
+    pub function: String,
+
+    /// Source text of the failing line
+    pub command: String,
+}
+
+impl std::fmt::Display for FailureLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{} in {}: `{}`",
+            self.file, self.line, self.function, self.command
+        )
+    }
 }
 
 impl StructuredResult {
@@ -127,7 +313,42 @@ impl StructuredResult {
             success,
             total_duration_ms,
             summary,
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
+        }
+    }
+
+    /// Mark the call as failed with `error`, recording the exit code it leads to
+    pub fn set_error(&mut self, error: &(dyn std::error::Error + 'static)) {
+        if self.success {
+            self.success = false;
+            self.summary = format!("Execution of {} failed", self.context.function_name);
+        }
+        self.exit_code = Some(crate::exit::code_of(error));
+        self.exit_reason = Some(crate::exit::reason_of(error));
+        let failure = crate::exit::failure_of(error);
+        self.signal = failure.and_then(|failure| failure.signal);
+        self.limit_exceeded = failure.and_then(|failure| failure.limit.clone());
+    }
+
+    /// List the operations the sandbox denied. Whether there were any.
+    fn write_sandbox_denied(&self, md: &mut String) -> bool {
+        let denied: Vec<&String> = self
+            .outputs
+            .iter()
+            .flat_map(|o| &o.sandbox_denied)
+            .collect();
+        if denied.is_empty() {
+            return false;
+        }
+        md.push_str("**Sandbox denied:**\n");
+        for line in denied {
+            let _ = writeln!(md, "- {line}");
         }
+        true
     }
 
     /// Format as JSON for programmatic consumption
@@ -139,6 +360,12 @@ impl StructuredResult {
     /// Format as Markdown for LLM readability
     #[must_use]
     pub fn to_markdown(&self) -> String {
+        self.to_markdown_with(StreamLayout::Separate)
+    }
+
+    /// Format as Markdown, with stdout and stderr laid out as `layout` says
+    #[must_use]
+    pub fn to_markdown_with(&self, layout: StreamLayout) -> String {
         let mut md = String::new();
 
         // Header with context
@@ -164,38 +391,106 @@ impl StructuredResult {
         );
         let _ = write!(md, "**Duration:** {}ms\n\n", self.total_duration_ms);
 
-        // Individual command outputs
-        for (i, output) in self.outputs.iter().enumerate() {
-            let _ = writeln!(md, "### Step {} ({}ms)", i + 1, output.duration_ms);
-            let _ = write!(md, "`{}`\n\n", output.command);
+        if let Some(failure) = &self.failure {
+            let _ = write!(md, "**Failed at:** {failure}\n\n");
+        }
+        if let Some(limit) = &self.limit_exceeded {
+            let _ = write!(md, "**Limit exceeded:** {}\n\n", limit.message);
+        }
+        if self.write_sandbox_denied(&mut md) {
+            md.push('\n');
+        }
 
-            if !output.stdout.is_empty() {
-                md.push_str("**Output:**\n```\n");
-                md.push_str(&output.stdout);
-                md.push_str("```\n\n");
+        // Individual command outputs, or the statements of traced ones
+        let mut step = 0;
+        for output in &self.outputs {
+            if !output.steps.is_empty() {
+                for traced in &output.steps {
+                    step += 1;
+                    self.write_trace_step(&mut md, step, traced);
+                }
+                continue;
             }
+            step += 1;
+            let _ = writeln!(md, "### Step {step} ({}ms)", output.duration_ms);
+            let _ = write!(md, "`{}`\n\n", output.command);
 
-            if !self.success && !output.stderr.is_empty() {
-                md.push_str("**Stderr:**\n```\n");
-                md.push_str(&output.stderr);
+            let merged = match layout {
+                StreamLayout::Merged => output.merged_output(),
+                StreamLayout::Separate => None,
+            };
+            if let Some(merged) = merged {
+                md.push_str("**Output:**\n```\n");
+                md.push_str(&merged);
                 md.push_str("```\n\n");
+            } else {
+                if !output.stdout.is_empty() {
+                    md.push_str("**Output:**\n```\n");
+                    md.push_str(&output.stdout);
+                    md.push_str("```\n\n");
+                }
+
+                if !self.success && !output.stderr.is_empty() {
+                    md.push_str("**Stderr:**\n```\n");
+                    md.push_str(&output.stderr);
+                    md.push_str("```\n\n");
+                }
             }
 
             if let Some(code) = output.exit_code
                 && code != 0
             {
                 let _ = writeln!(md, "**Exit Code:** {code}");
+            } else if let Some(signal) = output.signal {
+                let _ = writeln!(md, "**Killed by signal:** {signal}");
             }
         }
 
         md
     }
 
+    /// Write a traced statement as a Markdown step section
+    fn write_trace_step(&self, md: &mut String, number: usize, step: &TraceStep) {
+        let _ = writeln!(
+            md,
+            "### Step {number} ({}ms) at {}:{}",
+            step.duration_ms, step.file, step.line
+        );
+        if step.command.contains('\n') {
+            let _ = write!(md, "```\n{}\n```\n\n", step.command);
+        } else {
+            let _ = write!(md, "`{}`\n\n", step.command);
+        }
+        if !step.stdout.is_empty() {
+            md.push_str("**Output:**\n```\n");
+            md.push_str(&step.stdout);
+            md.push_str("```\n\n");
+        }
+        if !self.success && !step.stderr.is_empty() {
+            md.push_str("**Stderr:**\n```\n");
+            md.push_str(&step.stderr);
+            md.push_str("```\n\n");
+        }
+        match step.exit_code {
+            Some(0) => {}
+            Some(code) => {
+                let _ = writeln!(md, "**Exit Code:** {code}");
+            }
+            None => md.push_str("**Killed by signal**\n"),
+        }
+    }
+
     /// Format optimized for MCP tool response (clean markdown, no implementation details)
     /// This intentionally hides the command source code to protect sensitive information
     /// like database connection strings, API keys, etc.
     #[must_use]
     pub fn to_mcp_format(&self) -> String {
+        self.to_mcp_format_with(StreamLayout::Separate)
+    }
+
+    /// Format for an MCP tool response, with stdout and stderr laid out as `layout` says
+    #[must_use]
+    pub fn to_mcp_format_with(&self, layout: StreamLayout) -> String {
         let mut md = String::new();
 
         // Compact single-line header: status, function name, duration, and optional host
@@ -219,6 +514,21 @@ impl StructuredResult {
         }
         md.push('\n');
 
+        let merged =
+            layout == StreamLayout::Merged && self.outputs.iter().any(|o| !o.events.is_empty());
+        if merged {
+            // Steps without a timeline contribute their stdout
+            for output in &self.outputs {
+                let text = output
+                    .merged_output()
+                    .unwrap_or_else(|| output.stdout.clone());
+                md.push_str(&text);
+                if !text.is_empty() && !text.ends_with('\n') {
+                    md.push('\n');
+                }
+            }
+        }
+
         // Combine all outputs
         let all_stdout: String = self
             .outputs
@@ -236,7 +546,7 @@ impl StructuredResult {
             .collect::<Vec<_>>()
             .join("");
 
-        if !all_stdout.is_empty() {
+        if !merged && !all_stdout.is_empty() {
             md.push_str(&all_stdout);
             if !all_stdout.ends_with('\n') {
                 md.push('\n');
@@ -244,7 +554,7 @@ impl StructuredResult {
         }
 
         // Only show stderr when the command failed
-        if !self.success && !all_stderr.is_empty() {
+        if !merged && !self.success && !all_stderr.is_empty() {
             md.push_str("**Stderr:**\n```\n");
             md.push_str(&all_stderr);
             if !all_stderr.ends_with('\n') {
@@ -253,13 +563,47 @@ impl StructuredResult {
             md.push_str("```\n\n");
         }
 
+        // Traced statements, by location only
+        let steps: Vec<&TraceStep> = self.outputs.iter().flat_map(|o| &o.steps).collect();
+        if !steps.is_empty() {
+            md.push_str("**Steps:**\n");
+            for (i, step) in steps.iter().enumerate() {
+                let _ = writeln!(
+                    md,
+                    "{}. {}:{} ({}ms) {}",
+                    i + 1,
+                    step.file,
+                    step.line,
+                    step.duration_ms,
+                    step.status()
+                );
+            }
+        }
+
+        // Point at the failing Runfile line (location only, the source stays hidden)
+        if let Some(failure) = &self.failure {
+            let _ = writeln!(
+                md,
+                "**Failed at:** {}:{} in `{}`",
+                failure.file, failure.line, failure.function
+            );
+        }
+        if let Some(limit) = &self.limit_exceeded {
+            let _ = writeln!(md, "**Limit exceeded:** {}", limit.message);
+        }
+        let _ = self.write_sandbox_denied(&mut md);
+
         // Show exit code if failed
         if !self.success
             && let Some(output) = self.outputs.last()
-            && let Some(code) = output.exit_code
-            && code != 0
         {
-            let _ = writeln!(md, "**Exit Code:** {code}");
+            if let Some(code) = output.exit_code
+                && code != 0
+            {
+                let _ = writeln!(md, "**Exit Code:** {code}");
+            } else if let Some(signal) = output.signal {
+                let _ = writeln!(md, "**Killed by signal:** {signal}");
+            }
         }
 
         md
@@ -302,6 +646,16 @@ pub struct Parameter {
     pub is_rest: bool,
 }
 
+/// Where a definition appears in the parsed source (1-indexed lines)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SourceLocation {
+    /// Line of the function signature
+    pub line: usize,
+
+    /// Line of the first body line (same as `line` for one-line functions)
+    pub body_line: usize,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     Assignment {
@@ -313,6 +667,7 @@ pub enum Statement {
         params: Vec<Parameter>,
         command_template: String,
         attributes: Vec<Attribute>,
+        location: SourceLocation,
     },
     BlockFunctionDef {
         name: String,
@@ -320,6 +675,7 @@ pub enum Statement {
         commands: Vec<String>,
         attributes: Vec<Attribute>,
         shebang: Option<String>,
+        location: SourceLocation,
     },
     FunctionCall {
         name: String,
@@ -342,6 +698,41 @@ pub enum Attribute {
     Desc(String),
     Arg(ArgMetadata),
     Noerrexit,
+    /// `# @default`: the function bare `run` executes
+    Default,
+    /// `# @init`: runs before any function invoked from the Runfile
+    Init,
+    /// `# @exec file|inline`: how the generated script reaches the interpreter
+    Exec(ExecMode),
+    /// `# @siblings used|all`: which sibling functions the generated script defines
+    Siblings(SiblingScope),
+    /// `# @sources <glob>...`: files the function reads, watched by `run --watch`
+    Sources(Vec<String>),
+    /// `# @trace`: record each top-level statement of the body as a step
+    Trace,
+    /// `# @limit mem=2G cpu=60s nofile=1024 procs=200`: resource limits for the processes
+    /// running the function
+    Limit(crate::limits::ResourceLimits),
+    /// `# @sandbox rw=./build ro=/etc`: run the function in a Landlock sandbox
+    Sandbox(crate::sandbox::SandboxPaths),
+}
+
+/// Which sibling functions are inlined into a function's generated script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiblingScope {
+    /// Only siblings the body calls, directly or through other siblings
+    Used,
+    /// Every compatible sibling, for bodies that call functions by computed name
+    All,
+}
+
+/// How a function's generated script is handed to its interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecMode {
+    /// As an argument: `bash -c <script>`, `python3 -c <script>`
+    Inline,
+    /// From a script file named after the function: `python3 /tmp/run-x/deploy.py`
+    File,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -369,15 +760,22 @@ pub enum OsPlatform {
     Unix, // Matches both Linux and MacOS
 }
 
+/// Interpreter named by `@shell` or a shebang (`python3`, `deno`, `uv run --script`),
+/// resolved through the [`crate::registry`] when the function runs
 #[derive(Debug, Clone, PartialEq)]
-pub enum ShellType {
-    Python,
-    Python3,
-    Node,
-    Ruby,
-    Pwsh,
-    Bash,
-    Sh,
+pub struct ShellType(String);
+
+impl ShellType {
+    #[must_use]
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    /// Interpreter name, or the whole command line of a shebang
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.0
+    }
 }
 
 #[cfg(test)]
@@ -435,8 +833,12 @@ mod tests {
             stdout: "hello\n".to_string(),
             stderr: String::new(),
             exit_code: Some(0),
+            signal: None,
             duration_ms: 10,
             started_at: 1000,
+            events: Vec::new(),
+            steps: Vec::new(),
+            sandbox_denied: Vec::new(),
         }];
 
         let result = StructuredResult::from_outputs("test_fn", outputs, "sh");
@@ -457,8 +859,12 @@ mod tests {
             stdout: String::new(),
             stderr: "error\n".to_string(),
             exit_code: Some(1),
+            signal: None,
             duration_ms: 5,
             started_at: 1000,
+            events: Vec::new(),
+            steps: Vec::new(),
+            sandbox_denied: Vec::new(),
         }];
 
         let result = StructuredResult::from_outputs("failing_fn", outputs, "bash");
@@ -473,8 +879,12 @@ mod tests {
             stdout: "up 10 days\n".to_string(),
             stderr: String::new(),
             exit_code: Some(0),
+            signal: None,
             duration_ms: 100,
             started_at: 1000,
+            events: Vec::new(),
+            steps: Vec::new(),
+            sandbox_denied: Vec::new(),
         }];
 
         let result = StructuredResult::from_outputs("check_uptime", outputs, "sh");
@@ -493,16 +903,24 @@ mod tests {
                 stdout: "step1\n".to_string(),
                 stderr: String::new(),
                 exit_code: Some(0),
+                signal: None,
                 duration_ms: 5,
                 started_at: 1000,
+                events: Vec::new(),
+                steps: Vec::new(),
+                sandbox_denied: Vec::new(),
             },
             CommandOutput {
                 command: "echo step2".to_string(),
                 stdout: "step2\n".to_string(),
                 stderr: String::new(),
                 exit_code: Some(0),
+                signal: None,
                 duration_ms: 10,
                 started_at: 1005,
+                events: Vec::new(),
+                steps: Vec::new(),
+                sandbox_denied: Vec::new(),
             },
         ];
 
@@ -528,12 +946,21 @@ mod tests {
                 stdout: "hi\n".to_string(),
                 stderr: String::new(),
                 exit_code: Some(0),
+                signal: None,
                 duration_ms: 5,
                 started_at: 1000,
+                events: Vec::new(),
+                steps: Vec::new(),
+                sandbox_denied: Vec::new(),
             }],
             success: true,
             total_duration_ms: 5,
             summary: "ok".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
 
         let json = result.to_json();
@@ -560,12 +987,21 @@ mod tests {
                 stdout: "deployed\n".to_string(),
                 stderr: "warning: slow\n".to_string(),
                 exit_code: Some(0),
+                signal: None,
                 duration_ms: 100,
                 started_at: 1000,
+                events: Vec::new(),
+                steps: Vec::new(),
+                sandbox_denied: Vec::new(),
             }],
             success: true,
             total_duration_ms: 100,
             summary: "ok".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
 
         let md = result.to_markdown();
@@ -594,12 +1030,21 @@ mod tests {
                 stdout: String::new(),
                 stderr: "error\n".to_string(),
                 exit_code: Some(42),
+                signal: None,
                 duration_ms: 1,
                 started_at: 1000,
+                events: Vec::new(),
+                steps: Vec::new(),
+                sandbox_denied: Vec::new(),
             }],
             success: false,
             total_duration_ms: 1,
             summary: "failed".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
 
         let md = result.to_markdown();
@@ -607,6 +1052,63 @@ mod tests {
         assert!(md.contains("**Exit Code:** 42"));
     }
 
+    #[test]
+    fn test_structured_result_traced_steps() {
+        let step = |command: &str, line, exit_code, stderr: &str| TraceStep {
+            command: command.to_string(),
+            file: "Runfile".to_string(),
+            line,
+            duration_ms: 7,
+            exit_code: Some(exit_code),
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        };
+        let result = StructuredResult {
+            context: ExecutionContext {
+                function_name: "deploy".to_string(),
+                remote_host: None,
+                remote_user: None,
+                interpreter: "bash".to_string(),
+                working_directory: None,
+            },
+            outputs: vec![CommandOutput {
+                command: "deploy.sh".to_string(),
+                stdout: String::new(),
+                stderr: "no such target\n".to_string(),
+                exit_code: Some(2),
+                signal: None,
+                duration_ms: 14,
+                started_at: 1000,
+                events: Vec::new(),
+                steps: vec![
+                    step("if true; then\n  build\nfi", 3, 0, ""),
+                    step("make install", 6, 2, "no such target\n"),
+                ],
+                sandbox_denied: Vec::new(),
+            }],
+            success: false,
+            total_duration_ms: 14,
+            summary: "failed".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
+        };
+
+        let md = result.to_markdown();
+        assert!(md.contains("### Step 1 (7ms) at Runfile:3\n```\nif true; then\n  build\nfi\n```"));
+        assert!(md.contains("### Step 2 (7ms) at Runfile:6\n`make install`"));
+        assert!(md.contains("**Stderr:**\n```\nno such target\n```"));
+        assert!(md.contains("**Exit Code:** 2"));
+        assert!(!md.contains("deploy.sh"));
+
+        // Locations only, without the commands
+        let mcp = result.to_mcp_format();
+        assert!(mcp.contains("**Steps:**\n1. Runfile:3 (7ms) ok\n2. Runfile:6 (7ms) exit 2\n"));
+        assert!(!mcp.contains("make install"));
+    }
+
     #[test]
     fn test_structured_result_to_mcp_format() {
         let result = StructuredResult {
@@ -623,21 +1125,34 @@ mod tests {
                     stdout: "a\n".to_string(),
                     stderr: String::new(),
                     exit_code: Some(0),
+                    signal: None,
                     duration_ms: 5,
                     started_at: 1000,
+                    events: Vec::new(),
+                    steps: Vec::new(),
+                    sandbox_denied: Vec::new(),
                 },
                 CommandOutput {
                     command: "echo b".to_string(),
                     stdout: "b\n".to_string(),
                     stderr: String::new(),
                     exit_code: Some(0),
+                    signal: None,
                     duration_ms: 5,
                     started_at: 1005,
+                    events: Vec::new(),
+                    steps: Vec::new(),
+                    sandbox_denied: Vec::new(),
                 },
             ],
             success: true,
             total_duration_ms: 10,
             summary: "ok".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
 
         let mcp = result.to_mcp_format();
@@ -665,12 +1180,21 @@ mod tests {
                 stdout: String::new(),
                 stderr: "oh no\n".to_string(),
                 exit_code: Some(1),
+                signal: None,
                 duration_ms: 1,
                 started_at: 1000,
+                events: Vec::new(),
+                steps: Vec::new(),
+                sandbox_denied: Vec::new(),
             }],
             success: false,
             total_duration_ms: 1,
             summary: "failed".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
 
         let mcp = result.to_mcp_format();
@@ -693,9 +1217,106 @@ mod tests {
             success: true,
             total_duration_ms: 0,
             summary: "ok".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
 
         let md = result.to_markdown();
         assert!(!md.contains("**Host:**"));
     }
+
+    fn event(stream: OutputStream, at_ms: u128, text: &str) -> OutputEvent {
+        OutputEvent {
+            stream,
+            at_ms,
+            text: text.to_string(),
+        }
+    }
+
+    fn interleaved_failure() -> StructuredResult {
+        let outputs = vec![CommandOutput {
+            command: "cargo build".to_string(),
+            stdout: "compiling a\ncompiling b\n".to_string(),
+            stderr: "warning: unused x\nerror: mismatch".to_string(),
+            exit_code: Some(101),
+            signal: None,
+            duration_ms: 40,
+            started_at: 1000,
+            events: vec![
+                event(OutputStream::Stdout, 0, "compiling a\n"),
+                event(OutputStream::Stderr, 10, "warning: unused x\n"),
+                event(OutputStream::Stdout, 20, "compiling b\n"),
+                event(OutputStream::Stderr, 30, "error: mismatch"),
+            ],
+            steps: Vec::new(),
+            sandbox_denied: Vec::new(),
+        }];
+        StructuredResult::from_outputs("build", outputs, "sh")
+    }
+
+    #[test]
+    fn test_merged_output_keeps_the_order_written() {
+        let result = interleaved_failure();
+        assert_eq!(
+            result.outputs[0].merged_output().unwrap(),
+            "compiling a\n[stderr] warning: unused x\ncompiling b\n[stderr] error: mismatch\n"
+        );
+
+        let mut untimed = result.outputs[0].clone();
+        untimed.events.clear();
+        assert!(untimed.merged_output().is_none());
+    }
+
+    #[test]
+    fn test_merged_output_points_at_the_full_output() {
+        let mut output = interleaved_failure().outputs.remove(0);
+        output.stdout = crate::output_file::truncated_view(
+            &output.stdout,
+            5000,
+            std::path::Path::new("/tmp/build.log"),
+            100,
+        );
+        let merged = output.merged_output().unwrap();
+        assert!(merged.starts_with(
+            "[Output truncated: 5000 bytes, showing last 23 bytes]\n\
+             [Full output saved to: /tmp/build.log]\n\ncompiling a\n"
+        ));
+    }
+
+    #[test]
+    fn test_to_mcp_format_merged() {
+        let result = interleaved_failure();
+
+        let separate = result.to_mcp_format();
+        assert!(separate.contains("compiling a\ncompiling b\n**Stderr:**"));
+
+        let merged = result.to_mcp_format_with(StreamLayout::Merged);
+        assert!(merged.contains(
+            "compiling a\n[stderr] warning: unused x\ncompiling b\n[stderr] error: mismatch\n"
+        ));
+        assert!(!merged.contains("**Stderr:**"));
+        assert!(merged.contains("**Exit Code:** 101"));
+    }
+
+    #[test]
+    fn test_to_markdown_merged() {
+        let md = interleaved_failure().to_markdown_with(StreamLayout::Merged);
+        assert!(
+            md.contains("**Output:**\n```\ncompiling a\n[stderr] warning: unused x\ncompiling b\n")
+        );
+        assert!(!md.contains("**Stderr:**"));
+    }
+
+    #[test]
+    fn test_events_are_left_out_of_json_when_not_recorded() {
+        let mut result = interleaved_failure();
+        let json = result.to_json();
+        assert!(json.contains("\"stream\": \"stderr\""));
+
+        result.outputs[0].events.clear();
+        assert!(!result.to_json().contains("\"events\""));
+    }
 }
@@ -2,7 +2,9 @@
 //!
 //! This module is separated from main.rs to allow the runtool wrapper crate to reuse it.
 
-use crate::{completion, config, executor, mcp, repl};
+use crate::ast::{ShowScript, StreamLayout};
+use crate::limits::ResourceLimits;
+use crate::{completion, config, executor, exit, history, mcp, multi, repl, watch};
 use clap::Parser as ClapParser;
 use clap::ValueEnum;
 use std::path::PathBuf;
@@ -42,10 +44,30 @@ struct Cli {
     )]
     install_completion: Option<InstallCompletionArg>,
 
+    /// Print completion candidates for the word after WORDS (used by completion scripts)
+    #[arg(
+        long,
+        hide = true,
+        value_name = "WORDS",
+        num_args = 0..,
+        allow_hyphen_values = true
+    )]
+    complete: Option<Vec<String>>,
+
     /// Inspect and output JSON schema for all functions
     #[arg(long)]
     inspect: bool,
 
+    /// Print the function call graph (dot or mermaid)
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "dot",
+        value_enum
+    )]
+    graph: Option<GraphFormatArg>,
+
     /// Start MCP server for AI agent integration
     #[arg(long)]
     serve_mcp: bool,
@@ -54,13 +76,66 @@ struct Cli {
     #[arg(long, value_name = "FORMAT", default_value = "stream")]
     output_format: OutputFormatArg,
 
-    /// Working directory containing the Runfile (alias: --runfile)
+    /// With markdown output, show stdout and stderr together, in the order they were written
+    #[arg(long)]
+    merge_streams: bool,
+
+    /// Record each top-level statement of block functions as a step, as if they had @trace
+    #[arg(long)]
+    trace: bool,
+
+    /// Resource limits for every function, as with @limit: --limit "mem=2G cpu=60s"
+    #[arg(long, value_name = "LIMITS")]
+    limit: Option<ResourceLimits>,
+
+    /// Run every function in a Landlock sandbox, as if it had @sandbox (Linux only)
+    #[arg(long)]
+    sandbox: bool,
+
+    /// Working directory containing the Runfile (alias: --runfile); functions started
+    /// with one pass it on to the `run` calls they make
     #[arg(long = "working-dir", alias = "runfile", value_name = "PATH")]
     working_dir: Option<PathBuf>,
 
     /// Show the generated shell script without executing
     #[arg(long)]
     show_script: bool,
+
+    /// With --show-script, mark which Runfile function and line each part of the script came from
+    #[arg(long, requires = "show_script")]
+    annotate: bool,
+
+    /// Explain how a function call would be resolved and run, without running it
+    #[arg(long, conflicts_with = "show_script")]
+    explain: bool,
+
+    /// Run several functions: `run --all build test lint`, or with arguments separated by `+`
+    #[arg(long)]
+    all: bool,
+
+    /// With several functions, run up to N at once
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), requires = "all")]
+    parallel: u16,
+
+    /// With several functions, keep running the rest after one fails
+    #[arg(long, requires = "all")]
+    keep_going: bool,
+
+    /// Re-run the function whenever files matching GLOB change (repeatable)
+    #[arg(long, value_name = "GLOB", conflicts_with_all = ["show_script", "explain", "all"])]
+    watch: Vec<String>,
+
+    /// List recorded calls, or only the calls to the function given
+    #[arg(long, conflicts_with_all = ["show_script", "explain", "rerun"])]
+    history: bool,
+
+    /// Repeat a recorded call: `run --rerun` for the last one, `run --rerun N` for call N from --history
+    #[arg(long, conflicts_with_all = ["show_script", "explain"])]
+    rerun: bool,
+
+    /// Start the interactive shell, even if the Runfile has a `@default` function
+    #[arg(long)]
+    repl: bool,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
@@ -86,6 +161,15 @@ impl InstallCompletionArg {
     }
 }
 
+/// Output format for `--graph`
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GraphFormatArg {
+    /// Graphviz DOT (default)
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+}
+
 /// Output format for command execution
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
 pub enum OutputFormatArg {
@@ -98,6 +182,16 @@ pub enum OutputFormatArg {
 }
 
 impl OutputFormatArg {
+    /// Name of the format, as given to `--output-format`
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Stream => "stream",
+            Self::Json => "json",
+            Self::Markdown => "markdown",
+        }
+    }
+
     /// Get the output mode for this format
     #[must_use]
     pub fn mode(self) -> crate::ast::OutputMode {
@@ -114,21 +208,70 @@ impl OutputFormatArg {
         match self {
             Self::Stream => None,
             Self::Json => Some(result.to_json()),
-            Self::Markdown => Some(result.to_mcp_format()),
+            Self::Markdown => Some(result.to_mcp_format_with(config::get_stream_layout())),
         }
     }
 }
 
+/// Keep the flags that decide how functions run where the executor finds them
+fn apply_settings(cli: &Cli) {
+    // Set custom working directory (Runfile location) if provided, or inherited from
+    // the function that started this `run`
+    let working_dir = cli
+        .working_dir
+        .clone()
+        .or_else(|| std::env::var_os(config::WORKING_DIR_ENV).map(PathBuf::from));
+    if let Some(runfile_path) = working_dir {
+        config::set_custom_runfile_path(Some(runfile_path));
+    }
+    apply_run_settings(cli);
+}
+
+/// Turn on the flags [`config::run_flags`] gave for a call again, for `run --rerun`
+///
+/// # Errors
+///
+/// Returns `Err` if `flags` aren't flags this `run` takes.
+pub fn apply_run_flags(flags: &[String]) -> Result<(), clap::Error> {
+    let cli = Cli::try_parse_from(std::iter::once("run").chain(flags.iter().map(String::as_str)))?;
+    apply_run_settings(&cli);
+    Ok(())
+}
+
+/// The part of [`apply_settings`] that [`config::run_flags`] passes on
+fn apply_run_settings(cli: &Cli) {
+    if cli.merge_streams {
+        config::set_stream_layout(StreamLayout::Merged);
+    }
+    if cli.trace {
+        config::set_trace(true);
+    }
+    if let Some(limits) = cli.limit {
+        config::set_default_limits(limits);
+    }
+    if cli.sandbox {
+        config::set_sandbox(true);
+    }
+}
+
+/// The calls `run --all` was given, exiting with [`exit::USAGE`] if they can't be split
+fn parse_calls(first_arg: &str, args: &[String], all: bool) -> Option<Vec<multi::Call>> {
+    multi::parse_calls(first_arg, args, all).unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(exit::USAGE);
+    })
+}
+
 /// Main CLI logic that can be called from external wrappers.
 ///
 /// This function is public to allow the `runtool` wrapper crate to reuse the same logic.
 pub fn run_cli() {
-    let cli = Cli::parse();
-
-    // Set custom working directory (Runfile location) if provided
-    if let Some(ref runfile_path) = cli.working_dir {
-        config::set_custom_runfile_path(Some(runfile_path.clone()));
-    }
+    let cli = Cli::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        // `--help` and `--version` come through here too, on stdout
+        std::process::exit(if e.use_stderr() { exit::USAGE } else { 0 });
+    });
+    apply_settings(&cli);
 
     // Handle --install-completion flag
     if let Some(shell_arg) = cli.install_completion {
@@ -145,6 +288,12 @@ pub fn run_cli() {
         return;
     }
 
+    // Handle --complete (called by the completion scripts)
+    if let Some(ref words) = cli.complete {
+        completion::print_completions(words);
+        return;
+    }
+
     // Handle --list flag
     if cli.list {
         executor::list_functions();
@@ -157,32 +306,83 @@ pub fn run_cli() {
         return;
     }
 
+    // Handle --graph flag
+    if let Some(format) = cli.graph {
+        executor::print_graph(format);
+        return;
+    }
+
+    // Handle --history flag
+    if cli.history {
+        history::print_history(cli.first_arg.as_deref(), cli.output_format);
+        return;
+    }
+
+    // Handle --rerun flag
+    if cli.rerun {
+        if !cli.args.is_empty() {
+            eprintln!("error: --rerun takes one history number or `last`");
+            std::process::exit(exit::USAGE);
+        }
+        history::rerun(cli.first_arg.as_deref());
+        return;
+    }
+
     // Handle --serve-mcp flag
     if cli.serve_mcp {
         mcp::serve_mcp();
         return;
     }
 
+    let show_script = match (cli.show_script, cli.annotate) {
+        (false, _) => ShowScript::Off,
+        (true, false) => ShowScript::Plain,
+        (true, true) => ShowScript::Annotated,
+    };
+
     match cli.first_arg {
         Some(first_arg) => {
             // Check if it's a file that exists
             let path = PathBuf::from(&first_arg);
-            if path.exists() && path.is_file() {
-                // File mode: read and execute script
-                executor::execute_file(&path);
+            if cli.explain {
+                executor::explain_function(&first_arg, &cli.args, cli.output_format);
+            } else if !cli.watch.is_empty() {
+                watch::watch(&cli.watch, &first_arg, &cli.args, cli.output_format);
+            } else if first_arg == "-" {
+                // Script from stdin: `run - args...`
+                executor::execute_stdin(&cli.args);
+            } else if path.exists() && path.is_file() {
+                // File mode: read and execute script (also `#!/usr/bin/env run` executables)
+                executor::execute_file(&path, &cli.args);
+            } else if let Some(calls) = parse_calls(&first_arg, &cli.args, cli.all) {
+                let options = multi::Options {
+                    parallel: usize::from(cli.parallel),
+                    policy: if cli.keep_going {
+                        multi::FailurePolicy::KeepGoing
+                    } else {
+                        multi::FailurePolicy::FailFast
+                    },
+                    output_format: cli.output_format,
+                    show_script,
+                };
+                std::process::exit(multi::run_calls(&calls, options));
             } else {
                 // Function call mode: load config and call function with args
-                executor::run_function_call(
-                    &first_arg,
-                    &cli.args,
-                    cli.output_format,
-                    cli.show_script,
-                );
+                executor::run_function_call(&first_arg, &cli.args, cli.output_format, show_script);
             }
         }
+        None if cli.explain => {
+            eprintln!("error: --explain needs a function name, e.g. `run --explain build`");
+            std::process::exit(exit::USAGE);
+        }
+        None if !cli.watch.is_empty() => {
+            eprintln!("error: --watch needs a function name, e.g. `run --watch 'src/**' test`");
+            std::process::exit(exit::USAGE);
+        }
+        None if cli.repl => repl::run_repl(),
         None => {
-            // REPL mode: interactive shell
-            repl::run_repl();
+            // Bare `run`: the @default function, or the configured fallback (REPL by default)
+            executor::run_bare(cli.output_format, show_script);
         }
     }
 }
@@ -230,6 +430,11 @@ mod tests {
             success: true,
             total_duration_ms: 0,
             summary: "test".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
         assert!(OutputFormatArg::Stream.format_result(&result).is_none());
     }
@@ -248,6 +453,11 @@ mod tests {
             success: true,
             total_duration_ms: 0,
             summary: "test".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
         let formatted = OutputFormatArg::Json.format_result(&result);
         assert!(formatted.is_some());
@@ -269,6 +479,11 @@ mod tests {
             success: true,
             total_duration_ms: 0,
             summary: "test".to_string(),
+            failure: None,
+            exit_code: None,
+            exit_reason: None,
+            signal: None,
+            limit_exceeded: None,
         };
         let formatted = OutputFormatArg::Markdown.format_result(&result);
         assert!(formatted.is_some());
@@ -65,6 +65,57 @@ pub fn generate_completion_script(shell: Shell) {
     print!("{}", shell.completion_script());
 }
 
+/// Names at the next level of the namespace tree below the path `words`.
+///
+/// With no words this lists top-level functions and namespaces; with `["docker"]`
+/// it lists what follows `docker:` (e.g. `compose`, `shell`), and so on.
+#[must_use]
+pub fn next_level(names: &[String], words: &[String]) -> Vec<String> {
+    let prefix = if words.is_empty() {
+        String::new()
+    } else {
+        format!("{}:", words.join(":"))
+    };
+    let mut children: Vec<String> = names
+        .iter()
+        .filter_map(|n| n.strip_prefix(&prefix))
+        .filter_map(|rest| rest.split(':').next())
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string)
+        .collect();
+    children.sort();
+    children.dedup();
+    children
+}
+
+/// Print completion candidates for the word after `words`, one per line.
+///
+/// Used by the shell completion scripts (`run --complete docker compose`) to walk the
+/// namespace tree one level at a time. Functions are read from the Runfile without
+/// running anything, and items with syntax errors are skipped silently.
+pub fn print_completions(words: &[String]) {
+    let Some((content, _metadata)) = crate::config::load_merged_config() else {
+        return;
+    };
+    let (program, _) = crate::parser::parse_script_recovering(&content, None);
+    let names: Vec<String> = program
+        .statements
+        .into_iter()
+        .filter_map(|statement| match statement {
+            crate::ast::Statement::SimpleFunctionDef {
+                name, attributes, ..
+            }
+            | crate::ast::Statement::BlockFunctionDef {
+                name, attributes, ..
+            } if crate::utils::matches_current_platform(&attributes) => Some(name),
+            _ => None,
+        })
+        .collect();
+    for candidate in next_level(&names, words) {
+        println!("{candidate}");
+    }
+}
+
 /// Install shell completion interactively, detecting the shell and updating config files.
 pub fn install_completion_interactive(
     shell_opt: Option<Shell>,
@@ -259,6 +310,30 @@ fn install_powershell_completion(home: &Path) {
 mod tests {
     use super::*;
 
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_next_level_walks_tree() {
+        let known = words(&[
+            "build",
+            "docker:ps",
+            "docker:compose:up",
+            "docker:compose:down",
+        ]);
+        assert_eq!(next_level(&known, &[]), words(&["build", "docker"]));
+        assert_eq!(
+            next_level(&known, &words(&["docker"])),
+            words(&["compose", "ps"])
+        );
+        assert_eq!(
+            next_level(&known, &words(&["docker", "compose"])),
+            words(&["down", "up"])
+        );
+        assert!(next_level(&known, &words(&["build"])).is_empty());
+    }
+
     #[test]
     fn test_shell_name_bash() {
         assert_eq!(Shell::Bash.name(), "bash");
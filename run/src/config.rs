@@ -1,6 +1,9 @@
 //! Configuration file (Runfile) discovery and loading.
 
-use std::cell::RefCell;
+use crate::ast::StreamLayout;
+use crate::limits::ResourceLimits;
+use crate::source_map::SourceMap;
+use std::cell::{Cell, RefCell};
 use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -10,6 +13,12 @@ thread_local! {
     static CUSTOM_RUNFILE_PATH: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
     static MCP_OUTPUT_DIR: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
     static MCP_FUNCTION_NAME: RefCell<Option<String>> = const { RefCell::new(None) };
+    static STREAM_LAYOUT: Cell<StreamLayout> = const { Cell::new(StreamLayout::Separate) };
+    static TRACE: Cell<bool> = const { Cell::new(false) };
+    static SANDBOX: Cell<bool> = const { Cell::new(false) };
+    static DEFAULT_LIMITS: Cell<ResourceLimits> = const {
+        Cell::new(ResourceLimits { mem: None, cpu: None, nofile: None, procs: None })
+    };
 }
 
 static MCP_OUTPUT_ENV: OnceLock<Option<PathBuf>> = OnceLock::new();
@@ -28,6 +37,29 @@ pub fn get_custom_runfile_path() -> Option<PathBuf> {
     CUSTOM_RUNFILE_PATH.with(|p| p.borrow().clone())
 }
 
+/// Path of the `run` executable, set for every function so sibling wrappers and
+/// `run_fn` call back into the same binary instead of whichever `run` is on `PATH`
+pub const EXE_ENV: &str = "RUN_EXE";
+/// `--working-dir` of the current call, made absolute, set for every function when
+/// given. `run` reads it as the default for `--working-dir`, so sibling calls load the
+/// same Runfile.
+pub const WORKING_DIR_ENV: &str = "RUN_WORKING_DIR";
+
+/// Environment that lets a function call its siblings through `run`
+#[must_use]
+pub fn sibling_call_env() -> Vec<(&'static str, PathBuf)> {
+    let mut env = Vec::new();
+    if let Ok(exe) = std::env::current_exe() {
+        env.push((EXE_ENV, exe));
+    }
+    if let Some(path) = get_custom_runfile_path()
+        && let Ok(path) = std::path::absolute(path)
+    {
+        env.push((WORKING_DIR_ENV, path));
+    }
+    env
+}
+
 /// Set the MCP function name for the current thread
 pub fn set_mcp_function_name(name: &str) {
     MCP_FUNCTION_NAME.with(|n| {
@@ -41,6 +73,73 @@ pub fn get_mcp_function_name() -> Option<String> {
     MCP_FUNCTION_NAME.with(|n| n.borrow().clone())
 }
 
+/// Set how stdout and stderr are laid out in Markdown results, for the current thread
+pub fn set_stream_layout(layout: StreamLayout) {
+    STREAM_LAYOUT.with(|l| l.set(layout));
+}
+
+/// How stdout and stderr are laid out in Markdown results (`--merge-streams`)
+#[must_use]
+pub fn get_stream_layout() -> StreamLayout {
+    STREAM_LAYOUT.with(Cell::get)
+}
+
+/// Set whether every block function is traced, for the current thread
+pub fn set_trace(trace: bool) {
+    TRACE.with(|t| t.set(trace));
+}
+
+/// Whether every block function is traced, as if it had `@trace` (`--trace`)
+#[must_use]
+pub fn get_trace() -> bool {
+    TRACE.with(Cell::get)
+}
+
+/// Set whether every function runs sandboxed, for the current thread
+pub fn set_sandbox(sandbox: bool) {
+    SANDBOX.with(|s| s.set(sandbox));
+}
+
+/// Whether every function runs sandboxed, as if it had `@sandbox` (`--sandbox`)
+#[must_use]
+pub fn get_sandbox() -> bool {
+    SANDBOX.with(Cell::get)
+}
+
+/// Set the resource limits of functions without `@limit`, for the current thread
+pub fn set_default_limits(limits: ResourceLimits) {
+    DEFAULT_LIMITS.with(|l| l.set(limits));
+}
+
+/// Resource limits of functions without `@limit`, and of the resources their `@limit`
+/// leaves out (`--limit`)
+#[must_use]
+pub fn get_default_limits() -> ResourceLimits {
+    DEFAULT_LIMITS.with(Cell::get)
+}
+
+/// The flags that make a nested `run` run functions the way this one does:
+/// `--merge-streams`, `--trace`, `--limit` and `--sandbox`
+#[must_use]
+pub fn run_flags() -> Vec<String> {
+    let mut flags = Vec::new();
+    if get_stream_layout() == StreamLayout::Merged {
+        flags.push("--merge-streams".to_string());
+    }
+    if get_trace() {
+        flags.push("--trace".to_string());
+    }
+    let limits = get_default_limits();
+    if !limits.is_empty() {
+        flags.push("--limit".to_string());
+        flags.push(limits.to_string());
+    }
+    if get_sandbox() {
+        flags.push("--sandbox".to_string());
+    }
+    flags
+}
+
 /// Set the MCP output directory for the current thread
 pub fn set_mcp_output_dir(path: Option<PathBuf>) {
     MCP_OUTPUT_DIR.with(|p| {
@@ -75,18 +174,7 @@ fn resolve_runfile_dir() -> Option<PathBuf> {
 pub fn ensure_mcp_output_dir() -> PathBuf {
     MCP_OUTPUT_DIR.with(|p| {
         if p.borrow().is_none() {
-            let base_dir = mcp_output_dir_from_env()
-                .or_else(resolve_runfile_dir)
-                .unwrap_or_else(std::env::temp_dir);
-            let dir = if base_dir
-                .file_name()
-                .is_some_and(|name| name == ".run-output")
-            {
-                base_dir
-            } else {
-                base_dir.join(".run-output")
-            };
-            *p.borrow_mut() = Some(dir);
+            *p.borrow_mut() = Some(derive_output_dir());
         }
         // SAFETY: We just initialized the value above if it was None
         match p.borrow().clone() {
@@ -96,6 +184,29 @@ pub fn ensure_mcp_output_dir() -> PathBuf {
     })
 }
 
+/// The `.run-output` directory, as [`ensure_mcp_output_dir`] would pick it, without
+/// memoizing it: storing it turns on MCP output handling
+#[must_use]
+pub fn output_dir() -> PathBuf {
+    MCP_OUTPUT_DIR
+        .with(|p| p.borrow().clone())
+        .unwrap_or_else(derive_output_dir)
+}
+
+fn derive_output_dir() -> PathBuf {
+    let base_dir = mcp_output_dir_from_env()
+        .or_else(resolve_runfile_dir)
+        .unwrap_or_else(std::env::temp_dir);
+    if base_dir
+        .file_name()
+        .is_some_and(|name| name == ".run-output")
+    {
+        base_dir
+    } else {
+        base_dir.join(".run-output")
+    }
+}
+
 /// Check if MCP output directory is configured or derivable (env or Runfile)
 #[must_use]
 pub fn is_mcp_output_configured() -> bool {
@@ -138,6 +249,12 @@ pub fn get_home_dir() -> Option<PathBuf> {
 /// Returns Some(content) if found, None otherwise
 #[must_use]
 pub fn load_from_path(path: &Path) -> Option<String> {
+    load_from_path_mapped(path).map(|(content, _)| content)
+}
+
+/// Like [`load_from_path`], also returning a [`SourceMap`] of the expanded content.
+#[must_use]
+pub fn load_from_path_mapped(path: &Path) -> Option<(String, SourceMap)> {
     let runfile_path = if path.is_dir() {
         path.join("Runfile")
     } else {
@@ -146,8 +263,7 @@ pub fn load_from_path(path: &Path) -> Option<String> {
 
     if runfile_path.exists() {
         if let Ok(content) = fs::read_to_string(&runfile_path) {
-            let base_dir = runfile_path.parent().unwrap_or_else(|| Path::new("."));
-            Some(expand_source_directives(&content, base_dir))
+            Some(expand_source_directives_mapped(&content, &runfile_path))
         } else {
             None
         }
@@ -209,12 +325,18 @@ pub fn load_config() -> Option<String> {
 /// Returns Some(content) if found, or None otherwise.
 #[must_use]
 pub fn load_home_runfile() -> Option<String> {
+    load_home_runfile_mapped().map(|(content, _)| content)
+}
+
+/// Like [`load_home_runfile`], also returning a [`SourceMap`] of the expanded content.
+#[must_use]
+pub fn load_home_runfile_mapped() -> Option<(String, SourceMap)> {
     if let Some(home) = get_home_dir() {
         let runfile_path = home.join(".runfile");
         if runfile_path.exists()
             && let Ok(content) = fs::read_to_string(&runfile_path)
         {
-            return Some(expand_source_directives(&content, &home));
+            return Some(expand_source_directives_mapped(&content, &runfile_path));
         }
     }
     None
@@ -230,7 +352,22 @@ pub fn load_home_runfile() -> Option<String> {
 #[must_use]
 pub fn expand_source_directives(content: &str, base_dir: &Path) -> String {
     let mut seen = HashSet::new();
-    expand_sources_inner(content, base_dir, &mut seen)
+    let mut map = SourceMap::default();
+    expand_sources_inner(content, base_dir, base_dir, &mut seen, &mut map)
+}
+
+/// Expand `source` directives in the content of `file`, recording where each line of the
+/// result came from. Relative source paths resolve against the directory of `file`.
+#[must_use]
+pub fn expand_source_directives_mapped(content: &str, file: &Path) -> (String, SourceMap) {
+    let base_dir = file
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut seen = HashSet::new();
+    let mut map = SourceMap::default();
+    let expanded = expand_sources_inner(content, base_dir, file, &mut seen, &mut map);
+    (expanded, map)
 }
 
 /// Collect top-level `# @instructions ...` lines from expanded/merged `Runfile` content.
@@ -256,11 +393,17 @@ pub fn collect_mcp_instructions(content: &str) -> Vec<String> {
     result
 }
 
-fn expand_sources_inner(content: &str, base_dir: &Path, seen: &mut HashSet<PathBuf>) -> String {
+fn expand_sources_inner(
+    content: &str,
+    base_dir: &Path,
+    origin: &Path,
+    seen: &mut HashSet<PathBuf>,
+    map: &mut SourceMap,
+) -> String {
     let mut result = String::new();
     let mut brace_depth: usize = 0;
 
-    for line in content.lines() {
+    for (idx, line) in content.lines().enumerate() {
         if brace_depth == 0
             && let Some(path_str) = top_level_source_path(line)
         {
@@ -273,8 +416,17 @@ fn expand_sources_inner(content: &str, base_dir: &Path, seen: &mut HashSet<PathB
                             .parent()
                             .filter(|p| !p.as_os_str().is_empty())
                             .unwrap_or(base_dir);
-                        let expanded = expand_sources_inner(&source_content, source_base, seen);
+                        let expanded = expand_sources_inner(
+                            &source_content,
+                            source_base,
+                            &source_path,
+                            seen,
+                            map,
+                        );
                         result.push_str(&expanded);
+                        if expanded.is_empty() {
+                            map.push_synthetic();
+                        }
                         if !expanded.ends_with('\n') {
                             result.push('\n');
                         }
@@ -295,6 +447,7 @@ fn expand_sources_inner(content: &str, base_dir: &Path, seen: &mut HashSet<PathB
         brace_depth = brace_depth.saturating_add(opens).saturating_sub(closes);
         result.push_str(line);
         result.push('\n');
+        map.push_line(origin, idx + 1);
     }
 
     // Preserve original trailing-newline behaviour
@@ -365,7 +518,7 @@ fn resolve_source_path(path_str: &str, base_dir: &Path) -> PathBuf {
 }
 
 /// Count `{` and `}` characters in `line` that appear outside quoted strings and comments.
-fn count_unquoted_braces(line: &str) -> (usize, usize) {
+pub(crate) fn count_unquoted_braces(line: &str) -> (usize, usize) {
     let mut opens = 0usize;
     let mut closes = 0usize;
     let mut in_double = false;
@@ -414,6 +567,21 @@ pub fn load_config_or_exit() -> String {
     load_config().unwrap_or_else(|| crate::fatal_error(NO_RUNFILE_ERROR))
 }
 
+/// The directory of the project whose Runfile is run, for `__RUNFILE_DIR__`.
+///
+/// Prefers the `RUN_RUNFILE_DIR` env var (set by the MCP handler when the subprocess is
+/// given a temp merged file), so that it points to the actual project root rather than
+/// the system temp directory where the merged file was written. The env var is only
+/// accepted when it points to an existing directory, so that a stale or malformed value
+/// does not override a correctly resolved path.
+#[must_use]
+pub fn project_dir() -> Option<PathBuf> {
+    std::env::var_os("RUN_RUNFILE_DIR")
+        .map(PathBuf::from)
+        .filter(|p| p.is_dir())
+        .or_else(|| find_runfile_path().and_then(|p| p.parent().map(PathBuf::from)))
+}
+
 /// Find the path to the Runfile without loading its contents.
 /// Uses the same search logic as `load_config()`.
 /// Returns Some(path) if found, None otherwise.
@@ -546,15 +714,23 @@ pub fn find_project_runfile_path() -> Option<PathBuf> {
 /// When parsed, later function definitions naturally override earlier ones in the interpreter.
 #[must_use]
 pub fn load_merged_config() -> Option<(String, MergeMetadata)> {
+    load_merged_config_mapped().map(|(content, metadata, _)| (content, metadata))
+}
+
+/// Like [`load_merged_config`], also returning a [`SourceMap`] that resolves lines of the
+/// merged content back to the global, project, or sourced file they came from.
+#[must_use]
+pub fn load_merged_config_mapped() -> Option<(String, MergeMetadata, SourceMap)> {
     // If a custom runfile is explicitly specified, use ONLY that file (don't merge)
     if let Some(custom_path) = get_custom_runfile_path() {
-        return load_from_path(&custom_path).map(|content| {
+        return load_from_path_mapped(&custom_path).map(|(content, map)| {
             (
                 content,
                 MergeMetadata {
                     has_global: false,
                     has_project: true,
                 },
+                map,
             )
         });
     }
@@ -564,7 +740,7 @@ pub fn load_merged_config() -> Option<(String, MergeMetadata)> {
 
     // Load project runfile
     let project_content = if let Some(project_path) = find_project_runfile_path() {
-        load_from_path(&project_path)
+        load_from_path_mapped(&project_path)
     } else {
         None
     };
@@ -575,35 +751,44 @@ pub fn load_merged_config() -> Option<(String, MergeMetadata)> {
     let global_content = if disable_global_merge && project_content.is_some() {
         None
     } else {
-        load_home_runfile()
+        load_home_runfile_mapped()
     };
 
     match (global_content, project_content) {
         (None, None) => None,
-        (Some(global), None) => Some((
+        (Some((global, map)), None) => Some((
             global,
             MergeMetadata {
                 has_global: true,
                 has_project: false,
             },
+            map,
         )),
-        (None, Some(project)) => Some((
+        (None, Some((project, map))) => Some((
             project,
             MergeMetadata {
                 has_global: false,
                 has_project: true,
             },
+            map,
         )),
-        (Some(global), Some(project)) => {
+        (Some((global, global_map)), Some((project, project_map))) => {
             // Concatenate with global first, project second
             // Add a newline separator to ensure proper parsing
             let merged = format!("{global}\n{project}");
+            let mut map = global_map;
+            if global.ends_with('\n') {
+                // The separator newline introduces an empty line of its own
+                map.push_synthetic();
+            }
+            map.append(&project_map);
             Some((
                 merged,
                 MergeMetadata {
                     has_global: true,
                     has_project: true,
                 },
+                map,
             ))
         }
     }
@@ -622,6 +807,25 @@ mod tests {
     use super::*;
     use serial_test::serial;
 
+    #[test]
+    fn test_run_flags_pass_on_how_functions_run() {
+        assert!(run_flags().is_empty());
+        set_stream_layout(StreamLayout::Merged);
+        set_trace(true);
+        set_default_limits("cpu=5s".parse().unwrap());
+        set_sandbox(true);
+        assert_eq!(
+            run_flags(),
+            [
+                "--merge-streams",
+                "--trace",
+                "--limit",
+                "cpu=5s",
+                "--sandbox"
+            ]
+        );
+    }
+
     #[test]
     fn test_get_home_dir_returns_some() {
         // On any system with HOME or USERPROFILE set, should return Some
@@ -812,6 +1016,23 @@ mod tests {
         assert!(!result.contains("source "));
     }
 
+    #[test]
+    fn test_expand_source_directives_mapped_tracks_origin() {
+        let temp = tempfile::tempdir().expect("Failed to create temp dir");
+        let lib = temp.path().join("lib.run");
+        fs::write(&lib, "one() echo 1\ntwo() echo 2\n").expect("Failed to write");
+        let runfile = temp.path().join("Runfile");
+
+        let content = "first() echo a\nsource ./lib.run\nlast() echo z\n";
+        let (result, map) = expand_source_directives_mapped(content, &runfile);
+
+        assert_eq!(result.lines().count(), map.len());
+        assert_eq!(map.resolve(1), Some((runfile.as_path(), 1)));
+        assert_eq!(map.resolve(2), Some((lib.as_path(), 1)));
+        assert_eq!(map.resolve(3), Some((lib.as_path(), 2)));
+        assert_eq!(map.resolve(4), Some((runfile.as_path(), 3)));
+    }
+
     #[test]
     fn test_expand_source_directives_skips_inside_block() {
         let temp = tempfile::tempdir().expect("Failed to create temp dir");
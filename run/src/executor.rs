@@ -1,8 +1,15 @@
 //! Script execution and error formatting.
 
-use crate::{cli::OutputFormatArg, config, interpreter, parser};
+use crate::ast::{Attribute, ShowScript, Statement};
+use crate::cli::{GraphFormatArg, OutputFormatArg};
+use crate::exit;
+use crate::interpreter::explain::{Definition, Origin};
+use crate::settings::{BareAction, ProjectSettings};
+use crate::source_map::SourceMap;
+use crate::{config, history, interpreter, parser, repl};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::path::Path;
 
 /// Parse and execute a script file.
 ///
@@ -14,19 +21,22 @@ pub fn execute_script(script: &str, filename: Option<&str>) {
         Ok(prog) => prog,
         Err(e) => {
             eprintln!("{}", parser::ParseError::from_pest(&e, script, filename));
-            std::process::exit(1);
+            std::process::exit(exit::PARSE_ERROR);
         }
     };
 
     let mut interpreter = interpreter::Interpreter::new();
     if let Err(e) = interpreter.execute(program) {
         eprintln!("error: {e}");
-        std::process::exit(1);
+        std::process::exit(exit::code_of(e.as_ref()));
     }
 }
 
 /// Execute a script file by path.
-pub fn execute_file(path: &PathBuf) {
+///
+/// `args` are the script's arguments: top-level commands see them as `$1`/`$@`, and if
+/// the script defines `main()` it is called with them after the top level has run.
+pub fn execute_file(path: &Path, args: &[String]) {
     let script = match fs::read_to_string(path) {
         Ok(content) => content,
         Err(e) => {
@@ -34,26 +44,55 @@ pub fn execute_file(path: &PathBuf) {
             std::process::exit(1);
         }
     };
+    let base_dir = path.parent().unwrap_or(Path::new("."));
+    run_script(&script, path, base_dir, args);
+}
+
+/// Execute a script read from stdin (`run -`), with relative paths resolved against the
+/// current directory.
+pub fn execute_stdin(args: &[String]) {
+    let mut script = String::new();
+    if let Err(e) = io::stdin().read_to_string(&mut script) {
+        eprintln!("error: could not read script from stdin: {e}");
+        std::process::exit(1);
+    }
+    run_script(&script, Path::new("<stdin>"), Path::new("."), args);
+}
 
-    let base_dir = path.parent().unwrap_or(std::path::Path::new("."));
-    let processed = config::expand_source_directives(&script, base_dir);
+fn run_script(script: &str, origin: &Path, base_dir: &Path, args: &[String]) {
+    let (processed, source_map) = config::expand_source_directives_mapped(script, origin);
 
     let program = match parser::parse_script(&processed) {
         Ok(prog) => prog,
         Err(e) => {
             eprintln!(
                 "{}",
-                parser::ParseError::from_pest(&e, &processed, Some(&path.to_string_lossy()))
+                parser::ParseError::from_pest(&e, &processed, Some(&origin.to_string_lossy()))
             );
-            std::process::exit(1);
+            std::process::exit(exit::PARSE_ERROR);
         }
     };
 
+    ProjectSettings::from_content(&processed).register_interpreters();
+
     let mut interpreter = interpreter::Interpreter::new();
     interpreter.set_runfile_dir(Some(base_dir.to_path_buf()));
-    if let Err(e) = interpreter.execute(program) {
+    interpreter.set_source_map(Some(source_map));
+    interpreter.set_script_args(args.to_vec());
+    let result = interpreter.execute(program).and_then(|()| {
+        if interpreter
+            .list_available_functions()
+            .iter()
+            .any(|f| f == "main")
+        {
+            interpreter.call_function_with_args("main", args)
+        } else {
+            Ok(())
+        }
+    });
+    if let Err(e) = result {
         eprintln!("error: {e}");
-        std::process::exit(1);
+        std::process::exit(exit::code_of(e.as_ref()));
     }
 }
 
@@ -67,59 +106,61 @@ pub fn run_function_call(
     function_name: &str,
     args: &[String],
     output_format: OutputFormatArg,
-    show_script: bool,
+    show_script: ShowScript,
 ) {
-    let Some((config_content, _metadata)) = config::load_merged_config() else {
+    let Some((config_content, _metadata, source_map)) = config::load_merged_config_mapped() else {
         eprintln!("{}", config::NO_RUNFILE_ERROR);
         std::process::exit(1);
     };
+    ProjectSettings::from_content(&config_content).register_interpreters();
 
     let mut interpreter = interpreter::Interpreter::new();
     interpreter.set_output_mode(output_format.mode());
     interpreter.set_show_script(show_script);
 
-    // Inject __RUNFILE_DIR__ from the resolved Runfile path.
-    // Prefer the RUN_RUNFILE_DIR env var (set by the MCP handler when the subprocess is
-    // given a temp merged file) so that __RUNFILE_DIR__ always points to the actual project
-    // root, not the system temp directory where the merged file was written.
-    // Only accept the env-var value when it points to an existing directory, so a stale or
-    // malformed env var does not silently override a correctly resolved path.
-    let runfile_dir = std::env::var_os("RUN_RUNFILE_DIR")
-        .map(PathBuf::from)
-        .filter(|p| p.is_dir())
-        .or_else(|| config::find_runfile_path().and_then(|p| p.parent().map(PathBuf::from)));
-    interpreter.set_runfile_dir(runfile_dir);
-
-    match parser::parse_script(&config_content) {
-        Ok(program) => {
-            if let Err(e) = interpreter.execute(program) {
-                eprintln!("error: failed to load functions: {e}");
-                std::process::exit(1);
-            }
-        }
-        Err(e) => {
-            eprintln!(
-                "{}",
-                parser::ParseError::from_pest(&e, &config_content, Some("Runfile"))
-            );
-            std::process::exit(1);
-        }
+    // Inject __RUNFILE_DIR__ from the resolved Runfile path
+    interpreter.set_runfile_dir(config::project_dir());
+
+    let source_map = inherited_source_map().unwrap_or(source_map);
+    let (program, parse_errors) = parser::parse_runfile(&config_content, &source_map);
+    let skipped = interpreter.load_definitions(program);
+    if matches!(output_format.mode(), crate::ast::OutputMode::Stream)
+        && show_script == ShowScript::Off
+    {
+        warn_skipped_top_level(&skipped);
     }
+    interpreter.set_source_map(Some(source_map));
 
     config::set_mcp_function_name(function_name);
 
-    let exec_result = interpreter.call_function_without_parens(function_name, args);
+    let mut call = history::Entry::new(function_name, args, output_format);
+    call.runfile_hash = history::hash_content(&config_content);
+    let started = std::time::Instant::now();
+    // A broken item may be the function, or a project override of a global one
+    let exec_result = if parse_errors.is_empty() {
+        interpreter
+            .run_init_functions(function_name)
+            .and_then(|()| interpreter.call_function_without_parens(function_name, args))
+    } else {
+        Err(refuse_parse_errors(&parse_errors))
+    };
 
     if matches!(output_format.mode(), crate::ast::OutputMode::Structured) {
         let outputs = interpreter.take_captured_outputs();
-        if !outputs.is_empty() {
+        // A call that failed before running anything still reports why
+        if !outputs.is_empty() || exec_result.is_err() {
             let interpreter_name = interpreter.last_interpreter();
 
-            let result = crate::ast::StructuredResult::from_outputs(
+            let mut result = crate::ast::StructuredResult::from_outputs(
                 function_name,
                 outputs,
                 interpreter_name,
             );
+            result.failure = interpreter.take_failure();
+            result.exit_code = Some(0);
+            if let Err(e) = &exec_result {
+                result.set_error(e.as_ref());
+            }
 
             if let Some(formatted) = output_format.format_result(&result) {
                 println!("{formatted}");
@@ -127,15 +168,300 @@ pub fn run_function_call(
         }
     }
 
+    let exit_code = exec_result
+        .as_ref()
+        .map_or_else(|e| exit::code_of(e.as_ref()), |()| 0);
+    if show_script == ShowScript::Off {
+        call.exit_code = Some(exit_code);
+        call.duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        call.interpreter = Some(interpreter.last_interpreter().to_string());
+        history::record(&call);
+    }
+
     if let Err(e) = exec_result {
         eprintln!("error: {e}");
+        std::process::exit(exit_code);
+    }
+}
+
+/// Explain how `run <function_name> <args...>` would be executed, without running it.
+///
+/// Prints a human-readable report, or JSON with `--output-format json`.
+pub fn explain_function(function_name: &str, args: &[String], output_format: OutputFormatArg) {
+    let Some((config_content, _metadata, source_map)) = config::load_merged_config_mapped() else {
+        eprintln!("{}", config::NO_RUNFILE_ERROR);
         std::process::exit(1);
+    };
+    ProjectSettings::from_content(&config_content).register_interpreters();
+
+    let source_map = inherited_source_map().unwrap_or(source_map);
+    let (program, parse_errors) = parser::parse_runfile(&config_content, &source_map);
+    warn_parse_errors(&parse_errors);
+    let definitions: Vec<(String, usize, bool)> = program
+        .statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::SimpleFunctionDef {
+                name,
+                attributes,
+                location,
+                ..
+            }
+            | Statement::BlockFunctionDef {
+                name,
+                attributes,
+                location,
+                ..
+            } => Some((
+                name.clone(),
+                location.line,
+                crate::utils::matches_current_platform(attributes),
+            )),
+            _ => None,
+        })
+        .collect();
+
+    let mut interpreter = interpreter::Interpreter::new();
+    interpreter.load_definitions(program);
+    interpreter.set_source_map(Some(source_map.clone()));
+
+    let mut explanation = match interpreter.explain(function_name, args) {
+        Ok(explanation) => explanation,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(exit::code_of(e.as_ref()));
+        }
+    };
+    let active = definitions
+        .iter()
+        .rposition(|(name, _, for_platform)| *name == explanation.function && *for_platform);
+    explanation.definitions = definitions
+        .iter()
+        .enumerate()
+        .filter(|(_, (name, _, _))| *name == explanation.function)
+        .map(|(idx, (_, line, for_platform))| {
+            let (file, file_line, origin) = match source_map.resolve(*line) {
+                Some((path, file_line)) => (
+                    crate::source_map::display_path(path),
+                    file_line,
+                    runfile_origin(path),
+                ),
+                None => ("Runfile".to_string(), *line, Origin::Project),
+            };
+            Definition {
+                file,
+                line: file_line,
+                origin,
+                active: Some(idx) == active,
+                for_this_platform: *for_platform,
+            }
+        })
+        .collect();
+
+    match output_format {
+        OutputFormatArg::Json => println!("{}", explanation.to_json()),
+        OutputFormatArg::Stream | OutputFormatArg::Markdown => {
+            print!("{}", explanation.to_text());
+        }
+    }
+}
+
+/// Which kind of Runfile `path` is: the `--runfile` file, `~/.runfile`, the project
+/// Runfile, or a file pulled in with `source`
+fn runfile_origin(path: &Path) -> Origin {
+    if config::get_custom_runfile_path().is_some() {
+        return if config::find_runfile_path().is_some_and(|p| p == path) {
+            Origin::Custom
+        } else {
+            Origin::Sourced
+        };
+    }
+    if config::get_home_dir().is_some_and(|home| home.join(".runfile") == path) {
+        Origin::Global
+    } else if config::find_project_runfile_path().is_some_and(|p| p == path) {
+        Origin::Project
+    } else {
+        Origin::Sourced
+    }
+}
+
+/// Handle bare `run`: call the `@default` function if there is one, otherwise do what
+/// the project's `# @set bare` setting asks for (the REPL unless configured).
+pub fn run_bare(output_format: OutputFormatArg, show_script: ShowScript) {
+    let Some((content, _metadata)) = config::load_merged_config() else {
+        repl::run_repl();
+        return;
+    };
+
+    let (program, _) = parser::parse_script_recovering(&content, None);
+    let functions = callable_functions(&program);
+
+    // Later definitions win, so a project `@default` overrides a global one
+    if let Some(default) = functions.iter().rev().find(|f| f.is_default) {
+        run_function_call(&default.name, &[], output_format, show_script);
+        return;
+    }
+
+    match ProjectSettings::from_content(&content).bare {
+        BareAction::Repl => repl::run_repl(),
+        BareAction::List => list_functions(),
+        BareAction::Pick => pick_function(&functions, output_format, show_script),
+    }
+}
+
+/// A function as seen by the bare-`run` picker
+struct CallableFunction {
+    name: String,
+    description: Option<String>,
+    is_default: bool,
+}
+
+/// Functions defined for the current platform, in source order, without running anything.
+/// A redefinition replaces the earlier entry but keeps its position.
+fn callable_functions(program: &crate::ast::Program) -> Vec<CallableFunction> {
+    let mut functions: Vec<CallableFunction> = Vec::new();
+    for statement in &program.statements {
+        let (Statement::SimpleFunctionDef {
+            name, attributes, ..
+        }
+        | Statement::BlockFunctionDef {
+            name, attributes, ..
+        }) = statement
+        else {
+            continue;
+        };
+        if !crate::utils::matches_current_platform(attributes) {
+            continue;
+        }
+        let function = CallableFunction {
+            name: name.clone(),
+            description: attributes.iter().find_map(|a| match a {
+                Attribute::Desc(desc) => Some(desc.clone()),
+                _ => None,
+            }),
+            is_default: attributes.contains(&Attribute::Default),
+        };
+        match functions.iter_mut().find(|f| f.name == *name) {
+            Some(existing) => *existing = function,
+            None => functions.push(function),
+        }
+    }
+    functions
+}
+
+/// Show a numbered menu on stderr, read a choice from stdin and run it.
+///
+/// The answer is a number or a function name, optionally followed by arguments.
+fn pick_function(
+    functions: &[CallableFunction],
+    output_format: OutputFormatArg,
+    show_script: ShowScript,
+) {
+    if functions.is_empty() {
+        println!("No functions defined in Runfile.");
+        return;
+    }
+
+    let width = functions.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    for (idx, function) in functions.iter().enumerate() {
+        match &function.description {
+            Some(desc) => eprintln!("{:>3}) {:<width$}  {desc}", idx + 1, function.name),
+            None => eprintln!("{:>3}) {}", idx + 1, function.name),
+        }
+    }
+    eprint!("Run which function? ");
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return;
+    }
+    let mut words = answer.split_whitespace().map(str::to_string);
+    let Some(choice) = words.next() else {
+        return;
+    };
+    let args: Vec<String> = words.collect();
+
+    let name = match choice.parse::<usize>() {
+        Ok(number) if (1..=functions.len()).contains(&number) => functions[number - 1].name.clone(),
+        Ok(number) => {
+            eprintln!("error: no function numbered {number}");
+            std::process::exit(1);
+        }
+        Err(_) => choice,
+    };
+    run_function_call(&name, &args, output_format, show_script);
+}
+
+/// Source map handed down by a parent process (the MCP server) whose `--runfile` is a
+/// temporary merge, so failures point at the original files instead.
+fn inherited_source_map() -> Option<SourceMap> {
+    config::get_custom_runfile_path()?;
+    let path = std::env::var_os(crate::source_map::SOURCE_MAP_ENV)?;
+    let json = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// Print errors from a recovered parse as a warning on stderr.
+fn warn_parse_errors(errors: &[parser::ParseError]) {
+    if !errors.is_empty() {
+        eprint!("{}", parser::error::format_skipped_items(errors));
+    }
+}
+
+/// Print the errors from a recovered parse on stderr, returning the failure that stops
+/// the call: nothing is run from a Runfile with syntax errors.
+fn refuse_parse_errors(errors: &[parser::ParseError]) -> Box<dyn std::error::Error> {
+    for error in errors {
+        eprintln!("{error}");
+    }
+    exit::Failure::parse_error(parser::error::blocking_summary(errors)).into()
+}
+
+/// Point out top-level commands and calls in the Runfile, which are no longer run when
+/// loading it, on stderr.
+fn warn_skipped_top_level(skipped: &[Statement]) {
+    if skipped.is_empty() {
+        return;
+    }
+    eprintln!(
+        "warning: {} top-level command(s) in the Runfile were not run; \
+         move them into a function marked `# @init` to run them before each call:",
+        skipped.len()
+    );
+    for statement in skipped {
+        match statement {
+            Statement::Command { command } => eprintln!("  {command}"),
+            Statement::FunctionCall { name, args } => eprintln!("  {name}({})", args.join(", ")),
+            _ => {}
+        }
+    }
+}
+
+/// Print the call graph of the Runfile's functions.
+pub fn print_graph(format: GraphFormatArg) {
+    let Some((config_content, _metadata, source_map)) = config::load_merged_config_mapped() else {
+        eprintln!("{}", config::NO_RUNFILE_ERROR);
+        std::process::exit(1);
+    };
+    ProjectSettings::from_content(&config_content).register_interpreters();
+
+    let (program, parse_errors) = parser::parse_runfile(&config_content, &source_map);
+    warn_parse_errors(&parse_errors);
+    let mut interpreter = interpreter::Interpreter::new();
+    interpreter.load_definitions(program);
+    interpreter.set_source_map(Some(source_map));
+
+    let graph = interpreter.call_graph();
+    match format {
+        GraphFormatArg::Dot => print!("{}", graph.to_dot()),
+        GraphFormatArg::Mermaid => print!("{}", graph.to_mermaid()),
     }
 }
 
 /// List all available functions from the Runfile.
 pub fn list_functions() {
-    let Some((merged_content, metadata)) = config::load_merged_config() else {
+    let Some((merged_content, metadata, source_map)) = config::load_merged_config_mapped() else {
         eprintln!("{}", config::NO_RUNFILE_ERROR);
         std::process::exit(1);
     };
@@ -144,39 +470,27 @@ pub fn list_functions() {
     if metadata.has_global && metadata.has_project && !disable_global_merge {
         list_functions_with_sources();
     } else {
-        match parser::parse_script(&merged_content) {
-            Ok(program) => {
-                let mut interpreter = interpreter::Interpreter::new();
-                if let Err(e) = interpreter.execute(program) {
-                    eprintln!("error: failed to load functions: {e}");
-                    std::process::exit(1);
-                }
-
-                let functions = interpreter.list_available_functions();
-                if functions.is_empty() {
-                    println!("No functions defined in Runfile.");
-                    std::process::exit(0);
-                } else {
-                    let source_label = if let Some(custom_path) = config::get_custom_runfile_path()
-                    {
-                        custom_path.display().to_string()
-                    } else if metadata.has_global {
-                        "~/.runfile".to_string()
-                    } else {
-                        "./Runfile".to_string()
-                    };
-                    println!("Available functions from {source_label}:");
-                    for func in functions {
-                        println!("  {func}");
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!(
-                    "{}",
-                    parser::ParseError::from_pest(&e, &merged_content, Some("Runfile"))
-                );
-                std::process::exit(1);
+        let (program, parse_errors) = parser::parse_runfile(&merged_content, &source_map);
+        warn_parse_errors(&parse_errors);
+
+        let mut interpreter = interpreter::Interpreter::new();
+        interpreter.load_definitions(program);
+
+        let functions = interpreter.list_available_functions();
+        if functions.is_empty() {
+            println!("No functions defined in Runfile.");
+            std::process::exit(0);
+        } else {
+            let source_label = if let Some(custom_path) = config::get_custom_runfile_path() {
+                custom_path.display().to_string()
+            } else if metadata.has_global {
+                "~/.runfile".to_string()
+            } else {
+                "./Runfile".to_string()
+            };
+            println!("Available functions from {source_label}:");
+            for func in functions {
+                println!("  {func}");
             }
         }
     }
@@ -186,47 +500,26 @@ pub fn list_functions() {
 fn list_functions_with_sources() {
     use std::collections::HashSet;
 
-    let global_functions = if let Some(global_content) = config::load_home_runfile() {
-        match parser::parse_script(&global_content) {
-            Ok(program) => {
-                let mut interp = interpreter::Interpreter::new();
-                if let Err(e) = interp.execute(program) {
-                    eprintln!("error: failed to load global functions: {e}");
-                    std::process::exit(1);
-                }
-                interp.list_available_functions()
-            }
-            Err(e) => {
-                eprintln!(
-                    "{}",
-                    parser::ParseError::from_pest(&e, &global_content, Some("~/.runfile"))
-                );
-                std::process::exit(1);
-            }
-        }
+    let global_functions = if let Some((global_content, map)) = config::load_home_runfile_mapped() {
+        let (program, parse_errors) = parser::parse_runfile(&global_content, &map);
+        warn_parse_errors(&parse_errors);
+        let mut interp = interpreter::Interpreter::new();
+        interp.load_definitions(program);
+        interp.list_available_functions()
     } else {
         Vec::new()
     };
 
     let project_functions = if let Some(project_path) = config::find_project_runfile_path() {
         if let Ok(project_content) = fs::read_to_string(&project_path) {
-            match parser::parse_script(&project_content) {
-                Ok(program) => {
-                    let mut interp = interpreter::Interpreter::new();
-                    if let Err(e) = interp.execute(program) {
-                        eprintln!("error: failed to load project functions: {e}");
-                        std::process::exit(1);
-                    }
-                    interp.list_available_functions()
-                }
-                Err(e) => {
-                    eprintln!(
-                        "{}",
-                        parser::ParseError::from_pest(&e, &project_content, Some("Runfile"))
-                    );
-                    std::process::exit(1);
-                }
-            }
+            let (program, parse_errors) = parser::parse_runfile(
+                &project_content,
+                &SourceMap::for_file(&project_path, &project_content),
+            );
+            warn_parse_errors(&parse_errors);
+            let mut interp = interpreter::Interpreter::new();
+            interp.load_definitions(program);
+            interp.list_available_functions()
         } else {
             Vec::new()
         }
@@ -0,0 +1,265 @@
+//! Exit codes of `run` itself.
+//!
+//! When a function fails, `run` exits with the code of the command that failed, so CI
+//! steps, git hooks and wrapper scripts can tell one failure from another. Failures that
+//! aren't the function's own get reserved codes from the `sysexits.h` range (64-78),
+//! which shells and common tools leave alone, so they don't collide with the codes
+//! functions exit with.
+
+use crate::limits::LimitViolation;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::process::ExitStatus;
+
+/// The command line was wrong, like `EX_USAGE`
+pub const USAGE: i32 = 64;
+/// The Runfile or script couldn't be parsed, like `EX_DATAERR`
+pub const PARSE_ERROR: i32 = 65;
+/// No such function, like `EX_NOINPUT`
+pub const NOT_FOUND: i32 = 66;
+/// A time limit was reached, like `EX_TEMPFAIL`
+pub const TIMEOUT: i32 = 75;
+/// Added to the number of the signal that killed a command
+pub const SIGNAL_BASE: i32 = 128;
+
+/// Why a call failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitReason {
+    /// A command exited with a failing code, which `run` exits with too
+    Exited,
+    /// A command was killed by a signal: `run` exits with 128 + the signal number
+    Signal,
+    /// The function doesn't exist
+    NotFound,
+    /// The Runfile couldn't be parsed
+    ParseError,
+    /// A time limit was reached
+    Timeout,
+    /// A command ran into a resource limit set with `@limit`; `run` exits with its code
+    Limit,
+    /// Anything else, such as an ambiguous function name: exit code 1
+    Error,
+}
+
+/// An error that decides the exit code of `run`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub reason: ExitReason,
+    /// The code `run` exits with
+    pub code: i32,
+    /// The signal that killed the command, for [`ExitReason::Signal`] and sometimes
+    /// [`ExitReason::Limit`]
+    pub signal: Option<i32>,
+    /// The resource limit the command ran into, for [`ExitReason::Limit`]
+    pub limit: Option<LimitViolation>,
+    message: String,
+}
+
+impl Failure {
+    /// A command run by `program` that didn't succeed, from its exit code or the signal
+    /// that killed it
+    #[must_use]
+    pub fn command(program: &str, code: Option<i32>, signal: Option<i32>) -> Self {
+        match (code, signal) {
+            (_, Some(signal)) => Self {
+                reason: ExitReason::Signal,
+                code: SIGNAL_BASE + signal,
+                signal: Some(signal),
+                limit: None,
+                message: format!("{program}: command killed by signal {signal}"),
+            },
+            (code, None) => {
+                let code = code.unwrap_or(1);
+                Self {
+                    reason: ExitReason::Exited,
+                    code,
+                    signal: None,
+                    limit: None,
+                    message: format!("{program}: command failed (exit code {code})"),
+                }
+            }
+        }
+    }
+
+    /// A command run by `program` that finished with `status`, which isn't success
+    #[must_use]
+    pub fn from_status(program: &str, status: ExitStatus) -> Self {
+        Self::command(program, status.code(), signal_of(status))
+    }
+
+    #[must_use]
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::reserved(ExitReason::NotFound, NOT_FOUND, message.into())
+    }
+
+    #[must_use]
+    pub fn parse_error(message: impl Into<String>) -> Self {
+        Self::reserved(ExitReason::ParseError, PARSE_ERROR, message.into())
+    }
+
+    #[must_use]
+    pub fn timeout(message: impl Into<String>) -> Self {
+        Self::reserved(ExitReason::Timeout, TIMEOUT, message.into())
+    }
+
+    fn reserved(reason: ExitReason, code: i32, message: String) -> Self {
+        Self {
+            reason,
+            code,
+            signal: None,
+            limit: None,
+            message,
+        }
+    }
+
+    /// This failure, put down to `limit` when the command ran into one
+    #[must_use]
+    pub fn exceeding(mut self, limit: Option<LimitViolation>) -> Self {
+        if let Some(limit) = limit {
+            self.reason = ExitReason::Limit;
+            if let Some((program, _)) = self.message.split_once(": ") {
+                self.message = format!("{program}: {} (exit code {})", limit.message, self.code);
+            }
+            self.limit = Some(limit);
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failure {}
+
+/// The [`Failure`] behind `error`, if it is one
+#[must_use]
+pub fn failure_of<'a>(error: &'a (dyn Error + 'static)) -> Option<&'a Failure> {
+    error.downcast_ref()
+}
+
+/// The exit code for `error`: the failure's own, or 1
+#[must_use]
+pub fn code_of(error: &(dyn Error + 'static)) -> i32 {
+    failure_of(error).map_or(1, |failure| failure.code)
+}
+
+/// Why `error` happened, as far as the exit code goes
+#[must_use]
+pub fn reason_of(error: &(dyn Error + 'static)) -> ExitReason {
+    failure_of(error).map_or(ExitReason::Error, |failure| failure.reason)
+}
+
+/// `error` described by `describe(message)`, still deciding the same exit code
+#[must_use]
+pub fn with_context(
+    error: Box<dyn Error>,
+    describe: impl FnOnce(&str) -> String,
+) -> Box<dyn Error> {
+    match error.downcast::<Failure>() {
+        Ok(mut failure) => {
+            failure.message = describe(&failure.message);
+            failure
+        }
+        Err(error) => describe(&error.to_string()).into(),
+    }
+}
+
+/// The exit code a shell would report for `status`: its own, or 128 + the signal
+#[must_use]
+pub fn status_code(status: ExitStatus) -> i32 {
+    match (status.code(), signal_of(status)) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => SIGNAL_BASE + signal,
+        (None, None) => 1,
+    }
+}
+
+/// The signal that killed a process, if it was killed by one
+#[must_use]
+pub fn signal_of(status: ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_failure_codes() {
+        let exited = Failure::command("bash", Some(3), None);
+        assert_eq!(exited.code, 3);
+        assert_eq!(exited.reason, ExitReason::Exited);
+        assert_eq!(exited.to_string(), "bash: command failed (exit code 3)");
+
+        let killed = Failure::command("python3", None, Some(9));
+        assert_eq!(killed.code, 137);
+        assert_eq!(killed.reason, ExitReason::Signal);
+        assert_eq!(killed.signal, Some(9));
+        assert_eq!(killed.to_string(), "python3: command killed by signal 9");
+    }
+
+    #[test]
+    fn test_failure_exceeding_a_limit() {
+        let violation = LimitViolation {
+            resource: crate::limits::Resource::Mem,
+            limit: "2G".to_string(),
+            message: "memory limit of 2G exceeded".to_string(),
+        };
+        let failure = Failure::command("bash", Some(137), None).exceeding(Some(violation));
+        assert_eq!(failure.code, 137);
+        assert_eq!(failure.reason, ExitReason::Limit);
+        assert_eq!(
+            failure.to_string(),
+            "bash: memory limit of 2G exceeded (exit code 137)"
+        );
+        assert_eq!(
+            Failure::command("bash", Some(1), None)
+                .exceeding(None)
+                .reason,
+            ExitReason::Exited
+        );
+    }
+
+    #[test]
+    fn test_context_keeps_the_exit_code() {
+        let error: Box<dyn Error> = Failure::not_found("Function 'x' not found").into();
+        let error = with_context(error, |m| format!("@init function 'setup' failed: {m}"));
+        assert_eq!(code_of(error.as_ref()), NOT_FOUND);
+        assert_eq!(reason_of(error.as_ref()), ExitReason::NotFound);
+        assert_eq!(
+            error.to_string(),
+            "@init function 'setup' failed: Function 'x' not found"
+        );
+
+        let plain: Box<dyn Error> = "something else".into();
+        let plain = with_context(plain, |m| format!("{m}!"));
+        assert_eq!(code_of(plain.as_ref()), 1);
+        assert_eq!(reason_of(plain.as_ref()), ExitReason::Error);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_status_code_of_a_killed_process() {
+        let status = std::process::Command::new("sh")
+            .args(["-c", "kill -TERM $$"])
+            .status()
+            .unwrap();
+        assert_eq!(signal_of(status), Some(15));
+        assert_eq!(status_code(status), 143);
+        assert_eq!(Failure::from_status("sh", status).code, 143);
+    }
+}
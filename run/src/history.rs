@@ -0,0 +1,423 @@
+//! Invocation history: `run --history` and `run --rerun`.
+//!
+//! Every function call made from the command line or by an MCP client is appended to
+//! `history.jsonl` in the `.run-output` directory, one JSON object per line. Calls made
+//! from inside a running function (`run` wrappers for siblings) are part of their
+//! caller's entry and aren't recorded separately.
+
+use crate::cli::OutputFormatArg;
+use crate::config;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Set to `off` to stop recording; also set for everything a function runs
+pub const DISABLE_ENV: &str = "RUN_HISTORY";
+/// Set to `mcp` by the MCP server for the `run` processes it starts
+pub const SOURCE_ENV: &str = "RUN_HISTORY_SOURCE";
+
+const FILE_NAME: &str = "history.jsonl";
+
+/// Who made the call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Cli,
+    Mcp,
+}
+
+/// One recorded call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch when the call started
+    pub timestamp: u64,
+    pub source: Source,
+    /// Function name as given, before resolution (`docker` for `run docker build`)
+    pub function: String,
+    pub args: Vec<String>,
+    pub cwd: PathBuf,
+    /// The `--working-dir`/`--runfile` path, if one was given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runfile: Option<PathBuf>,
+    /// Hash of the Runfile text the call ran against, to notice edits before a rerun
+    pub runfile_hash: String,
+    pub output_format: String,
+    /// Flags that changed how the function ran (`--sandbox`, `--limit cpu=5s`, ...)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<String>,
+    /// [`crate::exit::TIMEOUT`] for an MCP call that timed out, `None` if the call never
+    /// finished
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<String>,
+}
+
+impl Entry {
+    /// A call that starts now, from the current directory
+    #[must_use]
+    pub fn new(function: &str, args: &[String], output_format: OutputFormatArg) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let source = match std::env::var(SOURCE_ENV).as_deref() {
+            Ok("mcp") => Source::Mcp,
+            _ => Source::Cli,
+        };
+        // A temp merged Runfile from the MCP server is gone once the call returns; the
+        // project Runfile is found again from `cwd`
+        let runfile = if std::env::var_os("RUN_RUNFILE_DIR").is_some() {
+            None
+        } else {
+            config::get_custom_runfile_path()
+        };
+        Self {
+            timestamp,
+            source,
+            function: function.to_string(),
+            args: args.to_vec(),
+            cwd: std::env::current_dir().unwrap_or_default(),
+            runfile,
+            runfile_hash: String::new(),
+            output_format: output_format.name().to_string(),
+            flags: config::run_flags(),
+            exit_code: None,
+            duration_ms: 0,
+            interpreter: None,
+        }
+    }
+
+    /// The call as it would be typed
+    #[must_use]
+    pub fn command_line(&self) -> String {
+        self.flags
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(self.function.as_str()))
+            .chain(self.args.iter().map(String::as_str))
+            .map(shell_quote)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Whether `filter` (`docker`, `docker build`, `docker:build`) is a prefix of the call
+    fn matches(&self, filter: &str) -> bool {
+        let call: Vec<&str> = self
+            .function
+            .split(':')
+            .chain(self.args.iter().map(String::as_str))
+            .collect();
+        let wanted: Vec<&str> = filter.split([':', ' ']).filter(|w| !w.is_empty()).collect();
+        call.starts_with(&wanted)
+    }
+}
+
+fn parse_format(name: &str) -> OutputFormatArg {
+    match name {
+        "json" => OutputFormatArg::Json,
+        "markdown" => OutputFormatArg::Markdown,
+        _ => OutputFormatArg::Stream,
+    }
+}
+
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:/=@+,%".contains(c))
+    {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// A stable hash of the Runfile text (FNV-1a), as hex
+#[must_use]
+pub fn hash_content(content: &str) -> String {
+    let hash = content
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{hash:016x}")
+}
+
+fn history_path() -> PathBuf {
+    config::output_dir().join(FILE_NAME)
+}
+
+/// Append `entry` to the history, unless recording is turned off. History is a
+/// convenience, so failing to write it never fails the call.
+pub fn record(entry: &Entry) {
+    if std::env::var(DISABLE_ENV).is_ok_and(|v| v == "off" || v == "0") {
+        return;
+    }
+    let path = history_path();
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    let Ok(mut line) = serde_json::to_string(entry) else {
+        return;
+    };
+    line.push('\n');
+    if let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+    {
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+/// Recorded calls, oldest first; unreadable lines are skipped
+#[must_use]
+pub fn load() -> Vec<Entry> {
+    std::fs::read_to_string(history_path())
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// Pick an entry by its number in `run --history` (from 1) or `last`
+///
+/// # Errors
+///
+/// Returns `Err` if the history is empty or `selector` doesn't name an entry.
+pub fn select<'a>(entries: &'a [Entry], selector: &str) -> Result<(usize, &'a Entry), String> {
+    if entries.is_empty() {
+        return Err("the history is empty".to_string());
+    }
+    let number = if selector == "last" {
+        entries.len()
+    } else {
+        selector
+            .parse::<usize>()
+            .map_err(|_| format!("expected a history number or `last`, got '{selector}'"))?
+    };
+    match number.checked_sub(1).and_then(|idx| entries.get(idx)) {
+        Some(entry) => Ok((number, entry)),
+        None => Err(format!(
+            "no call #{number} in the history (it has {})",
+            entries.len()
+        )),
+    }
+}
+
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{seconds}s ago"),
+        60..3600 => format!("{}m ago", seconds / 60),
+        3600..86400 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration.as_secs() >= 60 {
+        format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        )
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// The calls matching `filter` as a table, numbered for `run --rerun`
+#[must_use]
+pub fn to_table(entries: &[Entry], filter: Option<&str>, now: u64) -> String {
+    let rows: Vec<[String; 7]> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| filter.is_none_or(|f| entry.matches(f)))
+        .map(|(idx, entry)| {
+            [
+                (idx + 1).to_string(),
+                format_age(now.saturating_sub(entry.timestamp)),
+                format!("{:?}", entry.source).to_lowercase(),
+                entry
+                    .exit_code
+                    .map_or_else(|| "killed".to_string(), |code| code.to_string()),
+                format_duration(Duration::from_millis(entry.duration_ms)),
+                entry.interpreter.clone().unwrap_or_else(|| "-".to_string()),
+                entry.command_line(),
+            ]
+        })
+        .collect();
+    let header = ["#", "WHEN", "SOURCE", "EXIT", "TIME", "INTERPRETER", "CALL"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(header.map(String::from)).chain(rows) {
+        let mut line = String::new();
+        for (col, (cell, width)) in row.iter().zip(widths).enumerate() {
+            let _ = match col {
+                0 | 3 | 4 => write!(line, "{cell:>width$}  "),
+                _ => write!(line, "{cell:<width$}  "),
+            };
+        }
+        let _ = writeln!(table, "{}", line.trim_end());
+    }
+    table
+}
+
+/// Print the history, or the calls to one function
+pub fn print_history(filter: Option<&str>, output_format: OutputFormatArg) {
+    let entries = load();
+    if let OutputFormatArg::Json = output_format {
+        let selected: Vec<_> = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| filter.is_none_or(|f| entry.matches(f)))
+            .map(|(idx, entry)| {
+                let mut value = serde_json::to_value(entry).unwrap_or_default();
+                value["number"] = serde_json::json!(idx + 1);
+                value
+            })
+            .collect();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&selected).unwrap_or_default()
+        );
+        return;
+    }
+    if entries.is_empty() {
+        eprintln!("No calls recorded yet in {}", history_path().display());
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    print!("{}", to_table(&entries, filter, now));
+}
+
+/// Repeat a recorded call: same directory, Runfile, function, arguments and output format
+pub fn rerun(selector: Option<&str>) {
+    let entries = load();
+    let (number, entry) = match select(&entries, selector.unwrap_or("last")) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(crate::exit::USAGE);
+        }
+    };
+    if let Err(e) = std::env::set_current_dir(&entry.cwd) {
+        eprintln!("error: cannot enter {}: {e}", entry.cwd.display());
+        std::process::exit(1);
+    }
+    if entry.runfile.is_some() {
+        config::set_custom_runfile_path(entry.runfile.clone());
+    }
+    eprintln!(
+        "Re-running #{number}: run {} (in {})",
+        entry.command_line(),
+        entry.cwd.display()
+    );
+    if let Err(e) = crate::cli::apply_run_flags(&entry.flags) {
+        eprintln!("error: #{number} was recorded with flags this `run` doesn't take: {e}");
+        std::process::exit(crate::exit::USAGE);
+    }
+    if let Some((content, _)) = config::load_merged_config()
+        && hash_content(&content) != entry.runfile_hash
+    {
+        eprintln!("warning: the Runfile has changed since #{number} was run");
+    }
+    crate::executor::run_function_call(
+        &entry.function,
+        &entry.args,
+        parse_format(&entry.output_format),
+        crate::ast::ShowScript::Off,
+    );
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn entry(function: &str, args: &[&str], exit_code: Option<i32>) -> Entry {
+        Entry {
+            timestamp: 1_000,
+            source: Source::Cli,
+            function: function.to_string(),
+            args: args.iter().map(ToString::to_string).collect(),
+            cwd: PathBuf::from("/project"),
+            runfile: None,
+            runfile_hash: hash_content("build() echo hi"),
+            output_format: "stream".to_string(),
+            flags: Vec::new(),
+            exit_code,
+            duration_ms: 1_500,
+            interpreter: Some("bash".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_select() {
+        let entries = vec![entry("build", &[], Some(0)), entry("test", &[], Some(1))];
+        assert_eq!(select(&entries, "last").unwrap().1.function, "test");
+        assert_eq!(select(&entries, "1").unwrap().0, 1);
+        assert!(select(&entries, "3").unwrap_err().contains("no call #3"));
+        assert!(select(&entries, "0").is_err());
+        assert!(select(&entries, "first").is_err());
+        assert!(select(&[], "last").is_err());
+    }
+
+    #[test]
+    fn test_filter_matches_call_prefix() {
+        let call = entry("docker", &["build", "prod"], Some(0));
+        assert!(call.matches("docker"));
+        assert!(call.matches("docker:build"));
+        assert!(call.matches("docker build"));
+        assert!(!call.matches("docker push"));
+        assert!(!call.matches("dock"));
+        assert!(entry("docker:build", &[], Some(0)).matches("docker build"));
+    }
+
+    #[test]
+    fn test_to_table() {
+        let entries = vec![
+            entry("build", &["--release"], Some(0)),
+            entry("greet", &["hello world"], None),
+        ];
+        let table = to_table(&entries, None, 1_090);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(
+            lines[0],
+            "#  WHEN    SOURCE    EXIT  TIME  INTERPRETER  CALL"
+        );
+        assert_eq!(
+            lines[1],
+            "1  1m ago  cli          0  1.5s  bash         build --release"
+        );
+        assert!(lines[2].ends_with("greet 'hello world'"));
+        assert!(lines[2].contains("killed"));
+
+        let filtered = to_table(&entries, Some("greet"), 1_090);
+        assert_eq!(filtered.lines().count(), 2);
+        assert!(filtered.lines().nth(1).unwrap().starts_with("2 "));
+    }
+
+    #[test]
+    fn test_entry_round_trips_as_json() {
+        let original = entry("build", &["a b"], Some(3));
+        let line = serde_json::to_string(&original).unwrap();
+        assert!(line.contains("\"source\":\"cli\""));
+        assert!(!line.contains("runfile\":"));
+        let parsed: Entry = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.args, vec!["a b"]);
+        assert_eq!(parsed.exit_code, Some(3));
+        assert_eq!(hash_content("x"), hash_content("x"));
+        assert_ne!(hash_content("x"), hash_content("y"));
+    }
+}
@@ -0,0 +1,557 @@
+//! Streaming capture of a child's output (Capture and Structured modes)
+//!
+//! stdout and stderr are read concurrently as the child writes them. In Capture mode
+//! every chunk is also passed straight through to our own stdout/stderr. The output is
+//! recorded line by line, in the order it arrived, with the time of each line. Past
+//! [`SPILL_THRESHOLD`] bytes the record moves to a log file in the output directory and
+//! only the most recent lines stay in memory, so a multi-gigabyte build log costs no
+//! more memory than a short one.
+
+use crate::ast::{OutputEvent, OutputStream};
+use crate::output_file;
+use crate::process;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::time::Instant;
+
+/// Output kept in memory before the record moves to disk
+pub(super) const SPILL_THRESHOLD: usize = 4 * 1024 * 1024;
+/// Output kept in memory once the record is on disk, per stream
+const TAIL_BYTES: usize = 64 * 1024;
+/// Chunks read but not yet recorded, at most
+const CHANNEL_CHUNKS: usize = 64;
+
+fn index(stream: OutputStream) -> usize {
+    match stream {
+        OutputStream::Stdout => 0,
+        OutputStream::Stderr => 1,
+    }
+}
+
+/// Memory taken by a line, counting the bookkeeping that dominates for short lines
+fn size(line: &OutputEvent) -> usize {
+    std::mem::size_of::<OutputEvent>() + line.text.len()
+}
+
+/// Everything a finished child wrote
+#[derive(Debug)]
+pub(super) struct Captured {
+    pub(super) status: ExitStatus,
+    /// The whole of stdout, or its end and where to find the rest once spilled
+    pub(super) stdout: String,
+    pub(super) stderr: String,
+    /// The lines kept in memory (all of them unless spilled), in arrival order
+    pub(super) events: Vec<OutputEvent>,
+    /// Log file holding every line, once the output outgrew memory
+    pub(super) spill_path: Option<PathBuf>,
+    /// Lines read from the trace marks, when the run was traced
+    pub(super) marks: Marks,
+}
+
+/// Lines a traced run wrote to its marks, each with how much output had arrived before it
+#[derive(Debug, Default)]
+pub(super) struct Marks {
+    /// Mark lines, with the bytes of stdout and stderr received before each
+    pub(super) lines: Vec<(String, [usize; 2])>,
+    /// Bytes of stdout and stderr received in all
+    pub(super) totals: [usize; 2],
+}
+
+/// The FIFOs a traced child writes its marks to, and waits on for each to be read
+#[derive(Debug)]
+pub(super) struct MarkFifos {
+    pub(super) marks: File,
+    pub(super) acks: File,
+}
+
+/// Something read from the child
+enum Arrival {
+    Output(OutputStream, Instant, Vec<u8>),
+    Mark(String),
+}
+
+/// Run `cmd` to completion, capturing its output. With `tee`, output is also passed
+/// through as it arrives. `SIGINT` and `SIGTERM` are forwarded as by
+/// [`process::wait_forwarding`], so hold a [`process::Interrupts`] guard while calling this.
+///
+/// With `marks`, the child's trace marks are read too. The child waits for each mark to
+/// be acknowledged, which happens once the output it wrote before has been recorded, so
+/// every mark lands exactly between the output written before and after it.
+pub(super) fn run(
+    cmd: &mut Command,
+    tee: bool,
+    marks: Option<MarkFifos>,
+) -> std::io::Result<Captured> {
+    let started = Instant::now();
+    process::supervise(cmd);
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    // Bounded, so a child writing faster than the output is recorded waits for it
+    let (sender, receiver) = mpsc::sync_channel(CHANNEL_CHUNKS);
+    let mut pipes = Vec::new();
+    if let Some(pipe) = child.stdout.take() {
+        pipes.extend(marks.as_ref().and_then(|_| watch(&pipe)));
+        forward_chunks(OutputStream::Stdout, pipe, sender.clone());
+    }
+    if let Some(pipe) = child.stderr.take() {
+        pipes.extend(marks.as_ref().and_then(|_| watch(&pipe)));
+        forward_chunks(OutputStream::Stderr, pipe, sender.clone());
+    }
+    let (end_marks, mut acks) = match marks {
+        Some(MarkFifos { marks, acks }) => {
+            let end = marks.try_clone()?;
+            forward_marks(marks, sender.clone());
+            (Some(end), Some(acks))
+        }
+        None => (None, None),
+    };
+    drop(sender);
+    let waiter = std::thread::spawn(move || {
+        let status = process::wait_forwarding(&mut child);
+        if let Some(mut end) = end_marks {
+            // Everything the child marked is ahead of the empty line in the FIFO
+            let _ = end.write_all(b"\n");
+        }
+        status
+    });
+
+    let mut record = Record::new(SPILL_THRESHOLD);
+    let mut deferred = std::collections::VecDeque::new();
+    // Ends once both pipes are closed and the marks have ended
+    while let Some(arrival) = deferred.pop_front().or_else(|| receiver.recv().ok()) {
+        match arrival {
+            Arrival::Output(stream, at, chunk) => {
+                let at_ms = at.duration_since(started).as_millis();
+                receive(&mut record, tee, stream, at_ms, &chunk);
+            }
+            Arrival::Mark(line) => {
+                for early in settle(&receiver, &pipes) {
+                    match early {
+                        Arrival::Output(stream, at, chunk) => {
+                            let at_ms = at.duration_since(started).as_millis();
+                            receive(&mut record, tee, stream, at_ms, &chunk);
+                        }
+                        mark @ Arrival::Mark(_) => deferred.push_back(mark),
+                    }
+                }
+                record.marks.lines.push((line, record.total));
+                if let Some(acks) = &mut acks {
+                    let _ = acks.write_all(b"\n");
+                }
+            }
+        }
+    }
+    let status = waiter
+        .join()
+        .map_err(|_| std::io::Error::other("waiting for the command panicked"))??;
+    Ok(record.finish(status))
+}
+
+/// Record a chunk of output, passing it through first with `tee`
+fn receive(record: &mut Record, tee: bool, stream: OutputStream, at_ms: u128, chunk: &[u8]) {
+    if tee {
+        let _ = match stream {
+            OutputStream::Stdout => write_through(std::io::stdout().lock(), chunk),
+            OutputStream::Stderr => write_through(std::io::stderr().lock(), chunk),
+        };
+    }
+    record.push(stream, at_ms, chunk);
+}
+
+/// Take everything the child wrote before the mark just received: what is on its way
+/// here, and what is still in the `pipes`. The child waits for the mark to be
+/// acknowledged, so it isn't writing more meanwhile.
+fn settle(receiver: &mpsc::Receiver<Arrival>, pipes: &[File]) -> Vec<Arrival> {
+    let mut arrivals = Vec::new();
+    loop {
+        match receiver.try_recv() {
+            Ok(arrival) => arrivals.push(arrival),
+            Err(mpsc::TryRecvError::Empty) if pipes.iter().any(|pipe| unread(pipe) > 0) => {
+                std::thread::yield_now();
+            }
+            Err(_) => return arrivals,
+        }
+    }
+}
+
+/// Another handle to `pipe`, to see how much of it is still to be read
+#[cfg(unix)]
+fn watch(pipe: &impl std::os::fd::AsFd) -> Option<File> {
+    pipe.as_fd().try_clone_to_owned().ok().map(File::from)
+}
+
+#[cfg(not(unix))]
+fn watch<T>(_pipe: &T) -> Option<File> {
+    None
+}
+
+/// Bytes in `pipe` not read yet
+#[cfg(unix)]
+fn unread(pipe: &File) -> usize {
+    use std::os::fd::AsRawFd;
+    let mut bytes: libc::c_int = 0;
+    // SAFETY: FIONREAD stores the number of unread bytes in the int it is given
+    if unsafe { libc::ioctl(pipe.as_raw_fd(), libc::FIONREAD, &raw mut bytes) } != 0 {
+        return 0;
+    }
+    usize::try_from(bytes).unwrap_or(0)
+}
+
+#[cfg(not(unix))]
+fn unread(_pipe: &File) -> usize {
+    0
+}
+
+fn write_through(mut out: impl Write, chunk: &[u8]) -> std::io::Result<()> {
+    out.write_all(chunk)?;
+    out.flush()
+}
+
+/// Send what arrives on `pipe` to `sender` as soon as it is read
+fn forward_chunks(
+    stream: OutputStream,
+    mut pipe: impl Read + Send + 'static,
+    sender: mpsc::SyncSender<Arrival>,
+) {
+    std::thread::spawn(move || {
+        let mut buf = vec![0; 8192];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let chunk = buf[..n].to_vec();
+                    if sender
+                        .send(Arrival::Output(stream, Instant::now(), chunk))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+/// Copy `pipe` to our stderr as it arrives, keeping the last of it to return at the end
+pub(super) fn tee_stderr(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut kept = Vec::new();
+        let mut buf = vec![0; 8192];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = write_through(std::io::stderr().lock(), &buf[..n]);
+                    kept.extend_from_slice(&buf[..n]);
+                    if kept.len() > 2 * TAIL_BYTES {
+                        kept.drain(..kept.len() - TAIL_BYTES);
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        let start = kept.len().saturating_sub(TAIL_BYTES);
+        String::from_utf8_lossy(&kept[start..]).into_owned()
+    })
+}
+
+/// Send each line of `marks` to `sender` as soon as it is read, up to an empty line
+fn forward_marks(marks: File, sender: mpsc::SyncSender<Arrival>) {
+    std::thread::spawn(move || {
+        let mut lines = BufReader::new(marks).lines();
+        while let Some(Ok(line)) = lines.next() {
+            if line.is_empty() || sender.send(Arrival::Mark(line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Output recorded so far
+struct Record {
+    threshold: usize,
+    /// Bytes of each stream shown once spilled
+    budget: usize,
+    lines: std::collections::VecDeque<OutputEvent>,
+    /// Memory taken by `lines`, per stream
+    kept: [usize; 2],
+    /// Bytes received, per stream
+    total: [usize; 2],
+    /// Start of a line still being written, per stream
+    pending: [Option<(u128, Vec<u8>)>; 2],
+    spill: Option<(PathBuf, BufWriter<File>)>,
+    /// Set when the log file couldn't be written, so output stays in memory
+    spill_failed: bool,
+    marks: Marks,
+}
+
+impl Record {
+    fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            // Within the MCP budget when talking to an agent, otherwise the whole tail.
+            // Decided up front: finding the output directory makes it look configured.
+            budget: if output_file::is_mcp_output_enabled() {
+                output_file::OUTPUT_TRUNCATE_CHARS
+            } else {
+                TAIL_BYTES
+            },
+            lines: std::collections::VecDeque::new(),
+            kept: [0; 2],
+            total: [0; 2],
+            pending: [None, None],
+            spill: None,
+            spill_failed: false,
+            marks: Marks::default(),
+        }
+    }
+
+    fn push(&mut self, stream: OutputStream, at_ms: u128, mut chunk: &[u8]) {
+        let idx = index(stream);
+        self.total[idx] += chunk.len();
+        while !chunk.is_empty() {
+            let (at, mut bytes) = self.pending[idx]
+                .take()
+                .unwrap_or_else(|| (at_ms, Vec::new()));
+            let end = chunk.iter().position(|&b| b == b'\n').map(|pos| pos + 1);
+            bytes.extend_from_slice(&chunk[..end.unwrap_or(chunk.len())]);
+            chunk = &chunk[end.unwrap_or(chunk.len())..];
+            // A line without an end in sight is recorded in pieces
+            if end.is_some() || bytes.len() >= self.threshold {
+                self.add(OutputEvent {
+                    at_ms: at,
+                    stream,
+                    text: String::from_utf8_lossy(&bytes).into_owned(),
+                });
+            } else {
+                self.pending[idx] = Some((at, bytes));
+            }
+        }
+    }
+
+    fn add(&mut self, line: OutputEvent) {
+        let idx = index(line.stream);
+        self.kept[idx] += size(&line);
+        if let Some((_, file)) = &mut self.spill {
+            let _ = write_log_line(file, &line);
+        }
+        self.lines.push_back(line);
+
+        if self.spill.is_none()
+            && !self.spill_failed
+            && self.kept.iter().sum::<usize>() > self.threshold
+        {
+            self.spill = self.start_spill();
+            self.spill_failed = self.spill.is_none();
+        }
+        if self.spill.is_some() {
+            self.trim();
+        }
+    }
+
+    /// Move the record to a log file. Output stays in memory if that fails.
+    fn start_spill(&self) -> Option<(PathBuf, BufWriter<File>)> {
+        let path = output_file::new_output_path("output", "log").ok()?;
+        let mut file = BufWriter::new(File::create(&path).ok()?);
+        for line in &self.lines {
+            write_log_line(&mut file, line).ok()?;
+        }
+        Some((path, file))
+    }
+
+    /// Forget the oldest lines of each stream beyond its tail
+    fn trim(&mut self) {
+        for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+            let idx = index(stream);
+            while self.kept[idx] > TAIL_BYTES {
+                let Some(pos) = self.lines.iter().position(|l| l.stream == stream) else {
+                    break;
+                };
+                if let Some(line) = self.lines.remove(pos) {
+                    self.kept[idx] -= size(&line);
+                }
+            }
+        }
+    }
+
+    /// Record the unfinished last line of each stream
+    fn close(&mut self) {
+        for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+            if let Some((at_ms, bytes)) = self.pending[index(stream)].take() {
+                self.add(OutputEvent {
+                    at_ms,
+                    stream,
+                    text: String::from_utf8_lossy(&bytes).into_owned(),
+                });
+            }
+        }
+    }
+
+    fn finish(mut self, status: ExitStatus) -> Captured {
+        self.close();
+        self.marks.totals = self.total;
+        let text = |stream: OutputStream| -> String {
+            self.lines
+                .iter()
+                .filter(|l| l.stream == stream)
+                .map(|l| l.text.as_str())
+                .collect()
+        };
+        let (stdout, stderr) = (text(OutputStream::Stdout), text(OutputStream::Stderr));
+        let Some((path, mut file)) = self.spill else {
+            return Captured {
+                status,
+                stdout,
+                stderr,
+                events: self.lines.into(),
+                spill_path: None,
+                marks: self.marks,
+            };
+        };
+        let _ = file.flush();
+        let view = |text: String, stream: OutputStream| {
+            let total = self.total[index(stream)];
+            if total == text.len() {
+                text
+            } else {
+                output_file::truncated_view(&text, total, &path, self.budget)
+            }
+        };
+        Captured {
+            status,
+            stdout: view(stdout, OutputStream::Stdout),
+            stderr: view(stderr, OutputStream::Stderr),
+            events: self.lines.into(),
+            spill_path: Some(path),
+            marks: self.marks,
+        }
+    }
+}
+
+/// The last of `events` that together take at most `budget` bytes of text
+pub(super) fn last_events(mut events: Vec<OutputEvent>, budget: usize) -> Vec<OutputEvent> {
+    let mut used = 0;
+    let keep = events
+        .iter()
+        .rev()
+        .take_while(|event| {
+            used += event.text.len();
+            used <= budget
+        })
+        .count();
+    events.split_off(events.len() - keep)
+}
+
+/// `+1.234s stdout | text`: the log keeps both streams in order, with timestamps
+fn write_log_line(file: &mut impl Write, line: &OutputEvent) -> std::io::Result<()> {
+    #[allow(clippy::cast_precision_loss)]
+    let seconds = line.at_ms as f64 / 1000.0;
+    write!(
+        file,
+        "+{seconds:.3}s {} | {}",
+        line.stream.as_str(),
+        line.text
+    )?;
+    if !line.text.ends_with('\n') {
+        writeln!(file)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn success() -> ExitStatus {
+        Command::new("true").status().unwrap()
+    }
+
+    #[test]
+    fn test_record_assembles_lines_in_arrival_order() {
+        let mut record = Record::new(SPILL_THRESHOLD);
+        record.push(OutputStream::Stdout, 1, b"compiling");
+        record.push(OutputStream::Stderr, 2, b"warning: unused\n");
+        record.push(OutputStream::Stdout, 3, b" crate\ndone\n");
+        record.push(OutputStream::Stderr, 4, b"no newline");
+        record.close();
+
+        let lines: Vec<_> = record
+            .lines
+            .iter()
+            .map(|l| (l.at_ms, l.stream, l.text.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (2, OutputStream::Stderr, "warning: unused\n"),
+                // Timed from its first byte
+                (1, OutputStream::Stdout, "compiling crate\n"),
+                (3, OutputStream::Stdout, "done\n"),
+                (4, OutputStream::Stderr, "no newline"),
+            ]
+        );
+        let captured = record.finish(success());
+        assert_eq!(captured.stdout, "compiling crate\ndone\n");
+        assert_eq!(captured.stderr, "warning: unused\nno newline");
+        assert!(captured.spill_path.is_none());
+    }
+
+    #[test]
+    fn test_record_spills_to_a_log_past_the_threshold() {
+        let dir = tempfile::tempdir().unwrap();
+        crate::config::set_mcp_output_dir(Some(dir.path().to_path_buf()));
+
+        let mut record = Record::new(1024);
+        for i in 0..20_000 {
+            record.push(OutputStream::Stdout, i, format!("line {i}\n").as_bytes());
+            if i % 1000 == 0 {
+                record.push(
+                    OutputStream::Stderr,
+                    i,
+                    format!("progress {i}\n").as_bytes(),
+                );
+            }
+        }
+        // Memory holds a bounded tail
+        let kept: usize = record.lines.iter().map(size).sum();
+        assert!(kept <= 2 * TAIL_BYTES, "kept {kept} bytes");
+        let captured = record.finish(success());
+        crate::config::set_mcp_output_dir(None);
+
+        let path = captured.spill_path.expect("should have spilled");
+        assert!(path.starts_with(dir.path()));
+        assert!(captured.stdout.starts_with("[Output truncated: "));
+        assert!(captured.stdout.ends_with("line 19999"));
+        // stderr is small enough to show whole
+        assert!(captured.stderr.starts_with("progress 0\n"));
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 20_020);
+        assert_eq!(lines[0], "+0.000s stdout | line 0");
+        assert_eq!(lines[1], "+0.000s stderr | progress 0");
+        assert_eq!(lines[20_019], "+19.999s stdout | line 19999");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_reads_both_pipes_concurrently() {
+        // More than a pipe buffer on each stream: reading one pipe at a time would hang
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(
+            "i=0; while [ $i -lt 3000 ]; do echo \"out $i\"; echo \"err $i\" >&2; i=$((i+1)); done",
+        );
+        let captured = run(&mut cmd, false, None).unwrap();
+        assert!(captured.status.success());
+        assert_eq!(captured.stdout.lines().count(), 3000);
+        assert_eq!(captured.stderr.lines().last(), Some("err 2999"));
+        assert_eq!(captured.events.len(), 6000);
+    }
+}
@@ -1,12 +1,14 @@
 //! Helper functions for function execution
 
 use super::FunctionMetadata;
-use super::preamble;
+use super::preamble::{self, ScriptPart};
+use super::shell::Launch;
 use crate::ast::Attribute;
+use crate::registry::Dialect;
 use crate::transpiler::Interpreter as TranspilerInterpreter;
 use std::collections::HashMap;
 
-/// Collect all rewritable sibling names (compatible + incompatible colon siblings)
+/// Collect all rewritable sibling names (compatible + incompatible siblings)
 #[allow(clippy::type_complexity)]
 pub(super) fn collect_rewritable_siblings(
     target_name: &str,
@@ -16,6 +18,10 @@ pub(super) fn collect_rewritable_siblings(
     function_metadata: &HashMap<String, FunctionMetadata>,
     resolve_interpreter: &dyn Fn(&str, &[Attribute], Option<&str>) -> TranspilerInterpreter,
 ) -> Vec<String> {
+    // Call sites are only rewritten in shell code; other languages use `run_fn`
+    if !target_interpreter.spec().dialect.is_shell() {
+        return Vec::new();
+    }
     let mut rewritable_names = preamble::collect_compatible_siblings(
         target_name,
         target_interpreter,
@@ -24,7 +30,7 @@ pub(super) fn collect_rewritable_siblings(
         function_metadata,
         resolve_interpreter,
     );
-    rewritable_names.extend(preamble::collect_incompatible_colon_siblings(
+    rewritable_names.extend(preamble::collect_incompatible_siblings(
         target_name,
         target_interpreter,
         simple_functions,
@@ -37,21 +43,18 @@ pub(super) fn collect_rewritable_siblings(
 
 /// Compute the `set -e` / `set -eo pipefail` prefix for a generated script.
 ///
-/// Returns `"set -eo pipefail"` for Bash, `"set -e"` for Sh, and `""` for
-/// everything else (polyglot interpreters, `PowerShell`). The prefix is suppressed
-/// when the function's attributes include `@noerrexit`.
+/// The prefix comes from the interpreter's registry entry: `"set -eo pipefail"` for
+/// Bash, `"set -e"` for Sh, and `""` for everything else (polyglot interpreters,
+/// `PowerShell`). The prefix is suppressed when the function's attributes include
+/// `@noerrexit`.
 pub(super) fn errexit_prefix(
     interpreter: &TranspilerInterpreter,
     attributes: &[Attribute],
-) -> &'static str {
+) -> String {
     if attributes.iter().any(|a| matches!(a, Attribute::Noerrexit)) {
-        return "";
-    }
-    match interpreter {
-        TranspilerInterpreter::Bash => "set -eo pipefail",
-        TranspilerInterpreter::Sh => "set -e",
-        _ => "",
+        return String::new();
     }
+    interpreter.spec().errexit
 }
 
 /// Build the combined script with preambles and body.
@@ -96,6 +99,211 @@ pub(super) fn build_combined_script(
     parts.join("\n")
 }
 
+/// Number of lines a part occupies once joined into a script with `"\n"`
+fn line_count(text: &str) -> usize {
+    text.split('\n').count()
+}
+
+/// A run of generated script lines that came from one Runfile function body
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct LineRegion {
+    /// First script line of the region (1-indexed)
+    pub(super) start: usize,
+    /// Number of lines in the region
+    pub(super) len: usize,
+    /// Runfile function the lines belong to
+    pub(super) function: String,
+    /// Body offset of the region's first line (non-zero when earlier body lines were
+    /// dropped from the generated script)
+    pub(super) skip: usize,
+}
+
+/// Maps lines of a generated script back to the Runfile function bodies they came from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct LineMap {
+    pub(super) regions: Vec<LineRegion>,
+    /// First script line of each part of the script, for `--show-script --annotate`
+    pub(super) parts: Vec<(usize, ScriptPart)>,
+}
+
+impl LineMap {
+    /// Find the function containing a 1-indexed script line, and the 0-indexed
+    /// offset of that line within the function body.
+    pub(super) fn locate(&self, line: usize) -> Option<(&str, usize)> {
+        self.regions
+            .iter()
+            .find(|r| line >= r.start && line < r.start + r.len)
+            .map(|r| (r.function.as_str(), r.skip + line - r.start))
+    }
+}
+
+/// Build the combined script like [`build_combined_script`], from preamble sections,
+/// and record which script lines belong to which Runfile function body.
+pub(super) fn build_combined_script_mapped(
+    target_name: &str,
+    var_preamble: String,
+    sections: &[preamble::PreambleSection],
+    rewritten_body: String,
+    wrap_in_function: bool,
+    param_locals: &str,
+    errexit: &str,
+) -> (String, LineMap) {
+    let mut map = LineMap::default();
+    let mut line = 1;
+    if !errexit.is_empty() {
+        line += line_count(errexit);
+    }
+    if !var_preamble.is_empty() {
+        map.parts.push((line, ScriptPart::Generated("variables")));
+        line += line_count(&var_preamble);
+    }
+    for section in sections {
+        map.parts.push((line, section.source.clone()));
+        let section_lines = line_count(&section.text);
+        if let ScriptPart::Sibling(function) = &section.source {
+            // Transpiled sections open with a `name() {` line and close with `}`
+            map.regions.push(LineRegion {
+                start: line + 1,
+                len: section_lines.saturating_sub(2),
+                function: function.clone(),
+                skip: 0,
+            });
+        }
+        // Each section is followed by a blank line
+        line += section_lines + 1;
+    }
+    if !sections.is_empty() {
+        // The preamble's trailing newline leaves one more blank line before the body
+        line += 1;
+    }
+    map.parts
+        .push((line, ScriptPart::Body(target_name.to_string())));
+    if wrap_in_function {
+        line += 1;
+        if !param_locals.is_empty() {
+            line += line_count(param_locals);
+        }
+    }
+    map.regions.push(LineRegion {
+        start: line,
+        len: line_count(&rewritten_body),
+        function: target_name.to_string(),
+        skip: 0,
+    });
+
+    let script = build_combined_script(
+        var_preamble,
+        preamble::join_sections(sections),
+        rewritten_body,
+        wrap_in_function,
+        param_locals,
+        errexit,
+    );
+    (script, map)
+}
+
+/// Append an `ERR` trap to the errexit prefix that records the failing line number
+/// in `line_file`, so a failure can be traced back to its Runfile line.
+///
+/// Only Bash supports `ERR` traps; other interpreters, `@noerrexit` functions and
+/// paths that cannot be quoted safely get the prefix unchanged.
+pub(super) fn errexit_with_line_trap(
+    errexit: &str,
+    interpreter: &TranspilerInterpreter,
+    line_file: &std::path::Path,
+) -> String {
+    let path = line_file.to_string_lossy();
+    if errexit.is_empty() || !interpreter.is("bash") || path.contains(['\'', '"', '$', '`', '\\']) {
+        return errexit.to_string();
+    }
+    format!("{errexit}\nset -E\ntrap 'echo \"$LINENO\" >\"{path}\"' ERR")
+}
+
+/// Line map for a polyglot script: `prefix_lines` injected lines followed by the
+/// function body, minus the shebang line at `shebang_index` if it was stripped.
+pub(super) fn polyglot_line_map(
+    target_name: &str,
+    prefix_lines: usize,
+    total_lines: usize,
+    shebang_index: Option<usize>,
+) -> LineMap {
+    let mut map = LineMap::default();
+    if prefix_lines > 0 {
+        map.parts.push((1, ScriptPart::Generated("preamble")));
+    }
+    let body_start = prefix_lines + 1;
+    map.parts
+        .push((body_start, ScriptPart::Body(target_name.to_string())));
+    let body_len = total_lines.saturating_sub(prefix_lines);
+    match shebang_index {
+        Some(idx) => {
+            // Lines before the shebang keep their offsets, later ones move down by one
+            let before = idx.min(body_len);
+            map.regions.push(LineRegion {
+                start: body_start,
+                len: before,
+                function: target_name.to_string(),
+                skip: 0,
+            });
+            map.regions.push(LineRegion {
+                start: body_start + before,
+                len: body_len - before,
+                function: target_name.to_string(),
+                skip: before + 1,
+            });
+        }
+        None => map.regions.push(LineRegion {
+            start: body_start,
+            len: body_len,
+            function: target_name.to_string(),
+            skip: 0,
+        }),
+    }
+    map
+}
+
+/// Whether [`polyglot_error_line`] can find the failing line in `dialect`'s error output
+pub(super) fn reports_error_lines(dialect: Dialect) -> bool {
+    matches!(
+        dialect,
+        Dialect::Python | Dialect::Node | Dialect::Ruby | Dialect::Perl
+    )
+}
+
+/// Extract the failing script line from a polyglot interpreter's error output.
+///
+/// Python reports the innermost frame last (`File "<string>", line N`), while Node
+/// (`[eval]:N`), Ruby (`-e:N:`) and Perl (`at -e line N`) put it first. Scripts run from
+/// a file are named by their path instead (`File "/tmp/run-x/deploy.py", line N`).
+pub(super) fn polyglot_error_line(launch: &Launch, stderr: &str) -> Option<usize> {
+    let inline_name = match launch.spec.dialect {
+        Dialect::Python => "<string>",
+        Dialect::Node => r"\[eval\]",
+        Dialect::Ruby | Dialect::Perl => "-e",
+        _ => return None,
+    };
+    let script = if launch.uses_file() {
+        format!(r#"[^\s"]*[/\\]{}"#, regex::escape(&launch.file_name))
+    } else {
+        inline_name.to_string()
+    };
+    let (pattern, innermost_last) = match launch.spec.dialect {
+        Dialect::Python => (format!(r#"File "{script}", line (\d+)"#), true),
+        Dialect::Node => (format!(r"{script}:(\d+)"), false),
+        Dialect::Ruby => (format!(r"{script}:(\d+):"), false),
+        _ => (format!(r" at {script} line (\d+)"), false),
+    };
+    let re = regex::Regex::new(&pattern).ok()?;
+    let mut lines = re
+        .captures_iter(stderr)
+        .filter_map(|c| c.get(1)?.as_str().parse().ok());
+    if innermost_last {
+        lines.last()
+    } else {
+        lines.next()
+    }
+}
+
 #[cfg(test)]
 #[allow(clippy::expect_used, clippy::unwrap_used)]
 mod tests {
@@ -254,34 +462,162 @@ mod tests {
         assert!(var_pos < func_pos);
     }
 
+    #[test]
+    fn test_line_map_points_at_body_lines() {
+        let sections = vec![
+            preamble::PreambleSection {
+                source: preamble::ScriptPart::Sibling("helper".to_string()),
+                text: "helper() {\n    echo one\n    false\n}".to_string(),
+            },
+            preamble::PreambleSection {
+                source: preamble::ScriptPart::Wrapper("node:hi".to_string()),
+                text: "node__hi() {\n    run node hi \"$@\"\n}".to_string(),
+            },
+        ];
+        let (script, map) = build_combined_script_mapped(
+            "deploy",
+            "A=\"1\"\nB=\"2\"".to_string(),
+            &sections,
+            "echo start\nhelper".to_string(),
+            true,
+            "local env=\"$1\"",
+            "set -eo pipefail\nset -E",
+        );
+        let lines: Vec<&str> = script.lines().collect();
+
+        let (function, offset) = map.locate(6).unwrap();
+        assert_eq!((function, offset), ("helper", 0));
+        assert_eq!(lines[5], "    echo one");
+        assert_eq!(map.locate(7), Some(("helper", 1)));
+        assert_eq!(lines[6], "    false");
+
+        let body_start = lines.iter().position(|l| *l == "echo start").unwrap() + 1;
+        assert_eq!(map.locate(body_start), Some(("deploy", 0)));
+        assert_eq!(map.locate(body_start + 1), Some(("deploy", 1)));
+        assert_eq!(map.locate(body_start + 2), None);
+        // The wrapper for an incompatible sibling is not a Runfile body
+        let wrapper = lines
+            .iter()
+            .position(|l| l.contains("run node hi"))
+            .unwrap()
+            + 1;
+        assert_eq!(map.locate(wrapper), None);
+    }
+
+    #[test]
+    fn test_polyglot_line_map_skips_stripped_shebang() {
+        // One injected line, then a body whose shebang (index 0) was removed
+        let map = polyglot_line_map("calc", 1, 3, Some(0));
+        assert_eq!(map.locate(1), None);
+        assert_eq!(map.locate(2), Some(("calc", 1)));
+        assert_eq!(map.locate(3), Some(("calc", 2)));
+    }
+
+    #[test]
+    fn test_polyglot_error_line() {
+        let launch = |interpreter: &TranspilerInterpreter| Launch::new("f", interpreter, &[], 1);
+        let python = "Traceback (most recent call last):\n  File \"<string>\", line 7, in <module>\n  File \"<string>\", line 3, in f\nZeroDivisionError: division by zero\n";
+        assert_eq!(
+            polyglot_error_line(&launch(&TranspilerInterpreter::named("python3")), python),
+            Some(3)
+        );
+        let node = "[eval]:4\n    throw new Error('x')\n";
+        assert_eq!(
+            polyglot_error_line(&launch(&TranspilerInterpreter::named("node")), node),
+            Some(4)
+        );
+        let ruby = "-e:2:in `<main>': boom (RuntimeError)\n";
+        assert_eq!(
+            polyglot_error_line(&launch(&TranspilerInterpreter::named("ruby")), ruby),
+            Some(2)
+        );
+        let perl = "boom at -e line 5.\n";
+        assert_eq!(
+            polyglot_error_line(&launch(&TranspilerInterpreter::named("perl")), perl),
+            Some(5)
+        );
+        assert_eq!(
+            polyglot_error_line(&launch(&TranspilerInterpreter::named("bash")), python),
+            None
+        );
+    }
+
+    #[test]
+    fn test_polyglot_error_line_script_file() {
+        let attrs = [Attribute::Exec(crate::ast::ExecMode::File)];
+        let launch = Launch::new(
+            "deploy",
+            &TranspilerInterpreter::named("python3"),
+            &attrs,
+            1,
+        );
+        let python = "  File \"/tmp/run-a1/deploy.py\", line 2, in <module>\n  File \"/usr/lib/python3/json.py\", line 9\n";
+        assert_eq!(polyglot_error_line(&launch, python), Some(2));
+    }
+
+    #[test]
+    fn test_errexit_with_line_trap_bash_only() {
+        let file = std::path::Path::new("/tmp/run-a1/lineno");
+        let bash = errexit_with_line_trap(
+            "set -eo pipefail",
+            &TranspilerInterpreter::named("bash"),
+            file,
+        );
+        assert!(bash.starts_with("set -eo pipefail\nset -E\n"));
+        assert!(bash.contains("trap 'echo \"$LINENO\" >\"/tmp/run-a1/lineno\"' ERR"));
+        assert_eq!(
+            errexit_with_line_trap("set -e", &TranspilerInterpreter::named("sh"), file),
+            "set -e"
+        );
+        assert_eq!(
+            errexit_with_line_trap("", &TranspilerInterpreter::named("bash"), file),
+            ""
+        );
+    }
+
     #[test]
     fn test_errexit_prefix_bash() {
         assert_eq!(
-            errexit_prefix(&TranspilerInterpreter::Bash, &[]),
+            errexit_prefix(&TranspilerInterpreter::named("bash"), &[]),
             "set -eo pipefail"
         );
     }
 
     #[test]
     fn test_errexit_prefix_sh() {
-        assert_eq!(errexit_prefix(&TranspilerInterpreter::Sh, &[]), "set -e");
+        assert_eq!(
+            errexit_prefix(&TranspilerInterpreter::named("sh"), &[]),
+            "set -e"
+        );
     }
 
     #[test]
     fn test_errexit_prefix_python() {
-        assert_eq!(errexit_prefix(&TranspilerInterpreter::Python, &[]), "");
+        assert_eq!(
+            errexit_prefix(&TranspilerInterpreter::named("python"), &[]),
+            ""
+        );
     }
 
     #[test]
     fn test_errexit_prefix_node() {
-        assert_eq!(errexit_prefix(&TranspilerInterpreter::Node, &[]), "");
+        assert_eq!(
+            errexit_prefix(&TranspilerInterpreter::named("node"), &[]),
+            ""
+        );
     }
 
     #[test]
     fn test_errexit_prefix_noerrexit_attribute() {
         let attrs = vec![Attribute::Noerrexit];
-        assert_eq!(errexit_prefix(&TranspilerInterpreter::Bash, &attrs), "");
-        assert_eq!(errexit_prefix(&TranspilerInterpreter::Sh, &attrs), "");
+        assert_eq!(
+            errexit_prefix(&TranspilerInterpreter::named("bash"), &attrs),
+            ""
+        );
+        assert_eq!(
+            errexit_prefix(&TranspilerInterpreter::named("sh"), &attrs),
+            ""
+        );
     }
 
     #[test]
@@ -290,7 +626,10 @@ mod tests {
             Attribute::Desc("some desc".to_string()),
             Attribute::Noerrexit,
         ];
-        assert_eq!(errexit_prefix(&TranspilerInterpreter::Bash, &attrs), "");
+        assert_eq!(
+            errexit_prefix(&TranspilerInterpreter::named("bash"), &attrs),
+            ""
+        );
     }
 
     #[test]
@@ -302,7 +641,7 @@ mod tests {
 
         let result = collect_rewritable_siblings(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -324,13 +663,15 @@ mod tests {
                 attributes: vec![],
                 shebang: None,
                 params: vec![],
+                location: crate::ast::SourceLocation::default(),
             },
         );
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = collect_rewritable_siblings(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -0,0 +1,506 @@
+//! `run --explain`: the decisions behind a function call
+//!
+//! Reports which definition of a function is called and from which file, how the
+//! typed name was resolved, which interpreter runs it, which siblings its script
+//! includes, and how the arguments map onto its parameters — without running anything.
+
+use super::{Interpreter, preamble, resolve, shell};
+use crate::ast::{Attribute, Parameter};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// Everything `run --explain` reports about a call
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    /// The call as typed, e.g. `docker build --push`
+    pub call: String,
+    /// The function it resolved to, e.g. `docker:build`
+    pub function: String,
+    /// `simple` (one-line) or `block`
+    pub kind: &'static str,
+    pub resolution: Resolution,
+    /// Every definition of the function, in load order; the active one wins
+    pub definitions: Vec<Definition>,
+    pub interpreter: InterpreterChoice,
+    /// Siblings defined in the generated script, in script order
+    pub siblings: Vec<Sibling>,
+    /// Whether the script gets the `run_fn` helper for calling siblings
+    pub run_fn_helper: bool,
+    pub arguments: Vec<Argument>,
+}
+
+/// How the typed name was matched to a function
+#[derive(Debug, Clone, Serialize)]
+pub struct Resolution {
+    pub strategy: Strategy,
+    /// Leading arguments that were taken as part of the name
+    pub consumed_args: usize,
+}
+
+/// Which name-resolution rule matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// The name is a function as typed
+    Exact,
+    /// Arguments joined to the name with colons: `docker build` → `docker:build`
+    LongestPrefix,
+    /// Double underscores read as colons: `docker__build` → `docker:build`
+    DoubleUnderscore,
+    /// Single underscores read as colons: `docker_build` → `docker:build`
+    Underscore,
+}
+
+impl Strategy {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Exact => "exact name",
+            Self::LongestPrefix => "longest prefix (arguments joined with colons)",
+            Self::DoubleUnderscore => "double underscores as colons",
+            Self::Underscore => "underscores as colons",
+        }
+    }
+}
+
+/// One definition of the function
+#[derive(Debug, Clone, Serialize)]
+pub struct Definition {
+    pub file: String,
+    pub line: usize,
+    pub origin: Origin,
+    /// Whether this is the definition that runs
+    pub active: bool,
+    /// False when an `@os` attribute excludes the current platform
+    pub for_this_platform: bool,
+}
+
+/// Which kind of Runfile a definition comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// `~/.runfile`
+    Global,
+    /// The project `Runfile`
+    Project,
+    /// A file given with `--runfile`
+    Custom,
+    /// A file pulled in with `source`
+    Sourced,
+}
+
+impl Origin {
+    fn describe(self) -> &'static str {
+        match self {
+            Self::Global => "global",
+            Self::Project => "project",
+            Self::Custom => "--runfile",
+            Self::Sourced => "sourced",
+        }
+    }
+}
+
+/// The interpreter that runs the function, and why
+#[derive(Debug, Clone, Serialize)]
+pub struct InterpreterChoice {
+    pub name: String,
+    /// Executable that will be started
+    pub program: String,
+    pub source: InterpreterSource,
+    /// The function's shebang line, if it has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shebang: Option<String>,
+}
+
+/// What decided the interpreter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpreterSource {
+    /// An `@shell` attribute
+    Attribute,
+    /// A `#!` line in the body
+    Shebang,
+    /// Nothing; the platform default shell
+    Default,
+}
+
+/// A sibling included in the generated script
+#[derive(Debug, Clone, Serialize)]
+pub struct Sibling {
+    pub name: String,
+    pub how: SiblingUse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+/// How a sibling is made callable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SiblingUse {
+    /// Its definition is copied into the script
+    Inlined,
+    /// A wrapper that calls `run <name>` stands in for it
+    Wrapped,
+}
+
+/// The value a parameter gets
+#[derive(Debug, Clone, Serialize)]
+pub struct Argument {
+    /// `$name` for declared parameters, `$1`, `$2`, ... otherwise
+    pub parameter: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    pub source: ArgumentSource,
+}
+
+/// Where a parameter's value comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgumentSource {
+    /// The argument in the same position
+    Argument,
+    /// All remaining arguments (`...rest`)
+    Rest,
+    /// The parameter's default value
+    Default,
+    /// Nothing: a required argument was not given
+    Missing,
+}
+
+impl Explanation {
+    /// Pretty-printed JSON
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// Human-readable report
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{} ({} function)", self.function, self.kind);
+        let _ = writeln!(text, "  call:         run {}", self.call);
+        let _ = write!(
+            text,
+            "  resolution:   {}",
+            self.resolution.strategy.describe()
+        );
+        match self.resolution.consumed_args {
+            0 => text.push('\n'),
+            1 => text.push_str(", 1 argument used as part of the name\n"),
+            n => {
+                let _ = writeln!(text, ", {n} arguments used as part of the name");
+            }
+        }
+
+        for definition in &self.definitions {
+            let label = if definition.active {
+                "defined at:"
+            } else if definition.for_this_platform {
+                "overrides:"
+            } else {
+                "skipped:"
+            };
+            let _ = write!(
+                text,
+                "  {label:<13} {}:{} ({})",
+                definition.file,
+                definition.line,
+                definition.origin.describe()
+            );
+            if !definition.for_this_platform {
+                text.push_str(", not for this platform");
+            }
+            text.push('\n');
+        }
+
+        let interpreter = &self.interpreter;
+        let source = match (interpreter.source, &interpreter.shebang) {
+            (InterpreterSource::Attribute, _) => "from @shell".to_string(),
+            (InterpreterSource::Shebang, Some(shebang)) => format!("from shebang `{shebang}`"),
+            _ => "platform default".to_string(),
+        };
+        let _ = writeln!(
+            text,
+            "  interpreter:  {} ({source}), runs `{}`",
+            interpreter.name, interpreter.program
+        );
+
+        self.write_siblings(&mut text);
+        self.write_arguments(&mut text);
+        text
+    }
+
+    fn write_siblings(&self, text: &mut String) {
+        let mut lines: Vec<String> = self
+            .siblings
+            .iter()
+            .map(|sibling| {
+                let origin = match (&sibling.file, sibling.line) {
+                    (Some(file), Some(line)) => format!(" ({file}:{line})"),
+                    _ => String::new(),
+                };
+                match sibling.how {
+                    SiblingUse::Inlined => format!("{} inlined{origin}", sibling.name),
+                    SiblingUse::Wrapped => format!(
+                        "{} wrapped, calls `run {}`{origin}",
+                        sibling.name,
+                        sibling.name.replace(':', " ")
+                    ),
+                }
+            })
+            .collect();
+        if self.run_fn_helper {
+            lines.push("run_fn helper".to_string());
+        }
+        if lines.is_empty() {
+            lines.push("none".to_string());
+        }
+        write_list(text, "siblings:", &lines);
+    }
+
+    fn write_arguments(&self, text: &mut String) {
+        let mut lines: Vec<String> = self
+            .arguments
+            .iter()
+            .map(|argument| match (&argument.value, argument.source) {
+                (_, ArgumentSource::Missing) => format!("{} missing", argument.parameter),
+                (Some(value), ArgumentSource::Default) => {
+                    format!("{} = {value:?} (default)", argument.parameter)
+                }
+                (Some(value), ArgumentSource::Rest) => {
+                    format!("{} = {value} (remaining arguments)", argument.parameter)
+                }
+                (value, _) => format!(
+                    "{} = {:?}",
+                    argument.parameter,
+                    value.as_deref().unwrap_or_default()
+                ),
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push("none".to_string());
+        }
+        write_list(text, "arguments:", &lines);
+    }
+}
+
+/// Write `label` followed by `lines`, one per line, aligned after the label column
+fn write_list(text: &mut String, label: &str, lines: &[String]) {
+    for (idx, line) in lines.iter().enumerate() {
+        let label = if idx == 0 { label } else { "" };
+        let _ = writeln!(text, "  {label:<13} {line}");
+    }
+}
+
+impl Interpreter {
+    /// Work out how `run <function_name> <args...>` would be executed, without running it.
+    ///
+    /// `definitions` is left empty; only the caller knows which files the Runfile text
+    /// was assembled from.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the name doesn't resolve to exactly one function.
+    pub fn explain(
+        &self,
+        function_name: &str,
+        args: &[String],
+    ) -> Result<Explanation, Box<dyn std::error::Error>> {
+        let names = self.list_available_functions();
+        let (name, consumed) = match resolve::resolve(function_name, args, &names) {
+            resolve::Resolution::Found { name, consumed } => (name, consumed),
+            resolve::Resolution::Ambiguous(candidates) => {
+                return Err(format!(
+                    "Function name '{function_name}' is ambiguous; it could mean any of: {}",
+                    candidates.join(", ")
+                )
+                .into());
+            }
+            resolve::Resolution::Namespace(_) | resolve::Resolution::NotFound => {
+                return Err(crate::exit::Failure::not_found(format!(
+                    "Function '{function_name}' not found"
+                ))
+                .into());
+            }
+        };
+        let strategy = if consumed > 0 {
+            Strategy::LongestPrefix
+        } else if name == function_name {
+            Strategy::Exact
+        } else if name == function_name.replace("__", ":") {
+            Strategy::DoubleUnderscore
+        } else {
+            Strategy::Underscore
+        };
+
+        let (kind, body) = if let Some(template) = self.simple_functions.get(&name) {
+            ("simple", template.clone())
+        } else if let Some(commands) = self.block_functions.get(&name) {
+            ("block", commands.join("\n"))
+        } else {
+            return Err(format!("Function '{name}' has no script to explain").into());
+        };
+        let (attributes, shebang) = self.get_block_function_metadata(&name);
+        let shebang = shebang.map(String::from);
+        let target_interpreter =
+            Self::resolve_function_interpreter(&attributes, shebang.as_deref());
+        let source = if attributes.iter().any(|a| matches!(a, Attribute::Shell(_))) {
+            InterpreterSource::Attribute
+        } else if shebang
+            .as_deref()
+            .and_then(shell::resolve_shebang_interpreter)
+            .is_some()
+        {
+            InterpreterSource::Shebang
+        } else {
+            InterpreterSource::Default
+        };
+        let launch = shell::Launch::new(&name, &target_interpreter, &attributes, body.len());
+
+        let mut siblings = Vec::new();
+        let mut run_fn_helper = false;
+        for section in self.function_preamble_sections(&name, &target_interpreter, &body) {
+            let (sibling, how) = match section.source {
+                preamble::ScriptPart::Sibling(sibling) => (sibling, SiblingUse::Inlined),
+                preamble::ScriptPart::Wrapper(sibling) => (sibling, SiblingUse::Wrapped),
+                preamble::ScriptPart::Generated(_) => {
+                    run_fn_helper = true;
+                    continue;
+                }
+                preamble::ScriptPart::Body(_) => continue,
+            };
+            let origin = self.origin_of(&sibling);
+            siblings.push(Sibling {
+                name: sibling,
+                how,
+                file: origin.as_ref().map(|(file, _)| file.clone()),
+                line: origin.map(|(_, line)| line),
+            });
+        }
+
+        let params = self
+            .function_metadata
+            .get(&name)
+            .map_or(&[] as &[Parameter], |m| m.params.as_slice());
+        let call_args = &args[consumed..];
+
+        Ok(Explanation {
+            call: std::iter::once(function_name)
+                .chain(args.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(" "),
+            function: name,
+            kind,
+            resolution: Resolution {
+                strategy,
+                consumed_args: consumed,
+            },
+            definitions: Vec::new(),
+            interpreter: InterpreterChoice {
+                name: target_interpreter.name().to_string(),
+                program: launch.spec.program(),
+                source,
+                shebang,
+            },
+            siblings,
+            run_fn_helper,
+            arguments: map_arguments(params, call_args),
+        })
+    }
+}
+
+/// Map call arguments onto declared parameters, or onto `$1`, `$2`, ... without any
+fn map_arguments(params: &[Parameter], args: &[String]) -> Vec<Argument> {
+    let mut mapped = Vec::new();
+    for (idx, param) in params.iter().enumerate() {
+        let parameter = format!("${}", param.name);
+        let (value, source) = if param.is_rest {
+            let rest = args.get(idx..).unwrap_or_default();
+            (Some(super::shell_quote_args(rest)), ArgumentSource::Rest)
+        } else if let Some(arg) = args.get(idx) {
+            (Some(arg.clone()), ArgumentSource::Argument)
+        } else if let Some(default) = &param.default_value {
+            (Some(default.clone()), ArgumentSource::Default)
+        } else {
+            (None, ArgumentSource::Missing)
+        };
+        mapped.push(Argument {
+            parameter,
+            value,
+            source,
+        });
+    }
+    if params.iter().any(|p| p.is_rest) {
+        return mapped;
+    }
+    // Positional arguments beyond the declared parameters
+    for (idx, arg) in args.iter().enumerate().skip(params.len()) {
+        mapped.push(Argument {
+            parameter: format!("${}", idx + 1),
+            value: Some(arg.clone()),
+            source: ArgumentSource::Argument,
+        });
+    }
+    mapped
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::ast::ArgType;
+
+    fn param(name: &str, default_value: Option<&str>, is_rest: bool) -> Parameter {
+        Parameter {
+            name: name.to_string(),
+            param_type: ArgType::String,
+            default_value: default_value.map(String::from),
+            is_rest,
+        }
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_map_arguments_positional() {
+        let mapped = map_arguments(&[], &args(&["a", "b"]));
+        assert_eq!(mapped.len(), 2);
+        assert_eq!(mapped[1].parameter, "$2");
+        assert_eq!(mapped[1].value.as_deref(), Some("b"));
+        assert_eq!(mapped[1].source, ArgumentSource::Argument);
+    }
+
+    #[test]
+    fn test_map_arguments_params_defaults_and_missing() {
+        let params = [
+            param("env", None, false),
+            param("tag", Some("latest"), false),
+            param("region", None, false),
+        ];
+        let mapped = map_arguments(&params, &args(&["prod"]));
+        let sources: Vec<_> = mapped.iter().map(|a| a.source).collect();
+        assert_eq!(
+            sources,
+            vec![
+                ArgumentSource::Argument,
+                ArgumentSource::Default,
+                ArgumentSource::Missing
+            ]
+        );
+        assert_eq!(mapped[1].value.as_deref(), Some("latest"));
+        assert_eq!(mapped[2].value, None);
+    }
+
+    #[test]
+    fn test_map_arguments_rest() {
+        let params = [param("cmd", None, false), param("rest", None, true)];
+        let mapped = map_arguments(&params, &args(&["run", "a b", "c"]));
+        assert_eq!(mapped.len(), 2);
+        assert_eq!(mapped[1].value.as_deref(), Some("'a b' c"));
+        assert_eq!(mapped[1].source, ArgumentSource::Rest);
+    }
+}
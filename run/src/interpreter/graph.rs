@@ -0,0 +1,343 @@
+//! `run --graph`: which functions call which
+//!
+//! Builds the call graph of the loaded Runfile from the same sibling detection the
+//! generated scripts use, and renders it as Graphviz DOT or Mermaid. Functions are
+//! grouped by the file that defines them, so `source`d files show up as their own boxes.
+
+use super::{Interpreter, preamble};
+use crate::ast::Attribute;
+use crate::transpiler;
+use std::fmt::Write;
+
+/// Functions and the calls between them
+#[derive(Debug, Clone, Default)]
+pub struct CallGraph {
+    /// In source order
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// A Runfile function
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: String,
+    pub interpreter: String,
+    /// File that defines the function (the Runfile or a sourced file)
+    pub file: String,
+    pub line: usize,
+}
+
+impl Node {
+    /// `docker` for `docker:build`, `None` outside a namespace
+    #[must_use]
+    pub fn namespace(&self) -> Option<&str> {
+        self.name.rsplit_once(':').map(|(namespace, _)| namespace)
+    }
+
+    /// Node label: the name within its namespace, then namespace and interpreter
+    fn label_lines(&self) -> (&str, String) {
+        match self.name.rsplit_once(':') {
+            Some((namespace, short)) => (short, format!("{namespace} · {}", self.interpreter)),
+            None => (&self.name, self.interpreter.clone()),
+        }
+    }
+}
+
+/// A call from one function to another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+/// How the callee is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Same language: the callee is inlined into the caller's script
+    Inlined,
+    /// Another language: the caller's script gets a wrapper that calls `run <callee>`
+    Wrapper,
+    /// From Python, Node.js or Ruby through the `run_fn` helper
+    RunFn,
+}
+
+impl EdgeKind {
+    /// Edge label, `None` for plain calls
+    fn label(self) -> Option<&'static str> {
+        match self {
+            Self::Inlined => None,
+            Self::Wrapper => Some("run"),
+            Self::RunFn => Some("run_fn"),
+        }
+    }
+}
+
+impl CallGraph {
+    /// Files in order of their first function, each with its functions
+    fn files(&self) -> Vec<(&str, Vec<(usize, &Node)>)> {
+        let mut files: Vec<(&str, Vec<(usize, &Node)>)> = Vec::new();
+        for (idx, node) in self.nodes.iter().enumerate() {
+            match files.iter_mut().find(|(file, _)| *file == node.file) {
+                Some((_, nodes)) => nodes.push((idx, node)),
+                None => files.push((&node.file, vec![(idx, node)])),
+            }
+        }
+        files
+    }
+
+    fn index_of(&self, name: &str) -> usize {
+        self.nodes
+            .iter()
+            .position(|n| n.name == name)
+            .unwrap_or_default()
+    }
+
+    /// Render as a Graphviz `digraph`
+    #[must_use]
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph runfile {\n    rankdir=LR;\n    node [shape=box];\n");
+        for (cluster, (file, nodes)) in self.files().into_iter().enumerate() {
+            let _ = writeln!(dot, "\n    subgraph cluster_{cluster} {{");
+            let _ = writeln!(dot, "        label=\"{}\";", dot_escape(file));
+            for (_, node) in nodes {
+                let (name, detail) = node.label_lines();
+                let _ = writeln!(
+                    dot,
+                    "        \"{}\" [label=\"{}\\n{}\"];",
+                    dot_escape(&node.name),
+                    dot_escape(name),
+                    dot_escape(&detail)
+                );
+            }
+            dot.push_str("    }\n");
+        }
+        if !self.edges.is_empty() {
+            dot.push('\n');
+        }
+        for edge in &self.edges {
+            let _ = write!(
+                dot,
+                "    \"{}\" -> \"{}\"",
+                dot_escape(&edge.from),
+                dot_escape(&edge.to)
+            );
+            if let Some(label) = edge.kind.label() {
+                let _ = write!(dot, " [style=dashed, label=\"{label}\"]");
+            }
+            dot.push_str(";\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Render as a Mermaid `flowchart`
+    #[must_use]
+    pub fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("flowchart LR\n");
+        for (cluster, (file, nodes)) in self.files().into_iter().enumerate() {
+            let _ = writeln!(
+                mermaid,
+                "    subgraph file{cluster}[\"{}\"]",
+                mermaid_escape(file)
+            );
+            for (idx, node) in nodes {
+                let (name, detail) = node.label_lines();
+                let _ = writeln!(
+                    mermaid,
+                    "        n{idx}[\"{}<br/>{}\"]",
+                    mermaid_escape(name),
+                    mermaid_escape(&detail)
+                );
+            }
+            mermaid.push_str("    end\n");
+        }
+        for edge in &self.edges {
+            let from = self.index_of(&edge.from);
+            let to = self.index_of(&edge.to);
+            match edge.kind.label() {
+                Some(label) => {
+                    let _ = writeln!(mermaid, "    n{from} -.->|{label}| n{to}");
+                }
+                None => {
+                    let _ = writeln!(mermaid, "    n{from} --> n{to}");
+                }
+            }
+        }
+        mermaid
+    }
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+/// Sibling names passed as the first argument of `run_fn("name", ...)`
+fn run_fn_calls<'a>(body: &str, names: &[&'a str]) -> Vec<&'a str> {
+    let mut called = Vec::new();
+    for (pos, _) in body.match_indices("run_fn(") {
+        let rest = body[pos + "run_fn(".len()..].trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+            continue;
+        };
+        let Some((literal, _)) = rest[1..].split_once(quote) else {
+            continue;
+        };
+        if let Some(&name) = names.iter().find(|&&n| n == literal)
+            && !called.contains(&name)
+        {
+            called.push(name);
+        }
+    }
+    called
+}
+
+impl Interpreter {
+    /// Build the call graph of the loaded functions.
+    ///
+    /// Shell functions call siblings by name: same-language siblings are inlined and
+    /// the rest go through `run` wrappers, exactly as in the generated scripts. Python,
+    /// Node.js and Ruby functions call siblings with `run_fn("name")`.
+    #[must_use]
+    pub fn call_graph(&self) -> CallGraph {
+        let resolve_interpreter = |_: &str, attrs: &[Attribute], shebang: Option<&str>| {
+            Self::resolve_function_interpreter(attrs, shebang)
+        };
+        let mut names = self.list_available_functions();
+        names.retain(|name| self.function_metadata.contains_key(name));
+        names.sort_by_cached_key(|name| (self.function_metadata[name].location.line, name.clone()));
+        let all: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        let mut graph = CallGraph::default();
+        for name in &names {
+            let metadata = &self.function_metadata[name];
+            let interpreter = Self::resolve_function_interpreter(
+                &metadata.attributes,
+                metadata.shebang.as_deref(),
+            );
+            let (file, line) = self
+                .origin_of(name)
+                .unwrap_or_else(|| ("Runfile".to_string(), metadata.location.line));
+            graph.nodes.push(Node {
+                name: name.clone(),
+                interpreter: interpreter.name().to_string(),
+                file,
+                line,
+            });
+
+            let body = match (
+                self.simple_functions.get(name),
+                self.block_functions.get(name),
+            ) {
+                (Some(template), _) => template.clone(),
+                (None, Some(commands)) => commands.join("\n"),
+                (None, None) => continue,
+            };
+            if !interpreter.spec().dialect.is_shell() {
+                for callee in run_fn_calls(&body, &all) {
+                    graph.edges.push(Edge {
+                        from: name.clone(),
+                        to: callee.to_string(),
+                        kind: EdgeKind::RunFn,
+                    });
+                }
+                continue;
+            }
+            let compatible = preamble::collect_compatible_siblings(
+                name,
+                &interpreter,
+                &self.simple_functions,
+                &self.block_functions,
+                &self.function_metadata,
+                &resolve_interpreter,
+            );
+            let siblings: Vec<&str> = all.iter().copied().filter(|s| s != name).collect();
+            for callee in transpiler::called_siblings(&body, &siblings) {
+                let kind = if compatible.iter().any(|c| c == callee) {
+                    EdgeKind::Inlined
+                } else {
+                    EdgeKind::Wrapper
+                };
+                graph.edges.push(Edge {
+                    from: name.clone(),
+                    to: callee.to_string(),
+                    kind,
+                });
+            }
+        }
+        graph
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn graph(source: &str) -> CallGraph {
+        let program = crate::parser::parse_script(source).unwrap();
+        let mut interp = Interpreter::new();
+        interp.load_definitions(program);
+        interp.call_graph()
+    }
+
+    #[test]
+    fn test_call_graph_edges() {
+        let graph = graph(
+            "docker:build() docker build .\n\n# @shell python3\nreport() {\n    code, out = run_fn(\"docker:build\")\n}\n\nci() {\n    docker:build\n    report\n    echo report\n}\n",
+        );
+        let names: Vec<_> = graph.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["docker:build", "report", "ci"]);
+        assert_eq!(graph.nodes[0].namespace(), Some("docker"));
+        assert_eq!(graph.nodes[1].interpreter, "python3");
+
+        let edges: Vec<_> = graph
+            .edges
+            .iter()
+            .map(|e| (e.from.as_str(), e.to.as_str(), e.kind))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                ("report", "docker:build", EdgeKind::RunFn),
+                ("ci", "docker:build", EdgeKind::Inlined),
+                ("ci", "report", EdgeKind::Wrapper),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_graph_to_dot() {
+        let dot =
+            graph("# @shell bash\ndocker:build() docker build .\nci() docker:build\n").to_dot();
+        assert!(dot.starts_with("digraph runfile {"));
+        assert!(dot.contains("label=\"Runfile\";"));
+        assert!(dot.contains("\"docker:build\" [label=\"build\\ndocker · bash\"];"));
+        assert!(dot.contains("\"ci\" -> \"docker:build\";"));
+    }
+
+    #[test]
+    fn test_call_graph_to_mermaid() {
+        let mermaid = graph("# @shell python3\nreport() print(1)\nci() report\n").to_mermaid();
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("subgraph file0[\"Runfile\"]"));
+        assert!(mermaid.contains("n0[\"report<br/>python3\"]"));
+        assert!(mermaid.contains("n1 -.->|run| n0"));
+    }
+
+    #[test]
+    fn test_run_fn_calls() {
+        let names = ["build", "test"];
+        assert_eq!(
+            run_fn_calls(
+                "run_fn('test')\nrun_fn( \"build\", 1)\nrun_fn(name)",
+                &names
+            ),
+            vec!["test", "build"]
+        );
+    }
+}
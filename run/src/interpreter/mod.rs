@@ -4,25 +4,41 @@
 //! It handles function definitions, variable substitution, and command execution
 //! with support for multiple interpreters (sh, bash, python, node, ruby, etc.)
 
+mod capture;
 mod execution;
+pub mod explain;
+pub mod graph;
 mod preamble;
+mod resolve;
 mod shell;
-
-use crate::ast::{Attribute, CommandOutput, Expression, OutputMode, Program, Statement};
+mod trace;
+
+use crate::ast::{
+    Attribute, CommandOutput, Expression, FailureLocation, OutputMode, Program, ShowScript,
+    SourceLocation, Statement,
+};
+use crate::exit::{self, Failure};
+use crate::source_map::SourceMap;
 use crate::transpiler::{self, Interpreter as TranspilerInterpreter};
 use crate::utils;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+/// Empty argument list for scripts whose arguments were substituted into the text.
+const NO_ARGS: &[String] = &[];
+
 #[derive(Clone)]
 pub(crate) struct FunctionMetadata {
     pub(crate) attributes: Vec<Attribute>,
     pub(crate) shebang: Option<String>,
     pub(crate) params: Vec<crate::ast::Parameter>,
+    pub(crate) location: SourceLocation,
 }
 
 pub struct Interpreter {
     variables: HashMap<String, String>,
+    /// Variable names in definition order (a redefinition moves the name to the end)
+    variable_order: Vec<String>,
     functions: HashMap<String, Vec<Statement>>,
     simple_functions: HashMap<String, String>,
     block_functions: HashMap<String, Vec<String>>,
@@ -31,30 +47,49 @@ pub struct Interpreter {
     output_mode: OutputMode,
     /// Captured outputs when in Capture/Structured mode
     captured_outputs: Vec<CommandOutput>,
+    /// The end of what the last call in Stream mode wrote to stderr, for tracebacks
+    streamed_stderr: String,
     /// Last interpreter used (for structured output context)
     last_interpreter_name: String,
     /// When true, print the generated script instead of executing
     show_script: bool,
+    /// With `show_script`, mark where each part of the script came from
+    annotate_script: bool,
     /// Directory of the Runfile that was loaded, exposed as `__RUNFILE_DIR__`
     runfile_dir: Option<PathBuf>,
+    /// Origin of each line of the parsed Runfile text, for reporting failures
+    source_map: Option<SourceMap>,
+    /// Runfile location of the most recent failure, if it could be determined
+    last_failure: Option<FailureLocation>,
+    /// Arguments given to a script file, seen by top-level commands as `$1`/`$@`
+    script_args: Vec<String>,
+    /// `@init` functions in definition order
+    init_functions: Vec<String>,
+    /// The block function run being traced, while it runs
+    trace: Option<trace::Trace>,
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
-        let (_, _, default_interpreter_name) =
-            // ignored: (shell_command, shell_arg)
-            shell::interpreter_to_shell_args(&TranspilerInterpreter::default());
         Self {
             variables: HashMap::new(),
+            variable_order: Vec::new(),
             functions: HashMap::new(),
             simple_functions: HashMap::new(),
             block_functions: HashMap::new(),
             function_metadata: HashMap::new(),
             output_mode: OutputMode::default(),
             captured_outputs: Vec::new(),
-            last_interpreter_name: default_interpreter_name.to_string(),
+            streamed_stderr: String::new(),
+            last_interpreter_name: TranspilerInterpreter::default().name().to_string(),
             show_script: false,
+            annotate_script: false,
             runfile_dir: None,
+            source_map: None,
+            last_failure: None,
+            script_args: Vec::new(),
+            init_functions: Vec::new(),
+            trace: None,
         }
     }
 }
@@ -117,8 +152,9 @@ impl Interpreter {
     }
 
     /// Enable show-script mode (print script without executing)
-    pub fn set_show_script(&mut self, show: bool) {
-        self.show_script = show;
+    pub fn set_show_script(&mut self, show: ShowScript) {
+        self.show_script = show != ShowScript::Off;
+        self.annotate_script = show == ShowScript::Annotated;
     }
 
     /// Set the directory of the Runfile that was loaded.
@@ -127,6 +163,23 @@ impl Interpreter {
         self.runfile_dir = dir;
     }
 
+    /// Set the map from parsed Runfile lines back to the files they came from.
+    /// Without one, failures are reported against the parsed text as `Runfile`.
+    pub fn set_source_map(&mut self, map: Option<SourceMap>) {
+        self.source_map = map;
+    }
+
+    /// Set the arguments of a script run as `run script.run args...`.
+    /// Top-level commands see them as `$1`, `$2`, ... and `$@`.
+    pub fn set_script_args(&mut self, args: Vec<String>) {
+        self.script_args = args;
+    }
+
+    /// Take the Runfile location of the last failure (clears it)
+    pub fn take_failure(&mut self) -> Option<FailureLocation> {
+        self.last_failure.take()
+    }
+
     /// Get the current output mode
     #[must_use]
     pub fn output_mode(&self) -> OutputMode {
@@ -164,6 +217,16 @@ impl Interpreter {
         )
     }
 
+    /// Attributes of the function `run <function_name> <args...>` would call, empty if
+    /// the name doesn't resolve to exactly one function
+    #[must_use]
+    pub fn function_attributes(&self, function_name: &str, args: &[String]) -> Vec<Attribute> {
+        match resolve::resolve(function_name, args, &self.list_available_functions()) {
+            resolve::Resolution::Found { name, .. } => self.get_block_function_metadata(&name).0,
+            _ => Vec::new(),
+        }
+    }
+
     /// Execute a parsed program
     ///
     /// # Errors
@@ -179,6 +242,47 @@ impl Interpreter {
         Ok(())
     }
 
+    /// Load a Runfile without side effects: record variables and function definitions,
+    /// but do not run top-level commands or calls.
+    ///
+    /// Returns the top-level commands and calls that were not run, so callers can point
+    /// out that they belong in an `@init` function.
+    pub fn load_definitions(&mut self, program: Program) -> Vec<Statement> {
+        let mut skipped = Vec::new();
+        for statement in program.statements {
+            match statement {
+                Statement::Command { .. } | Statement::FunctionCall { .. } => {
+                    skipped.push(statement);
+                }
+                definition => self.define(definition),
+            }
+        }
+        skipped
+    }
+
+    /// Run the `@init` functions, in definition order, before calling `function_name`.
+    /// An init function is not run again when it is the function being called.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if an init function fails.
+    pub fn run_init_functions(
+        &mut self,
+        function_name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.show_script {
+            return Ok(());
+        }
+        for name in self.init_functions.clone() {
+            if name != function_name {
+                self.call_function_with_args(&name, &[]).map_err(|e| {
+                    exit::with_context(e, |m| format!("@init function '{name}' failed: {m}"))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
     #[must_use]
     pub fn list_available_functions(&self) -> Vec<String> {
         let mut functions = Vec::new();
@@ -207,137 +311,57 @@ impl Interpreter {
         functions
     }
 
-    /// Call a function without parentheses, trying multiple name resolution strategies
+    /// Call a function without parentheses, resolving namespaced names from the arguments
     ///
-    /// This method attempts to match function names in different ways:
-    /// 1. Direct match: `docker_shell` with args
-    /// 2. If args exist, try first arg as subcommand: `docker` + `shell` -> `docker:shell`
-    /// 3. Try replacing underscores with colons: `docker_shell` -> `docker:shell`
+    /// Names are matched in this order:
+    /// 1. Longest prefix over any depth: `docker compose up` -> `docker:compose:up`,
+    ///    falling back to `docker:compose` with arg `up`, then `docker` with both args
+    /// 2. Double underscores as colons (MCP sanitisation): `docker__shell` -> `docker:shell`
+    /// 3. Underscores as colons: `docker_shell` -> `docker:shell`, unless several
+    ///    functions fit, which is reported as ambiguous
     ///
     /// # Errors
     ///
     /// Returns `Err` if:
     /// - The function is not found after trying all resolution strategies
+    /// - The name is ambiguous or only names a namespace
     /// - The function execution fails
     pub fn call_function_without_parens(
         &mut self,
         function_name: &str,
         args: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
-        // Strategy: try to match function names in different ways
-        // 1. Direct match: "docker_shell" with args
-        // 2. If args exist, try first arg as subcommand: "docker" + "shell" -> "docker:shell"
-        // 3. Try replacing underscores with colons: "docker_shell" -> "docker:shell"
-
-        // Try direct match first - simple functions
-        if let Some(command_template) = self.simple_functions.get(function_name).cloned() {
-            let attributes = self.get_simple_function_attributes(function_name).to_vec();
-            return self.execute_simple_function(
-                function_name,
-                &command_template,
-                args,
-                &attributes,
-            );
-        }
-
-        // Try direct match - block functions
-        if let Some(commands) = self.block_functions.get(function_name).cloned() {
-            let (attributes, shebang) = self.get_block_function_metadata(function_name);
-            let shebang_owned = shebang.map(String::from);
-            return self.execute_block_commands(
-                function_name,
-                &commands,
-                args,
-                &attributes,
-                shebang_owned.as_deref(),
-            );
-        }
-
-        // If we have args, try treating the first arg as a subcommand
-        if !args.is_empty() {
-            let nested_name = format!("{}:{}", function_name, args[0]);
-            if let Some(command_template) = self.simple_functions.get(&nested_name).cloned() {
-                let attributes = self.get_simple_function_attributes(&nested_name).to_vec();
-                return self.execute_simple_function(
-                    &nested_name,
-                    &command_template,
-                    &args[1..],
-                    &attributes,
-                );
-            }
-            if let Some(commands) = self.block_functions.get(&nested_name).cloned() {
-                let (attributes, shebang) = self.get_block_function_metadata(&nested_name);
-                let shebang_owned = shebang.map(String::from);
-                return self.execute_block_commands(
-                    &nested_name,
-                    &commands,
-                    &args[1..],
-                    &attributes,
-                    shebang_owned.as_deref(),
-                );
-            }
-        }
-
-        // Try replacing double underscores with colons (MCP sanitization convention)
-        // e.g., "nested__function" -> "nested:function"
-        if function_name.contains("__") {
-            let with_colons = function_name.replace("__", ":");
-            if let Some(command_template) = self.simple_functions.get(&with_colons).cloned() {
-                let attributes = self.get_simple_function_attributes(&with_colons).to_vec();
-                return self.execute_simple_function(
-                    &with_colons,
-                    &command_template,
-                    args,
-                    &attributes,
-                );
+        let names = self.list_available_functions();
+        match resolve::resolve(function_name, args, &names) {
+            resolve::Resolution::Found { name, consumed } => {
+                self.call_function_with_args(&name, &args[consumed..])
             }
-            if let Some(commands) = self.block_functions.get(&with_colons).cloned() {
-                let (attributes, shebang) = self.get_block_function_metadata(&with_colons);
-                let shebang_owned = shebang.map(String::from);
-                return self.execute_block_commands(
-                    &with_colons,
-                    &commands,
-                    args,
-                    &attributes,
-                    shebang_owned.as_deref(),
-                );
-            }
-        }
-
-        // Try replacing underscores with colons
-        let with_colons = function_name.replace('_', ":");
-        if with_colons != function_name {
-            if let Some(command_template) = self.simple_functions.get(&with_colons).cloned() {
-                let attributes = self.get_simple_function_attributes(&with_colons).to_vec();
-                return self.execute_simple_function(
-                    &with_colons,
-                    &command_template,
-                    args,
-                    &attributes,
-                );
+            resolve::Resolution::Ambiguous(candidates) => Err(format!(
+                "Function name '{function_name}' is ambiguous; it could mean any of: {}\n\
+                 Use the colon form to pick one, e.g. `run {}`",
+                candidates.join(", "),
+                candidates[0].replace(':', " ")
+            )
+            .into()),
+            resolve::Resolution::Namespace(children) => {
+                let typed = std::iter::once(function_name)
+                    .chain(args.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let listed = children
+                    .iter()
+                    .map(|c| format!("  {}", c.replace(':', " ")))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                Err(Failure::not_found(format!(
+                    "Function '{function_name}' not found: '{typed}' is a namespace. Available commands:\n{listed}"
+                ))
+                .into())
             }
-            if let Some(commands) = self.block_functions.get(&with_colons).cloned() {
-                let (attributes, shebang) = self.get_block_function_metadata(&with_colons);
-                let shebang_owned = shebang.map(String::from);
-                return self.execute_block_commands(
-                    &with_colons,
-                    &commands,
-                    args,
-                    &attributes,
-                    shebang_owned.as_deref(),
-                );
+            resolve::Resolution::NotFound => {
+                Err(Failure::not_found(format!("Function '{function_name}' not found")).into())
             }
         }
-
-        // Check for full function definitions
-        if let Some(body) = self.functions.get(function_name).cloned() {
-            for stmt in body {
-                self.execute_statement(stmt)?;
-            }
-            return Ok(());
-        }
-
-        Err(format!("Function '{function_name}' not found").into())
     }
 
     /// Call a function with explicit arguments (parentheses syntax)
@@ -355,6 +379,10 @@ impl Interpreter {
         // Direct function call with args in parentheses
         // Try to find the function and execute it with substituted arguments
 
+        // Caught before the call's temp files (script, `lineno`, trace FIFOs) exist, so
+        // an interrupt is re-raised only after they have been removed
+        let _interrupts = crate::process::catch_interrupts();
+
         if let Some(command_template) = self.simple_functions.get(function_name).cloned() {
             let attributes = self.get_simple_function_attributes(function_name).to_vec();
             return self.execute_simple_function(
@@ -386,7 +414,7 @@ impl Interpreter {
             return Ok(());
         }
 
-        Err(format!("Function '{function_name}' not found").into())
+        Err(Failure::not_found(format!("Function '{function_name}' not found")).into())
     }
 
     fn substitute_args(&self, template: &str, args: &[String]) -> String {
@@ -440,8 +468,11 @@ impl Interpreter {
             result = result.replace("$@", &quoted);
         }
 
-        // Replace user-defined variables (e.g., $myvar)
-        for (var_name, var_value) in &self.variables {
+        // Replace user-defined variables (e.g., $myvar), longest names first so
+        // `$VERSION` isn't taken for `$VER` followed by `SION`
+        let mut variables: Vec<_> = self.variables.iter().collect();
+        variables.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        for (var_name, var_value) in variables {
             let placeholder = format!("${var_name}");
             result = result.replace(&placeholder, var_value);
         }
@@ -502,9 +533,28 @@ impl Interpreter {
         &mut self,
         statement: Statement,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        match statement {
+            Statement::FunctionCall { name, args } => {
+                // Call the function with the provided arguments
+                self.call_function_with_args(&name, &args)?;
+            }
+            Statement::Command { command } => {
+                // Substitute variables in the command before executing
+                let substituted_command = self.substitute_args(&command, &self.script_args);
+                shell::execute_command(&substituted_command, &[])?;
+            }
+            definition => self.define(definition),
+        }
+        Ok(())
+    }
+
+    /// Record an assignment or function definition (other statements are ignored)
+    fn define(&mut self, statement: Statement) {
         match statement {
             Statement::Assignment { name, value } => {
                 let Expression::String(val) = value;
+                self.variable_order.retain(|n| *n != name);
+                self.variable_order.push(name.clone());
                 self.variables.insert(name, val);
             }
             Statement::SimpleFunctionDef {
@@ -512,9 +562,13 @@ impl Interpreter {
                 params,
                 command_template,
                 attributes,
+                location,
             } => {
                 // Only store function if it matches the current platform
                 if utils::matches_current_platform(&attributes) {
+                    self.note_init_function(&name, &attributes);
+                    // A later definition replaces an earlier one of either form
+                    self.block_functions.remove(&name);
                     self.simple_functions.insert(name.clone(), command_template);
                     self.function_metadata.insert(
                         name,
@@ -522,6 +576,7 @@ impl Interpreter {
                             attributes,
                             shebang: None,
                             params,
+                            location,
                         },
                     );
                 }
@@ -532,9 +587,12 @@ impl Interpreter {
                 commands,
                 attributes,
                 shebang,
+                location,
             } => {
                 // Only store function if it matches the current platform
                 if utils::matches_current_platform(&attributes) {
+                    self.note_init_function(&name, &attributes);
+                    self.simple_functions.remove(&name);
                     self.block_functions.insert(name.clone(), commands);
                     self.function_metadata.insert(
                         name,
@@ -542,21 +600,21 @@ impl Interpreter {
                             attributes,
                             shebang: shebang.clone(),
                             params,
+                            location,
                         },
                     );
                 }
             }
-            Statement::FunctionCall { name, args } => {
-                // Call the function with the provided arguments
-                self.call_function_with_args(&name, &args)?;
-            }
-            Statement::Command { command } => {
-                // Substitute variables in the command before executing
-                let substituted_command = self.substitute_args(&command, &[]);
-                shell::execute_command(&substituted_command, &[])?;
-            }
+            Statement::FunctionCall { .. } | Statement::Command { .. } => {}
+        }
+    }
+
+    /// Track `@init` functions; a redefinition without `@init` removes the earlier one
+    fn note_init_function(&mut self, name: &str, attributes: &[Attribute]) {
+        self.init_functions.retain(|n| n != name);
+        if attributes.contains(&Attribute::Init) {
+            self.init_functions.push(name.to_string());
         }
-        Ok(())
     }
 
     /// Resolve the interpreter for a given function
@@ -582,6 +640,36 @@ impl Interpreter {
         TranspilerInterpreter::default()
     }
 
+    /// Build the sibling preamble for a function: inlined compatible siblings and
+    /// `run` wrappers for shells, or the `run_fn` helper for other languages
+    fn function_preamble_sections(
+        &self,
+        target_name: &str,
+        target_interpreter: &TranspilerInterpreter,
+        body: &str,
+    ) -> Vec<preamble::PreambleSection> {
+        let resolve_interpreter = |_: &str, attrs: &[Attribute], shebang: Option<&str>| {
+            Self::resolve_function_interpreter(attrs, shebang)
+        };
+        let mut sections = preamble::build_function_preamble_sections(
+            target_name,
+            Some(body),
+            target_interpreter,
+            &self.simple_functions,
+            &self.block_functions,
+            &self.function_metadata,
+            &resolve_interpreter,
+        );
+        let dialect = target_interpreter.spec().dialect;
+        if let Some(text) = preamble::build_run_fn_helper(dialect, body) {
+            sections.push(preamble::PreambleSection {
+                source: preamble::ScriptPart::Generated("run_fn helper"),
+                text,
+            });
+        }
+        sections
+    }
+
     /// Execute a simple function with preambles for composition
     fn execute_simple_function(
         &mut self,
@@ -615,21 +703,15 @@ impl Interpreter {
 
         // Build preambles
         let user_var_preamble =
-            preamble::build_variable_preamble(&self.variables, &target_interpreter);
+            preamble::build_variable_preamble(&self.ordered_variables(), &target_interpreter);
         let runfile_dir_line = self
             .runfile_dir
             .as_ref()
             .and_then(|p| p.to_str())
-            .map(|dir| preamble::build_runfile_dir_preamble(dir, &target_interpreter));
+            .and_then(|dir| preamble::build_runfile_dir_preamble(dir, &target_interpreter));
         let var_preamble = preamble::combine_with_builtin(runfile_dir_line, user_var_preamble);
-        let func_preamble = preamble::build_function_preamble(
-            target_name,
-            &target_interpreter,
-            &self.simple_functions,
-            &self.block_functions,
-            &self.function_metadata,
-            &resolve_interpreter,
-        );
+        let func_sections =
+            self.function_preamble_sections(target_name, &target_interpreter, command_template);
 
         // Get params from metadata for building locals
         let params = self
@@ -639,10 +721,7 @@ impl Interpreter {
 
         // Combine preambles and body — wrap in function for shell interpreters
         // so `return` works in the body
-        let is_shell = matches!(
-            target_interpreter,
-            TranspilerInterpreter::Sh | TranspilerInterpreter::Bash | TranspilerInterpreter::Pwsh
-        );
+        let is_shell = target_interpreter.spec().dialect.is_shell();
 
         // For shell functions with named params, use local variable assignment
         // instead of textual substitution — lets bash handle expansion natively
@@ -652,23 +731,26 @@ impl Interpreter {
             String::new()
         };
 
-        let errexit = execution::errexit_prefix(&target_interpreter, attributes);
+        let call_dir = self.call_dir()?;
+        let errexit = Self::errexit_for(&target_interpreter, attributes, call_dir.as_ref());
 
-        if is_shell && !params.is_empty() {
+        // (script, command shown in output, args passed as positional parameters)
+        let (script, display_cmd, shell_args, line_map) = if is_shell && !params.is_empty() {
             // Shell functions with named params: pass args natively via positional parameters
-            let combined_script = execution::build_combined_script(
+            let (combined_script, line_map) = execution::build_combined_script_mapped(
+                target_name,
                 var_preamble,
-                func_preamble,
+                &func_sections,
                 rewritten_body,
                 is_shell,
                 &param_locals,
-                errexit,
+                &errexit,
             );
-            self.execute_with_mode_args(
-                &combined_script,
-                &target_interpreter,
-                Some(command_template),
+            (
+                combined_script,
+                command_template.to_string(),
                 args,
+                line_map,
             )
         } else if is_shell {
             // Shell functions without named params: apply substitution only to the body
@@ -676,30 +758,53 @@ impl Interpreter {
             // Applying substitution to the full combined script would incorrectly resolve
             // positional params like ${1:-default} inside helper bodies at generation time.
             let substituted_body = self.substitute_args(&rewritten_body, args);
-            let combined_script = execution::build_combined_script(
+            let (combined_script, line_map) = execution::build_combined_script_mapped(
+                target_name,
                 var_preamble,
-                func_preamble,
+                &func_sections,
                 substituted_body,
                 is_shell,
                 &param_locals,
-                errexit,
+                &errexit,
             );
             let display_cmd = self.substitute_args(command_template, args);
-            self.execute_with_mode(&combined_script, &target_interpreter, Some(&display_cmd))
+            (combined_script, display_cmd, NO_ARGS, line_map)
         } else {
             // Non-shell (polyglot): use textual substitution on the combined script
-            let combined_script = execution::build_combined_script(
+            let (combined_script, line_map) = execution::build_combined_script_mapped(
+                target_name,
                 var_preamble,
-                func_preamble,
+                &func_sections,
                 rewritten_body,
                 is_shell,
                 &param_locals,
-                errexit,
+                &errexit,
             );
-            let substituted = self.substitute_args_with_params(&combined_script, args, params);
-            let display_cmd = self.substitute_args_with_params(command_template, args, params);
-            self.execute_with_mode(&substituted, &target_interpreter, Some(&display_cmd))
+            if target_interpreter.spec().dialect.has_dollar_variables() {
+                (
+                    combined_script,
+                    command_template.to_string(),
+                    NO_ARGS,
+                    line_map,
+                )
+            } else {
+                (
+                    self.substitute_args_with_params(&combined_script, args, params),
+                    self.substitute_args_with_params(command_template, args, params),
+                    NO_ARGS,
+                    line_map,
+                )
+            }
+        };
+
+        let launch = shell::Launch::new(target_name, &target_interpreter, attributes, script.len())
+            .in_dir(call_dir);
+        if self.show_script {
+            self.print_script(&script, &launch, &line_map);
+            return Ok(());
         }
+        let result = self.execute_with_mode_args(&script, &launch, Some(&display_cmd), shell_args);
+        self.annotate_failure(result, &launch, &line_map)
     }
 
     fn execute_block_commands(
@@ -719,44 +824,27 @@ impl Interpreter {
             .get(target_name)
             .map_or(&[] as &[crate::ast::Parameter], |m| m.params.as_slice());
 
-        // Check if this is a polyglot language (Python, Node, Ruby)
-        let is_polyglot = matches!(
-            target_interpreter,
-            TranspilerInterpreter::Python
-                | TranspilerInterpreter::Python3
-                | TranspilerInterpreter::Node
-                | TranspilerInterpreter::Ruby
-        );
+        // Anything that isn't a shell (Python, Node, Perl, ...) runs the body as-is
+        let is_polyglot = !target_interpreter.spec().dialect.is_shell();
 
         let full_script = commands.join("\n");
+        self.trace = self.start_trace(target_name, &full_script, &target_interpreter, attributes);
 
         if is_polyglot {
-            let script = if shebang.is_some() {
-                shell::strip_shebang(&full_script)
-            } else {
-                full_script
-            };
-
-            // Inject named arg variables from function parameters
-            let arg_preamble = preamble::build_polyglot_arg_preamble(params, &target_interpreter);
-            let script = if arg_preamble.is_empty() {
-                script
-            } else {
-                format!("{arg_preamble}\n{script}")
-            };
-
-            // Inject __RUNFILE_DIR__ built-in at the top of the polyglot script
-            let script = if let Some(dir) = self.runfile_dir.as_ref().and_then(|p| p.to_str()) {
-                let dir_line = preamble::build_runfile_dir_preamble(dir, &target_interpreter);
-                format!("{dir_line}\n{script}")
-            } else {
-                script
-            };
-
-            let substituted = self.substitute_args_with_params(&script, args, params);
-
-            // Use execute_with_mode_polyglot for proper capture support with args
-            return self.execute_with_mode_polyglot(&substituted, &target_interpreter, args);
+            let launch = shell::Launch::new(
+                target_name,
+                &target_interpreter,
+                attributes,
+                full_script.len(),
+            );
+            return self.execute_polyglot_block(
+                target_name,
+                full_script,
+                args,
+                shebang,
+                &target_interpreter,
+                &launch,
+            );
         }
 
         // For shell-compatible languages, build preamble and compose
@@ -779,159 +867,433 @@ impl Interpreter {
         // Rewrite call sites and build preambles
         let rewritten_body = transpiler::rewrite_call_sites(&full_script, &sibling_names);
         let user_var_preamble =
-            preamble::build_variable_preamble(&self.variables, &target_interpreter);
+            preamble::build_variable_preamble(&self.ordered_variables(), &target_interpreter);
         let runfile_dir_line = self
             .runfile_dir
             .as_ref()
             .and_then(|p| p.to_str())
-            .map(|dir| preamble::build_runfile_dir_preamble(dir, &target_interpreter));
+            .and_then(|dir| preamble::build_runfile_dir_preamble(dir, &target_interpreter));
         let var_preamble = preamble::combine_with_builtin(runfile_dir_line, user_var_preamble);
-        let func_preamble = preamble::build_function_preamble(
-            target_name,
-            &target_interpreter,
-            &self.simple_functions,
-            &self.block_functions,
-            &self.function_metadata,
-            &resolve_interpreter,
-        );
+        let func_sections =
+            self.function_preamble_sections(target_name, &target_interpreter, &full_script);
 
         // For shell functions with named params, use local variable assignment
         let param_locals = preamble::build_shell_param_locals(params);
 
         // Combine preambles and body — always wrap for shell (polyglot returns early above)
-        let errexit = execution::errexit_prefix(&target_interpreter, attributes);
+        let call_dir = self.call_dir()?;
+        let mut errexit = Self::errexit_for(&target_interpreter, attributes, call_dir.as_ref());
+        if let Some(trace) = &self.trace {
+            if !errexit.is_empty() {
+                errexit.push('\n');
+            }
+            errexit.push_str(&trace.bash_trap());
+        }
 
-        if params.is_empty() {
+        let (script, display_cmd, shell_args, line_map) = if params.is_empty() {
             // No named params: apply substitution only to the body so that sibling helper
             // function preambles are left intact. Applying substitution to the full combined
             // script would incorrectly resolve positional params like ${1:-default} inside
             // helper bodies at script-generation time rather than at runtime.
             let substituted_body = self.substitute_args(&rewritten_body, args);
             let display_cmd = self.substitute_args(&full_script, args);
-            let combined_script = execution::build_combined_script(
+            let (combined_script, line_map) = execution::build_combined_script_mapped(
+                target_name,
                 var_preamble,
-                func_preamble,
+                &func_sections,
                 substituted_body,
                 true,
                 &param_locals,
-                errexit,
+                &errexit,
             );
-            self.execute_with_mode(&combined_script, &target_interpreter, Some(&display_cmd))
+            (combined_script, display_cmd, NO_ARGS, line_map)
         } else {
             // Shell functions with named params: pass args natively via positional parameters
-            let combined_script = execution::build_combined_script(
+            let (combined_script, line_map) = execution::build_combined_script_mapped(
+                target_name,
                 var_preamble,
-                func_preamble,
+                &func_sections,
                 rewritten_body,
                 true,
                 &param_locals,
-                errexit,
+                &errexit,
             );
-            self.execute_with_mode_args(
-                &combined_script,
-                &target_interpreter,
-                Some(&full_script),
-                args,
-            )
+            (combined_script, full_script, args, line_map)
+        };
+
+        let mut launch =
+            shell::Launch::new(target_name, &target_interpreter, attributes, script.len())
+                .in_dir(call_dir);
+        if let (Some(trace), Some(sandbox)) = (&self.trace, &mut launch.sandbox) {
+            trace.allow_in(sandbox);
+        }
+        if self.show_script {
+            self.print_script(&script, &launch, &line_map);
+            return Ok(());
+        }
+        let recorded = self.captured_outputs.len();
+        let result = self.execute_with_mode_args(&script, &launch, Some(&display_cmd), shell_args);
+        if let Some(trace) = self.trace.take() {
+            self.finish_trace(trace, &result, &line_map, recorded);
         }
+        self.annotate_failure(result, &launch, &line_map)
     }
 
-    /// Execute a command with the current output mode
-    /// The `display_command` is shown in structured output instead of the full script (which may include preamble)
-    /// Optional `shell_args` are passed as positional parameters to the shell (for native param handling)
-    fn execute_with_mode(
+    /// Start tracing a block function run when it has `@trace` or `--trace` was given.
+    /// Only Bash can be traced; other interpreters run untraced, with a warning.
+    fn start_trace(
+        &self,
+        target_name: &str,
+        body: &str,
+        interpreter: &TranspilerInterpreter,
+        attributes: &[Attribute],
+    ) -> Option<trace::Trace> {
+        if self.show_script
+            || !(attributes.contains(&Attribute::Trace) || crate::config::get_trace())
+        {
+            return None;
+        }
+        if !interpreter.is("bash") {
+            eprintln!(
+                "warning: tracing needs bash; {target_name} runs untraced with {}",
+                interpreter.spec().name
+            );
+            return None;
+        }
+        trace::Trace::start(target_name, body, self.output_mode != OutputMode::Stream)
+    }
+
+    /// Record the steps of a traced run in its captured output (the first one after
+    /// `recorded`), or print them after streamed output
+    fn finish_trace(
         &mut self,
-        script: &str,
+        trace: trace::Trace,
+        result: &Result<(), Box<dyn std::error::Error>>,
+        line_map: &execution::LineMap,
+        recorded: usize,
+    ) {
+        let exit_code = match result {
+            Ok(()) => Some(0),
+            Err(e) => match exit::failure_of(e.as_ref()) {
+                Some(failure) if failure.signal.is_some() => None,
+                _ => Some(exit::code_of(e.as_ref())),
+            },
+        };
+        let function = trace.function().to_string();
+        let output = self.captured_outputs.get(recorded);
+        let steps = trace.finish(line_map, exit_code, output, &|offset| {
+            self.failure_location(&function, offset)
+        });
+        match self.captured_outputs.get_mut(recorded) {
+            Some(output) => output.steps = steps,
+            None => eprint!("{}", trace::summary(&function, &steps)),
+        }
+    }
+
+    /// Execute a Python/Node/Ruby block function as a standalone script
+    fn execute_polyglot_block(
+        &mut self,
+        target_name: &str,
+        full_script: String,
+        args: &[String],
+        shebang: Option<&str>,
+        target_interpreter: &TranspilerInterpreter,
+        launch: &shell::Launch,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let params: Vec<crate::ast::Parameter> = self
+            .function_metadata
+            .get(target_name)
+            .map(|m| m.params.clone())
+            .unwrap_or_default();
+
+        // Body lines shift up by one after the shebang line is removed
+        let shebang_index = shebang.and_then(|_| {
+            full_script
+                .lines()
+                .position(|l| l.trim_start().starts_with("#!"))
+        });
+        let script = if shebang.is_some() {
+            shell::strip_shebang(&full_script)
+        } else {
+            full_script
+        };
+        let mut prefix_lines = 0;
+
+        // Inject named arg variables from function parameters
+        let arg_preamble = preamble::build_polyglot_arg_preamble(&params, &launch.spec);
+        let script = if arg_preamble.is_empty() {
+            script
+        } else {
+            prefix_lines += arg_preamble.split('\n').count();
+            format!("{arg_preamble}\n{script}")
+        };
+
+        // Inject the run_fn helper so the body can call sibling functions
+        let script = match preamble::build_run_fn_helper(launch.spec.dialect, &script) {
+            Some(helper) => {
+                prefix_lines += helper.split('\n').count();
+                format!("{helper}\n{script}")
+            }
+            None => script,
+        };
+
+        // Inject top-level Runfile variables as globals
+        let var_preamble =
+            preamble::build_variable_preamble(&self.ordered_variables(), target_interpreter);
+        let script = if var_preamble.is_empty() {
+            script
+        } else {
+            prefix_lines += var_preamble.split('\n').count();
+            format!("{var_preamble}\n{script}")
+        };
+
+        // Inject __RUNFILE_DIR__ built-in at the top of the polyglot script
+        let dir_line = self
+            .runfile_dir
+            .as_ref()
+            .and_then(|p| p.to_str())
+            .and_then(|dir| preamble::build_runfile_dir_preamble(dir, target_interpreter));
+        let script = if let Some(dir_line) = dir_line {
+            prefix_lines += dir_line.split('\n').count();
+            format!("{dir_line}\n{script}")
+        } else {
+            script
+        };
+
+        let substituted = if launch.spec.dialect.has_dollar_variables() {
+            script
+        } else {
+            self.substitute_args_with_params(&script, args, &params)
+        };
+
+        let line_map = execution::polyglot_line_map(
+            target_name,
+            prefix_lines,
+            substituted.split('\n').count(),
+            shebang_index,
+        );
+        if self.show_script {
+            self.print_script(&substituted, launch, &line_map);
+            return Ok(());
+        }
+        // Use execute_with_mode_polyglot for proper capture support with args
+        let result = self.execute_with_mode_polyglot(&substituted, launch, args);
+        self.annotate_failure(result, launch, &line_map)
+    }
+
+    /// Private directory for the files of a call, unless the script is only shown
+    fn call_dir(&self) -> Result<Option<shell::CallDir>, Box<dyn std::error::Error>> {
+        if self.show_script {
+            return Ok(None);
+        }
+        Ok(Some(shell::CallDir::create()?))
+    }
+
+    /// Errexit prefix for a generated script, with the failing-line trap writing to
+    /// `call_dir` added when the script is going to be executed (it has no directory when
+    /// only shown).
+    fn errexit_for(
         interpreter: &TranspilerInterpreter,
-        display_command: Option<&str>,
+        attributes: &[Attribute],
+        call_dir: Option<&shell::CallDir>,
+    ) -> String {
+        let errexit = execution::errexit_prefix(interpreter, attributes);
+        match call_dir {
+            Some(call_dir) => {
+                execution::errexit_with_line_trap(&errexit, interpreter, &call_dir.line_file())
+            }
+            None => errexit,
+        }
+    }
+
+    /// After a function has run, work out which Runfile line failed (from the `ERR` trap
+    /// file for shells, or the traceback for polyglot interpreters), remember it for
+    /// structured output, and mention it in the returned error.
+    fn annotate_failure(
+        &mut self,
+        result: Result<(), Box<dyn std::error::Error>>,
+        launch: &shell::Launch,
+        line_map: &execution::LineMap,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        self.execute_with_mode_args(script, interpreter, display_command, &[])
+        let trap_line = launch
+            .line_file()
+            .and_then(|file| std::fs::read_to_string(file).ok())
+            .and_then(|s| s.trim().parse::<usize>().ok());
+
+        let Err(e) = result else {
+            return Ok(());
+        };
+        let script_line = trap_line.or_else(|| {
+            let stderr = match self.output_mode {
+                OutputMode::Stream => &self.streamed_stderr,
+                _ => &self.captured_outputs.last()?.stderr,
+            };
+            execution::polyglot_error_line(launch, stderr)
+        });
+        let Some(failure) = script_line
+            .and_then(|line| line_map.locate(line))
+            .and_then(|(function, offset)| self.failure_location(function, offset))
+        else {
+            return Err(e);
+        };
+
+        let error = exit::with_context(e, |m| format!("{m}\n  failed at {failure}"));
+        self.last_failure = Some(failure);
+        Err(error)
+    }
+
+    /// Resolve a line within a function body to its Runfile location and source text
+    fn failure_location(&self, function: &str, offset: usize) -> Option<FailureLocation> {
+        let metadata = self.function_metadata.get(function)?;
+        if metadata.location.body_line == 0 {
+            return None;
+        }
+        let body = if let Some(template) = self.simple_functions.get(function) {
+            template.clone()
+        } else {
+            let commands = self.block_functions.get(function)?;
+            if commands.len() > 1 {
+                // One-line block split on `;`: every command sits on the first body line
+                return self.failure_at(function, &commands[0], 0, metadata);
+            }
+            commands.join("\n")
+        };
+        let command = body.lines().nth(offset).unwrap_or_default();
+        self.failure_at(function, command, offset, metadata)
+    }
+
+    /// Print a generated script for `--show-script`. With `--annotate`, each part is
+    /// preceded by a comment naming the Runfile function and line it came from.
+    fn print_script(&self, script: &str, launch: &shell::Launch, line_map: &execution::LineMap) {
+        if !self.annotate_script {
+            println!("{script}");
+            return;
+        }
+        let comment = launch.spec.dialect.line_comment();
+        let mut parts = line_map.parts.iter().peekable();
+        for (idx, line) in script.split('\n').enumerate() {
+            while let Some((_, part)) = parts.next_if(|(start, _)| *start == idx + 1) {
+                println!("{comment} --- {} ---", self.describe_part(part));
+            }
+            println!("{line}");
+        }
     }
 
-    /// Execute a command with positional args passed natively to the shell
+    /// Describe where a part of a generated script came from, e.g. `sibling build (Runfile:3)`
+    fn describe_part(&self, part: &preamble::ScriptPart) -> String {
+        let origin = part
+            .function()
+            .and_then(|name| self.origin_of(name))
+            .map(|(file, line)| format!(" ({file}:{line})"))
+            .unwrap_or_default();
+        match part {
+            preamble::ScriptPart::Generated(what) => format!("generated {what}"),
+            preamble::ScriptPart::Sibling(name) => format!("sibling {name}{origin}"),
+            preamble::ScriptPart::Wrapper(name) => {
+                format!(
+                    "wrapper for {name}{origin}, calls `run {}`",
+                    name.replace(':', " ")
+                )
+            }
+            preamble::ScriptPart::Body(name) => format!("function {name}{origin}"),
+        }
+    }
+
+    /// File and line where a function is defined, for display
+    fn origin_of(&self, function: &str) -> Option<(String, usize)> {
+        let line = self.function_metadata.get(function)?.location.line;
+        match &self.source_map {
+            Some(map) => map.describe(line),
+            None => Some(("Runfile".to_string(), line)),
+        }
+    }
+
+    fn failure_at(
+        &self,
+        function: &str,
+        command: &str,
+        offset: usize,
+        metadata: &FunctionMetadata,
+    ) -> Option<FailureLocation> {
+        let merged_line = metadata.location.body_line + offset;
+        let (file, line) = match &self.source_map {
+            Some(map) => map.describe(merged_line)?,
+            None => ("Runfile".to_string(), merged_line),
+        };
+        Some(FailureLocation {
+            file,
+            line,
+            function: function.to_string(),
+            command: command.trim().to_string(),
+        })
+    }
+
+    /// Execute a command with the current output mode
+    /// The `display_command` is shown in structured output instead of the full script (which may include preamble)
+    /// `shell_args` are passed as positional parameters to the shell (for native param handling)
     fn execute_with_mode_args(
         &mut self,
         script: &str,
-        interpreter: &TranspilerInterpreter,
+        launch: &shell::Launch,
         display_command: Option<&str>,
         shell_args: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
         use crate::ast::OutputMode;
 
-        // Show-script mode: print and return without executing
-        if self.show_script {
-            println!("{script}");
-            return Ok(());
-        }
-
         // Track the interpreter for structured output context
-        let (shell_cmd, shell_arg, interpreter_name) =
-            shell::interpreter_to_shell_args(interpreter);
-        self.last_interpreter_name = interpreter_name.to_string();
+        self.last_interpreter_name.clone_from(&launch.spec.name);
 
         match self.output_mode {
             OutputMode::Stream => {
                 // Stream mode: execute with optional positional args
-                shell::execute_single_shell_invocation_with_args(script, interpreter, shell_args)
-            }
-            OutputMode::Capture | OutputMode::Structured => {
-                // Capture mode: use the shell args we already have
-                self.execute_with_mode_custom_args(
-                    &shell_cmd,
-                    shell_arg,
+                self.streamed_stderr.clear();
+                shell::execute_single_shell_invocation_with_args(
                     script,
-                    display_command,
+                    launch,
                     shell_args,
+                    &mut self.streamed_stderr,
                 )
             }
+            OutputMode::Capture | OutputMode::Structured => {
+                self.execute_with_mode_custom_args(launch, script, display_command, shell_args)
+            }
         }
     }
 
-    /// Execute a command with custom shell and capture output, with optional positional args
+    /// Execute a command with the given interpreter and capture output, with optional positional args
     fn execute_with_mode_custom_args(
         &mut self,
-        shell_cmd: &str,
-        shell_arg: &str,
+        launch: &shell::Launch,
         script: &str,
         display_command: Option<&str>,
         shell_args: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let output = if shell_args.is_empty() {
-            shell::execute_with_capture(script, shell_cmd, shell_arg, display_command)?
-        } else {
-            // For shell -c with positional args: bash -c "script" bash arg1 arg2
-            // We prepend the interpreter name as $0
-            let mut args_with_dollar0 = vec![shell_cmd.to_string()];
-            args_with_dollar0.extend_from_slice(shell_args);
-            shell::execute_with_capture_and_args(
-                script,
-                shell_cmd,
-                shell_arg,
-                &args_with_dollar0,
-                display_command,
-            )?
-        };
-
-        // Only print output in Capture mode (not Structured, where we format it later)
-        if matches!(self.output_mode, crate::ast::OutputMode::Capture) {
-            if !output.stdout.is_empty() {
-                print!("{}", output.stdout);
-            }
-            if !output.stderr.is_empty() {
-                eprint!("{}", output.stderr);
-            }
+        // Positional args follow the interpreter's convention: bash -c "script" bash arg1 arg2
+        // Output is shown live in Capture mode (not Structured, where we format it later)
+        let marks = self.trace.as_ref().and_then(trace::Trace::marks_source);
+        let shell::CapturedRun {
+            output,
+            marks,
+            limit,
+        } = shell::execute_with_capture_and_args(
+            script,
+            launch,
+            shell_args,
+            display_command,
+            matches!(self.output_mode, crate::ast::OutputMode::Capture),
+            marks,
+        )?;
+        if let Some(trace) = &mut self.trace {
+            trace.record(marks);
         }
 
         // Check for errors
-        if let Some(code) = output.exit_code
-            && code != 0
-        {
+        if output.exit_code != Some(0) {
+            let failure =
+                Failure::command(&self.last_interpreter_name, output.exit_code, output.signal)
+                    .exceeding(limit);
             self.add_captured_output(output);
-            return Err(format!(
-                "{}: command failed (exit code {code})",
-                self.last_interpreter_name
-            )
-            .into());
+            return Err(failure.into());
         }
 
         // Store the captured output
@@ -954,64 +1316,48 @@ impl Interpreter {
         &self.variables
     }
 
+    /// Variables and their values in definition order
+    fn ordered_variables(&self) -> Vec<(&str, &str)> {
+        self.variable_order
+            .iter()
+            .filter_map(|name| Some((name.as_str(), self.variables.get(name)?.as_str())))
+            .collect()
+    }
+
     /// Execute a polyglot command with arguments (for Python, Node, Ruby)
     /// Arguments are passed as command-line arguments, accessible via sys.argv, process.argv, etc.
     fn execute_with_mode_polyglot(
         &mut self,
         script: &str,
-        interpreter: &TranspilerInterpreter,
+        launch: &shell::Launch,
         args: &[String],
     ) -> Result<(), Box<dyn std::error::Error>> {
         use crate::ast::OutputMode;
 
-        // Show-script mode: print and return without executing
-        if self.show_script {
-            println!("{script}");
-            return Ok(());
-        }
-
         // Track the interpreter for structured output context
-        let (shell_cmd, shell_arg, interpreter_name) =
-            shell::interpreter_to_shell_args(interpreter);
-        self.last_interpreter_name = interpreter_name.to_string();
+        let interpreter_name = launch.spec.name.clone();
+        self.last_interpreter_name.clone_from(&interpreter_name);
 
         match self.output_mode {
             OutputMode::Stream => {
-                // Stream mode: execute with arguments using the original path
-                let exec_attributes = vec![crate::ast::Attribute::Shell(match interpreter {
-                    TranspilerInterpreter::Python => crate::ast::ShellType::Python,
-                    TranspilerInterpreter::Python3 => crate::ast::ShellType::Python3,
-                    TranspilerInterpreter::Node => crate::ast::ShellType::Node,
-                    TranspilerInterpreter::Ruby => crate::ast::ShellType::Ruby,
-                    _ => crate::ast::ShellType::Sh,
-                })];
-                shell::execute_command_with_args(script, &exec_attributes, args)
+                self.streamed_stderr.clear();
+                shell::execute_streaming_with_args(script, launch, args, &mut self.streamed_stderr)
             }
             OutputMode::Capture | OutputMode::Structured => {
                 // Capture mode: capture output with arguments
                 // For polyglot, the script IS the user command (no preamble), so pass None
-                let output = shell::execute_with_capture_and_args(
-                    script, &shell_cmd, shell_arg, args, None,
-                )?;
-
-                // Only print output in Capture mode
-                if matches!(self.output_mode, OutputMode::Capture) {
-                    if !output.stdout.is_empty() {
-                        print!("{}", output.stdout);
-                    }
-                    if !output.stderr.is_empty() {
-                        eprint!("{}", output.stderr);
-                    }
-                }
+                // Output is shown live in Capture mode only
+                let tee = matches!(self.output_mode, OutputMode::Capture);
+                let shell::CapturedRun { output, limit, .. } =
+                    shell::execute_with_capture_and_args(script, launch, args, None, tee, None)?;
 
                 // Check for errors
-                if let Some(code) = output.exit_code
-                    && code != 0
-                {
+                if output.exit_code != Some(0) {
+                    let failure =
+                        Failure::command(&interpreter_name, output.exit_code, output.signal)
+                            .exceeding(limit);
                     self.add_captured_output(output);
-                    return Err(
-                        format!("{interpreter_name}: command failed (exit code {code})").into(),
-                    );
+                    return Err(failure.into());
                 }
 
                 // Store the captured output
@@ -1124,6 +1470,51 @@ mod tests {
         assert!(interp.get_variables().is_empty());
     }
 
+    #[test]
+    fn test_load_definitions_skips_top_level_commands() {
+        let program = crate::parser::parse_script(
+            "X=1\necho side effect\nbuild() echo b\nbuild()\n# @init\nsetup() echo s\n",
+        )
+        .unwrap();
+        let mut interp = Interpreter::new();
+        let skipped = interp.load_definitions(program);
+
+        assert_eq!(skipped.len(), 2);
+        assert!(
+            matches!(&skipped[0], Statement::Command { command } if command == "echo side effect")
+        );
+        assert!(matches!(&skipped[1], Statement::FunctionCall { name, .. } if name == "build"));
+        assert_eq!(
+            interp.get_variables().get("X").map(String::as_str),
+            Some("1")
+        );
+        assert_eq!(interp.list_available_functions(), vec!["build", "setup"]);
+        assert_eq!(interp.init_functions, vec!["setup"]);
+    }
+
+    #[test]
+    fn test_redefinition_without_init_drops_init_function() {
+        let program =
+            crate::parser::parse_script("# @init\nsetup() echo a\n\nsetup() echo b\n").unwrap();
+        let mut interp = Interpreter::new();
+        interp.load_definitions(program);
+        assert!(interp.init_functions.is_empty());
+    }
+
+    #[test]
+    fn test_redefinition_replaces_other_form() {
+        let program = crate::parser::parse_script(
+            "build() echo simple\n\nbuild() {\n    echo block\n}\n\ntest() {\n    echo block\n}\n\ntest() echo simple\n",
+        )
+        .unwrap();
+        let mut interp = Interpreter::new();
+        interp.load_definitions(program);
+        assert!(!interp.simple_functions.contains_key("build"));
+        assert!(interp.block_functions.contains_key("build"));
+        assert!(interp.simple_functions.contains_key("test"));
+        assert!(!interp.block_functions.contains_key("test"));
+    }
+
     #[test]
     fn test_set_output_mode() {
         let mut interp = Interpreter::new();
@@ -1142,8 +1533,12 @@ mod tests {
             stdout: "hi\n".to_string(),
             stderr: String::new(),
             exit_code: Some(0),
+            signal: None,
             duration_ms: 10,
             started_at: 0,
+            events: Vec::new(),
+            steps: Vec::new(),
+            sandbox_denied: Vec::new(),
         };
         interp.add_captured_output(output);
 
@@ -1178,6 +1573,7 @@ mod tests {
                 params: vec![],
                 command_template: "echo hello".to_string(),
                 attributes: vec![],
+                location: SourceLocation::default(),
             }],
         };
         interp.execute(program).unwrap();
@@ -1198,6 +1594,7 @@ mod tests {
                 commands: vec!["echo step1".to_string(), "echo step2".to_string()],
                 attributes: vec![],
                 shebang: None,
+                location: SourceLocation::default(),
             }],
         };
         interp.execute(program).unwrap();
@@ -1221,12 +1618,14 @@ mod tests {
                     params: vec![],
                     command_template: "echo z".to_string(),
                     attributes: vec![],
+                    location: SourceLocation::default(),
                 },
                 Statement::SimpleFunctionDef {
                     name: "alpha".to_string(),
                     params: vec![],
                     command_template: "echo a".to_string(),
                     attributes: vec![],
+                    location: SourceLocation::default(),
                 },
                 Statement::BlockFunctionDef {
                     name: "middle".to_string(),
@@ -1234,6 +1633,7 @@ mod tests {
                     commands: vec!["echo m".to_string()],
                     attributes: vec![],
                     shebang: None,
+                    location: SourceLocation::default(),
                 },
             ],
         };
@@ -1378,24 +1778,24 @@ mod tests {
 
     #[test]
     fn test_resolve_function_interpreter_shell_attribute() {
-        let attrs = vec![Attribute::Shell(ShellType::Python)];
+        let attrs = vec![Attribute::Shell(ShellType::new("python"))];
         let result = Interpreter::resolve_function_interpreter(&attrs, None);
-        assert_eq!(result, TranspilerInterpreter::Python);
+        assert_eq!(result, TranspilerInterpreter::named("python"));
     }
 
     #[test]
     fn test_resolve_function_interpreter_shebang() {
         let result = Interpreter::resolve_function_interpreter(&[], Some("/usr/bin/env node"));
-        assert_eq!(result, TranspilerInterpreter::Node);
+        assert_eq!(result, TranspilerInterpreter::named("node"));
     }
 
     #[test]
     fn test_resolve_function_interpreter_attribute_overrides_shebang() {
-        let attrs = vec![Attribute::Shell(ShellType::Ruby)];
+        let attrs = vec![Attribute::Shell(ShellType::new("ruby"))];
         let result =
             Interpreter::resolve_function_interpreter(&attrs, Some("/usr/bin/env python3"));
         // Attribute should take precedence
-        assert_eq!(result, TranspilerInterpreter::Ruby);
+        assert_eq!(result, TranspilerInterpreter::named("ruby"));
     }
 
     #[test]
@@ -1409,6 +1809,7 @@ mod tests {
                     params: vec![],
                     command_template: "echo hello".to_string(),
                     attributes: vec![],
+                    location: SourceLocation::default(),
                 },
                 Statement::FunctionCall {
                     name: "greet".to_string(),
@@ -1433,6 +1834,7 @@ mod tests {
                 attributes: vec![],
                 shebang: None,
                 params: vec![],
+                location: SourceLocation::default(),
             },
         );
 
@@ -1453,6 +1855,7 @@ mod tests {
                 attributes: vec![],
                 shebang: None,
                 params: vec![],
+                location: SourceLocation::default(),
             },
         );
 
@@ -1473,6 +1876,7 @@ mod tests {
                 attributes: vec![],
                 shebang: None,
                 params: vec![],
+                location: SourceLocation::default(),
             },
         );
 
@@ -1491,6 +1895,7 @@ mod tests {
                 params: vec![],
                 command_template: "echo windows".to_string(),
                 attributes: vec![Attribute::Os(crate::ast::OsPlatform::Windows)],
+                location: SourceLocation::default(),
             }],
         };
         interp.execute(program).unwrap();
@@ -1510,15 +1915,16 @@ mod tests {
         interp.function_metadata.insert(
             "test".to_string(),
             FunctionMetadata {
-                attributes: vec![Attribute::Shell(ShellType::Bash)],
+                attributes: vec![Attribute::Shell(ShellType::new("bash"))],
                 shebang: None,
                 params: vec![],
+                location: SourceLocation::default(),
             },
         );
 
         let attrs = interp.get_simple_function_attributes("test");
         assert_eq!(attrs.len(), 1);
-        assert!(matches!(attrs[0], Attribute::Shell(ShellType::Bash)));
+        assert_eq!(attrs[0], Attribute::Shell(ShellType::new("bash")));
 
         // Non-existent function returns empty slice
         let attrs = interp.get_simple_function_attributes("nonexistent");
@@ -1531,9 +1937,10 @@ mod tests {
         interp.function_metadata.insert(
             "build".to_string(),
             FunctionMetadata {
-                attributes: vec![Attribute::Shell(ShellType::Bash)],
+                attributes: vec![Attribute::Shell(ShellType::new("bash"))],
                 shebang: Some("#!/bin/bash".to_string()),
                 params: vec![],
+                location: SourceLocation::default(),
             },
         );
 
@@ -4,9 +4,10 @@
 //! and variables into function execution contexts for composition support.
 
 use super::shell::{escape_pwsh_value, escape_shell_value};
-use crate::ast::Attribute;
+use crate::ast::{Attribute, SiblingScope};
+use crate::registry::{CodeDelivery, Dialect, InterpreterSpec};
 use crate::transpiler::{self, Interpreter as TranspilerInterpreter};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 type InterpreterResolver<'a> =
     dyn Fn(&str, &[Attribute], Option<&str>) -> TranspilerInterpreter + 'a;
 
@@ -56,8 +57,8 @@ pub(super) fn collect_compatible_siblings(
     compatible
 }
 
-/// Collect incompatible sibling function names (those with colons that need run wrappers)
-pub(super) fn collect_incompatible_colon_siblings(
+/// Collect incompatible sibling function names (those that need `run` wrappers)
+pub(super) fn collect_incompatible_siblings(
     target_name: &str,
     target_interpreter: &TranspilerInterpreter,
     simple_functions: &HashMap<String, String>,
@@ -69,7 +70,7 @@ pub(super) fn collect_incompatible_colon_siblings(
 
     // Check simple functions
     for name in simple_functions.keys() {
-        if name == target_name || !name.contains(':') {
+        if name == target_name {
             continue;
         }
         let metadata = function_metadata.get(name);
@@ -84,7 +85,7 @@ pub(super) fn collect_incompatible_colon_siblings(
 
     // Check block functions
     for name in block_functions.keys() {
-        if name == target_name || !name.contains(':') {
+        if name == target_name {
             continue;
         }
         let metadata = function_metadata.get(name);
@@ -103,40 +104,118 @@ pub(super) fn collect_incompatible_colon_siblings(
     incompatible
 }
 
-/// Build wrapper functions for incompatible siblings (calls `run <function>`)
-fn build_incompatible_wrappers(
-    incompatible: &[String],
-    target_interpreter: &TranspilerInterpreter,
-) -> String {
-    if incompatible.is_empty() {
-        return String::new();
+/// Where a part of a generated script came from
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum ScriptPart {
+    /// Code `run` generates itself (variables, argument parsing, the `run_fn` helper)
+    Generated(&'static str),
+    /// The inlined body of a sibling function
+    Sibling(String),
+    /// A wrapper that calls an incompatible sibling through `run`
+    Wrapper(String),
+    /// The body of the function being run
+    Body(String),
+}
+
+impl ScriptPart {
+    /// Runfile function the part was generated from, if any
+    pub(super) fn function(&self) -> Option<&str> {
+        match self {
+            Self::Generated(_) => None,
+            Self::Sibling(name) | Self::Wrapper(name) | Self::Body(name) => Some(name),
+        }
     }
+}
 
-    let mut wrappers = String::new();
+/// One function definition within a generated preamble
+pub(super) struct PreambleSection {
+    /// What the section was generated from
+    pub(super) source: ScriptPart,
+    /// Definition text, without the trailing blank line
+    pub(super) text: String,
+}
 
-    for name in incompatible {
-        let sanitised = transpiler::sanitise_name(name);
-        // Convert colon notation to space notation for run command
-        // e.g., "node:hello" -> "node hello"
-        let run_args = name.replace(':', " ");
+/// Join preamble sections into the text placed before the function body
+pub(super) fn join_sections(sections: &[PreambleSection]) -> String {
+    let mut preamble = String::new();
+    for section in sections {
+        preamble.push_str(&section.text);
+        preamble.push_str("\n\n");
+    }
+    preamble
+}
 
-        let wrapper = match target_interpreter {
-            TranspilerInterpreter::Pwsh => {
-                format!("function {sanitised} {{\n    run {run_args} @args\n}}")
-            }
-            _ => {
-                format!("{sanitised}() {{\n    run {run_args} \"$@\"\n}}")
-            }
-        };
+/// Build wrapper functions for incompatible siblings (calls `run <function>`).
+///
+/// The wrappers start the `run` binary and Runfile of the current call, which every
+/// function gets through its environment (see [`crate::config::sibling_call_env`]).
+fn build_incompatible_wrappers(
+    incompatible: &[String],
+    target_interpreter: &TranspilerInterpreter,
+) -> Vec<PreambleSection> {
+    incompatible
+        .iter()
+        .map(|name| {
+            let sanitised = transpiler::sanitise_name(name);
+            // Convert colon notation to space notation for run command
+            // e.g., "node:hello" -> "node hello"
+            let run_args = name.replace(':', " ");
+
+            let text = if target_interpreter.spec().dialect == Dialect::Pwsh {
+                format!(
+                    "function {sanitised} {{\n    \
+                     & $(if ($env:RUN_EXE) {{ $env:RUN_EXE }} else {{ 'run' }}) {run_args} @args\n}}"
+                )
+            } else {
+                format!("{sanitised}() {{\n    {} {run_args} \"$@\"\n}}", transpiler::RUN_COMMAND)
+            };
 
-        wrappers.push_str(&wrapper);
-        wrappers.push_str("\n\n");
-    }
+            PreambleSection {
+                source: ScriptPart::Wrapper(name.clone()),
+                text,
+            }
+        })
+        .collect()
+}
 
-    wrappers
+/// Build the `run_fn` helper that lets a Python, Node.js or Ruby function call any
+/// sibling through `run`, returning the exit code and captured stdout. Like the shell
+/// wrappers, it starts the `run` binary and Runfile of the current call.
+///
+/// Returns `None` for other dialects, or when `body` never mentions `run_fn`, so
+/// scripts that don't call siblings are left untouched.
+pub(super) fn build_run_fn_helper(dialect: Dialect, body: &str) -> Option<String> {
+    if !body.contains("run_fn") {
+        return None;
+    }
+    let helper = match dialect {
+        Dialect::Python => concat!(
+            "def run_fn(name, *args):\n",
+            "    import os, subprocess\n",
+            "    run = os.environ.get(\"RUN_EXE\", \"run\")\n",
+            "    result = subprocess.run([run, *name.split(\":\"), *map(str, args)], stdout=subprocess.PIPE, text=True)\n",
+            "    return result.returncode, result.stdout",
+        ),
+        Dialect::Node => concat!(
+            "function run_fn(name, ...args) {\n",
+            "    const result = require(\"child_process\").spawnSync(process.env.RUN_EXE || \"run\", [...name.split(\":\"), ...args.map(String)], { encoding: \"utf8\", stdio: [\"inherit\", \"pipe\", \"inherit\"] });\n",
+            "    return { code: result.status ?? 127, output: result.stdout ?? \"\" };\n",
+            "}",
+        ),
+        Dialect::Ruby => concat!(
+            "def run_fn(name, *args)\n",
+            "  require \"open3\"\n",
+            "  out, status = Open3.capture2(ENV.fetch(\"RUN_EXE\", \"run\"), *name.split(\":\"), *args.map(&:to_s))\n",
+            "  [status.exitstatus, out]\n",
+            "end",
+        ),
+        _ => return None,
+    };
+    Some(helper.to_string())
 }
 
 /// Build a preamble of all compatible sibling functions
+#[cfg(test)]
 pub(super) fn build_function_preamble(
     target_name: &str,
     target_interpreter: &TranspilerInterpreter,
@@ -145,7 +224,38 @@ pub(super) fn build_function_preamble(
     function_metadata: &HashMap<String, super::FunctionMetadata>,
     resolve_interpreter: &InterpreterResolver<'_>,
 ) -> String {
-    let mut preamble = String::new();
+    join_sections(&build_function_preamble_sections(
+        target_name,
+        None,
+        target_interpreter,
+        simple_functions,
+        block_functions,
+        function_metadata,
+        resolve_interpreter,
+    ))
+}
+
+/// Build the preamble as separate sections, one per injected function, so callers
+/// can tell which Runfile function each generated line belongs to.
+///
+/// With a `target_body`, only siblings reachable from it through calls are included
+/// (see [`reachable_siblings`]); `None` includes every sibling.
+pub(super) fn build_function_preamble_sections(
+    target_name: &str,
+    target_body: Option<&str>,
+    target_interpreter: &TranspilerInterpreter,
+    simple_functions: &HashMap<String, String>,
+    block_functions: &HashMap<String, Vec<String>>,
+    function_metadata: &HashMap<String, super::FunctionMetadata>,
+    resolve_interpreter: &InterpreterResolver<'_>,
+) -> Vec<PreambleSection> {
+    let mut sections = Vec::new();
+
+    // Siblings are inlined as shell functions, which only a shell can define;
+    // other languages call siblings through `run_fn` instead
+    if !target_interpreter.spec().dialect.is_shell() {
+        return sections;
+    }
 
     // Collect compatible sibling function names
     let compatible_siblings = collect_compatible_siblings(
@@ -157,9 +267,9 @@ pub(super) fn build_function_preamble(
         resolve_interpreter,
     );
 
-    // Also collect incompatible colon siblings so their call sites within
+    // Also collect incompatible siblings so their call sites within
     // compatible preamble functions get rewritten to match the wrapper names
-    let incompatible_colon_siblings = collect_incompatible_colon_siblings(
+    let incompatible_siblings = collect_incompatible_siblings(
         target_name,
         target_interpreter,
         simple_functions,
@@ -171,82 +281,138 @@ pub(super) fn build_function_preamble(
     // Combine both lists for call site rewriting
     let all_rewritable: Vec<&str> = compatible_siblings
         .iter()
-        .chain(incompatible_colon_siblings.iter())
+        .chain(incompatible_siblings.iter())
         .map(String::as_str)
         .collect();
 
-    // Transpile simple functions
-    for (name, command_template) in simple_functions {
-        if name == target_name {
+    // Drop siblings the target never reaches
+    let reachable = target_body.and_then(|body| {
+        reachable_siblings(
+            target_name,
+            body,
+            &compatible_siblings,
+            &all_rewritable,
+            simple_functions,
+            block_functions,
+            function_metadata,
+        )
+    });
+    let included = |name: &str| reachable.as_ref().is_none_or(|r| r.contains(name));
+
+    // Transpile compatible siblings, simple and block functions alike
+    let siblings = simple_functions
+        .iter()
+        .map(|(name, command_template)| (name, command_template.clone(), false))
+        .chain(
+            block_functions
+                .iter()
+                .map(|(name, commands)| (name, commands.join("\n"), true)),
+        );
+    for (name, body, is_block) in siblings {
+        if name == target_name || !included(name) {
             continue;
         }
 
         let metadata = function_metadata.get(name);
         let attributes: &[Attribute] =
             metadata.map_or(&[] as &[Attribute], |m| m.attributes.as_slice());
-        let func_interpreter = resolve_interpreter(name, attributes, None);
+        // Only block functions can have a shebang
+        let shebang = metadata
+            .and_then(|m| m.shebang.as_deref())
+            .filter(|_| is_block);
+        let func_interpreter = resolve_interpreter(name, attributes, shebang);
 
         if !target_interpreter.is_compatible_with(&func_interpreter) {
             continue;
         }
 
-        // Rewrite call sites in the command template
-        let rewritten_body = transpiler::rewrite_call_sites(command_template, &all_rewritable);
+        // Rewrite call sites in the body
+        let rewritten_body = transpiler::rewrite_call_sites(&body, &all_rewritable);
 
-        let transpiled = match target_interpreter {
-            TranspilerInterpreter::Pwsh => {
-                transpiler::transpile_to_pwsh(name, &rewritten_body, false)
-            }
-            _ => transpiler::transpile_to_shell(name, &rewritten_body, false),
+        let transpiled = if target_interpreter.spec().dialect == Dialect::Pwsh {
+            transpiler::transpile_to_pwsh(name, &rewritten_body, is_block)
+        } else {
+            transpiler::transpile_to_shell(name, &rewritten_body, is_block)
         };
 
-        preamble.push_str(&transpiled);
-        preamble.push_str("\n\n");
+        sections.push(PreambleSection {
+            source: ScriptPart::Sibling(name.clone()),
+            text: transpiled,
+        });
     }
 
-    // Transpile block functions
-    for (name, commands) in block_functions {
-        if name == target_name {
-            continue;
-        }
+    // Add wrapper functions for incompatible siblings
+    let incompatible_siblings: Vec<String> = incompatible_siblings
+        .into_iter()
+        .filter(|name| included(name))
+        .collect();
+    sections.extend(build_incompatible_wrappers(
+        &incompatible_siblings,
+        target_interpreter,
+    ));
+
+    // Source order, so the same Runfile always produces the same script
+    sections.sort_by_cached_key(|section| {
+        let name = section.source.function().unwrap_or_default().to_string();
+        let line = function_metadata
+            .get(&name)
+            .map_or(usize::MAX, |m| m.location.line);
+        (line, name)
+    });
+    sections
+}
 
-        let metadata = function_metadata.get(name);
-        let (attributes, shebang) = metadata.map_or_else(
-            || (Vec::new(), None),
-            |m| (m.attributes.clone(), m.shebang.as_deref()),
-        );
-        let func_interpreter = resolve_interpreter(name, &attributes, shebang);
+/// Siblings a function's generated script needs: those its body calls, and,
+/// transitively, those called by the siblings inlined alongside it. Wrapped
+/// (incompatible) siblings run in their own process, so their calls aren't followed.
+///
+/// Returns `None`, meaning every sibling is needed, when the target or a function it
+/// reaches is marked `@siblings all` because it calls functions by computed name.
+fn reachable_siblings(
+    target_name: &str,
+    target_body: &str,
+    inlined: &[String],
+    rewritable: &[&str],
+    simple_functions: &HashMap<String, String>,
+    block_functions: &HashMap<String, Vec<String>>,
+    function_metadata: &HashMap<String, super::FunctionMetadata>,
+) -> Option<HashSet<String>> {
+    let wants_all = |name: &str| {
+        function_metadata.get(name).is_some_and(|m| {
+            m.attributes
+                .contains(&Attribute::Siblings(SiblingScope::All))
+        })
+    };
+    if wants_all(target_name) {
+        return None;
+    }
 
-        if !target_interpreter.is_compatible_with(&func_interpreter) {
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<String> = transpiler::called_siblings(target_body, rewritable)
+        .into_iter()
+        .map(ToString::to_string)
+        .collect();
+    while let Some(name) = pending.pop() {
+        if !reachable.insert(name.clone()) || !inlined.contains(&name) {
             continue;
         }
-
-        // Join commands and rewrite call sites
-        let body = commands
-            .iter()
-            .map(String::as_str)
-            .collect::<Vec<_>>()
-            .join("\n");
-        let rewritten_body = transpiler::rewrite_call_sites(&body, &all_rewritable);
-
-        let transpiled = match target_interpreter {
-            TranspilerInterpreter::Pwsh => {
-                transpiler::transpile_to_pwsh(name, &rewritten_body, true)
-            }
-            _ => transpiler::transpile_to_shell(name, &rewritten_body, true),
-        };
-
-        preamble.push_str(&transpiled);
-        preamble.push_str("\n\n");
-    }
-
-    // Add wrapper functions for incompatible siblings with colons
-    let wrappers = build_incompatible_wrappers(&incompatible_colon_siblings, target_interpreter);
-    if !wrappers.is_empty() {
-        preamble.push_str(&wrappers);
+        if wants_all(&name) {
+            return None;
+        }
+        let bodies = simple_functions.get(&name).cloned().into_iter().chain(
+            block_functions
+                .get(&name)
+                .map(|commands| commands.join("\n")),
+        );
+        for body in bodies {
+            pending.extend(
+                transpiler::called_siblings(&body, rewritable)
+                    .into_iter()
+                    .map(ToString::to_string),
+            );
+        }
     }
-
-    preamble
+    Some(reachable)
 }
 
 /// Build `local` declarations for shell function parameters.
@@ -302,31 +468,30 @@ pub(super) fn build_shell_param_locals(params: &[crate::ast::Parameter]) -> Stri
 /// `sys.argv`, `process.argv`, etc.
 pub(super) fn build_polyglot_arg_preamble(
     params: &[crate::ast::Parameter],
-    interpreter: &TranspilerInterpreter,
+    spec: &InterpreterSpec,
 ) -> String {
     if params.is_empty() {
         return String::new();
     }
 
-    let is_polyglot = matches!(
-        interpreter,
-        TranspilerInterpreter::Python
-            | TranspilerInterpreter::Python3
-            | TranspilerInterpreter::Node
-            | TranspilerInterpreter::Ruby
-    );
-
-    if !is_polyglot {
-        return String::new();
-    }
-
-    match interpreter {
-        TranspilerInterpreter::Python | TranspilerInterpreter::Python3 => {
-            build_python_arg_preamble(params)
+    match spec.dialect {
+        Dialect::Python => build_python_arg_preamble(params),
+        Dialect::Node => {
+            // A script file sits between the runtime and the user args (`bun run <file>`)
+            let offset = if matches!(spec.code, CodeDelivery::TempFile { .. }) {
+                2
+            } else {
+                1
+            };
+            build_node_arg_preamble(params, "process.argv", offset)
         }
-        TranspilerInterpreter::Node => build_node_arg_preamble(params),
-        TranspilerInterpreter::Ruby => build_ruby_arg_preamble(params),
-        _ => String::new(),
+        Dialect::Deno => build_node_arg_preamble(params, "Deno.args", 0),
+        Dialect::Ruby => build_ruby_arg_preamble(params),
+        Dialect::Perl => build_perl_arg_preamble(params),
+        Dialect::Lua => build_lua_arg_preamble(params),
+        Dialect::Php => build_php_arg_preamble(params),
+        Dialect::Fish => build_fish_arg_preamble(params),
+        Dialect::Sh | Dialect::Pwsh | Dialect::None => String::new(),
     }
 }
 
@@ -384,45 +549,46 @@ fn build_python_arg_preamble(params: &[crate::ast::Parameter]) -> String {
     lines.join("\n")
 }
 
-/// Build Node.js variable declarations from parameters.
-/// `process.argv[0]` is the node binary path; user args start at index 1.
-fn build_node_arg_preamble(params: &[crate::ast::Parameter]) -> String {
+/// Build JavaScript variable declarations from parameters.
+/// User args start at `offset` in `argv`: index 1 of `process.argv` for `node -e` (index 0
+/// is the node binary path), index 2 when a script file comes first, index 0 of `Deno.args`.
+fn build_node_arg_preamble(params: &[crate::ast::Parameter], argv: &str, offset: usize) -> String {
     let mut lines = Vec::new();
 
     for (i, param) in params.iter().enumerate() {
-        let idx = i + 1; // process.argv[0] is node path
+        let idx = i + offset;
 
         if param.is_rest {
-            let raw = format!("process.argv.slice({idx})");
+            let raw = format!("{argv}.slice({idx})");
             let converted = convert_node_list(&raw, &param.param_type);
             lines.push(format!("const {} = {};", param.name, converted));
         } else if let Some(ref default) = param.default_value {
             let default_literal = node_literal(default, &param.param_type);
             let line = match param.param_type {
                 crate::ast::ArgType::Integer => format!(
-                    "const {} = process.argv.length > {idx} ? parseInt(process.argv[{idx}], 10) : {default_literal};",
+                    "const {} = {argv}.length > {idx} ? parseInt({argv}[{idx}], 10) : {default_literal};",
                     param.name
                 ),
                 crate::ast::ArgType::Float => format!(
-                    "const {} = process.argv.length > {idx} ? parseFloat(process.argv[{idx}]) : {default_literal};",
+                    "const {} = {argv}.length > {idx} ? parseFloat({argv}[{idx}]) : {default_literal};",
                     param.name
                 ),
                 crate::ast::ArgType::Boolean => format!(
-                    "const {} = process.argv.length > {idx} ? !['false', '0', ''].includes(process.argv[{idx}].toLowerCase()) : {default_literal};",
+                    "const {} = {argv}.length > {idx} ? !['false', '0', ''].includes({argv}[{idx}].toLowerCase()) : {default_literal};",
                     param.name
                 ),
                 crate::ast::ArgType::Object => format!(
-                    "const {} = process.argv.length > {idx} ? JSON.parse(process.argv[{idx}]) : {default_literal};",
+                    "const {} = {argv}.length > {idx} ? JSON.parse({argv}[{idx}]) : {default_literal};",
                     param.name
                 ),
                 crate::ast::ArgType::String => format!(
-                    "const {} = process.argv.length > {idx} ? process.argv[{idx}] : {default_literal};",
+                    "const {} = {argv}.length > {idx} ? {argv}[{idx}] : {default_literal};",
                     param.name
                 ),
             };
             lines.push(line);
         } else {
-            let raw = format!("process.argv[{idx}]");
+            let raw = format!("{argv}[{idx}]");
             let converted = convert_node_value(&raw, &param.param_type);
             lines.push(format!("const {} = {};", param.name, converted));
         }
@@ -482,6 +648,128 @@ fn build_ruby_arg_preamble(params: &[crate::ast::Parameter]) -> String {
     lines.join("\n")
 }
 
+/// Build Perl variable declarations from parameters.
+/// Perl's `@ARGV` holds only the user arguments, so index 0 is the first one.
+fn build_perl_arg_preamble(params: &[crate::ast::Parameter]) -> String {
+    let needs_json = params
+        .iter()
+        .any(|p| matches!(p.param_type, crate::ast::ArgType::Object));
+    let mut lines = Vec::new();
+    if needs_json {
+        lines.push("use JSON::PP;".to_string());
+    }
+
+    for (i, param) in params.iter().enumerate() {
+        if param.is_rest {
+            let raw = format!("@ARGV[{i}..$#ARGV]");
+            let converted = convert_perl_list(&raw, &param.param_type);
+            lines.push(format!("my @{} = {};", param.name, converted));
+        } else {
+            let value = convert_perl_value(&format!("$ARGV[{i}]"), &param.param_type);
+            let value = match param.default_value {
+                Some(ref default) => format!(
+                    "@ARGV > {i} ? {value} : {}",
+                    perl_literal(default, &param.param_type)
+                ),
+                None => value,
+            };
+            lines.push(format!("my ${} = {};", param.name, value));
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Build Lua variable declarations from parameters.
+/// Lua runs a script file, so `arg[0]` is the file and user args start at `arg[1]`.
+fn build_lua_arg_preamble(params: &[crate::ast::Parameter]) -> String {
+    let mut lines = Vec::new();
+
+    for (i, param) in params.iter().enumerate() {
+        let idx = i + 1; // arg[0] is the script file
+
+        if param.is_rest {
+            let converted = convert_lua_value("arg[i]", &param.param_type);
+            lines.push(format!(
+                "local {name} = {{}}; for i = {idx}, #arg do {name}[#{name} + 1] = {converted} end",
+                name = param.name
+            ));
+        } else {
+            let raw = format!("arg[{idx}]");
+            let converted = convert_lua_value(&raw, &param.param_type);
+            // Not `a and b or c`: a converted `false` would pick the default
+            let line = match param.default_value {
+                Some(ref default) => format!(
+                    "local {name} = {}; if {raw} ~= nil then {name} = {converted} end",
+                    lua_literal(default, &param.param_type),
+                    name = param.name
+                ),
+                None => format!("local {} = {};", param.name, converted),
+            };
+            lines.push(line);
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Build PHP variable declarations from parameters.
+/// `$argv[0]` is the script (`Standard input code` for `php -r`); user args start at index 1.
+fn build_php_arg_preamble(params: &[crate::ast::Parameter]) -> String {
+    let mut lines = Vec::new();
+
+    for (i, param) in params.iter().enumerate() {
+        let idx = i + 1; // $argv[0] is the script
+
+        if param.is_rest {
+            let raw = format!("array_slice($argv, {idx})");
+            let converted = match param.param_type {
+                crate::ast::ArgType::String => raw,
+                ref t => format!("array_map(fn($x) => {}, {raw})", convert_php_value("$x", t)),
+            };
+            lines.push(format!("${} = {};", param.name, converted));
+        } else {
+            let value = convert_php_value(&format!("$argv[{idx}]"), &param.param_type);
+            let value = match param.default_value {
+                Some(ref default) => format!(
+                    "isset($argv[{idx}]) ? {value} : {}",
+                    php_literal(default, &param.param_type)
+                ),
+                None => value,
+            };
+            lines.push(format!("${} = {};", param.name, value));
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Build fish variable declarations from parameters.
+/// `fish -c` puts the user args in `$argv`, starting at index 1. Fish has no
+/// number or boolean types, so every value stays a string.
+fn build_fish_arg_preamble(params: &[crate::ast::Parameter]) -> String {
+    let mut lines = Vec::new();
+
+    for (i, param) in params.iter().enumerate() {
+        let idx = i + 1;
+
+        let line = if param.is_rest {
+            format!("set -l {} $argv[{idx}..-1]", param.name)
+        } else if let Some(ref default) = param.default_value {
+            format!(
+                "set -l {name} {}; set -q argv[{idx}]; and set {name} $argv[{idx}]",
+                single_quoted(default),
+                name = param.name
+            )
+        } else {
+            format!("set -l {} $argv[{idx}]", param.name)
+        };
+        lines.push(line);
+    }
+
+    lines.join("\n")
+}
+
 // --- Python helpers ---
 
 fn python_literal(value: &str, arg_type: &crate::ast::ArgType) -> String {
@@ -628,6 +916,102 @@ fn convert_ruby_list(expr: &str, arg_type: &crate::ast::ArgType) -> String {
     }
 }
 
+// --- Perl helpers ---
+
+fn perl_literal(value: &str, arg_type: &crate::ast::ArgType) -> String {
+    match arg_type {
+        crate::ast::ArgType::Integer | crate::ast::ArgType::Float => value.to_string(),
+        crate::ast::ArgType::Boolean => {
+            if ["true", "1", "yes"].contains(&value.to_lowercase().as_str()) {
+                "1".to_string()
+            } else {
+                "0".to_string()
+            }
+        }
+        crate::ast::ArgType::Object => format!("decode_json({})", single_quoted(value)),
+        crate::ast::ArgType::String => single_quoted(value),
+    }
+}
+
+fn convert_perl_value(expr: &str, arg_type: &crate::ast::ArgType) -> String {
+    match arg_type {
+        crate::ast::ArgType::Integer => format!("int({expr})"),
+        crate::ast::ArgType::Float => format!("0 + {expr}"),
+        crate::ast::ArgType::Boolean => format!("(lc({expr}) =~ /^(?:false|0|)$/ ? 0 : 1)"),
+        crate::ast::ArgType::Object => format!("decode_json({expr})"),
+        crate::ast::ArgType::String => expr.to_string(),
+    }
+}
+
+fn convert_perl_list(expr: &str, arg_type: &crate::ast::ArgType) -> String {
+    match arg_type {
+        crate::ast::ArgType::String => expr.to_string(),
+        _ => format!("map {{ {} }} {expr}", convert_perl_value("$_", arg_type)),
+    }
+}
+
+// --- Lua helpers ---
+
+fn lua_literal(value: &str, arg_type: &crate::ast::ArgType) -> String {
+    match arg_type {
+        crate::ast::ArgType::Integer | crate::ast::ArgType::Float => value.to_string(),
+        crate::ast::ArgType::Boolean => {
+            if ["true", "1", "yes"].contains(&value.to_lowercase().as_str()) {
+                "true".to_string()
+            } else {
+                "false".to_string()
+            }
+        }
+        // No JSON in the Lua standard library: objects stay strings
+        crate::ast::ArgType::Object | crate::ast::ArgType::String => single_quoted(value),
+    }
+}
+
+fn convert_lua_value(expr: &str, arg_type: &crate::ast::ArgType) -> String {
+    match arg_type {
+        crate::ast::ArgType::Integer | crate::ast::ArgType::Float => format!("tonumber({expr})"),
+        crate::ast::ArgType::Boolean => {
+            format!("not ({{['false'] = true, ['0'] = true, [''] = true}})[{expr}:lower()]")
+        }
+        crate::ast::ArgType::Object | crate::ast::ArgType::String => expr.to_string(),
+    }
+}
+
+// --- PHP helpers ---
+
+fn php_literal(value: &str, arg_type: &crate::ast::ArgType) -> String {
+    match arg_type {
+        crate::ast::ArgType::Integer | crate::ast::ArgType::Float => value.to_string(),
+        crate::ast::ArgType::Boolean => {
+            if ["true", "1", "yes"].contains(&value.to_lowercase().as_str()) {
+                "true".to_string()
+            } else {
+                "false".to_string()
+            }
+        }
+        crate::ast::ArgType::Object => format!("json_decode({}, true)", single_quoted(value)),
+        crate::ast::ArgType::String => single_quoted(value),
+    }
+}
+
+fn convert_php_value(expr: &str, arg_type: &crate::ast::ArgType) -> String {
+    match arg_type {
+        crate::ast::ArgType::Integer => format!("(int) {expr}"),
+        crate::ast::ArgType::Float => format!("(float) {expr}"),
+        crate::ast::ArgType::Boolean => {
+            format!("!in_array(strtolower({expr}), ['false', '0', ''], true)")
+        }
+        crate::ast::ArgType::Object => format!("json_decode({expr}, true)"),
+        crate::ast::ArgType::String => expr.to_string(),
+    }
+}
+
+/// Single-quoted string literal for Perl, Lua, PHP and fish, which all treat `\\` and
+/// `\'` as the only escapes inside single quotes
+fn single_quoted(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
 /// Strip surrounding shell quotes from a variable value captured by the grammar.
 ///
 /// The Runfile grammar captures the entire right-hand side of an assignment
@@ -648,45 +1032,87 @@ fn strip_surrounding_shell_quotes(value: &str) -> &str {
     value
 }
 
-/// Build a preamble of variable assignments
+/// The string a Runfile variable's value stands for, as the shell would read it:
+/// outer quotes removed and, inside double quotes, backslash escapes resolved.
+/// Polyglot scripts get this plain value rather than shell syntax.
+fn shell_unquote(value: &str) -> String {
+    let inner = strip_surrounding_shell_quotes(value);
+    if inner.len() == value.len() || value.starts_with('\'') {
+        return inner.to_string();
+    }
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\'
+            && let Some(&next) = chars.peek()
+            && matches!(next, '"' | '\\' | '$' | '`')
+        {
+            result.push(next);
+            chars.next();
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Build a preamble assigning the Runfile's top-level variables in the target
+/// language: shell assignments for shells, native globals for polyglot scripts
 pub(super) fn build_variable_preamble(
-    variables: &HashMap<String, String>,
+    variables: &[(&str, &str)],
     target_interpreter: &TranspilerInterpreter,
 ) -> String {
     if variables.is_empty() {
         return String::new();
     }
 
-    match target_interpreter {
-        TranspilerInterpreter::Pwsh => {
-            // PowerShell variable syntax: $VAR = "value"
-            variables
-                .iter()
-                .map(|(k, v)| {
-                    format!(
-                        "${} = \"{}\"",
-                        k,
-                        escape_pwsh_value(strip_surrounding_shell_quotes(v))
-                    )
-                })
-                .collect::<Vec<_>>()
-                .join("\n")
-        }
-        _ => {
-            // Shell variable syntax: VAR="value"
-            variables
-                .iter()
-                .map(|(k, v)| {
-                    format!(
-                        "{}=\"{}\"",
-                        k,
-                        escape_shell_value(strip_surrounding_shell_quotes(v))
-                    )
-                })
-                .collect::<Vec<_>>()
-                .join("\n")
+    let dialect = target_interpreter.spec().dialect;
+    variables
+        .iter()
+        .filter_map(|(k, v)| {
+            let line = match dialect {
+                // PowerShell variable syntax: $VAR = "value"
+                Dialect::Pwsh => format!(
+                    "${k} = \"{}\"",
+                    escape_pwsh_value(strip_surrounding_shell_quotes(v))
+                ),
+                // Shell variable syntax: VAR="value"
+                Dialect::Sh => format!(
+                    "{k}=\"{}\"",
+                    escape_shell_value(strip_surrounding_shell_quotes(v))
+                ),
+                _ => polyglot_assignment(dialect, k, &shell_unquote(v))?,
+            };
+            Some(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Assign `value` to the global `name` in a polyglot language (`None` for languages
+/// without a known syntax)
+fn polyglot_assignment(dialect: Dialect, name: &str, value: &str) -> Option<String> {
+    let string = crate::ast::ArgType::String;
+    let line = match dialect {
+        Dialect::Python => format!("{name} = {}", python_literal(value, &string)),
+        // A global property rather than a declaration, so the body may still
+        // declare its own `const VERSION`
+        Dialect::Node | Dialect::Deno => {
+            format!("globalThis.{name} = {};", node_literal(value, &string))
         }
-    }
+        // `def` bodies can't see top-level locals, so the value is a `$global`, and the
+        // bare name a constant (or, for a lowercase name, a local for top-level code)
+        Dialect::Ruby => format!(
+            "${name} = {}\n{name} = ${name}",
+            ruby_literal(value, &string)
+        ),
+        Dialect::Perl => format!("our ${name} = {};", perl_literal(value, &string)),
+        Dialect::Lua => format!("{name} = {}", lua_literal(value, &string)),
+        Dialect::Php => format!("${name} = {};", php_literal(value, &string)),
+        Dialect::Fish => format!("set -g {name} {}", single_quoted(value)),
+        Dialect::Sh | Dialect::Pwsh | Dialect::None => return None,
+    };
+    Some(line)
 }
 
 /// Escape a path string for safe embedding in polyglot (Python/Node/Ruby) string literals.
@@ -696,30 +1122,36 @@ fn escape_polyglot_path(path: &str) -> String {
 
 /// Build a single-line preamble that sets the `__RUNFILE_DIR__` built-in variable.
 ///
-/// The assignment syntax is adapted to the target interpreter so that the
+/// The assignment syntax is adapted to the target interpreter's dialect so that the
 /// variable is immediately accessible inside every function body:
 /// - Shell (sh/bash): `__RUNFILE_DIR__="<escaped-path>"`
 /// - `PowerShell`: `$__RUNFILE_DIR__ = "<escaped-path>"`
 /// - Python/Ruby: `__RUNFILE_DIR__ = "<escaped-path>"`
-/// - Node: `const __RUNFILE_DIR__ = "<escaped-path>";`
-pub(super) fn build_runfile_dir_preamble(dir: &str, interpreter: &TranspilerInterpreter) -> String {
-    match interpreter {
-        TranspilerInterpreter::Pwsh => {
-            format!("$__RUNFILE_DIR__ = \"{}\"", escape_pwsh_value(dir))
-        }
-        TranspilerInterpreter::Python
-        | TranspilerInterpreter::Python3
-        | TranspilerInterpreter::Ruby => {
+/// - Node/Deno: `const __RUNFILE_DIR__ = "<escaped-path>";`
+/// - Perl/PHP: `my $__RUNFILE_DIR__ = '<path>';` / `$__RUNFILE_DIR__ = '<path>';`
+/// - Lua/fish: `local __RUNFILE_DIR__ = '<path>'` / `set -l __RUNFILE_DIR__ '<path>'`
+///
+/// Returns `None` for interpreters without a known dialect.
+pub(super) fn build_runfile_dir_preamble(
+    dir: &str,
+    interpreter: &TranspilerInterpreter,
+) -> Option<String> {
+    Some(match interpreter.spec().dialect {
+        Dialect::Pwsh => format!("$__RUNFILE_DIR__ = \"{}\"", escape_pwsh_value(dir)),
+        Dialect::Python | Dialect::Ruby => {
             format!("__RUNFILE_DIR__ = \"{}\"", escape_polyglot_path(dir))
         }
-        TranspilerInterpreter::Node => {
+        Dialect::Node | Dialect::Deno => {
             format!("const __RUNFILE_DIR__ = \"{}\";", escape_polyglot_path(dir))
         }
-        _ => {
-            // sh / bash: standard shell assignment
-            format!("__RUNFILE_DIR__=\"{}\"", escape_shell_value(dir))
-        }
-    }
+        Dialect::Perl => format!("my $__RUNFILE_DIR__ = {};", single_quoted(dir)),
+        Dialect::Php => format!("$__RUNFILE_DIR__ = {};", single_quoted(dir)),
+        Dialect::Lua => format!("local __RUNFILE_DIR__ = {}", single_quoted(dir)),
+        Dialect::Fish => format!("set -l __RUNFILE_DIR__ {}", single_quoted(dir)),
+        // sh / bash: standard shell assignment
+        Dialect::Sh => format!("__RUNFILE_DIR__=\"{}\"", escape_shell_value(dir)),
+        Dialect::None => return None,
+    })
 }
 
 /// Combine an optional builtin preamble line with user-defined variable preamble lines.
@@ -735,93 +1167,150 @@ pub(super) fn combine_with_builtin(builtin: Option<String>, user: String) -> Str
 }
 
 #[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
 mod tests {
     use super::*;
     use std::collections::HashMap;
 
     #[test]
     fn test_build_variable_preamble_empty() {
-        let vars = HashMap::new();
         assert_eq!(
-            build_variable_preamble(&vars, &TranspilerInterpreter::Sh),
+            build_variable_preamble(&[], &TranspilerInterpreter::named("sh")),
             ""
         );
     }
 
     #[test]
     fn test_build_variable_preamble_shell() {
-        let mut vars = HashMap::new();
-        vars.insert("MY_VAR".to_string(), "hello".to_string());
-        let result = build_variable_preamble(&vars, &TranspilerInterpreter::Sh);
+        let vars = [("MY_VAR", "hello")];
+        let result = build_variable_preamble(&vars, &TranspilerInterpreter::named("sh"));
         assert_eq!(result, "MY_VAR=\"hello\"");
     }
 
     #[test]
     fn test_build_variable_preamble_shell_with_special_chars() {
-        let mut vars = HashMap::new();
-        vars.insert("VAR".to_string(), "say \"hi\"".to_string());
-        let result = build_variable_preamble(&vars, &TranspilerInterpreter::Bash);
+        let vars = [("VAR", "say \"hi\"")];
+        let result = build_variable_preamble(&vars, &TranspilerInterpreter::named("bash"));
         assert_eq!(result, "VAR=\"say \\\"hi\\\"\"");
     }
 
     #[test]
     fn test_build_variable_preamble_pwsh() {
-        let mut vars = HashMap::new();
-        vars.insert("MY_VAR".to_string(), "hello".to_string());
-        let result = build_variable_preamble(&vars, &TranspilerInterpreter::Pwsh);
+        let vars = [("MY_VAR", "hello")];
+        let result = build_variable_preamble(&vars, &TranspilerInterpreter::named("pwsh"));
         assert_eq!(result, "$MY_VAR = \"hello\"");
     }
 
     #[test]
     fn test_build_variable_preamble_pwsh_with_special_chars() {
-        let mut vars = HashMap::new();
-        vars.insert("VAR".to_string(), "$env:PATH".to_string());
-        let result = build_variable_preamble(&vars, &TranspilerInterpreter::Pwsh);
+        let vars = [("VAR", "$env:PATH")];
+        let result = build_variable_preamble(&vars, &TranspilerInterpreter::named("pwsh"));
         assert_eq!(result, "$VAR = \"`$env:PATH\"");
     }
 
+    #[test]
+    fn test_build_variable_preamble_polyglot() {
+        let vars = [("VERSION", "\"it's \\\"1.0\\\"\"")];
+        let cases = [
+            (
+                TranspilerInterpreter::named("python"),
+                "VERSION = \"it's \\\"1.0\\\"\"",
+            ),
+            (
+                TranspilerInterpreter::named("node"),
+                "globalThis.VERSION = \"it's \\\"1.0\\\"\";",
+            ),
+            (
+                TranspilerInterpreter::named("ruby"),
+                "$VERSION = \"it's \\\"1.0\\\"\"\nVERSION = $VERSION",
+            ),
+            (
+                TranspilerInterpreter::named("perl"),
+                "our $VERSION = 'it\\'s \"1.0\"';",
+            ),
+            (
+                TranspilerInterpreter::named("lua"),
+                "VERSION = 'it\\'s \"1.0\"'",
+            ),
+            (
+                TranspilerInterpreter::named("php"),
+                "$VERSION = 'it\\'s \"1.0\"';",
+            ),
+        ];
+        for (interpreter, expected) in cases {
+            assert_eq!(build_variable_preamble(&vars, &interpreter), expected);
+        }
+
+        // Interpreters of unknown languages get no assignments
+        let unknown = TranspilerInterpreter::named("janet");
+        assert_eq!(build_variable_preamble(&vars, &unknown), "");
+    }
+
+    #[test]
+    fn test_shell_unquote() {
+        assert_eq!(shell_unquote("plain"), "plain");
+        assert_eq!(shell_unquote("'a \\\" b'"), "a \\\" b");
+        assert_eq!(shell_unquote("\"a \\\" \\$x \\n\""), "a \" $x \\n");
+    }
+
     #[test]
     fn test_build_runfile_dir_preamble_sh() {
-        let result = build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::Sh);
+        let result =
+            build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::named("sh"))
+                .unwrap();
         assert_eq!(result, "__RUNFILE_DIR__=\"/home/user/project\"");
     }
 
     #[test]
     fn test_build_runfile_dir_preamble_bash() {
-        let result = build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::Bash);
+        let result =
+            build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::named("bash"))
+                .unwrap();
         assert_eq!(result, "__RUNFILE_DIR__=\"/home/user/project\"");
     }
 
     #[test]
     fn test_build_runfile_dir_preamble_pwsh() {
-        let result = build_runfile_dir_preamble("C:\\Users\\user", &TranspilerInterpreter::Pwsh);
+        let result =
+            build_runfile_dir_preamble("C:\\Users\\user", &TranspilerInterpreter::named("pwsh"))
+                .unwrap();
         assert_eq!(result, "$__RUNFILE_DIR__ = \"C:\\Users\\user\"");
     }
 
     #[test]
     fn test_build_runfile_dir_preamble_python() {
-        let result =
-            build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::Python);
+        let result = build_runfile_dir_preamble(
+            "/home/user/project",
+            &TranspilerInterpreter::named("python"),
+        )
+        .unwrap();
         assert_eq!(result, "__RUNFILE_DIR__ = \"/home/user/project\"");
     }
 
     #[test]
     fn test_build_runfile_dir_preamble_node() {
-        let result = build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::Node);
+        let result =
+            build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::named("node"))
+                .unwrap();
         assert_eq!(result, "const __RUNFILE_DIR__ = \"/home/user/project\";");
     }
 
     #[test]
     fn test_build_runfile_dir_preamble_ruby() {
-        let result = build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::Ruby);
+        let result =
+            build_runfile_dir_preamble("/home/user/project", &TranspilerInterpreter::named("ruby"))
+                .unwrap();
         assert_eq!(result, "__RUNFILE_DIR__ = \"/home/user/project\"");
     }
 
     #[test]
     fn test_build_runfile_dir_preamble_path_with_special_chars() {
         // Paths with double quotes should be escaped
-        let result =
-            build_runfile_dir_preamble("/home/user/my\"project", &TranspilerInterpreter::Sh);
+        let result = build_runfile_dir_preamble(
+            "/home/user/my\"project",
+            &TranspilerInterpreter::named("sh"),
+        )
+        .unwrap();
         assert_eq!(result, "__RUNFILE_DIR__=\"/home/user/my\\\"project\"");
     }
 
@@ -858,11 +1347,12 @@ mod tests {
         let simple = HashMap::new();
         let block = HashMap::new();
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = collect_compatible_siblings(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -878,11 +1368,12 @@ mod tests {
         simple.insert("target".to_string(), "echo target".to_string());
         let block = HashMap::new();
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = collect_compatible_siblings(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -901,11 +1392,12 @@ mod tests {
             vec!["echo step1".to_string(), "echo step2".to_string()],
         );
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = collect_compatible_siblings(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -915,15 +1407,16 @@ mod tests {
     }
 
     #[test]
-    fn test_collect_incompatible_colon_siblings_empty() {
+    fn test_collect_incompatible_siblings_empty() {
         let simple = HashMap::new();
         let block = HashMap::new();
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
-        let result = collect_incompatible_colon_siblings(
+        let result = collect_incompatible_siblings(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -933,7 +1426,7 @@ mod tests {
     }
 
     #[test]
-    fn test_collect_incompatible_colon_siblings_finds_colon_functions() {
+    fn test_collect_incompatible_siblings_finds_colon_functions() {
         let mut simple = HashMap::new();
         simple.insert("node:hello".to_string(), "console.log('hi')".to_string());
         simple.insert("no_colon".to_string(), "echo hi".to_string());
@@ -942,9 +1435,10 @@ mod tests {
         metadata.insert(
             "node:hello".to_string(),
             super::super::FunctionMetadata {
-                attributes: vec![Attribute::Shell(crate::ast::ShellType::Node)],
+                attributes: vec![Attribute::Shell(crate::ast::ShellType::new("node"))],
                 shebang: None,
                 params: vec![],
+                location: crate::ast::SourceLocation::default(),
             },
         );
         let resolve = |_name: &str, attrs: &[Attribute], _: Option<&str>| {
@@ -953,12 +1447,12 @@ mod tests {
                     return TranspilerInterpreter::from_shell_type(st);
                 }
             }
-            TranspilerInterpreter::Sh
+            TranspilerInterpreter::named("sh")
         };
 
-        let result = collect_incompatible_colon_siblings(
+        let result = collect_incompatible_siblings(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -968,16 +1462,82 @@ mod tests {
         assert!(!result.contains(&"no_colon".to_string()));
     }
 
+    #[test]
+    fn test_collect_incompatible_siblings_includes_plain_names() {
+        let mut simple = HashMap::new();
+        simple.insert("report".to_string(), "print('hi')".to_string());
+        let block = HashMap::new();
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "report".to_string(),
+            super::super::FunctionMetadata {
+                attributes: vec![Attribute::Shell(crate::ast::ShellType::new("python"))],
+                shebang: None,
+                params: vec![],
+                location: crate::ast::SourceLocation::default(),
+            },
+        );
+        let resolve = |_name: &str, attrs: &[Attribute], _: Option<&str>| {
+            for attr in attrs {
+                if let Attribute::Shell(st) = attr {
+                    return TranspilerInterpreter::from_shell_type(st);
+                }
+            }
+            TranspilerInterpreter::named("sh")
+        };
+
+        let result = collect_incompatible_siblings(
+            "target",
+            &TranspilerInterpreter::named("sh"),
+            &simple,
+            &block,
+            &metadata,
+            &resolve,
+        );
+        assert_eq!(result, vec!["report".to_string()]);
+
+        // A polyglot target never inlines siblings
+        let sections = build_function_preamble_sections(
+            "target",
+            None,
+            &TranspilerInterpreter::named("python"),
+            &simple,
+            &block,
+            &metadata,
+            &resolve,
+        );
+        assert!(sections.is_empty());
+    }
+
+    #[test]
+    fn test_build_run_fn_helper() {
+        let python = build_run_fn_helper(Dialect::Python, "run_fn('build')").unwrap();
+        assert!(python.starts_with("def run_fn(name, *args):"));
+        assert!(python.contains("os.environ.get(\"RUN_EXE\", \"run\")"));
+        assert!(python.contains("subprocess.run([run, *name.split(\":\")"));
+
+        let node = build_run_fn_helper(Dialect::Node, "run_fn('build')").unwrap();
+        assert!(node.starts_with("function run_fn(name, ...args) {"));
+
+        let ruby = build_run_fn_helper(Dialect::Ruby, "run_fn('build')").unwrap();
+        assert!(ruby.contains("Open3.capture2"));
+
+        // Only injected when the body uses it, and only for supported languages
+        assert!(build_run_fn_helper(Dialect::Python, "print('hi')").is_none());
+        assert!(build_run_fn_helper(Dialect::Perl, "run_fn('build')").is_none());
+    }
+
     #[test]
     fn test_build_function_preamble_empty() {
         let simple = HashMap::new();
         let block = HashMap::new();
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = build_function_preamble(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -993,11 +1553,12 @@ mod tests {
         simple.insert("target".to_string(), "helper".to_string());
         let block = HashMap::new();
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = build_function_preamble(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -1007,6 +1568,97 @@ mod tests {
         assert!(result.contains("echo help"));
     }
 
+    #[test]
+    fn test_build_function_preamble_sections_prunes_unreachable_siblings() {
+        let mut simple = HashMap::new();
+        simple.insert("build".to_string(), "compile && bundle".to_string());
+        simple.insert("compile".to_string(), "echo compile".to_string());
+        simple.insert("bundle".to_string(), "echo bundle".to_string());
+        simple.insert("lint".to_string(), "echo lint".to_string());
+        simple.insert("target".to_string(), "build".to_string());
+        let mut block = HashMap::new();
+        block.insert("docs".to_string(), vec!["echo docs".to_string()]);
+        let mut metadata = HashMap::new();
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
+
+        let names = |metadata: &HashMap<String, super::super::FunctionMetadata>| {
+            let mut names: Vec<String> = build_function_preamble_sections(
+                "target",
+                Some("if build; then echo ok; fi"),
+                &TranspilerInterpreter::named("sh"),
+                &simple,
+                &block,
+                metadata,
+                &resolve,
+            )
+            .into_iter()
+            .filter_map(|section| section.source.function().map(ToString::to_string))
+            .collect();
+            names.sort();
+            names
+        };
+
+        // `build` is called directly, `compile` and `bundle` through it
+        assert_eq!(names(&metadata), vec!["build", "bundle", "compile"]);
+
+        // A reached function that calls siblings dynamically brings in everything
+        metadata.insert(
+            "compile".to_string(),
+            super::super::FunctionMetadata {
+                attributes: vec![Attribute::Siblings(SiblingScope::All)],
+                shebang: None,
+                params: vec![],
+                location: crate::ast::SourceLocation::default(),
+            },
+        );
+        assert_eq!(
+            names(&metadata),
+            vec!["build", "bundle", "compile", "docs", "lint"]
+        );
+    }
+
+    #[test]
+    fn test_build_function_preamble_sections_in_source_order() {
+        let mut simple = HashMap::new();
+        let mut metadata = HashMap::new();
+        for (line, name) in ["zeta", "alpha", "mid", "beta"].iter().enumerate() {
+            simple.insert((*name).to_string(), format!("echo {name}"));
+            metadata.insert(
+                (*name).to_string(),
+                super::super::FunctionMetadata {
+                    attributes: vec![],
+                    shebang: None,
+                    params: vec![],
+                    location: crate::ast::SourceLocation {
+                        line: line + 1,
+                        body_line: line + 1,
+                    },
+                },
+            );
+        }
+        let block = HashMap::new();
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
+
+        let order: Vec<ScriptPart> = build_function_preamble_sections(
+            "target",
+            None,
+            &TranspilerInterpreter::named("sh"),
+            &simple,
+            &block,
+            &metadata,
+            &resolve,
+        )
+        .into_iter()
+        .map(|section| section.source)
+        .collect();
+        assert_eq!(
+            order,
+            ["zeta", "alpha", "mid", "beta"].map(|n| ScriptPart::Sibling(n.to_string()))
+        );
+    }
+
     #[test]
     fn test_build_function_preamble_with_block_sibling() {
         let simple = HashMap::new();
@@ -1016,11 +1668,12 @@ mod tests {
             vec!["echo step1".to_string(), "echo step2".to_string()],
         );
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = build_function_preamble(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -1036,11 +1689,12 @@ mod tests {
         simple.insert("target".to_string(), "echo target".to_string());
         let block = HashMap::new();
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Sh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("sh");
 
         let result = build_function_preamble(
             "target",
-            &TranspilerInterpreter::Sh,
+            &TranspilerInterpreter::named("sh"),
             &simple,
             &block,
             &metadata,
@@ -1056,11 +1710,12 @@ mod tests {
         simple.insert("target".to_string(), "helper".to_string());
         let block = HashMap::new();
         let metadata = HashMap::new();
-        let resolve = |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::Pwsh;
+        let resolve =
+            |_: &str, _: &[Attribute], _: Option<&str>| TranspilerInterpreter::named("pwsh");
 
         let result = build_function_preamble(
             "target",
-            &TranspilerInterpreter::Pwsh,
+            &TranspilerInterpreter::named("pwsh"),
             &simple,
             &block,
             &metadata,
@@ -1148,21 +1803,24 @@ mod tests {
 
     #[test]
     fn test_polyglot_preamble_empty_params() {
-        let result = build_polyglot_arg_preamble(&[], &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&[], &TranspilerInterpreter::named("python").spec());
         assert_eq!(result, "");
     }
 
     #[test]
     fn test_polyglot_preamble_non_polyglot() {
         let params = vec![make_param("name", None, false)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Sh);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("sh").spec());
         assert_eq!(result, "");
     }
 
     #[test]
     fn test_python_preamble_required_param() {
         let params = vec![make_param("name", None, false)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("import sys"));
         assert!(result.contains("name = sys.argv[1]"));
     }
@@ -1173,7 +1831,8 @@ mod tests {
             make_param("name", None, false),
             make_param("greeting", Some("Hello"), false),
         ];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("name = sys.argv[1]"));
         assert!(result.contains("greeting = sys.argv[2] if len(sys.argv) > 2 else \"Hello\""));
     }
@@ -1184,7 +1843,8 @@ mod tests {
             make_param("name", None, false),
             make_param("extra", None, true),
         ];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("name = sys.argv[1]"));
         assert!(result.contains("extra = sys.argv[2:]"));
     }
@@ -1196,7 +1856,8 @@ mod tests {
             crate::ast::ArgType::Integer,
             None,
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("count = int(sys.argv[1])"));
     }
 
@@ -1207,14 +1868,16 @@ mod tests {
             crate::ast::ArgType::Boolean,
             None,
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("verbose = sys.argv[1].lower() in ('true', '1', 'yes')"));
     }
 
     #[test]
     fn test_node_preamble_required_param() {
         let params = vec![make_param("name", None, false)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains("const name = process.argv[1];"));
     }
 
@@ -1224,7 +1887,8 @@ mod tests {
             make_param("name", None, false),
             make_param("greeting", Some("Hello"), false),
         ];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains("const name = process.argv[1];"));
         assert!(
             result.contains(
@@ -1239,7 +1903,8 @@ mod tests {
             make_param("name", None, false),
             make_param("extra", None, true),
         ];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains("const extra = process.argv.slice(2);"));
     }
 
@@ -1250,14 +1915,16 @@ mod tests {
             crate::ast::ArgType::Integer,
             None,
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains("const count = parseInt(process.argv[1], 10);"));
     }
 
     #[test]
     fn test_ruby_preamble_required_param() {
         let params = vec![make_param("name", None, false)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Ruby);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("ruby").spec());
         assert!(result.contains("name = ARGV[0]"));
     }
 
@@ -1267,7 +1934,8 @@ mod tests {
             make_param("name", None, false),
             make_param("greeting", Some("Hello"), false),
         ];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Ruby);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("ruby").spec());
         assert!(result.contains("name = ARGV[0]"));
         assert!(result.contains("greeting = ARGV.length > 1 ? ARGV[1] : \"Hello\""));
     }
@@ -1278,14 +1946,16 @@ mod tests {
             make_param("name", None, false),
             make_param("extra", None, true),
         ];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Ruby);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("ruby").spec());
         assert!(result.contains("extra = ARGV[1..]"));
     }
 
     #[test]
     fn test_python3_preamble_works() {
         let params = vec![make_param("name", None, false)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python3);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python3").spec());
         assert!(result.contains("import sys"));
         assert!(result.contains("name = sys.argv[1]"));
     }
@@ -1297,7 +1967,8 @@ mod tests {
             crate::ast::ArgType::Integer,
             Some("42"),
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("int(sys.argv[1]) if len(sys.argv) > 1 else 42"));
     }
 
@@ -1308,7 +1979,8 @@ mod tests {
             crate::ast::ArgType::Boolean,
             Some("false"),
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains("const verbose = process.argv.length > 1 ? !['false', '0', ''].includes(process.argv[1].toLowerCase()) : false;"));
     }
 
@@ -1317,7 +1989,8 @@ mod tests {
     #[test]
     fn test_python_preamble_float_type() {
         let params = vec![make_typed_param("rate", crate::ast::ArgType::Float, None)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("rate = float(sys.argv[1])"));
     }
 
@@ -1328,14 +2001,16 @@ mod tests {
             crate::ast::ArgType::Float,
             Some("3.14"),
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("float(sys.argv[1]) if len(sys.argv) > 1 else 3.14"));
     }
 
     #[test]
     fn test_node_preamble_float_type() {
         let params = vec![make_typed_param("rate", crate::ast::ArgType::Float, None)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains("const rate = parseFloat(process.argv[1]);"));
     }
 
@@ -1346,7 +2021,8 @@ mod tests {
             crate::ast::ArgType::Float,
             Some("3.14"),
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains(
             "const rate = process.argv.length > 1 ? parseFloat(process.argv[1]) : 3.14;"
         ));
@@ -1355,7 +2031,8 @@ mod tests {
     #[test]
     fn test_ruby_preamble_float_type() {
         let params = vec![make_typed_param("rate", crate::ast::ArgType::Float, None)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Ruby);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("ruby").spec());
         assert!(result.contains("rate = ARGV[0].to_f"));
     }
 
@@ -1366,7 +2043,8 @@ mod tests {
             crate::ast::ArgType::Float,
             Some("3.14"),
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Ruby);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("ruby").spec());
         assert!(result.contains("rate = ARGV.length > 0 ? ARGV[0].to_f : 3.14"));
     }
 
@@ -1379,7 +2057,8 @@ mod tests {
             crate::ast::ArgType::Object,
             None,
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(result.contains("import json"));
         assert!(result.contains("config = json.loads(sys.argv[1])"));
     }
@@ -1387,7 +2066,8 @@ mod tests {
     #[test]
     fn test_python_preamble_object_no_json_import_without_object() {
         let params = vec![make_typed_param("name", crate::ast::ArgType::String, None)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Python);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("python").spec());
         assert!(!result.contains("import json"));
     }
 
@@ -1398,7 +2078,8 @@ mod tests {
             crate::ast::ArgType::Object,
             None,
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Node);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("node").spec());
         assert!(result.contains("const config = JSON.parse(process.argv[1]);"));
     }
 
@@ -1409,7 +2090,8 @@ mod tests {
             crate::ast::ArgType::Object,
             None,
         )];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Ruby);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("ruby").spec());
         assert!(result.contains("require 'json'"));
         assert!(result.contains("config = JSON.parse(ARGV[0])"));
     }
@@ -1417,7 +2099,107 @@ mod tests {
     #[test]
     fn test_ruby_preamble_no_json_require_without_object() {
         let params = vec![make_typed_param("name", crate::ast::ArgType::String, None)];
-        let result = build_polyglot_arg_preamble(&params, &TranspilerInterpreter::Ruby);
+        let result =
+            build_polyglot_arg_preamble(&params, &TranspilerInterpreter::named("ruby").spec());
         assert!(!result.contains("require 'json'"));
     }
+
+    fn registered(name: &str) -> TranspilerInterpreter {
+        TranspilerInterpreter::named(name)
+    }
+
+    #[test]
+    fn test_perl_preamble() {
+        let params = vec![
+            make_param("env", None, false),
+            make_typed_param("count", crate::ast::ArgType::Integer, Some("3")),
+            make_param("rest", None, true),
+        ];
+        let result = build_polyglot_arg_preamble(&params, &registered("perl").spec());
+        assert_eq!(
+            result,
+            "my $env = $ARGV[0];\n\
+             my $count = @ARGV > 1 ? int($ARGV[1]) : 3;\n\
+             my @rest = @ARGV[2..$#ARGV];"
+        );
+    }
+
+    #[test]
+    fn test_lua_preamble() {
+        let params = vec![
+            make_param("env", None, false),
+            make_param("greeting", Some("it's"), false),
+            make_param("rest", None, true),
+        ];
+        let result = build_polyglot_arg_preamble(&params, &registered("lua").spec());
+        assert_eq!(
+            result,
+            "local env = arg[1];\n\
+             local greeting = 'it\\'s'; if arg[2] ~= nil then greeting = arg[2] end\n\
+             local rest = {}; for i = 3, #arg do rest[#rest + 1] = arg[i] end"
+        );
+    }
+
+    #[test]
+    fn test_php_preamble() {
+        let params = vec![
+            make_param("env", None, false),
+            make_typed_param("verbose", crate::ast::ArgType::Boolean, Some("false")),
+        ];
+        let result = build_polyglot_arg_preamble(&params, &registered("php").spec());
+        assert_eq!(
+            result,
+            "$env = $argv[1];\n\
+             $verbose = isset($argv[2]) ? !in_array(strtolower($argv[2]), ['false', '0', ''], true) : false;"
+        );
+    }
+
+    #[test]
+    fn test_fish_preamble() {
+        let params = vec![
+            make_param("env", None, false),
+            make_param("version", Some("latest"), false),
+            make_param("rest", None, true),
+        ];
+        let result = build_polyglot_arg_preamble(&params, &registered("fish").spec());
+        assert_eq!(
+            result,
+            "set -l env $argv[1]\n\
+             set -l version 'latest'; set -q argv[2]; and set version $argv[2]\n\
+             set -l rest $argv[3..-1]"
+        );
+    }
+
+    #[test]
+    fn test_js_runtime_argv_offsets() {
+        let params = vec![make_param("name", None, false)];
+        assert_eq!(
+            build_polyglot_arg_preamble(&params, &registered("bun").spec()),
+            "const name = process.argv[2];"
+        );
+        assert_eq!(
+            build_polyglot_arg_preamble(&params, &registered("deno").spec()),
+            "const name = Deno.args[0];"
+        );
+    }
+
+    #[test]
+    fn test_unknown_interpreter_has_no_preambles() {
+        let params = vec![make_param("name", None, false)];
+        let unknown = registered("no-such-interpreter");
+        assert_eq!(build_polyglot_arg_preamble(&params, &unknown.spec()), "");
+        assert_eq!(build_runfile_dir_preamble("/tmp", &unknown), None);
+    }
+
+    #[test]
+    fn test_build_runfile_dir_preamble_single_quoted_dialects() {
+        assert_eq!(
+            build_runfile_dir_preamble("/it's", &registered("perl")).unwrap(),
+            "my $__RUNFILE_DIR__ = '/it\\'s';"
+        );
+        assert_eq!(
+            build_runfile_dir_preamble("/p", &registered("fish")).unwrap(),
+            "set -l __RUNFILE_DIR__ '/p'"
+        );
+    }
 }
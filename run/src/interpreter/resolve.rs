@@ -0,0 +1,185 @@
+//! Function name resolution for CLI-style calls
+//!
+//! Maps `run <name> [args...]` onto a Runfile function, supporting any depth of
+//! colon-separated namespaces (`run docker compose up` → `docker:compose:up`) and
+//! the underscore spellings used where colons are not allowed.
+
+/// Outcome of resolving a function call
+#[derive(Debug, PartialEq)]
+pub(super) enum Resolution {
+    /// A function matched; `consumed` leading args were part of its name
+    Found {
+        name: String,
+        consumed: usize,
+    },
+    /// Several functions match an underscore spelling equally well
+    Ambiguous(Vec<String>),
+    /// The name is a namespace with these functions under it, but not a function
+    Namespace(Vec<String>),
+    NotFound,
+}
+
+/// Resolve `name args...` against the known function `names`.
+///
+/// Resolution order:
+/// 1. Longest prefix: `name:arg1:…:argN` for the largest N that names a function,
+///    down to `name` itself.
+/// 2. Double underscores as colons (`docker__build` → `docker:build`), the MCP
+///    tool-name convention.
+/// 3. Single underscores as colons (`docker_build` → `docker:build`). Each
+///    underscore may stand for either character, so this fails with
+///    [`Resolution::Ambiguous`] if more than one function fits.
+pub(super) fn resolve(name: &str, args: &[String], names: &[String]) -> Resolution {
+    let exists = |candidate: &str| names.iter().any(|n| n == candidate);
+
+    let mut candidate = name.to_string();
+    let mut prefixes = vec![candidate.clone()];
+    for arg in args {
+        // Arguments that can't be part of a name end the search
+        if arg.is_empty() || arg.starts_with('-') || arg.contains(char::is_whitespace) {
+            break;
+        }
+        candidate = format!("{candidate}:{arg}");
+        prefixes.push(candidate.clone());
+    }
+    for (consumed, prefix) in prefixes.iter().enumerate().rev() {
+        if exists(prefix) {
+            return Resolution::Found {
+                name: prefix.clone(),
+                consumed,
+            };
+        }
+    }
+
+    if name.contains("__") {
+        let with_colons = name.replace("__", ":");
+        if exists(&with_colons) {
+            return Resolution::Found {
+                name: with_colons,
+                consumed: 0,
+            };
+        }
+    }
+
+    if name.contains('_') {
+        let mut matches: Vec<String> = names
+            .iter()
+            .filter(|n| n.contains(':') && n.replace(':', "_") == name)
+            .cloned()
+            .collect();
+        match matches.len() {
+            0 => {}
+            1 => {
+                return Resolution::Found {
+                    name: matches.remove(0),
+                    consumed: 0,
+                };
+            }
+            _ => {
+                matches.sort();
+                return Resolution::Ambiguous(matches);
+            }
+        }
+    }
+
+    // Nothing callable: point at the deepest namespace the words describe
+    for prefix in prefixes.iter().rev() {
+        let namespace = format!("{prefix}:");
+        let mut children: Vec<String> = names
+            .iter()
+            .filter(|n| n.starts_with(&namespace))
+            .cloned()
+            .collect();
+        if !children.is_empty() {
+            children.sort();
+            return Resolution::Namespace(children);
+        }
+    }
+
+    Resolution::NotFound
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        names(list)
+    }
+
+    #[test]
+    fn test_resolve_longest_prefix() {
+        let known = names(&["docker", "docker:compose", "docker:compose:up"]);
+        assert_eq!(
+            resolve("docker", &args(&["compose", "up", "-d"]), &known),
+            Resolution::Found {
+                name: "docker:compose:up".to_string(),
+                consumed: 2
+            }
+        );
+        assert_eq!(
+            resolve("docker", &args(&["compose", "down"]), &known),
+            Resolution::Found {
+                name: "docker:compose".to_string(),
+                consumed: 1
+            }
+        );
+        assert_eq!(
+            resolve("docker", &args(&["ps"]), &known),
+            Resolution::Found {
+                name: "docker".to_string(),
+                consumed: 0
+            }
+        );
+        assert_eq!(
+            resolve("docker:compose", &args(&["up"]), &known),
+            Resolution::Found {
+                name: "docker:compose:up".to_string(),
+                consumed: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_underscore_spellings() {
+        let known = names(&["nested:func", "docker:build_all"]);
+        assert_eq!(
+            resolve("nested__func", &[], &known),
+            Resolution::Found {
+                name: "nested:func".to_string(),
+                consumed: 0
+            }
+        );
+        assert_eq!(
+            resolve("docker_build_all", &[], &known),
+            Resolution::Found {
+                name: "docker:build_all".to_string(),
+                consumed: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_ambiguous_underscores() {
+        let known = names(&["a:b_c", "a_b:c"]);
+        assert_eq!(
+            resolve("a_b_c", &[], &known),
+            Resolution::Ambiguous(names(&["a:b_c", "a_b:c"]))
+        );
+    }
+
+    #[test]
+    fn test_resolve_namespace_and_not_found() {
+        let known = names(&["docker:compose:up", "docker:compose:down", "docker:ps"]);
+        assert_eq!(
+            resolve("docker", &args(&["compose"]), &known),
+            Resolution::Namespace(names(&["docker:compose:down", "docker:compose:up"]))
+        );
+        assert_eq!(resolve("podman", &[], &known), Resolution::NotFound);
+    }
+}
@@ -1,73 +1,289 @@
 //! Shell command execution and interpreter resolution
 
-use crate::ast::{Attribute, CommandOutput, ShellType};
+use super::capture;
+use crate::ast::{Attribute, CommandOutput, ExecMode, ShellType};
+use crate::exit::Failure;
+use crate::limits::{self, LimitViolation, ResourceLimits};
+use crate::process;
+use crate::registry::{self, ArgvConvention, CodeDelivery, InterpreterSpec};
+use crate::sandbox::{PrivateTemp, Sandbox};
 use crate::transpiler::Interpreter as TranspilerInterpreter;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-/// Get the Python executable (prefers python3)
-pub(super) fn get_python_executable() -> String {
-    if which::which("python3").is_ok() {
-        "python3".to_string()
-    } else {
-        "python".to_string()
+/// Scripts longer than this run from a file instead of inline. A single argument is
+/// capped at 128 KiB on Linux, and a whole command line at 32 KiB on Windows.
+const INLINE_SCRIPT_LIMIT: usize = 24 * 1024;
+
+/// How a generated script is handed to its interpreter
+pub(super) struct Launch {
+    /// Registry entry, switched to a script file when the code doesn't go inline
+    pub(super) spec: InterpreterSpec,
+    /// Name of the script file, after the function: `deploy.py`
+    pub(super) file_name: String,
+    /// Resource limits for the interpreter and everything it starts
+    pub(super) limits: ResourceLimits,
+    /// Landlock sandbox for the interpreter and everything it starts
+    pub(super) sandbox: Option<Sandbox>,
+    /// Private directory for the call's files, when it is going to be executed
+    dir: Option<CallDir>,
+}
+
+impl Launch {
+    /// Decide how to run a `script_len`-byte script for `function`: from a file when
+    /// `@exec file` asks for it, when the interpreter only reads files (or a shebang
+    /// gave it arguments), or when the script is too long to pass inline.
+    pub(super) fn new(
+        function: &str,
+        interpreter: &TranspilerInterpreter,
+        attributes: &[Attribute],
+        script_len: usize,
+    ) -> Self {
+        let spec = interpreter.spec();
+        let exec_mode = attributes.iter().find_map(|attr| match attr {
+            Attribute::Exec(mode) => Some(*mode),
+            _ => None,
+        });
+        let from_file = match exec_mode {
+            Some(mode) => mode == ExecMode::File,
+            None => script_len > INLINE_SCRIPT_LIMIT,
+        };
+        let spec = if from_file {
+            spec.run_from_file()
+        } else {
+            spec
+        };
+
+        let stem: String = function
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let file_name = match spec.file_extension() {
+            "" => stem,
+            extension => format!("{stem}.{extension}"),
+        };
+        Self {
+            spec,
+            file_name,
+            limits: limits_of(attributes),
+            sandbox: sandbox_of(attributes),
+            dir: None,
+        }
+    }
+
+    /// Keep the call's files in `dir`
+    pub(super) fn in_dir(mut self, dir: Option<CallDir>) -> Self {
+        self.dir = dir;
+        self
+    }
+
+    /// File the Bash `ERR` trap writes the failing line number to
+    pub(super) fn line_file(&self) -> Option<PathBuf> {
+        self.dir.as_ref().map(CallDir::line_file)
+    }
+
+    /// Whether the script runs from a file (as opposed to `-c`/`-e`)
+    pub(super) fn uses_file(&self) -> bool {
+        matches!(self.spec.code, CodeDelivery::TempFile { .. })
     }
 }
 
-/// Map a `TranspilerInterpreter` to shell command and argument
-/// Returns (`shell_command`, `shell_arg`, `interpreter_name`)
-pub(super) fn interpreter_to_shell_args(
-    interpreter: &TranspilerInterpreter,
-) -> (String, &'static str, &'static str) {
-    match interpreter {
-        TranspilerInterpreter::Sh => ("sh".to_string(), "-c", "sh"),
-        TranspilerInterpreter::Bash => ("bash".to_string(), "-c", "bash"),
-        TranspilerInterpreter::Pwsh => ("pwsh".to_string(), "-Command", "pwsh"),
-        TranspilerInterpreter::Python => (get_python_executable(), "-c", "python"),
-        TranspilerInterpreter::Python3 => ("python3".to_string(), "-c", "python3"),
-        TranspilerInterpreter::Node => ("node".to_string(), "-e", "node"),
-        TranspilerInterpreter::Ruby => ("ruby".to_string(), "-e", "ruby"),
+/// The function's `@limit`, with the `--limit` defaults for what it leaves out
+fn limits_of(attributes: &[Attribute]) -> ResourceLimits {
+    let own = attributes
+        .iter()
+        .find_map(|attr| match attr {
+            Attribute::Limit(limits) => Some(*limits),
+            _ => None,
+        })
+        .unwrap_or_default();
+    own.or(crate::config::get_default_limits())
+}
+
+/// The function's `@sandbox`, or the default sandbox when `--sandbox` was given
+fn sandbox_of(attributes: &[Attribute]) -> Option<Sandbox> {
+    let paths = attributes
+        .iter()
+        .find_map(|attr| match attr {
+            Attribute::Sandbox(paths) => Some(paths.clone()),
+            _ => None,
+        })
+        .or_else(|| crate::config::get_sandbox().then(Default::default))?;
+    let project = crate::config::project_dir();
+    let output_dir = crate::config::output_dir();
+    Some(Sandbox::new(&paths, project.as_deref(), &output_dir))
+}
+
+/// A private temporary directory (only the current user can enter it) for the files of
+/// one function call, removed when dropped
+pub(super) struct CallDir {
+    dir: tempfile::TempDir,
+}
+
+impl CallDir {
+    pub(super) fn create() -> std::io::Result<Self> {
+        let dir = private_dir()?;
+        // Created empty, so that a sandboxed call can be let write to it
+        std::fs::File::create(dir.path().join(LINE_FILE))?;
+        Ok(Self { dir })
+    }
+
+    /// File the Bash `ERR` trap writes the failing line number to
+    pub(super) fn line_file(&self) -> PathBuf {
+        self.dir.path().join(LINE_FILE)
     }
 }
 
-/// Execute a command and capture its output
-pub(super) fn execute_with_capture(
-    command: &str,
-    shell_cmd: &str,
-    shell_arg: &str,
-    display_command: Option<&str>,
-) -> Result<CommandOutput, Box<dyn std::error::Error>> {
-    execute_with_capture_and_args(command, shell_cmd, shell_arg, &[], display_command)
+/// Name of [`CallDir::line_file`]
+const LINE_FILE: &str = "lineno";
+
+fn private_dir() -> std::io::Result<tempfile::TempDir> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("run-");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(std::fs::Permissions::from_mode(0o700));
+    }
+    builder.tempdir()
+}
+
+/// A generated script written to the call's private directory, or to one of its own
+/// that is removed together with it when dropped
+pub(super) struct ScriptFile {
+    _dir: Option<tempfile::TempDir>,
+    path: PathBuf,
+}
+
+impl ScriptFile {
+    fn write(launch: &Launch, script: &str) -> std::io::Result<Self> {
+        let (dir, path) = if let Some(call_dir) = &launch.dir {
+            (None, call_dir.dir.path().join(&launch.file_name))
+        } else {
+            let dir = private_dir()?;
+            let path = dir.path().join(&launch.file_name);
+            (Some(dir), path)
+        };
+        let content = match launch.spec.dialect.file_header() {
+            Some(header) => format!("{header}\n{script}"),
+            None => script.to_string(),
+        };
+        std::fs::write(&path, content)?;
+        Ok(Self { _dir: dir, path })
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Build the command that runs `script` as described by `launch`, handing it `args`
+/// according to the interpreter's argv convention.
+///
+/// Scripts run from a file get a [`ScriptFile`]; keep it alive until the process has
+/// exited.
+pub(super) fn interpreter_command(
+    launch: &Launch,
+    script: &str,
+    args: &[String],
+) -> Result<(Command, Option<ScriptFile>), Box<dyn std::error::Error>> {
+    let spec = &launch.spec;
+    let mut cmd = Command::new(spec.program());
+    // `run` calls made by the function belong to this call's history entry
+    cmd.env(crate::history::DISABLE_ENV, "off");
+    cmd.envs(crate::config::sibling_call_env());
+
+    let script_file = match &spec.code {
+        CodeDelivery::Inline(flags) => {
+            cmd.args(flags).arg(script);
+            if !args.is_empty() && spec.argv == ArgvConvention::DollarZero {
+                // $0 placeholder (convention: use the interpreter name)
+                cmd.arg(&spec.name);
+            }
+            None
+        }
+        CodeDelivery::TempFile { args: flags, .. } => {
+            let script_file = ScriptFile::write(launch, script)?;
+            cmd.args(flags).arg(script_file.path());
+            Some(script_file)
+        }
+    };
+    cmd.args(args);
+
+    Ok((cmd, script_file))
+}
+
+/// Set up `cmd`, built by [`interpreter_command`], to run within the limits and the
+/// sandbox of `launch`. The sandbox comes last, as it would keep the limits from moving
+/// the command into its cgroup. Its temporary directory is returned, to be kept until
+/// the command has finished.
+fn confine(
+    cmd: &mut Command,
+    launch: &Launch,
+    script_file: Option<&ScriptFile>,
+) -> Result<(limits::Confinement, Option<PrivateTemp>), Box<dyn std::error::Error>> {
+    let confinement = limits::confine(cmd, launch.limits);
+    let mut temp = None;
+    if let Some(sandbox) = &launch.sandbox {
+        let mut sandbox = sandbox.clone();
+        if let Some(script_file) = script_file {
+            sandbox.allow_reads(script_file.path());
+        }
+        if let Ok(program) = which::which(launch.spec.program()) {
+            sandbox.allow_reads(&program);
+        }
+        if let Some(line_file) = launch.line_file() {
+            sandbox.allow_writes(&line_file);
+        }
+        temp = Some(sandbox.apply(cmd)?);
+    }
+    Ok((confinement, temp))
 }
 
 /// Execute a command and capture its output, with additional arguments
-/// Arguments are passed after the script for polyglot languages (Python, Node, Ruby)
+/// Arguments are handed over the way the interpreter expects (`$1...` for shells,
+/// `sys.argv[1:]` for Python, `process.argv` for Node, ...)
 /// The `display_command` is used for output/logging instead of the full script (which may include preamble)
+/// With `tee`, output is also shown as it arrives instead of only once the command ends.
+/// The trace marks read from `marks`, if given, and the resource limit the command ran
+/// into, if any, are returned along with the output.
 pub(super) fn execute_with_capture_and_args(
     command: &str,
-    shell_cmd: &str,
-    shell_arg: &str,
+    launch: &Launch,
     args: &[String],
     display_command: Option<&str>,
-) -> Result<CommandOutput, Box<dyn std::error::Error>> {
+    tee: bool,
+    marks: Option<capture::MarkFifos>,
+) -> Result<CapturedRun, Box<dyn std::error::Error>> {
     let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
     let start = Instant::now();
 
-    let mut cmd = Command::new(shell_cmd);
-    cmd.arg(shell_arg).arg(command);
-
-    // Pass additional arguments after the script
-    for arg in args {
-        cmd.arg(arg);
-    }
-
-    let output = cmd.output()?;
-
-    let mut stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let mut stderr = String::from_utf8_lossy(&output.stderr).to_string();
-
-    // Process output for MCP mode if configured
-    if crate::output_file::is_mcp_output_enabled() {
+    let mcp_output = crate::output_file::is_mcp_output_enabled();
+    let _interrupts = process::catch_interrupts();
+    let (mut cmd, script_file) = interpreter_command(launch, command, args)?;
+    let (confinement, _temp) = confine(&mut cmd, launch, script_file.as_ref())?;
+    let captured = capture::run(&mut cmd, tee, marks)?;
+    let limit = confinement.exceeded(captured.status);
+
+    let sandbox_denied = launch
+        .sandbox
+        .as_ref()
+        .map(|sandbox| sandbox.denied(&captured.stderr))
+        .unwrap_or_default();
+
+    let mut stdout = captured.stdout;
+    let mut stderr = captured.stderr;
+    let mut events = captured.events;
+
+    // Process output for MCP mode if configured; spilled output is already cut down
+    if mcp_output && captured.spill_path.is_none() {
         match crate::output_file::process_output_for_mcp(&stdout, "stdout") {
             Ok(processed) => stdout = processed.display_output,
             Err(e) => eprintln!("Warning: Failed to process stdout for MCP: {e}"),
@@ -77,123 +293,171 @@ pub(super) fn execute_with_capture_and_args(
             Err(e) => eprintln!("Warning: Failed to process stderr for MCP: {e}"),
         }
     }
+    if mcp_output {
+        // Interleaved, the streams share the budget they each get on their own
+        events = capture::last_events(events, 2 * crate::output_file::OUTPUT_TRUNCATE_CHARS);
+    }
 
-    Ok(CommandOutput {
+    let output = CommandOutput {
         // Use display_command if provided, otherwise fall back to the full command
         command: display_command.unwrap_or(command).to_string(),
         stdout,
         stderr,
-        exit_code: output.status.code(),
+        exit_code: captured.status.code(),
+        signal: crate::exit::signal_of(captured.status),
         duration_ms: start.elapsed().as_millis(),
         started_at,
+        events,
+        steps: Vec::new(),
+        sandbox_denied,
+    };
+    Ok(CapturedRun {
+        output,
+        marks: captured.marks,
+        limit,
     })
 }
 
+/// What [`execute_with_capture_and_args`] ran into
+pub(super) struct CapturedRun {
+    pub(super) output: CommandOutput,
+    pub(super) marks: capture::Marks,
+    pub(super) limit: Option<LimitViolation>,
+}
+
 /// Execute a script in a single shell invocation with positional arguments.
 ///
 /// For `bash -c "script" bash arg1 arg2`, bash sets `$0=bash`, `$1=arg1`, `$2=arg2`.
 /// This lets shell functions use `local name="$1"` to receive args natively.
+/// What the interpreter writes to stderr is kept in `stderr` as well, see
+/// [`run_inheriting_output`].
 pub(super) fn execute_single_shell_invocation_with_args(
     script: &str,
-    interpreter: &TranspilerInterpreter,
+    launch: &Launch,
     args: &[String],
+    stderr: &mut String,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let (shell_cmd, shell_arg, interpreter_name) = interpreter_to_shell_args(interpreter);
-
-    let mut cmd = Command::new(&shell_cmd);
-    cmd.arg(shell_arg).arg(script);
-
-    if !args.is_empty() {
-        // $0 placeholder (convention: use the interpreter name)
-        cmd.arg(interpreter_name);
-        cmd.args(args);
-    }
-
-    let status = cmd
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()?;
+    let spec = &launch.spec;
+    let (status, limit) = run_inheriting_output(launch, script, args, stderr)?;
 
     if !status.success() {
-        let code = status.code().unwrap_or(-1);
-        if code == 2
-            && matches!(
-                interpreter,
-                TranspilerInterpreter::Sh | TranspilerInterpreter::Bash
-            )
-        {
+        if status.code() == Some(2) && spec.dialect == registry::Dialect::Sh && limit.is_none() {
             eprintln!(
                 "\nhint: exit code 2 typically indicates a syntax error in the function body"
             );
         }
-        return Err(format!("{interpreter_name}: command failed (exit code {code})").into());
+        return Err(Failure::from_status(&spec.name, status)
+            .exceeding(limit)
+            .into());
+    }
+
+    Ok(())
+}
+
+/// Execute a polyglot script with its output going straight to the terminal, keeping
+/// what it writes to stderr in `stderr` as well (see [`run_inheriting_output`])
+pub(super) fn execute_streaming_with_args(
+    script: &str,
+    launch: &Launch,
+    args: &[String],
+    stderr: &mut String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (status, limit) = run_inheriting_output(launch, script, args, stderr)?;
+
+    if !status.success() {
+        return Err(Failure::from_status(&launch.spec.program(), status)
+            .exceeding(limit)
+            .into());
     }
 
     Ok(())
 }
 
+/// Run `script` with its output going to ours, returning how it ended and the resource
+/// limit it ran into. For an interpreter whose tracebacks name the failing line, stderr
+/// passes through a pipe so that its last 64 KiB can be kept in `stderr`.
+fn run_inheriting_output(
+    launch: &Launch,
+    script: &str,
+    args: &[String],
+    stderr: &mut String,
+) -> Result<(ExitStatus, Option<LimitViolation>), Box<dyn std::error::Error>> {
+    // Declared first so the script file is removed before a caught signal is delivered
+    let _interrupts = process::catch_interrupts();
+    let (mut cmd, script_file) = interpreter_command(launch, script, args)?;
+    let (confinement, _temp) = confine(&mut cmd, launch, script_file.as_ref())?;
+    process::supervise(&mut cmd);
+    let keep_stderr = super::execution::reports_error_lines(launch.spec.dialect);
+    let mut child = cmd
+        .stdout(Stdio::inherit())
+        .stderr(if keep_stderr {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .spawn()?;
+    let tee = child.stderr.take().map(capture::tee_stderr);
+    let status = process::wait_forwarding(&mut child)?;
+    if let Some(kept) = tee.and_then(|tee| tee.join().ok()) {
+        *stderr = kept;
+    }
+    Ok((status, confinement.exceeded(status)))
+}
+
 /// Execute a command with optional shell attributes and arguments
 pub(super) fn execute_command_with_args(
     command: &str,
     attributes: &[Attribute],
     args: &[String],
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Check if there's a custom shell attribute
-    let shell_attr: Option<&ShellType> = attributes.iter().find_map(|attr| match attr {
+    // A custom shell attribute picks the interpreter from the registry
+    if let Some(shell_type) = attributes.iter().find_map(|attr| match attr {
         Attribute::Shell(shell) => Some(shell),
         _ => None,
-    });
-
-    let (shell_cmd, shell_arg) = if let Some(shell_type) = shell_attr {
-        // Use the specified shell from attributes
-        match shell_type {
-            ShellType::Python => (get_python_executable(), "-c".to_string()),
-            ShellType::Python3 => ("python3".to_string(), "-c".to_string()),
-            ShellType::Node => ("node".to_string(), "-e".to_string()),
-            ShellType::Ruby => ("ruby".to_string(), "-e".to_string()),
-            ShellType::Pwsh => ("pwsh".to_string(), "-c".to_string()),
-            ShellType::Bash => ("bash".to_string(), "-c".to_string()),
-            ShellType::Sh => ("sh".to_string(), "-c".to_string()),
+    }) {
+        let interpreter = TranspilerInterpreter::from_shell_type(shell_type);
+        let launch = Launch::new(interpreter.name(), &interpreter, attributes, command.len());
+        return execute_streaming_with_args(command, &launch, args, &mut String::new());
+    }
+
+    // Check for RUN_SHELL environment variable, otherwise use platform defaults
+    let shell_cmd = if let Ok(custom_shell) = std::env::var("RUN_SHELL") {
+        custom_shell
+    } else if cfg!(target_os = "windows") {
+        // Default to PowerShell on Windows
+        // Try to find pwsh (PowerShell 7+) first, then fallback to powershell (Windows PowerShell)
+        if which::which("pwsh").is_ok() {
+            "pwsh".to_string()
+        } else {
+            "powershell".to_string()
         }
+    } else if which::which("bash").is_ok() {
+        // Prefer bash over sh on Unix-like systems when available
+        "bash".to_string()
     } else {
-        // Check for RUN_SHELL environment variable, otherwise use platform defaults
-        let shell = if let Ok(custom_shell) = std::env::var("RUN_SHELL") {
-            custom_shell
-        } else if cfg!(target_os = "windows") {
-            // Default to PowerShell on Windows
-            // Try to find pwsh (PowerShell 7+) first, then fallback to powershell (Windows PowerShell)
-            if which::which("pwsh").is_ok() {
-                "pwsh".to_string()
-            } else {
-                "powershell".to_string()
-            }
-        } else if which::which("bash").is_ok() {
-            // Prefer bash over sh on Unix-like systems when available
-            "bash".to_string()
-        } else {
-            // Fall back to sh
-            "sh".to_string()
-        };
-        (shell, "-c".to_string())
+        // Fall back to sh
+        "sh".to_string()
     };
 
+    let _interrupts = process::catch_interrupts();
     let mut cmd = Command::new(&shell_cmd);
-    cmd.arg(&shell_arg).arg(command);
-
-    // For custom shells with arguments, pass them after the script
-    // This makes them available as sys.argv[1:], process.argv[2:], etc.
-    if shell_attr.is_some() {
-        for arg in args {
-            cmd.arg(arg);
-        }
-    }
-
-    let status = cmd
+    cmd.arg("-c")
+        .arg(command)
+        .env(crate::history::DISABLE_ENV, "off")
+        .envs(crate::config::sibling_call_env())
         .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()?;
-
-    if !status.success() {
+        .stderr(Stdio::inherit());
+    let confinement = limits::confine(&mut cmd, limits_of(attributes));
+    let _temp = sandbox_of(attributes)
+        .map(|sandbox| sandbox.apply(&mut cmd))
+        .transpose()?;
+    process::supervise(&mut cmd);
+    let mut child = cmd.spawn()?;
+    let status = process::wait_forwarding(&mut child)?;
+
+    if let Some(limit) = confinement.exceeded(status) {
+        eprintln!("{shell_cmd}: {}", limit.message);
+    } else if !status.success() {
         let code = status.code().unwrap_or(-1);
         eprintln!("{shell_cmd}: command failed (exit code {code})");
     }
@@ -210,34 +474,39 @@ pub(super) fn execute_command(
 }
 
 /// Resolve interpreter from shebang to `ShellType`
+///
+/// A shebang that passes arguments to its interpreter (`#!/usr/bin/env -S uv run --script`,
+/// `#!/usr/bin/python3 -u`) resolves to the whole command line, which runs the function
+/// from a script file the way the kernel would run it.
 pub(super) fn resolve_shebang_interpreter(shebang: &str) -> Option<ShellType> {
-    // Extract the binary name from the shebang
-    let binary_name = if let Some(env_part) = shebang.strip_prefix("/usr/bin/env ") {
-        // Format: #!/usr/bin/env python
-        // Extract first word after "env"
-        env_part.split_whitespace().next()?.to_string()
-    } else {
-        // Format: #!/bin/bash or #!/usr/bin/python3
-        // Extract basename
-        std::path::Path::new(shebang)
-            .file_name()?
-            .to_str()?
-            .split_whitespace()
-            .next()?
-            .to_string()
-    };
+    let command = shebang_command(shebang)?;
+    let (binary_name, args) = command.split_first()?;
+    if !args.is_empty() {
+        return Some(ShellType::new(command.join(" ")));
+    }
+
+    // Known interpreters only; the registry gives the canonical name of an alias
+    let spec = registry::lookup(binary_name)?;
+    Some(ShellType::new(spec.name))
+}
 
-    // Map binary name to ShellType
-    match binary_name.as_str() {
-        "python" => Some(ShellType::Python),
-        "python3" => Some(ShellType::Python3),
-        "node" => Some(ShellType::Node),
-        "ruby" => Some(ShellType::Ruby),
-        "pwsh" | "powershell" => Some(ShellType::Pwsh),
-        "bash" => Some(ShellType::Bash),
-        "sh" => Some(ShellType::Sh),
-        _ => None, // Unknown interpreter
+/// Interpreter command line of a shebang: `["python3"]` for `/usr/bin/env python3` or
+/// `/usr/bin/python3`, `["uv", "run", "--script"]` for `/usr/bin/env -S uv run --script`
+fn shebang_command(shebang: &str) -> Option<Vec<&str>> {
+    let mut words = shebang.split_whitespace();
+    // Format: #!/bin/bash or #!/usr/bin/python3: use the basename
+    let program = Path::new(words.next()?).file_name()?.to_str()?;
+    let mut rest: Vec<&str> = words.collect();
+
+    if program == "env" {
+        // Format: #!/usr/bin/env python, or `env -S` to pass arguments along
+        if rest.first() == Some(&"-S") {
+            rest.remove(0);
+        }
+        return (!rest.is_empty()).then_some(rest);
     }
+    rest.insert(0, program);
+    Some(rest)
 }
 
 /// Strip shebang line from function body
@@ -356,7 +625,7 @@ mod tests {
     fn test_resolve_shebang_python() {
         assert_eq!(
             resolve_shebang_interpreter("/usr/bin/env python"),
-            Some(ShellType::Python)
+            Some(ShellType::new("python"))
         );
     }
 
@@ -364,7 +633,7 @@ mod tests {
     fn test_resolve_shebang_python3() {
         assert_eq!(
             resolve_shebang_interpreter("/usr/bin/env python3"),
-            Some(ShellType::Python3)
+            Some(ShellType::new("python3"))
         );
     }
 
@@ -372,7 +641,7 @@ mod tests {
     fn test_resolve_shebang_node() {
         assert_eq!(
             resolve_shebang_interpreter("/usr/bin/env node"),
-            Some(ShellType::Node)
+            Some(ShellType::new("node"))
         );
     }
 
@@ -380,7 +649,7 @@ mod tests {
     fn test_resolve_shebang_ruby() {
         assert_eq!(
             resolve_shebang_interpreter("/usr/bin/env ruby"),
-            Some(ShellType::Ruby)
+            Some(ShellType::new("ruby"))
         );
     }
 
@@ -388,20 +657,23 @@ mod tests {
     fn test_resolve_shebang_bash_direct() {
         assert_eq!(
             resolve_shebang_interpreter("/bin/bash"),
-            Some(ShellType::Bash)
+            Some(ShellType::new("bash"))
         );
     }
 
     #[test]
     fn test_resolve_shebang_sh_direct() {
-        assert_eq!(resolve_shebang_interpreter("/bin/sh"), Some(ShellType::Sh));
+        assert_eq!(
+            resolve_shebang_interpreter("/bin/sh"),
+            Some(ShellType::new("sh"))
+        );
     }
 
     #[test]
     fn test_resolve_shebang_pwsh() {
         assert_eq!(
             resolve_shebang_interpreter("/usr/bin/env pwsh"),
-            Some(ShellType::Pwsh)
+            Some(ShellType::new("pwsh"))
         );
     }
 
@@ -409,20 +681,20 @@ mod tests {
     fn test_resolve_shebang_powershell() {
         assert_eq!(
             resolve_shebang_interpreter("/usr/bin/env powershell"),
-            Some(ShellType::Pwsh)
+            Some(ShellType::new("pwsh"))
         );
     }
 
     #[test]
     fn test_resolve_shebang_unknown() {
-        assert_eq!(resolve_shebang_interpreter("/usr/bin/env perl"), None);
+        assert_eq!(resolve_shebang_interpreter("/usr/bin/env tclsh"), None);
     }
 
     #[test]
     fn test_resolve_shebang_direct_path_python3() {
         assert_eq!(
             resolve_shebang_interpreter("/usr/bin/python3"),
-            Some(ShellType::Python3)
+            Some(ShellType::new("python3"))
         );
     }
 
@@ -450,57 +722,168 @@ mod tests {
         assert_eq!(strip_shebang(body), "");
     }
 
+    fn command_line(interpreter: &TranspilerInterpreter, args: &[&str]) -> Vec<String> {
+        let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+        let launch = Launch::new("f", interpreter, &[], 4);
+        let (cmd, _script_file) = interpreter_command(&launch, "CODE", &args).unwrap();
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|s| s.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_interpreter_command_sh() {
+        assert_eq!(
+            command_line(&TranspilerInterpreter::named("sh"), &[]),
+            ["sh", "-c", "CODE"]
+        );
+    }
+
+    #[test]
+    fn test_interpreter_command_bash_dollar_zero() {
+        assert_eq!(
+            command_line(&TranspilerInterpreter::named("bash"), &["a", "b"]),
+            ["bash", "-c", "CODE", "bash", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_interpreter_command_pwsh() {
+        assert_eq!(
+            command_line(&TranspilerInterpreter::named("pwsh"), &[]),
+            ["pwsh", "-Command", "CODE"]
+        );
+    }
+
     #[test]
-    fn test_interpreter_to_shell_args_sh() {
-        let (cmd, arg, name) = interpreter_to_shell_args(&TranspilerInterpreter::Sh);
-        assert_eq!(cmd, "sh");
-        assert_eq!(arg, "-c");
-        assert_eq!(name, "sh");
+    fn test_interpreter_command_node_direct_args() {
+        assert_eq!(
+            command_line(&TranspilerInterpreter::named("node"), &["x"]),
+            ["node", "-e", "CODE", "x"]
+        );
     }
 
     #[test]
-    fn test_interpreter_to_shell_args_bash() {
-        let (cmd, arg, name) = interpreter_to_shell_args(&TranspilerInterpreter::Bash);
-        assert_eq!(cmd, "bash");
-        assert_eq!(arg, "-c");
-        assert_eq!(name, "bash");
+    fn test_interpreter_command_ruby() {
+        assert_eq!(
+            command_line(&TranspilerInterpreter::named("ruby"), &[]),
+            ["ruby", "-e", "CODE"]
+        );
     }
 
     #[test]
-    fn test_interpreter_to_shell_args_pwsh() {
-        let (cmd, arg, name) = interpreter_to_shell_args(&TranspilerInterpreter::Pwsh);
-        assert_eq!(cmd, "pwsh");
-        assert_eq!(arg, "-Command");
-        assert_eq!(name, "pwsh");
+    fn test_interpreter_command_python3() {
+        assert_eq!(
+            command_line(&TranspilerInterpreter::named("python3"), &[]),
+            ["python3", "-c", "CODE"]
+        );
     }
 
     #[test]
-    fn test_interpreter_to_shell_args_node() {
-        let (cmd, arg, name) = interpreter_to_shell_args(&TranspilerInterpreter::Node);
-        assert_eq!(cmd, "node");
-        assert_eq!(arg, "-e");
-        assert_eq!(name, "node");
+    fn test_interpreter_command_python_prefers_python3() {
+        let program = &command_line(&TranspilerInterpreter::named("python"), &[])[0];
+        assert!(program == "python3" || program == "python");
     }
 
     #[test]
-    fn test_interpreter_to_shell_args_ruby() {
-        let (cmd, arg, name) = interpreter_to_shell_args(&TranspilerInterpreter::Ruby);
-        assert_eq!(cmd, "ruby");
-        assert_eq!(arg, "-e");
-        assert_eq!(name, "ruby");
+    fn test_interpreter_command_temp_file() {
+        let interpreter = TranspilerInterpreter::named("lua");
+        let launch = Launch::new("build:docs", &interpreter, &[], 8);
+        let (cmd, script_file) =
+            interpreter_command(&launch, "print(1)", &["a".to_string()]).unwrap();
+        let script_file = script_file.expect("lua runs a script file");
+        let path = script_file.path().to_path_buf();
+
+        assert_eq!(cmd.get_program(), "lua");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, [path.as_os_str(), "a".as_ref()]);
+        assert_eq!(path.file_name().unwrap(), "build_docs.lua");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "print(1)");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let dir = path.parent().unwrap();
+            let mode = std::fs::metadata(dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o077, 0, "script directory is private");
+        }
+
+        drop(script_file);
+        assert!(!path.exists());
+        assert!(!path.parent().unwrap().exists());
     }
 
     #[test]
-    fn test_interpreter_to_shell_args_python3() {
-        let (cmd, arg, name) = interpreter_to_shell_args(&TranspilerInterpreter::Python3);
-        assert_eq!(cmd, "python3");
-        assert_eq!(arg, "-c");
-        assert_eq!(name, "python3");
+    fn test_launch_exec_file() {
+        let attrs = [Attribute::Exec(ExecMode::File)];
+        let launch = Launch::new(
+            "deploy",
+            &TranspilerInterpreter::named("python3"),
+            &attrs,
+            10,
+        );
+        assert!(launch.uses_file());
+        assert_eq!(launch.file_name, "deploy.py");
+
+        let (cmd, script_file) = interpreter_command(&launch, "pass", &["x".to_string()]).unwrap();
+        let path = script_file.unwrap().path().to_path_buf();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, [path.as_os_str(), "x".as_ref()]);
     }
 
     #[test]
-    fn test_get_python_executable_returns_valid() {
-        let result = get_python_executable();
-        assert!(result == "python3" || result == "python");
+    fn test_call_dir_holds_the_script_and_line_file() {
+        let interpreter = TranspilerInterpreter::named("python3");
+        let attrs = [Attribute::Exec(ExecMode::File)];
+        let launch = Launch::new("deploy", &interpreter, &attrs, 4);
+        let launch = launch.in_dir(Some(CallDir::create().unwrap()));
+        let line_file = launch.line_file().unwrap();
+        assert!(line_file.exists());
+
+        let (_cmd, script_file) = interpreter_command(&launch, "pass", &[]).unwrap();
+        let script_file = script_file.unwrap();
+        assert_eq!(script_file.path().parent(), line_file.parent());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let dir = std::fs::metadata(line_file.parent().unwrap()).unwrap();
+            assert_eq!(dir.permissions().mode() & 0o777, 0o700);
+        }
+        // Each call gets a directory of its own
+        assert_ne!(CallDir::create().unwrap().line_file(), line_file);
+    }
+
+    #[test]
+    fn test_launch_long_script_uses_file() {
+        let bash = TranspilerInterpreter::named("bash");
+        assert!(!Launch::new("f", &bash, &[], 100).uses_file());
+        assert!(Launch::new("f", &bash, &[], INLINE_SCRIPT_LIMIT + 1).uses_file());
+        let inline = [Attribute::Exec(ExecMode::Inline)];
+        assert!(!Launch::new("f", &bash, &inline, INLINE_SCRIPT_LIMIT + 1).uses_file());
+    }
+
+    #[test]
+    fn test_resolve_shebang_with_arguments() {
+        assert_eq!(
+            resolve_shebang_interpreter("/usr/bin/env -S uv run --script"),
+            Some(ShellType::new("uv run --script"))
+        );
+        assert_eq!(
+            resolve_shebang_interpreter("/usr/bin/python3 -u"),
+            Some(ShellType::new("python3 -u"))
+        );
+        assert_eq!(resolve_shebang_interpreter("/usr/bin/env"), None);
+    }
+
+    #[test]
+    fn test_resolve_shebang_registered() {
+        assert_eq!(
+            resolve_shebang_interpreter("/usr/bin/env perl"),
+            Some(ShellType::new("perl"))
+        );
+        assert_eq!(
+            resolve_shebang_interpreter("/usr/bin/zsh"),
+            Some(ShellType::new("zsh"))
+        );
     }
 }
@@ -0,0 +1,705 @@
+//! Step tracing for block functions (`@trace`, `--trace`)
+//!
+//! A Bash `DEBUG` trap marks the first time each line of the function body starts to
+//! run, with the time and the status of the command before it. After the run, those
+//! lines are matched to the body's top-level statements, found by [`statements`], which
+//! gives each statement its duration, its exit status and the output written meanwhile.
+//!
+//! When output is captured, the marks go through a FIFO that is read along with stdout
+//! and stderr, and the trap waits on a second FIFO until capture has taken the output
+//! written before the mark, so each step gets exactly the output written while it ran.
+//! Otherwise the marks are appended to a file.
+
+use super::capture::{MarkFifos, Marks};
+use super::execution::LineMap;
+use crate::ast::{CommandOutput, FailureLocation, OutputStream, TraceStep};
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A top-level statement of a function body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct BodyStatement {
+    /// Line of the body the statement starts on (0-indexed)
+    pub(super) offset: usize,
+    /// Source text, over as many lines as the statement takes
+    pub(super) text: String,
+}
+
+/// The moment a traced line first started to run
+#[derive(Debug, Clone, PartialEq)]
+struct Mark {
+    /// Line of the generated script (1-indexed)
+    line: usize,
+    /// Unix epoch milliseconds
+    at_ms: u128,
+    /// Exit status of the command that ran before it
+    status: i32,
+    /// Bytes of stdout and stderr received before it, when output was captured
+    before: [usize; 2],
+}
+
+/// A traced run of a block function
+#[derive(Debug)]
+pub(super) struct Trace {
+    function: String,
+    statements: Vec<BodyStatement>,
+    /// Where the `DEBUG` trap writes its marks
+    path: PathBuf,
+    /// The FIFOs at `path` and [`Trace::acks_path`], when output is captured. Holding
+    /// them open keeps the trap from waiting for the other end.
+    fifos: Option<MarkFifos>,
+    /// Marks read along with the captured output
+    recorded: Option<Marks>,
+    /// Unix epoch milliseconds when the run started
+    started_ms: u128,
+}
+
+impl Trace {
+    /// Start tracing a run of `function`, whose body is `body` and whose output is
+    /// `captured` or not. `None` if the marks have nowhere safe to go.
+    pub(super) fn start(function: &str, body: &str, captured: bool) -> Option<Self> {
+        let path = std::env::temp_dir().join(format!("run-trace-{}", std::process::id()));
+        if path.to_string_lossy().contains(['\'', '"', '$', '`', '\\']) {
+            return None;
+        }
+        // Don't let marks from an earlier run be picked up
+        let acks_path = path.with_extension("ack");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&acks_path);
+        let fifos = if captured {
+            open_fifo(&path)
+                .zip(open_fifo(&acks_path))
+                .map(|(marks, acks)| MarkFifos { marks, acks })
+        } else {
+            None
+        };
+        Some(Self {
+            function: function.to_string(),
+            statements: statements(body),
+            path,
+            fifos,
+            recorded: None,
+            started_ms: now_ms(),
+        })
+    }
+
+    pub(super) fn function(&self) -> &str {
+        &self.function
+    }
+
+    /// Lines to add after the errexit prefix of a Bash script so that every line of the
+    /// `__run__` wrapper is marked when it first runs. `set -T` lets the trap reach into
+    /// functions; lines of other functions are left out. When output is captured, the
+    /// trap then waits for capture to acknowledge the mark.
+    pub(super) fn bash_trap(&self) -> String {
+        let wait = if self.fifos.is_some() {
+            format!(
+                "; {{ read -r __run_ack <\"{}\"; }} 2>/dev/null || :",
+                self.acks_path().display()
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            "set -T\n__run_last=0\n\
+             trap '__run_s=$?; if [[ ${{FUNCNAME[0]:-}} == __run__ ]] && (( LINENO > __run_last )); \
+             then __run_last=$LINENO; printf \"%s %s %s\\n\" \"$LINENO\" \"${{EPOCHREALTIME:-$SECONDS}}\" \
+             \"$__run_s\" >>\"{}\"{wait}; fi' DEBUG",
+            self.path.display()
+        )
+    }
+
+    /// Let a run in `sandbox` write its marks and read their acknowledgements
+    pub(super) fn allow_in(&self, sandbox: &mut crate::sandbox::Sandbox) {
+        if self.fifos.is_none() {
+            // The sandbox only opens up paths that exist
+            let _ = File::create(&self.path);
+        }
+        sandbox.allow_writes(&self.path);
+        sandbox.allow_writes(&self.acks_path());
+    }
+
+    fn acks_path(&self) -> PathBuf {
+        self.path.with_extension("ack")
+    }
+
+    /// The FIFOs capture should read the marks from and acknowledge them on, to read them
+    /// along with the output
+    pub(super) fn marks_source(&self) -> Option<MarkFifos> {
+        let fifos = self.fifos.as_ref()?;
+        Some(MarkFifos {
+            marks: fifos.marks.try_clone().ok()?,
+            acks: fifos.acks.try_clone().ok()?,
+        })
+    }
+
+    /// Keep the marks capture read
+    pub(super) fn record(&mut self, marks: Marks) {
+        self.recorded = Some(marks);
+    }
+
+    /// The steps of the finished run, which exited with `exit_code`. `output` is what the
+    /// run printed when it was captured, and `locate` resolves a body line to its Runfile
+    /// location.
+    pub(super) fn finish(
+        self,
+        line_map: &LineMap,
+        exit_code: Option<i32>,
+        output: Option<&CommandOutput>,
+        locate: &dyn Fn(usize) -> Option<FailureLocation>,
+    ) -> Vec<TraceStep> {
+        let recorded = match (&self.recorded, &self.fifos) {
+            (Some(marks), _) => Some((marks.lines.clone(), marks.totals)),
+            // Nothing read the FIFO, so nothing was marked
+            (None, Some(_)) => None,
+            (None, None) => {
+                let text = std::fs::read_to_string(&self.path).unwrap_or_default();
+                Some((
+                    text.lines().map(|l| (l.to_string(), [0; 2])).collect(),
+                    [0; 2],
+                ))
+            }
+        };
+        let _ = std::fs::remove_file(&self.path);
+        let _ = std::fs::remove_file(self.acks_path());
+        let (lines, totals) = recorded.unwrap_or_default();
+        let marks: Vec<Mark> = lines
+            .iter()
+            .filter_map(|(line, before)| parse_mark(line, *before, self.started_ms))
+            .collect();
+        let output = output.filter(|_| self.recorded.is_some());
+        self.steps(
+            &marks,
+            line_map,
+            now_ms(),
+            exit_code,
+            output.map(|o| (o, totals)),
+            locate,
+        )
+    }
+
+    /// Steps from the marks of a run that ended at `finished_ms`, with their share of
+    /// `output` when it was captured along with the marks, which `totals` bytes of each
+    /// stream were received in all
+    fn steps(
+        &self,
+        marks: &[Mark],
+        line_map: &LineMap,
+        finished_ms: u128,
+        exit_code: Option<i32>,
+        output: Option<(&CommandOutput, [usize; 2])>,
+        locate: &dyn Fn(usize) -> Option<FailureLocation>,
+    ) -> Vec<TraceStep> {
+        // A subshell inherits the last line marked, so lines only ever go forward
+        let mut last_line = 0;
+        let mut starts: Vec<(&BodyStatement, &Mark)> = Vec::new();
+        for mark in marks {
+            if mark.line <= last_line {
+                continue;
+            }
+            last_line = mark.line;
+            let Some((function, offset)) = line_map.locate(mark.line) else {
+                continue;
+            };
+            if function != self.function {
+                continue;
+            }
+            if let Some(statement) = self.statements.iter().find(|s| s.offset == offset) {
+                starts.push((statement, mark));
+            }
+        }
+
+        let mut steps: Vec<TraceStep> = starts
+            .iter()
+            .enumerate()
+            .map(|(i, (statement, mark))| {
+                let next = starts.get(i + 1).map(|(_, next)| *next);
+                let until_ms = next.map_or(finished_ms, |next| next.at_ms);
+                let (file, line) = locate(statement.offset).map_or_else(
+                    || ("Runfile".to_string(), 0),
+                    |location| (location.file, location.line),
+                );
+                TraceStep {
+                    command: statement.text.clone(),
+                    file,
+                    line,
+                    duration_ms: until_ms.saturating_sub(mark.at_ms),
+                    exit_code: next.map_or(exit_code, |next| Some(next.status)),
+                    stdout: String::new(),
+                    stderr: String::new(),
+                }
+            })
+            .collect();
+        if let Some((output, totals)) = output {
+            let before: Vec<[usize; 2]> = starts.iter().map(|(_, mark)| mark.before).collect();
+            share_output(&mut steps, &before, output, totals);
+        }
+        steps
+    }
+}
+
+/// Give each step the output that arrived after its mark and before the next one.
+/// `before` holds the bytes of each stream received before each step's mark, and the
+/// events kept are the last of the `totals` bytes received.
+fn share_output(
+    steps: &mut [TraceStep],
+    before: &[[usize; 2]],
+    output: &CommandOutput,
+    totals: [usize; 2],
+) {
+    for (idx, stream) in [OutputStream::Stdout, OutputStream::Stderr]
+        .into_iter()
+        .enumerate()
+    {
+        let kept: usize = output
+            .events
+            .iter()
+            .filter(|e| e.stream == stream)
+            .map(|e| e.text.len())
+            .sum();
+        let mut offset = totals[idx].saturating_sub(kept);
+        for event in output.events.iter().filter(|e| e.stream == stream) {
+            // Output that arrived before the first mark belongs to the first step
+            let step = before
+                .iter()
+                .rposition(|b| b[idx] <= offset)
+                .unwrap_or_default();
+            if let Some(step) = steps.get_mut(step) {
+                match stream {
+                    OutputStream::Stdout => step.stdout.push_str(&event.text),
+                    OutputStream::Stderr => step.stderr.push_str(&event.text),
+                }
+            }
+            offset += event.text.len();
+        }
+    }
+}
+
+/// A FIFO at `path`, open for reading. It is opened for writing too, so that opening
+/// it never waits and reading it doesn't end when a writer closes it.
+#[cfg(unix)]
+fn open_fifo(path: &std::path::Path) -> Option<File> {
+    use std::os::unix::ffi::OsStrExt;
+    let name = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `name` is a valid NUL-terminated path for the duration of the call
+    if unsafe { libc::mkfifo(name.as_ptr(), 0o600) } != 0 {
+        return None;
+    }
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn open_fifo(_path: &std::path::Path) -> Option<File> {
+    None
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis())
+}
+
+/// Parse a mark the trap wrote: the script line, the time, and the status before it.
+/// `started_ms` is when the script was started, for shells too old to tell the time
+/// more precisely than in seconds since.
+fn parse_mark(text: &str, before: [usize; 2], started_ms: u128) -> Option<Mark> {
+    let mut fields = text.split_whitespace();
+    let line = fields.next()?.parse().ok()?;
+    let time = fields.next()?;
+    let status = fields.next()?.parse().ok()?;
+    // EPOCHREALTIME is seconds with microseconds, after the locale's decimal separator
+    let (seconds, fraction) = time.split_once(['.', ',']).unwrap_or((time, ""));
+    let seconds: u128 = seconds.parse().ok()?;
+    let millis: u128 = format!("{fraction:0<3}").get(..3)?.parse().ok()?;
+    let at_ms = if seconds < 1_000_000_000 {
+        // $SECONDS, counted from when the shell started
+        started_ms + seconds * 1000
+    } else {
+        seconds * 1000 + millis
+    };
+    Some(Mark {
+        line,
+        at_ms,
+        status,
+        before,
+    })
+}
+
+/// A table of `steps` for the terminal, after a streamed run
+pub(super) fn summary(function: &str, steps: &[TraceStep]) -> String {
+    use std::fmt::Write as _;
+    let mut text = format!("trace: {function}\n");
+    for (i, step) in steps.iter().enumerate() {
+        let status = step.status();
+        let command = step.command.lines().next().unwrap_or_default();
+        let _ = writeln!(
+            text,
+            "  {:>2}  {}:{:<4} {:>7}ms  {:<7} {command}",
+            i + 1,
+            step.file,
+            step.line,
+            step.duration_ms,
+            status,
+        );
+    }
+    text
+}
+
+/// Split a shell function body into its top-level statements.
+///
+/// Enough of the shell grammar is followed to keep a compound command (`if ... fi`,
+/// loops, `case`, `{ ... }`, subshells), a quoted string, a here-document or a line
+/// ending in `\`, `&&`, `||` or `|` together with the lines it spans. Blank and comment
+/// lines between statements belong to none.
+pub(super) fn statements(body: &str) -> Vec<BodyStatement> {
+    let mut scanner = Scanner::default();
+    let mut statements = Vec::new();
+    let mut current: Option<(usize, Vec<&str>)> = None;
+    for (offset, line) in body.split('\n').enumerate() {
+        if scanner.in_heredoc(line) {
+            if let Some((_, lines)) = current.as_mut() {
+                lines.push(line);
+            }
+        } else {
+            let trimmed = line.trim();
+            if current.is_none() {
+                if trimmed.is_empty() || trimmed.starts_with('#') {
+                    continue;
+                }
+                current = Some((offset, Vec::new()));
+            }
+            if let Some((_, lines)) = current.as_mut() {
+                lines.push(line);
+            }
+            scanner.scan(line);
+        }
+        if !scanner.continues()
+            && let Some((offset, lines)) = current.take()
+        {
+            statements.push(BodyStatement {
+                offset,
+                text: dedent(&lines),
+            });
+        }
+    }
+    if let Some((offset, lines)) = current {
+        statements.push(BodyStatement {
+            offset,
+            text: dedent(&lines),
+        });
+    }
+    statements
+}
+
+/// Lines joined, less the indentation of the first
+fn dedent(lines: &[&str]) -> String {
+    let first = lines.first().copied().unwrap_or_default();
+    let indent = &first[..first.len() - first.trim_start().len()];
+    lines
+        .iter()
+        .map(|line| line.strip_prefix(indent).unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+/// Where a scan of a function body is, between lines
+#[derive(Debug, Default)]
+struct Scanner {
+    /// Open compound commands
+    depth: usize,
+    /// Open parentheses, for subshells and `$(...)`
+    parens: usize,
+    /// The quote a string spanning lines was opened with
+    quote: Option<char>,
+    /// Here-documents started on the last line: delimiter, and whether tabs are stripped
+    heredocs: Vec<(String, bool)>,
+    /// The last line carries on onto the next
+    joined: bool,
+}
+
+impl Scanner {
+    /// Whether the statement goes on past the last line scanned
+    fn continues(&self) -> bool {
+        self.depth > 0
+            || self.parens > 0
+            || self.quote.is_some()
+            || self.joined
+            || !self.heredocs.is_empty()
+    }
+
+    /// Whether `line` is part of a here-document, which it ends if it's the delimiter
+    fn in_heredoc(&mut self, line: &str) -> bool {
+        let Some((delimiter, strip_tabs)) = self.heredocs.first() else {
+            return false;
+        };
+        let line = if *strip_tabs {
+            line.trim_start_matches('\t')
+        } else {
+            line
+        };
+        if line == delimiter {
+            self.heredocs.remove(0);
+        }
+        true
+    }
+
+    fn scan(&mut self, line: &str) {
+        let chars: Vec<char> = line.chars().collect();
+        let mut command_start = !self.joined;
+        let mut word = String::new();
+        let mut i = 0;
+        self.joined = false;
+        while i < chars.len() {
+            let c = chars[i];
+            if let Some(quote) = self.quote {
+                if c == '\\' && quote != '\'' {
+                    i += 1;
+                } else if c == quote {
+                    self.quote = None;
+                }
+                i += 1;
+                continue;
+            }
+            if c.is_alphanumeric() || matches!(c, '_' | '{' | '}' | '!' | '-' | '.' | '/') {
+                word.push(c);
+                i += 1;
+                continue;
+            }
+            self.end_word(&mut word, &mut command_start);
+            match c {
+                '\\' if i + 1 == chars.len() => self.joined = true,
+                '\\' => i += 1,
+                '\'' | '"' | '`' => {
+                    self.quote = Some(c);
+                    command_start = false;
+                }
+                '#' if i == 0 || chars[i - 1].is_whitespace() => break,
+                '(' => {
+                    self.parens += 1;
+                    command_start = true;
+                }
+                ')' => {
+                    // Unbalanced in `case` patterns, which don't matter inside `case`
+                    self.parens = self.parens.saturating_sub(1);
+                    command_start = true;
+                }
+                ';' | '&' | '|' => command_start = true,
+                '<' if chars.get(i + 1) == Some(&'<') && chars.get(i + 2) != Some(&'<') => {
+                    i = self.heredoc(&chars, i + 2);
+                    command_start = false;
+                    continue;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        self.end_word(&mut word, &mut command_start);
+        let code = line.trim_end();
+        if self.quote.is_none()
+            && (code.ends_with("&&") || code.ends_with("||") || code.ends_with('|'))
+        {
+            self.joined = true;
+        }
+    }
+
+    /// Note a here-document whose delimiter follows `<<` at `start`, returning where
+    /// the delimiter ends
+    fn heredoc(&mut self, chars: &[char], mut start: usize) -> usize {
+        let strip_tabs = chars.get(start) == Some(&'-');
+        if strip_tabs {
+            start += 1;
+        }
+        while chars.get(start).is_some_and(|c| c.is_whitespace()) {
+            start += 1;
+        }
+        let mut end = start;
+        let mut delimiter = String::new();
+        while let Some(&c) = chars.get(end) {
+            if c.is_whitespace() || matches!(c, ';' | '&' | '|' | '<' | '>' | ')') {
+                break;
+            }
+            if !matches!(c, '\'' | '"' | '\\') {
+                delimiter.push(c);
+            }
+            end += 1;
+        }
+        if !delimiter.is_empty() {
+            self.heredocs.push((delimiter, strip_tabs));
+        }
+        end
+    }
+
+    /// Count a finished word if it opens or closes a compound command
+    fn end_word(&mut self, word: &mut String, command_start: &mut bool) {
+        if word.is_empty() {
+            return;
+        }
+        if *command_start {
+            match word.as_str() {
+                "if" | "case" | "for" | "while" | "until" | "select" | "{" => self.depth += 1,
+                "fi" | "esac" | "done" | "}" => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        *command_start = matches!(
+            word.as_str(),
+            "if" | "then" | "else" | "elif" | "do" | "while" | "until" | "{" | "}" | "!" | "time"
+        );
+        word.clear();
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn starts(body: &str) -> Vec<(usize, String)> {
+        statements(body)
+            .into_iter()
+            .map(|s| (s.offset, s.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_statements_keep_compound_commands_together() {
+        let body = "  echo start\n\
+                    \n  # build everything\n\
+                    \x20 if [ -n \"$CI\" ]; then\n    make ci\n  else\n    make\n  fi\n\
+                    \x20 for f in a b; do echo \"$f done\"; done\n\
+                    \x20 case $1 in\n    a) echo a ;;\n    *) echo other ;;\n  esac\n\
+                    \x20 cargo build &&\n    cargo test\n\
+                    \x20 echo done";
+        assert_eq!(
+            starts(body),
+            vec![
+                (0, "echo start".to_string()),
+                (
+                    3,
+                    "if [ -n \"$CI\" ]; then\n  make ci\nelse\n  make\nfi".to_string()
+                ),
+                (8, "for f in a b; do echo \"$f done\"; done".to_string()),
+                (
+                    9,
+                    "case $1 in\n  a) echo a ;;\n  *) echo other ;;\nesac".to_string()
+                ),
+                (13, "cargo build &&\n  cargo test".to_string()),
+                (15, "echo done".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_statements_span_strings_heredocs_and_continuations() {
+        let body = "msg='one\ntwo'\n\
+                    cat <<-'EOF' > out.txt\n\tif this is text\n\tEOF\n\
+                    docker run \\\n  --rm image\n\
+                    x=$(\n  echo sub\n)\n\
+                    { echo a; echo b; } | sort # done";
+        let lines: Vec<usize> = statements(body).iter().map(|s| s.offset).collect();
+        assert_eq!(lines, vec![0, 2, 5, 7, 10]);
+    }
+
+    #[test]
+    fn test_steps_from_marks() {
+        let trace = Trace {
+            function: "deploy".to_string(),
+            statements: statements("echo one\nfor i in 1 2; do\n  echo \"$i\"\ndone\nfalse"),
+            path: PathBuf::from("/nonexistent/trace"),
+            fifos: None,
+            recorded: None,
+            started_ms: 1_699_999_999_900,
+        };
+        // Script lines 10.. hold the body; 3 is a sibling, 13 a nested line of step 2,
+        // and the second 11 comes from a subshell
+        let marks: Vec<Mark> = [
+            ("3 1700000000.000000 0", [0, 0]),
+            ("10 1700000000.000100 0", [0, 0]),
+            ("11 1700000000.250900 0", [4, 0]),
+            ("13 1700000000.300000 0", [6, 0]),
+            ("11 1700000000.400000 0", [8, 0]),
+            ("14 1700000001.000000 4", [8, 0]),
+        ]
+        .iter()
+        .filter_map(|(text, before)| parse_mark(text, *before, trace.started_ms))
+        .collect();
+        assert_eq!(marks.len(), 6);
+
+        let map = LineMap {
+            regions: vec![super::super::execution::LineRegion {
+                start: 10,
+                len: 6,
+                function: "deploy".to_string(),
+                skip: 0,
+            }],
+            parts: Vec::new(),
+        };
+        let event = |stream, text: &str| crate::ast::OutputEvent {
+            stream,
+            at_ms: 0,
+            text: text.to_string(),
+        };
+        // The first line of output is no longer kept
+        let output = CommandOutput {
+            command: String::new(),
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: Some(1),
+            signal: None,
+            duration_ms: 1200,
+            started_at: 1_699_999_999_900,
+            events: vec![
+                event(OutputStream::Stdout, "1\n"),
+                event(OutputStream::Stdout, "2\n"),
+                event(OutputStream::Stderr, "oops\n"),
+            ],
+            steps: Vec::new(),
+            sandbox_denied: Vec::new(),
+        };
+        let locate = |offset: usize| {
+            Some(FailureLocation {
+                file: "Runfile".to_string(),
+                line: 20 + offset,
+                function: "deploy".to_string(),
+                command: String::new(),
+            })
+        };
+        let steps = trace.steps(
+            &marks,
+            &map,
+            1_700_000_001_100,
+            Some(1),
+            Some((&output, [8, 5])),
+            &locate,
+        );
+
+        let summary: Vec<_> = steps
+            .iter()
+            .map(|s| {
+                (
+                    s.line,
+                    s.duration_ms,
+                    s.exit_code,
+                    s.stdout.as_str(),
+                    s.stderr.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (20, 250, Some(0), "", ""),
+                (21, 750, Some(4), "1\n2\n", ""),
+                (24, 100, Some(1), "", "oops\n"),
+            ]
+        );
+        assert_eq!(steps[1].command, "for i in 1 2; do\n  echo \"$i\"\ndone");
+    }
+}
@@ -7,13 +7,23 @@ pub mod cli;
 pub mod completion;
 pub mod config;
 pub mod executor;
+pub mod exit;
+pub mod history;
 pub mod interpreter;
+pub mod limits;
 pub mod mcp;
+pub mod multi;
 pub mod output_file;
 pub mod parser;
+pub mod process;
+pub mod registry;
 pub mod repl;
+pub mod sandbox;
+pub mod settings;
+pub mod source_map;
 pub mod transpiler;
 pub mod utils;
+pub mod watch;
 
 // Re-export the main CLI entry point for use by wrapper crates
 // Note: This is defined in main.rs but we need to make it accessible
@@ -0,0 +1,574 @@
+//! Resource limits for the processes that run a function (`@limit`, `--limit`).
+//!
+//! Limits are set with `setrlimit` in the child before the interpreter starts, so they
+//! hold for it and for everything it starts. On Linux, when `run` can create a cgroup v2
+//! group below its own, memory and processes are limited by the group instead: that
+//! covers the function as a whole, and the kernel counts each time a limit is hit.
+//! Otherwise `mem` limits the data segment of each process (`RLIMIT_DATA`, which leaves
+//! the address space runtimes reserve up front alone), and `procs` isn't enforced, as
+//! `RLIMIT_NPROC` would count every process of the user.
+//!
+//! A violation is only reported when the kernel tells: the signal a CPU time limit
+//! sends, or the group's event counters. Running out of memory or files under
+//! `setrlimit` just makes calls fail, which the command reports as it sees fit.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
+
+/// CPU seconds a process gets after `SIGXCPU` before it is killed
+#[cfg(unix)]
+const CPU_GRACE_SECS: u64 = 5;
+
+/// Limits on the processes running a function:
+/// `# @limit mem=2G cpu=60s nofile=1024 procs=200`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Memory, in bytes
+    pub mem: Option<u64>,
+    /// CPU time, in seconds
+    pub cpu: Option<u64>,
+    /// Open files per process
+    pub nofile: Option<u64>,
+    /// Processes
+    pub procs: Option<u64>,
+}
+
+/// Something `@limit` limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    Mem,
+    Cpu,
+    Nofile,
+    Procs,
+}
+
+/// A limit a function ran into
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LimitViolation {
+    pub resource: Resource,
+    /// The limit, as written in `@limit`: `2G`, `60s`
+    pub limit: String,
+    /// What happened: `memory limit of 2G exceeded`
+    pub message: String,
+}
+
+impl Resource {
+    const ALL: [Self; 4] = [Self::Mem, Self::Cpu, Self::Nofile, Self::Procs];
+
+    /// Name in `@limit`
+    fn key(self) -> &'static str {
+        match self {
+            Self::Mem => "mem",
+            Self::Cpu => "cpu",
+            Self::Nofile => "nofile",
+            Self::Procs => "procs",
+        }
+    }
+
+    fn noun(self) -> &'static str {
+        match self {
+            Self::Mem => "memory",
+            Self::Cpu => "CPU time",
+            Self::Nofile => "open file",
+            Self::Procs => "process",
+        }
+    }
+}
+
+impl ResourceLimits {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        Resource::ALL
+            .iter()
+            .all(|resource| self.get(*resource).is_none())
+    }
+
+    #[must_use]
+    pub fn get(&self, resource: Resource) -> Option<u64> {
+        match resource {
+            Resource::Mem => self.mem,
+            Resource::Cpu => self.cpu,
+            Resource::Nofile => self.nofile,
+            Resource::Procs => self.procs,
+        }
+    }
+
+    fn slot(&mut self, resource: Resource) -> &mut Option<u64> {
+        match resource {
+            Resource::Mem => &mut self.mem,
+            Resource::Cpu => &mut self.cpu,
+            Resource::Nofile => &mut self.nofile,
+            Resource::Procs => &mut self.procs,
+        }
+    }
+
+    /// These limits, with `defaults` for the resources they leave unlimited
+    #[must_use]
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            mem: self.mem.or(defaults.mem),
+            cpu: self.cpu.or(defaults.cpu),
+            nofile: self.nofile.or(defaults.nofile),
+            procs: self.procs.or(defaults.procs),
+        }
+    }
+
+    /// The limit on `resource` as it would be written in `@limit`
+    #[must_use]
+    pub fn describe(&self, resource: Resource) -> Option<String> {
+        let value = self.get(resource)?;
+        Some(match resource {
+            Resource::Mem => format_bytes(value),
+            Resource::Cpu => format!("{value}s"),
+            Resource::Nofile | Resource::Procs => value.to_string(),
+        })
+    }
+}
+
+impl FromStr for ResourceLimits {
+    type Err = String;
+
+    /// `mem=2G cpu=60s nofile=1024 procs=200`, in any order, with any of them left out
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut limits = Self::default();
+        for setting in text.split_whitespace() {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected name=value, got '{setting}'"))?;
+            let resource = Resource::ALL
+                .into_iter()
+                .find(|resource| resource.key() == key)
+                .ok_or_else(|| {
+                    format!("unknown limit '{key}' (expected mem, cpu, nofile or procs)")
+                })?;
+            let parsed = match resource {
+                Resource::Mem => parse_bytes(value),
+                Resource::Cpu => parse_seconds(value),
+                Resource::Nofile | Resource::Procs => value.parse().ok(),
+            };
+            *limits.slot(resource) = Some(
+                parsed
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| format!("invalid {key} limit '{value}'"))?,
+            );
+        }
+        if limits.is_empty() {
+            return Err("no limits given".to_string());
+        }
+        Ok(limits)
+    }
+}
+
+impl fmt::Display for ResourceLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let settings: Vec<String> = Resource::ALL
+            .iter()
+            .filter_map(|resource| {
+                Some(format!("{}={}", resource.key(), self.describe(*resource)?))
+            })
+            .collect();
+        f.write_str(&settings.join(" "))
+    }
+}
+
+/// Bytes, with an optional binary `K`, `M`, `G` or `T` suffix: `512M`, `2G`, `2GiB`
+fn parse_bytes(text: &str) -> Option<u64> {
+    let upper = text.to_ascii_uppercase();
+    let number = upper
+        .strip_suffix("IB")
+        .or_else(|| upper.strip_suffix('B'))
+        .unwrap_or(&upper);
+    let (digits, shift) = match number.char_indices().last()? {
+        (at, 'K') => (&number[..at], 10),
+        (at, 'M') => (&number[..at], 20),
+        (at, 'G') => (&number[..at], 30),
+        (at, 'T') => (&number[..at], 40),
+        _ => (number, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    for (suffix, shift) in [("T", 40), ("G", 30), ("M", 20), ("K", 10)] {
+        if bytes.trailing_zeros() >= shift {
+            return format!("{}{suffix}", bytes >> shift);
+        }
+    }
+    bytes.to_string()
+}
+
+/// Seconds, with an optional `s`, `m` or `h` suffix: `90`, `60s`, `5m`
+fn parse_seconds(text: &str) -> Option<u64> {
+    let (digits, scale) = match text.char_indices().last()? {
+        (at, 's') => (&text[..at], 1),
+        (at, 'm') => (&text[..at], 60),
+        (at, 'h') => (&text[..at], 3600),
+        _ => (text, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// Limits set up for a command about to be spawned. Keep it until the command has
+/// exited, then ask it which limit was exceeded.
+pub struct Confinement {
+    limits: ResourceLimits,
+    group: Option<cgroup::Group>,
+}
+
+/// Apply `limits` to `cmd` and everything it starts
+#[must_use]
+pub fn confine(cmd: &mut Command, limits: ResourceLimits) -> Confinement {
+    if limits.is_empty() {
+        return Confinement {
+            limits,
+            group: None,
+        };
+    }
+    let group = cgroup::Group::create(limits.mem, limits.procs);
+    if group.is_none()
+        && let Some(procs) = limits.procs
+    {
+        eprintln!(
+            "warning: procs={procs} is not enforced: it needs a cgroup v2 group with the pids controller"
+        );
+    }
+    #[cfg(unix)]
+    {
+        // The group covers memory and processes better than per-process limits do
+        let grouped = group.is_some();
+        let rlimits = ResourceLimits {
+            mem: limits.mem.filter(|_| !grouped),
+            procs: None,
+            ..limits
+        };
+        set_rlimits(
+            cmd,
+            rlimits,
+            group.as_ref().map(|group| group.procs_file().to_owned()),
+        );
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = cmd;
+        eprintln!("warning: resource limits are only enforced on Unix");
+    }
+    Confinement { limits, group }
+}
+
+impl Confinement {
+    /// The limit the command ran into, if it failed because of one: from the signal
+    /// that ended it (`status`) or the events the kernel counted in its group
+    #[must_use]
+    pub fn exceeded(&self, status: ExitStatus) -> Option<LimitViolation> {
+        if status.success() || self.limits.is_empty() {
+            return None;
+        }
+        let resource = self
+            .group
+            .as_ref()
+            .and_then(cgroup::Group::exceeded)
+            .or_else(|| exceeded_by(status, self.limits))?;
+        let limit = self.limits.describe(resource)?;
+        Some(LimitViolation {
+            resource,
+            message: format!("{} limit of {limit} exceeded", resource.noun()),
+            limit,
+        })
+    }
+}
+
+/// The limit among `limits`, set with `setrlimit`, that a command ending with `status`
+/// ran into, as far as the status tells
+fn exceeded_by(status: ExitStatus, limits: ResourceLimits) -> Option<Resource> {
+    // The kernel sends SIGXCPU at the limit; a shell reports a child it killed as 128 + N
+    #[cfg(unix)]
+    if limits.cpu.is_some()
+        && crate::exit::status_code(status) == crate::exit::SIGNAL_BASE + libc::SIGXCPU
+    {
+        return Some(Resource::Cpu);
+    }
+    #[cfg(not(unix))]
+    let _ = (status, limits);
+    None
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(all(unix, not(all(target_os = "linux", target_env = "gnu"))))]
+type RlimitResource = libc::c_int;
+
+/// Set `limits` in the child between fork and exec, after moving it into the cgroup
+/// whose `cgroup.procs` file is `join`. The child isn't started if that fails.
+#[cfg(unix)]
+fn set_rlimits(cmd: &mut Command, limits: ResourceLimits, join: Option<std::ffi::CString>) {
+    use std::os::unix::process::CommandExt;
+    let cpu = limits
+        .cpu
+        .map(|secs| (secs, secs.saturating_add(CPU_GRACE_SECS)));
+    // SAFETY: the closure only makes async-signal-safe calls (open, write, close,
+    // getrlimit, setrlimit) and doesn't allocate
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(procs_file) = &join {
+                join_group(procs_file)?;
+            }
+            if let Some(bytes) = limits.mem {
+                set_rlimit(libc::RLIMIT_DATA, bytes, bytes)?;
+            }
+            if let Some((soft, hard)) = cpu {
+                set_rlimit(libc::RLIMIT_CPU, soft, hard)?;
+            }
+            if let Some(files) = limits.nofile {
+                set_rlimit(libc::RLIMIT_NOFILE, files, files)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Lower the calling process's limit on `resource`; the hard limit can't be raised
+#[cfg(unix)]
+fn set_rlimit(resource: RlimitResource, soft: u64, hard: u64) -> std::io::Result<()> {
+    let mut current = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: getrlimit and setrlimit only access the struct they are given
+    unsafe {
+        if libc::getrlimit(resource, &raw mut current) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let hard = hard.min(current.rlim_max);
+        let limit = libc::rlimit {
+            rlim_cur: soft.min(hard),
+            rlim_max: hard,
+        };
+        if libc::setrlimit(resource, &raw const limit) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Move the calling process into the cgroup whose `cgroup.procs` file is `procs_file`
+#[cfg(unix)]
+fn join_group(procs_file: &std::ffi::CStr) -> std::io::Result<()> {
+    // SAFETY: `procs_file` is NUL-terminated and the byte written outlives the call
+    unsafe {
+        let fd = libc::open(procs_file.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // Writing 0 moves the process that writes it
+        let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+        let error = (written != 1).then(std::io::Error::last_os_error);
+        libc::close(fd);
+        error.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod cgroup {
+    use super::Resource;
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+    /// A cgroup v2 group of its own for one command, removed when dropped
+    pub(super) struct Group {
+        path: PathBuf,
+        procs_file: CString,
+    }
+
+    impl Group {
+        /// A new group below the one `run` is in, limiting memory to `mem` bytes and the
+        /// number of processes to `procs`. `None` when cgroup v2 isn't mounted, the
+        /// group can't be created, or a limit can't be set in it (when its controller
+        /// isn't enabled for the group).
+        pub(super) fn create(mem: Option<u64>, procs: Option<u64>) -> Option<Self> {
+            if mem.is_none() && procs.is_none() {
+                return None;
+            }
+            let own = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+            let own = own.lines().find_map(|line| line.strip_prefix("0::"))?;
+            let name = format!(
+                "run-{}-{}",
+                std::process::id(),
+                CREATED.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = mount()?.join(own.trim_start_matches('/')).join(name);
+            let procs_file = CString::new(path.join("cgroup.procs").as_os_str().as_bytes()).ok()?;
+            std::fs::create_dir(&path).ok()?;
+            let group = Self { path, procs_file };
+            if let Some(bytes) = mem {
+                group.write("memory.max", bytes)?;
+                // Swapping out would only slow the function down on its way to the limit
+                let _ = group.write("memory.swap.max", 0);
+            }
+            if let Some(processes) = procs {
+                group.write("pids.max", processes)?;
+            }
+            Some(group)
+        }
+
+        pub(super) fn procs_file(&self) -> &CStr {
+            &self.procs_file
+        }
+
+        /// The limit the kernel counted as hit in the group
+        pub(super) fn exceeded(&self) -> Option<Resource> {
+            if self.count("memory.events", "oom_kill") > 0 {
+                Some(Resource::Mem)
+            } else if self.count("pids.events", "max") > 0 {
+                Some(Resource::Procs)
+            } else {
+                None
+            }
+        }
+
+        fn write(&self, file: &str, value: u64) -> Option<()> {
+            std::fs::write(self.path.join(file), value.to_string()).ok()
+        }
+
+        /// A counter from one of the group's `*.events` files
+        fn count(&self, file: &str, key: &str) -> u64 {
+            std::fs::read_to_string(self.path.join(file))
+                .ok()
+                .and_then(|events| {
+                    events
+                        .lines()
+                        .find_map(|line| line.strip_prefix(key)?.strip_prefix(' ')?.parse().ok())
+                })
+                .unwrap_or(0)
+        }
+    }
+
+    impl Drop for Group {
+        fn drop(&mut self) {
+            // Processes left running in the background keep the group, and its limits
+            for _ in 0..10 {
+                if std::fs::remove_dir(&self.path).is_ok() {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    /// Where the cgroup v2 hierarchy is mounted
+    fn mount() -> Option<PathBuf> {
+        let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+        mounts.lines().find_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let path = fields.next()?;
+            (fields.next()? == "cgroup2").then(|| PathBuf::from(path))
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod cgroup {
+    use super::Resource;
+
+    /// Limits are only grouped on Linux
+    pub(super) enum Group {}
+
+    impl Group {
+        pub(super) fn create(_mem: Option<u64>, _procs: Option<u64>) -> Option<Self> {
+            None
+        }
+
+        #[cfg(unix)]
+        pub(super) fn procs_file(&self) -> &std::ffi::CStr {
+            match *self {}
+        }
+
+        pub(super) fn exceeded(&self) -> Option<Resource> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_limits() {
+        let limits: ResourceLimits = "mem=2G cpu=60s nofile=1024 procs=200".parse().unwrap();
+        assert_eq!(
+            limits,
+            ResourceLimits {
+                mem: Some(2 << 30),
+                cpu: Some(60),
+                nofile: Some(1024),
+                procs: Some(200),
+            }
+        );
+        assert_eq!(limits.to_string(), "mem=2G cpu=60s nofile=1024 procs=200");
+
+        let limits: ResourceLimits = "cpu=5m mem=512MiB".parse().unwrap();
+        assert_eq!(limits.cpu, Some(300));
+        assert_eq!(limits.describe(Resource::Mem).as_deref(), Some("512M"));
+        assert_eq!(limits.nofile, None);
+        assert_eq!(
+            "mem=1000".parse::<ResourceLimits>().unwrap().to_string(),
+            "mem=1000"
+        );
+    }
+
+    #[test]
+    fn test_parse_limits_rejects_mistakes() {
+        for (text, error) in [
+            ("", "no limits given"),
+            ("mem", "expected name=value, got 'mem'"),
+            (
+                "memory=2G",
+                "unknown limit 'memory' (expected mem, cpu, nofile or procs)",
+            ),
+            ("mem=2X", "invalid mem limit '2X'"),
+            ("cpu=0", "invalid cpu limit '0'"),
+            ("procs=-1", "invalid procs limit '-1'"),
+        ] {
+            assert_eq!(
+                text.parse::<ResourceLimits>(),
+                Err(error.to_string()),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_function_limits_override_defaults() {
+        let own: ResourceLimits = "mem=1G".parse().unwrap();
+        let defaults: ResourceLimits = "mem=4G cpu=60s".parse().unwrap();
+        assert_eq!(own.or(defaults).to_string(), "mem=1G cpu=60s");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exceeded_by_signal() {
+        use std::os::unix::process::ExitStatusExt;
+        let limits: ResourceLimits = "cpu=1 nofile=16".parse().unwrap();
+        let killed = ExitStatus::from_raw(libc::SIGXCPU);
+        assert_eq!(exceeded_by(killed, limits), Some(Resource::Cpu));
+        // A shell reporting its child killed by SIGXCPU
+        let reported = ExitStatus::from_raw((128 + libc::SIGXCPU) << 8);
+        assert_eq!(exceeded_by(reported, limits), Some(Resource::Cpu));
+
+        // A plain failure isn't put down to a limit, whatever the command said
+        let failed = ExitStatus::from_raw(1 << 8);
+        assert_eq!(exceeded_by(failed, limits), None);
+        // Only limits that were set count
+        let memory: ResourceLimits = "mem=1G".parse().unwrap();
+        assert_eq!(exceeded_by(killed, memory), None);
+    }
+}
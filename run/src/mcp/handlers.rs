@@ -3,9 +3,10 @@
 use super::mapping::map_arguments_to_positional;
 use super::mapping::resolve_tool_name;
 use super::tools::inspect;
-use crate::config;
+use crate::exit::Failure;
+use crate::source_map::SOURCE_MAP_ENV;
+use crate::{config, process};
 use serde::Serialize;
-use std::fmt::Write as _;
 use std::io::Read as _;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
@@ -80,6 +81,11 @@ pub(super) fn handle_tools_list(
 ) -> Result<serde_json::Value, JsonRpcError> {
     match inspect() {
         Ok(mut output) => {
+            // Tools/list has no field for diagnostics, so skipped items go to the server log
+            for warning in output.warnings.drain(..) {
+                eprintln!("warning: Runfile item skipped from tools list:\n{warning}");
+            }
+
             // Append built-in tools
             output.tools.extend(super::tools::get_builtin_tools());
 
@@ -106,8 +112,8 @@ pub(super) fn handle_tools_list(
 /// Returns `(runfile_path, temp_path_to_clean_up)`.  `temp_path_to_clean_up` is `Some`
 /// only when a temp file was created and must be removed after the subprocess exits.
 fn resolve_subprocess_runfile() -> Result<(PathBuf, Option<PathBuf>), JsonRpcError> {
-    let (merged_content, merge_metadata) =
-        config::load_merged_config().ok_or_else(|| JsonRpcError {
+    let (merged_content, merge_metadata, source_map) = config::load_merged_config_mapped()
+        .ok_or_else(|| JsonRpcError {
             code: -32603,
             message: "No Runfile found".to_string(),
             data: None,
@@ -124,6 +130,11 @@ fn resolve_subprocess_runfile() -> Result<(PathBuf, Option<PathBuf>), JsonRpcErr
             message: format!("Failed to write merged runfile: {e}"),
             data: None,
         })?;
+        // Failures are reported against the original files rather than the temp file.
+        // Losing the map only degrades those locations, so a write failure is ignored.
+        if let Ok(json) = serde_json::to_string(&source_map) {
+            let _ = std::fs::write(merged_source_map_path(&temp_path), json);
+        }
         Ok((temp_path.clone(), Some(temp_path)))
     } else {
         let runfile_path = config::find_runfile_path().ok_or_else(|| JsonRpcError {
@@ -135,6 +146,17 @@ fn resolve_subprocess_runfile() -> Result<(PathBuf, Option<PathBuf>), JsonRpcErr
     }
 }
 
+/// Location of the source map written alongside a temp merged Runfile
+fn merged_source_map_path(temp_path: &std::path::Path) -> PathBuf {
+    temp_path.with_extension("map.json")
+}
+
+/// Remove a temp merged Runfile and its source map
+fn remove_merged_runfile(temp_path: &std::path::Path) {
+    let _ = std::fs::remove_file(temp_path);
+    let _ = std::fs::remove_file(merged_source_map_path(temp_path));
+}
+
 /// Handle the built-in `run_docs` tool call.
 fn handle_run_docs(arguments: &serde_json::Value) -> serde_json::Value {
     let topic = arguments
@@ -224,8 +246,10 @@ fn join_reader(
 /// Run a command, optionally killing it after `timeout_secs` seconds.
 ///
 /// When `timeout_secs` is `None` the subprocess runs to completion with no time limit.
-/// When `Some(secs)` is provided the subprocess is killed and an error is returned if it
-/// does not exit within the allotted time.
+/// When `Some(secs)` is provided the subprocess is stopped and an error is returned if it
+/// does not exit within the allotted time. The subprocess runs in a process group of its
+/// own, which is stopped as a whole: `SIGTERM`, then `SIGKILL` after a grace period. The
+/// same happens when `run` itself is interrupted (see [`process::catch_interrupts`]).
 ///
 /// stdout and stderr are drained in background threads so that a subprocess producing
 /// more than the OS pipe buffer (~64 KB) never deadlocks waiting for a reader.
@@ -235,6 +259,7 @@ fn run_command_with_timeout(
 ) -> Result<std::process::Output, JsonRpcError> {
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
+    process::isolate(&mut cmd);
 
     let mut child = cmd.spawn().map_err(|e| JsonRpcError {
         code: -32603,
@@ -268,46 +293,60 @@ fn run_command_with_timeout(
         Ok(buf)
     });
 
-    if let Some(secs) = timeout_secs {
-        let deadline = Instant::now()
-            .checked_add(Duration::from_secs(secs))
-            .ok_or_else(|| JsonRpcError {
-                code: -32602,
-                message: format!("Invalid timeout: {secs} second(s) is too large"),
-                data: None,
-            })?;
-        loop {
-            match child.try_wait() {
-                Ok(Some(_)) => break, // process finished in time
-                Ok(None) if Instant::now() >= deadline => {
-                    let kill_error = child.kill().err();
-                    let wait_error = child.wait().err();
-                    // Reader threads see EOF once the child is killed; join to clean up.
-                    let _ = stdout_thread.join();
-                    let _ = stderr_thread.join();
-                    let mut msg = format!("Tool call timed out after {secs} second(s)");
-                    if let Some(e) = kill_error {
-                        let _ = write!(msg, " (kill failed: {e})");
-                    }
-                    if let Some(e) = wait_error {
-                        let _ = write!(msg, " (wait failed: {e})");
-                    }
-                    return Err(JsonRpcError {
-                        code: -32603,
-                        message: msg,
-                        data: None,
-                    });
+    let deadline = match timeout_secs {
+        Some(secs) => Some(
+            Instant::now()
+                .checked_add(Duration::from_secs(secs))
+                .ok_or_else(|| JsonRpcError {
+                    code: -32602,
+                    message: format!("Invalid timeout: {secs} second(s) is too large"),
+                    data: None,
+                })?,
+        ),
+        None => None,
+    };
+    loop {
+        let (mut msg, failure) = match child.try_wait() {
+            Ok(Some(_)) => break, // process finished in time
+            Ok(None) => match (process::interrupted(), timeout_secs) {
+                (Some(signal), _) => (
+                    format!("Tool call interrupted by signal {signal}"),
+                    Failure::command("run", None, Some(signal)),
+                ),
+                (None, Some(secs)) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                    let msg = format!("Tool call timed out after {secs} second(s)");
+                    let failure = Failure::timeout(msg.clone());
+                    (msg, failure)
                 }
-                Ok(None) => std::thread::sleep(Duration::from_millis(10)),
-                Err(e) => {
-                    return Err(JsonRpcError {
-                        code: -32603,
-                        message: format!("Failed to wait for tool process: {e}"),
-                        data: None,
-                    });
+                (None, _) => {
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
                 }
+            },
+            Err(e) => {
+                return Err(JsonRpcError {
+                    code: -32603,
+                    message: format!("Failed to wait for tool process: {e}"),
+                    data: None,
+                });
             }
+        };
+        // Stop the whole process group: the function's interpreter and anything it started
+        if process::terminate(&mut child, process::GRACE_PERIOD).is_none() {
+            msg.push_str(" (wait failed)");
         }
+        // Reader threads see EOF once the group is gone; join to clean up.
+        let _ = stdout_thread.join();
+        let _ = stderr_thread.join();
+        return Err(JsonRpcError {
+            code: -32603,
+            message: msg,
+            data: Some(serde_json::json!({
+                "exit_code": failure.code,
+                "exit_reason": failure.reason,
+                "signal": failure.signal,
+            })),
+        });
     }
 
     let status = child.wait().map_err(|e| JsonRpcError {
@@ -323,6 +362,66 @@ fn run_command_with_timeout(
     })
 }
 
+/// Add a tool call that didn't finish to the history, with the exit code it was given
+/// if it was stopped
+fn record_unfinished_call(
+    function: &str,
+    args: &[String],
+    runfile: Option<PathBuf>,
+    started: Instant,
+    exit_code: Option<i32>,
+) {
+    let mut call =
+        crate::history::Entry::new(function, args, crate::cli::OutputFormatArg::Markdown);
+    call.source = crate::history::Source::Mcp;
+    call.runfile = runfile;
+    call.runfile_hash = config::load_merged_config()
+        .map(|(content, _)| crate::history::hash_content(&content))
+        .unwrap_or_default();
+    call.duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+    call.exit_code = exit_code;
+    crate::history::record(&call);
+}
+
+/// Separate the built-in `timeout` parameter from the function's own arguments
+fn split_timeout_argument(
+    arguments: &serde_json::Value,
+) -> Result<(Option<u64>, serde_json::Value), JsonRpcError> {
+    // Extract the built-in timeout parameter before mapping arguments.
+    // It is a reserved MCP-level parameter and must never be forwarded to the
+    // underlying shell function as a positional argument.
+    // If the key is present but is not a valid non-negative integer, reject the call
+    // with -32602 (Invalid params) so client mistakes are surfaced rather than
+    // silently ignored.
+    let timeout_secs = match arguments.get(super::tools::TIMEOUT_PARAM) {
+        None | Some(serde_json::Value::Null) => None,
+        Some(v) => {
+            let secs = v.as_u64().ok_or_else(|| JsonRpcError {
+                code: -32602,
+                message: format!(
+                    "Invalid value for '{}': expected a non-negative integer, got {v}",
+                    super::tools::TIMEOUT_PARAM
+                ),
+                data: None,
+            })?;
+            Some(secs)
+        }
+    };
+
+    // Build a filtered argument object that excludes the built-in timeout key so
+    // it is not mistakenly mapped to a positional argument of the shell function.
+    // Preserve non-object arguments unchanged so downstream validation still
+    // returns the original "Arguments must be an object" error for invalid input.
+    let filtered_arguments = if let Some(obj) = arguments.as_object() {
+        let mut filtered_obj = obj.clone();
+        filtered_obj.remove(super::tools::TIMEOUT_PARAM);
+        serde_json::Value::Object(filtered_obj)
+    } else {
+        arguments.clone()
+    };
+    Ok((timeout_secs, filtered_arguments))
+}
+
 /// Handle tools/call request
 pub(super) fn handle_tools_call(
     params: Option<serde_json::Value>,
@@ -361,38 +460,7 @@ pub(super) fn handle_tools_call(
     let default_args = serde_json::json!({});
     let arguments = params_obj.get("arguments").unwrap_or(&default_args);
 
-    // Extract the built-in timeout parameter before mapping arguments.
-    // It is a reserved MCP-level parameter and must never be forwarded to the
-    // underlying shell function as a positional argument.
-    // If the key is present but is not a valid non-negative integer, reject the call
-    // with -32602 (Invalid params) so client mistakes are surfaced rather than
-    // silently ignored.
-    let timeout_secs = match arguments.get(super::tools::TIMEOUT_PARAM) {
-        None | Some(serde_json::Value::Null) => None,
-        Some(v) => {
-            let secs = v.as_u64().ok_or_else(|| JsonRpcError {
-                code: -32602,
-                message: format!(
-                    "Invalid value for '{}': expected a non-negative integer, got {v}",
-                    super::tools::TIMEOUT_PARAM
-                ),
-                data: None,
-            })?;
-            Some(secs)
-        }
-    };
-
-    // Build a filtered argument object that excludes the built-in timeout key so
-    // it is not mistakenly mapped to a positional argument of the shell function.
-    // Preserve non-object arguments unchanged so downstream validation still
-    // returns the original "Arguments must be an object" error for invalid input.
-    let filtered_arguments = if let Some(obj) = arguments.as_object() {
-        let mut filtered_obj = obj.clone();
-        filtered_obj.remove(super::tools::TIMEOUT_PARAM);
-        serde_json::Value::Object(filtered_obj)
-    } else {
-        arguments.clone()
-    };
+    let (timeout_secs, filtered_arguments) = split_timeout_argument(arguments)?;
 
     // Map arguments to positional (use resolved original function name)
     let positional_args = map_arguments_to_positional(&actual_function_name, &filtered_arguments)?;
@@ -413,6 +481,9 @@ pub(super) fn handle_tools_call(
     // When both ~/.runfile and a project Runfile exist, passing --runfile <project_path>
     // causes the subprocess to skip the merge and miss global functions.  Instead we
     // write the already-merged content to a temp file so the subprocess sees everything.
+    // Until the temp file is removed, a signal only stops the subprocess; it is delivered
+    // when this guard is dropped
+    let _interrupts = process::catch_interrupts();
     let (runfile_path, temp_merged_path) = resolve_subprocess_runfile()?;
 
     let mut cmd = Command::new(run_binary);
@@ -424,10 +495,14 @@ pub(super) fn handle_tools_call(
     cmd.arg(&runfile_path);
     // Use structured markdown output for better LLM readability
     cmd.arg("--output-format=markdown");
+    // The flags the server was started with apply to every tool call
+    cmd.args(config::run_flags());
 
-    // Pass MCP output directory to the subprocess via env so it writes to project .run-output
+    // Pass MCP output directory to the subprocess via env so it writes to project .run-output,
+    // where it records the call in the history as coming from an MCP client
     let mcp_output_dir = config::ensure_mcp_output_dir();
     cmd.env("RUN_MCP_OUTPUT_DIR", &mcp_output_dir);
+    cmd.env(crate::history::SOURCE_ENV, "mcp");
 
     // When a temp merged file is used, the subprocess would derive __RUNFILE_DIR__ from
     // the temp file location.  Pass the real project Runfile directory explicitly so that
@@ -438,21 +513,42 @@ pub(super) fn handle_tools_call(
     {
         cmd.env("RUN_RUNFILE_DIR", real_dir);
     }
+    if let Some(ref tp) = temp_merged_path {
+        cmd.env(SOURCE_MAP_ENV, merged_source_map_path(tp));
+    }
 
     cmd.arg(&actual_function_name); // Use the original function name with colons
+    // Everything after `--` is an argument of this function, never another call or flag
+    cmd.arg("--");
+    cmd.args(&positional_args);
 
-    for arg in positional_args {
-        cmd.arg(arg);
-    }
-
-    let output = run_command_with_timeout(cmd, timeout_secs)?;
+    let started = Instant::now();
+    let output = run_command_with_timeout(cmd, timeout_secs);
 
     // Clean up temp file if we created one.
     // Cleanup failure is non-critical — the OS will eventually reclaim the temp file —
     // so we intentionally ignore the result here.
     if let Some(ref tp) = temp_merged_path {
-        let _ = std::fs::remove_file(tp);
+        remove_merged_runfile(tp);
+    }
+
+    // A subprocess that was killed or never started didn't record itself
+    if let Err(e) = &output {
+        let runfile = temp_merged_path.is_none().then_some(runfile_path);
+        let exit_code = e
+            .data
+            .as_ref()
+            .and_then(|data| data["exit_code"].as_i64())
+            .and_then(|code| i32::try_from(code).ok());
+        record_unfinished_call(
+            &actual_function_name,
+            &positional_args,
+            runfile,
+            started,
+            exit_code,
+        );
     }
+    let output = output?;
 
     let stdout = String::from_utf8_lossy(&output.stdout).to_string();
     let stderr = String::from_utf8_lossy(&output.stderr).to_string();
@@ -11,20 +11,7 @@ use std::collections::HashMap;
 /// This is needed because MCP requires [a-zA-Z0-9_-] but we support colons in function names
 /// Uses merged global+project config to ensure all exposed tools are resolvable
 pub(super) fn resolve_tool_name(sanitised_name: &str) -> Result<String, JsonRpcError> {
-    let (config_content, _metadata) = config::load_merged_config().ok_or_else(|| JsonRpcError {
-        code: -32603,
-        message: "No Runfile found".to_string(),
-        data: None,
-    })?;
-
-    let program = parser::parse_script(&config_content).map_err(|e| JsonRpcError {
-        code: -32603,
-        message: format!(
-            "Runfile syntax error: {}",
-            parser::ParseError::from_pest(&e, &config_content, Some("Runfile"))
-        ),
-        data: None,
-    })?;
+    let program = load_merged_program()?;
     // Process in reverse order (project overrides global, like in inspect())
     let mut matching_name: Option<String> = None;
     for statement in program.statements.iter().rev() {
@@ -56,21 +43,29 @@ pub(super) fn resolve_tool_name(sanitised_name: &str) -> Result<String, JsonRpcE
     })
 }
 
+/// The merged global+project program for a tool call.
+///
+/// Tools/list skips items that fail to parse, but a call is refused while any remain:
+/// the broken item may be the tool itself, or a project override of a global function.
 fn load_merged_program() -> Result<Program, JsonRpcError> {
-    let (config_content, _metadata) = config::load_merged_config().ok_or_else(|| JsonRpcError {
-        code: -32603,
-        message: "No Runfile found".to_string(),
-        data: None,
-    })?;
-
-    parser::parse_script(&config_content).map_err(|e| JsonRpcError {
-        code: -32603,
-        message: format!(
-            "Runfile syntax error: {}",
-            parser::ParseError::from_pest(&e, &config_content, Some("Runfile"))
-        ),
-        data: None,
-    })
+    let (config_content, _metadata, source_map) =
+        config::load_merged_config_mapped().ok_or_else(|| JsonRpcError {
+            code: -32603,
+            message: "No Runfile found".to_string(),
+            data: None,
+        })?;
+
+    let (program, parse_errors) = parser::parse_runfile(&config_content, &source_map);
+    if !parse_errors.is_empty() {
+        return Err(JsonRpcError {
+            code: -32603,
+            message: parser::error::format_blocking_errors(&parse_errors),
+            data: Some(serde_json::json!({
+                "parse_errors": parse_errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            })),
+        });
+    }
+    Ok(program)
 }
 
 fn collect_arg_metadata(
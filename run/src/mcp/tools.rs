@@ -110,6 +110,9 @@ pub const DOCS: &[(&str, &str, &str)] = &[
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InspectOutput {
     pub tools: Vec<Tool>,
+    /// Runfile items skipped because they could not be parsed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
 }
 
 /// Returns the built-in tools provided by the MCP server itself
@@ -379,17 +382,17 @@ pub(super) fn extract_function_metadata(
 /// - The Runfile cannot be parsed (syntax errors)
 /// - The parser encounters an unexpected error
 pub fn inspect() -> Result<InspectOutput, String> {
-    let Some((config_content, _metadata)) = config::load_merged_config() else {
+    let Some((config_content, _metadata, source_map)) = config::load_merged_config_mapped() else {
         // No Runfile = no tools
-        return Ok(InspectOutput { tools: Vec::new() });
+        return Ok(InspectOutput {
+            tools: Vec::new(),
+            warnings: Vec::new(),
+        });
     };
 
-    let program = parser::parse_script(&config_content).map_err(|e| {
-        format!(
-            "Runfile syntax error: {}",
-            parser::ParseError::from_pest(&e, &config_content, Some("Runfile"))
-        )
-    })?;
+    // Broken items are skipped so the valid functions remain available as tools
+    let (program, parse_errors) = parser::parse_runfile(&config_content, &source_map);
+    let warnings = parse_errors.iter().map(ToString::to_string).collect();
 
     let mut tools = Vec::new();
     let mut seen_names = std::collections::HashSet::new();
@@ -425,7 +428,7 @@ pub fn inspect() -> Result<InspectOutput, String> {
     // Reverse to restore original order (since we processed in reverse)
     tools.reverse();
 
-    Ok(InspectOutput { tools })
+    Ok(InspectOutput { tools, warnings })
 }
 
 /// Print inspection output as JSON
@@ -0,0 +1,634 @@
+//! Running several functions in one invocation.
+//!
+//! `run --all build test lint` and `run --all build + test + lint` run each function in its
+//! own `run` subprocess, one after another or `--parallel N` at a time. In parallel,
+//! output lines are prefixed with the function they came from. Structured output
+//! formats combine the results into one [`StructuredResult`] with an entry per function.
+
+use crate::ast::{CommandOutput, OutputEvent, ShowScript, StructuredResult};
+use crate::cli::OutputFormatArg;
+use crate::exit::ExitReason;
+use crate::{config, process};
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Word separating calls: `run --all build + test --release + lint`
+pub const SEPARATOR: &str = "+";
+
+/// Word ending the calls: `run --all build + calc -- 2 + 3`
+pub const END_OF_CALLS: &str = "--";
+
+/// Colours cycled through for output prefixes (ANSI foreground codes)
+const PREFIX_COLOURS: &[u8] = &[36, 35, 33, 32, 34, 31];
+
+/// One function call: name and arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl Call {
+    /// The call as typed: `build --release`
+    #[must_use]
+    pub fn display(&self) -> String {
+        std::iter::once(self.name.as_str())
+            .chain(self.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// What to do when a function fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// Stop the others and start nothing new
+    FailFast,
+    /// Run everything and report all failures at the end
+    KeepGoing,
+}
+
+/// How to run a list of calls
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Maximum number of functions running at once
+    pub parallel: usize,
+    pub policy: FailurePolicy,
+    pub output_format: OutputFormatArg,
+    pub show_script: ShowScript,
+}
+
+/// Split a command line into calls.
+///
+/// Only with `all`: calls are separated by a standalone `+`, or each word is a function
+/// called without arguments when there is none. A `--` ends the calls; the words after
+/// it are arguments of the last one. Returns `Ok(None)` for an ordinary single call.
+///
+/// # Errors
+///
+/// Returns `Err` if a `+` has no function on one of its sides.
+pub fn parse_calls(first: &str, args: &[String], all: bool) -> Result<Option<Vec<Call>>, String> {
+    if !all {
+        return Ok(None);
+    }
+    let words: Vec<&str> = std::iter::once(first)
+        .chain(args.iter().map(String::as_str))
+        .collect();
+    let (words, rest) = match words.iter().position(|word| *word == END_OF_CALLS) {
+        Some(end) => (&words[..end], &words[end + 1..]),
+        None => (&words[..], &[][..]),
+    };
+
+    let mut calls = Vec::new();
+    if words.contains(&SEPARATOR) {
+        for call in words.split(|word| *word == SEPARATOR) {
+            let Some((name, args)) = call.split_first() else {
+                return Err(format!(
+                    "expected a function name on both sides of `{SEPARATOR}`"
+                ));
+            };
+            calls.push(Call {
+                name: (*name).to_string(),
+                args: args.iter().map(ToString::to_string).collect(),
+            });
+        }
+    } else {
+        calls.extend(words.iter().map(|name| Call {
+            name: (*name).to_string(),
+            args: Vec::new(),
+        }));
+    }
+    if let Some(last) = calls.last_mut() {
+        last.args.extend(rest.iter().map(ToString::to_string));
+    }
+    Ok(Some(calls))
+}
+
+/// How a finished call ended
+struct Finished {
+    status: Option<ExitStatus>,
+    stdout: String,
+    stderr: String,
+    started_at: u128,
+    duration: Duration,
+}
+
+/// A call that has been started
+struct Running {
+    index: usize,
+    child: Child,
+    started: Instant,
+    started_at: u128,
+    /// Output pipes that haven't reached end of file yet
+    open_pipes: usize,
+    status: Option<ExitStatus>,
+    killed: bool,
+    /// When a stopped call gets `SIGKILL` if it hasn't exited
+    kill_at: Option<Instant>,
+    stdout: String,
+    stderr: String,
+}
+
+/// Output from a call's pipes
+enum Event {
+    Line {
+        index: usize,
+        stderr: bool,
+        line: String,
+    },
+    Closed {
+        index: usize,
+    },
+}
+
+/// Run `calls` and return the exit code for the whole invocation.
+#[must_use]
+pub fn run_calls(calls: &[Call], options: Options) -> i32 {
+    let structured = matches!(
+        options.output_format.mode(),
+        crate::ast::OutputMode::Structured
+    );
+    // One at a time with nothing to combine: let each function use the terminal directly
+    let piped = structured || options.parallel > 1;
+    let prefixes = prefixes(calls, std::io::stdout().is_terminal() && colour_enabled());
+
+    // Ctrl+C stops the calls (and everything they started) before `run` exits
+    let _interrupts = process::catch_interrupts();
+    let wall_clock = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    let mut finished: Vec<Option<Finished>> = calls.iter().map(|_| None).collect();
+    let mut running: Vec<Running> = Vec::new();
+    let mut next = 0;
+    let mut failed = false;
+
+    loop {
+        let stop_starting = process::interrupted().is_some()
+            || (failed && options.policy == FailurePolicy::FailFast);
+        while !stop_starting && running.len() < options.parallel && next < calls.len() {
+            match start(next, &calls[next], options, piped, &sender) {
+                Ok(started) => running.push(started),
+                Err(e) => {
+                    eprintln!("error: could not start `{}`: {e}", calls[next].display());
+                    failed = true;
+                }
+            }
+            next += 1;
+        }
+        if running.is_empty() {
+            break;
+        }
+
+        // Pass on output for a moment, then check for exits
+        let deadline = Instant::now() + Duration::from_millis(20);
+        while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+            match receiver.recv_timeout(wait) {
+                Ok(event) => handle_event(event, &mut running, &prefixes, structured),
+                Err(_) => break,
+            }
+        }
+        for call in &mut running {
+            if call.status.is_none() {
+                call.status = call.child.try_wait().ok().flatten();
+            }
+        }
+
+        let mut idx = 0;
+        while idx < running.len() {
+            let call = &running[idx];
+            // A killed call's grandchildren may hold its pipes open; don't wait for them
+            if call.status.is_none() || (call.open_pipes > 0 && !call.killed) {
+                idx += 1;
+                continue;
+            }
+            let mut call = running.remove(idx);
+            if call.killed {
+                // Whatever it started in the background goes too
+                process::kill(&mut call.child);
+            }
+            let success = call.status.is_some_and(|s| s.success());
+            if !success && !call.killed {
+                failed = true;
+            }
+            finished[call.index] = Some(Finished {
+                status: call.status,
+                stdout: call.stdout,
+                stderr: call.stderr,
+                started_at: call.started_at,
+                duration: call.started.elapsed(),
+            });
+        }
+
+        let stop_all = process::interrupted().is_some()
+            || (failed && options.policy == FailurePolicy::FailFast);
+        for call in &mut running {
+            if stop_all && !call.killed {
+                process::stop(&call.child);
+                call.killed = true;
+                call.kill_at = Some(Instant::now() + process::GRACE_PERIOD);
+            }
+            if call.kill_at.is_some_and(|at| Instant::now() >= at) {
+                process::kill(&mut call.child);
+                call.kill_at = None;
+            }
+        }
+    }
+
+    report(calls, finished, options, wall_clock.elapsed())
+}
+
+/// Start one call as a `run` subprocess
+fn start(
+    index: usize,
+    call: &Call,
+    options: Options,
+    piped: bool,
+    sender: &mpsc::Sender<Event>,
+) -> std::io::Result<Running> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    if let Some(path) = config::get_custom_runfile_path() {
+        cmd.arg("--working-dir").arg(path);
+    }
+    if matches!(
+        options.output_format.mode(),
+        crate::ast::OutputMode::Structured
+    ) {
+        cmd.arg("--output-format=json");
+    }
+    cmd.args(config::run_flags());
+    match options.show_script {
+        ShowScript::Off => {}
+        ShowScript::Plain => {
+            cmd.arg("--show-script");
+        }
+        ShowScript::Annotated => {
+            cmd.args(["--show-script", "--annotate"]);
+        }
+    }
+    cmd.arg(&call.name).arg("--").args(&call.args);
+    if piped {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        // Nothing reads the terminal, so the call can have a process group to stop
+        process::isolate(&mut cmd);
+    }
+
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let mut child = cmd.spawn()?;
+    let mut open_pipes = 0;
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(index, false, stdout, sender.clone());
+        open_pipes += 1;
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(index, true, stderr, sender.clone());
+        open_pipes += 1;
+    }
+    Ok(Running {
+        index,
+        child,
+        started: Instant::now(),
+        started_at,
+        open_pipes,
+        status: None,
+        killed: false,
+        kill_at: None,
+        stdout: String::new(),
+        stderr: String::new(),
+    })
+}
+
+/// Send each line read from `pipe` to `sender`, then a `Closed` event
+fn forward_lines(
+    index: usize,
+    stderr: bool,
+    pipe: impl Read + Send + 'static,
+    sender: mpsc::Sender<Event>,
+) {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buf = Vec::new();
+        while reader.read_until(b'\n', &mut buf).is_ok_and(|n| n > 0) {
+            let line = String::from_utf8_lossy(&buf).into_owned();
+            if sender
+                .send(Event::Line {
+                    index,
+                    stderr,
+                    line,
+                })
+                .is_err()
+            {
+                return;
+            }
+            buf.clear();
+        }
+        let _ = sender.send(Event::Closed { index });
+    });
+}
+
+/// Print a line with its call's prefix, or keep it for the structured result
+fn handle_event(event: Event, running: &mut [Running], prefixes: &[String], structured: bool) {
+    let (index, stderr, line) = match event {
+        Event::Closed { index } => {
+            if let Some(call) = running.iter_mut().find(|c| c.index == index) {
+                call.open_pipes = call.open_pipes.saturating_sub(1);
+            }
+            return;
+        }
+        Event::Line {
+            index,
+            stderr,
+            line,
+        } => (index, stderr, line),
+    };
+    let Some(call) = running.iter_mut().find(|c| c.index == index) else {
+        return;
+    };
+    if structured {
+        let buffer = if stderr {
+            &mut call.stderr
+        } else {
+            &mut call.stdout
+        };
+        buffer.push_str(&line);
+        return;
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let prefix = &prefixes[index];
+    if stderr {
+        let _ = writeln!(std::io::stderr().lock(), "{prefix} {line}");
+    } else {
+        let _ = writeln!(std::io::stdout().lock(), "{prefix} {line}");
+    }
+}
+
+/// `[build]`-style prefixes padded to the same width, coloured if `colour`
+fn prefixes(calls: &[Call], colour: bool) -> Vec<String> {
+    let width = calls.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    calls
+        .iter()
+        .enumerate()
+        .map(|(idx, call)| {
+            let label = format!("[{}]{}", call.name, " ".repeat(width - call.name.len()));
+            if colour {
+                let code = PREFIX_COLOURS[idx % PREFIX_COLOURS.len()];
+                format!("\x1b[{code}m{label}\x1b[0m")
+            } else {
+                label
+            }
+        })
+        .collect()
+}
+
+/// Whether coloured output is allowed (<https://no-color.org>)
+fn colour_enabled() -> bool {
+    std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+}
+
+/// Print the outcome and work out the exit code
+fn report(
+    calls: &[Call],
+    finished: Vec<Option<Finished>>,
+    options: Options,
+    wall_clock: Duration,
+) -> i32 {
+    let structured = matches!(
+        options.output_format.mode(),
+        crate::ast::OutputMode::Structured
+    );
+    let mut exit_code = 0;
+    let mut outputs = Vec::new();
+    let mut results = Vec::new();
+    let mut not_run = Vec::new();
+    for (call, finished) in calls.iter().zip(finished) {
+        let Some(finished) = finished else {
+            not_run.push(call.display());
+            continue;
+        };
+        let code = finished.status.and_then(|s| s.code());
+        let signal = finished.status.and_then(crate::exit::signal_of);
+        if code != Some(0) && exit_code == 0 {
+            exit_code = finished.status.map_or(1, crate::exit::status_code);
+        }
+        if code != Some(0) && !structured {
+            match code {
+                Some(code) => eprintln!("error: `{}` failed (exit code {code})", call.display()),
+                None => eprintln!("error: `{}` was stopped", call.display()),
+            }
+        }
+        let child_result: Option<StructuredResult> = serde_json::from_str(&finished.stdout).ok();
+        let (stdout, stderr) = match &child_result {
+            Some(result) => (
+                result.outputs.iter().map(|o| o.stdout.as_str()).collect(),
+                result
+                    .outputs
+                    .iter()
+                    .map(|o| o.stderr.as_str())
+                    .chain(std::iter::once(finished.stderr.as_str()))
+                    .collect(),
+            ),
+            None => (finished.stdout, finished.stderr),
+        };
+        // The steps' timelines, timed from the start of this call
+        let events = child_result
+            .iter()
+            .flat_map(|result| &result.outputs)
+            .flat_map(|output| {
+                output.events.iter().map(move |event| OutputEvent {
+                    at_ms: (output.started_at + event.at_ms).saturating_sub(finished.started_at),
+                    ..event.clone()
+                })
+            })
+            .collect();
+        let steps = child_result
+            .iter()
+            .flat_map(|result| &result.outputs)
+            .flat_map(|output| output.steps.iter().cloned())
+            .collect();
+        let sandbox_denied = child_result
+            .iter()
+            .flat_map(|result| &result.outputs)
+            .flat_map(|output| output.sandbox_denied.iter().cloned())
+            .collect();
+        outputs.push(CommandOutput {
+            command: call.display(),
+            stdout,
+            stderr,
+            exit_code: code,
+            signal,
+            duration_ms: finished.duration.as_millis(),
+            started_at: finished.started_at,
+            events,
+            steps,
+            sandbox_denied,
+        });
+        results.push(child_result);
+    }
+    if exit_code == 0 && !not_run.is_empty() {
+        exit_code = 1;
+    }
+    if !not_run.is_empty() && !structured {
+        eprintln!("error: not run after a failure: {}", not_run.join(", "));
+    }
+
+    if !structured {
+        return exit_code;
+    }
+    let mut result = combined_result(calls, outputs, &results, &not_run, wall_clock);
+    result.exit_code = Some(exit_code);
+    if exit_code != 0 {
+        result.exit_reason = Some(first_failure_reason(&result.outputs, &results));
+        result.limit_exceeded = results
+            .iter()
+            .flatten()
+            .find_map(|r| r.limit_exceeded.clone());
+    }
+    if let Some(formatted) = options.output_format.format_result(&result) {
+        println!("{formatted}");
+    }
+    exit_code
+}
+
+/// Why the first call that failed did, which decides the exit code
+fn first_failure_reason(
+    outputs: &[CommandOutput],
+    results: &[Option<StructuredResult>],
+) -> ExitReason {
+    let Some((output, result)) = outputs
+        .iter()
+        .zip(results)
+        .find(|(output, _)| output.exit_code != Some(0))
+    else {
+        // Nothing failed by itself; the rest were stopped, e.g. by an interrupt
+        return ExitReason::Error;
+    };
+    match result.as_ref().and_then(|r| r.exit_reason) {
+        Some(reason) => reason,
+        None if output.signal.is_some() => ExitReason::Signal,
+        None => ExitReason::Exited,
+    }
+}
+
+/// One [`StructuredResult`] for all calls, with an output entry per function run
+fn combined_result(
+    calls: &[Call],
+    outputs: Vec<CommandOutput>,
+    results: &[Option<StructuredResult>],
+    not_run: &[String],
+    wall_clock: Duration,
+) -> StructuredResult {
+    let name = calls
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect::<Vec<_>>()
+        .join(" + ");
+    let mut interpreters: Vec<&str> = Vec::new();
+    for result in results.iter().flatten() {
+        if !interpreters.contains(&result.context.interpreter.as_str()) {
+            interpreters.push(&result.context.interpreter);
+        }
+    }
+    let ran = outputs.len();
+    let failed = outputs.iter().filter(|o| o.exit_code != Some(0)).count();
+
+    let mut result = StructuredResult::from_outputs(&name, outputs, &interpreters.join(", "));
+    result.success = result.success && not_run.is_empty();
+    result.total_duration_ms = wall_clock.as_millis();
+    result.failure = results.iter().flatten().find_map(|r| r.failure.clone());
+    result.summary = match (failed, not_run.len()) {
+        (0, 0) => format!("Successfully ran {ran} function(s)"),
+        (_, 0) => format!("{failed} of {ran} function(s) failed"),
+        _ => format!(
+            "{failed} of {ran} function(s) failed; not run: {}",
+            not_run.join(", ")
+        ),
+    };
+    result
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(ToString::to_string).collect()
+    }
+
+    fn call(name: &str, args: &[&str]) -> Call {
+        Call {
+            name: name.to_string(),
+            args: words(args),
+        }
+    }
+
+    #[test]
+    fn test_parse_calls_single_call() {
+        assert_eq!(
+            parse_calls("build", &words(&["test", "lint"]), false),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_parse_calls_all() {
+        assert_eq!(
+            parse_calls("build", &words(&["test", "lint"]), true),
+            Ok(Some(vec![
+                call("build", &[]),
+                call("test", &[]),
+                call("lint", &[])
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_calls_separator() {
+        assert_eq!(
+            parse_calls(
+                "build",
+                &words(&["--release", "+", "docker", "push", "+", "lint"]),
+                true
+            ),
+            Ok(Some(vec![
+                call("build", &["--release"]),
+                call("docker", &["push"]),
+                call("lint", &[]),
+            ]))
+        );
+        assert!(parse_calls("build", &words(&["+"]), true).is_err());
+        assert!(parse_calls("build", &words(&["+", "+", "lint"]), true).is_err());
+    }
+
+    #[test]
+    fn test_parse_calls_separator_needs_all() {
+        assert_eq!(
+            parse_calls("calc", &words(&["2", "+", "3"]), false),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_parse_calls_end_of_calls() {
+        assert_eq!(
+            parse_calls("build", &words(&["+", "calc", "--", "2", "+", "3"]), true),
+            Ok(Some(vec![
+                call("build", &[]),
+                call("calc", &["2", "+", "3"]),
+            ]))
+        );
+        assert_eq!(
+            parse_calls("build", &words(&["lint", "--", "+"]), true),
+            Ok(Some(vec![call("build", &[]), call("lint", &["+"])]))
+        );
+    }
+
+    #[test]
+    fn test_prefixes_padded() {
+        let calls = [call("build", &[]), call("ci", &[])];
+        assert_eq!(prefixes(&calls, false), vec!["[build]", "[ci]   "]);
+        assert_eq!(prefixes(&calls, true)[0], "\x1b[36m[build]\x1b[0m");
+    }
+}
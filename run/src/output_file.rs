@@ -10,12 +10,14 @@
 use crate::config;
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 // Character budget targeting ~300 tokens at ~4 chars/token
-const OUTPUT_TRUNCATE_CHARS: usize = 1200;
+pub const OUTPUT_TRUNCATE_CHARS: usize = 1200;
+// Start of the note heading truncated output
+const TRUNCATED_PREFIX: &str = "[Output truncated: ";
 static OUTPUT_SEQ: AtomicU64 = AtomicU64::new(0);
 
 /// Result of processing command output for MCP mode
@@ -54,6 +56,31 @@ pub fn process_output_for_mcp(
         });
     }
 
+    let file_path = new_output_path(stream_label, "txt")?;
+
+    // Write full output to file
+    let mut file = fs::File::create(&file_path)?;
+    file.write_all(output.as_bytes())?;
+
+    let display_output = truncated_view(output, total_bytes, &file_path, OUTPUT_TRUNCATE_CHARS);
+
+    Ok(ProcessedOutput {
+        display_output,
+        file_path: Some(file_path),
+        total_bytes,
+    })
+}
+
+/// A new, unique path in the output directory, which is created if needed
+///
+/// # Errors
+///
+/// Returns `Err` if the system time cannot be determined or the directory cannot be
+/// created.
+pub fn new_output_path(
+    stream_label: &str,
+    extension: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
     // Generate unique filename based on timestamp and a monotonic counter to avoid collisions
     let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
     let sequence = OUTPUT_SEQ.fetch_add(1, Ordering::Relaxed);
@@ -66,14 +93,15 @@ pub fn process_output_for_mcp(
     // Replace colons with hyphens for Windows compatibility
     let prefix = config::get_mcp_function_name()
         .map_or_else(|| "run-output".to_string(), |n| n.replace(':', "__"));
-    let file_path = output_dir.join(format!(
-        "{prefix}-{timestamp}-{stream_label}-{sequence}.txt"
-    ));
-
-    // Write full output to file
-    let mut file = fs::File::create(&file_path)?;
-    file.write_all(output.as_bytes())?;
+    Ok(output_dir.join(format!(
+        "{prefix}-{timestamp}-{stream_label}-{sequence}.{extension}"
+    )))
+}
 
+/// The end of `output` that fits in `budget` bytes, headed by a note that the full
+/// `total_bytes` are in `file_path`
+#[must_use]
+pub fn truncated_view(output: &str, total_bytes: usize, file_path: &Path, budget: usize) -> String {
     // Extract tail content: walk backwards through lines, accumulating bytes
     let lines: Vec<&str> = output.lines().collect();
     let mut accumulated = 0usize;
@@ -81,7 +109,7 @@ pub fn process_output_for_mcp(
 
     for (i, line) in lines.iter().enumerate().rev() {
         let line_cost = line.len() + 1; // +1 for newline separator
-        if accumulated + line_cost > OUTPUT_TRUNCATE_CHARS {
+        if accumulated + line_cost > budget {
             break;
         }
         accumulated += line_cost;
@@ -94,27 +122,34 @@ pub fn process_output_for_mcp(
         let bytes = tail.len();
         (tail, bytes)
     } else {
-        // Single last line exceeds the budget — take the last OUTPUT_TRUNCATE_CHARS bytes
+        // Single last line exceeds the budget — take the last `budget` bytes
         let last = lines.last().unwrap_or(&"");
-        let start = last.len().saturating_sub(OUTPUT_TRUNCATE_CHARS);
+        let mut start = last.len().saturating_sub(budget);
+        while !last.is_char_boundary(start) {
+            start += 1;
+        }
         let snippet = &last[start..];
         let tail = format!("...{snippet}");
         let bytes = tail.len();
         (tail, bytes)
     };
 
-    let display_output = format!(
-        "[Output truncated: {total_bytes} bytes, showing last {shown_bytes} bytes]\n\
+    format!(
+        "{TRUNCATED_PREFIX}{total_bytes} bytes, showing last {shown_bytes} bytes]\n\
          [Full output saved to: {}]\n\n\
          {truncated_output}",
         file_path.display(),
-    );
+    )
+}
 
-    Ok(ProcessedOutput {
-        display_output,
-        file_path: Some(file_path),
-        total_bytes,
-    })
+/// The note at the start of a [`truncated_view`], including its newline, if `display`
+/// is one
+#[must_use]
+pub fn truncation_header(display: &str) -> Option<&str> {
+    if !display.starts_with(TRUNCATED_PREFIX) {
+        return None;
+    }
+    display.find("\n\n").map(|end| &display[..=end])
 }
 
 /// Check if MCP output directory is configured (indicating MCP mode)
@@ -201,4 +236,14 @@ mod tests {
         // The shown portion should be around OUTPUT_TRUNCATE_CHARS + 3 ("..." prefix)
         assert!(tail_part.len() <= OUTPUT_TRUNCATE_CHARS + 4);
     }
+
+    #[test]
+    fn test_truncated_view_cuts_on_a_char_boundary() {
+        let output = "é".repeat(100);
+        let view = truncated_view(&output, 1_000_000, Path::new("/tmp/out.log"), 51);
+
+        assert!(view.starts_with("[Output truncated: 1000000 bytes, showing last 53 bytes]\n"));
+        assert!(view.contains("[Full output saved to: /tmp/out.log]"));
+        assert!(view.ends_with(&format!("...{}", "é".repeat(25))));
+    }
 }
@@ -2,15 +2,29 @@
 //!
 //! Handles parsing of @ directives like @os, @shell, @desc, and @arg
 
-use crate::ast::{ArgMetadata, ArgType, Attribute, OsPlatform, ShellType};
+use crate::ast::{ArgMetadata, ArgType, Attribute, ExecMode, OsPlatform, ShellType, SiblingScope};
 
-/// Parse attributes from lines of the original input
-pub(super) fn parse_attributes_from_lines(input: &str, line_num: usize) -> Vec<Attribute> {
+/// An attribute line that is recognised but can't be used
+pub(super) struct AttributeError {
+    /// Index of the line in the input (0-based)
+    pub line: usize,
+    pub message: String,
+}
+
+/// Parse attributes from lines of the original input.
+///
+/// `declared` holds the interpreter names declared with `# @interpreter` in the whole
+/// input; `@shell` must name one of them or an interpreter the registry knows.
+pub(super) fn parse_attributes_from_lines(
+    input: &str,
+    line_num: usize,
+    declared: &[String],
+) -> Result<Vec<Attribute>, AttributeError> {
     let mut attributes = Vec::new();
     let lines: Vec<&str> = input.lines().collect();
 
     if line_num == 0 {
-        return attributes;
+        return Ok(attributes);
     }
 
     // Look backward from the function definition line to collect attributes
@@ -31,6 +45,10 @@ pub(super) fn parse_attributes_from_lines(input: &str, line_num: usize) -> Vec<A
         // If it's an attribute comment, parse it
         if line.starts_with("# @") || line.starts_with("#@") {
             if let Some(attr) = parse_attribute_line(line) {
+                if let Attribute::Shell(shell) = &attr {
+                    check_interpreter(shell, declared)
+                        .map_err(|message| AttributeError { line: i, message })?;
+                }
                 attributes.push(attr);
             }
         } else if line.starts_with('#') {
@@ -47,7 +65,19 @@ pub(super) fn parse_attributes_from_lines(input: &str, line_num: usize) -> Vec<A
 
     // Reverse since we collected them backward
     attributes.reverse();
-    attributes
+    Ok(attributes)
+}
+
+/// Reject an `@shell` naming an interpreter nobody knows, instead of guessing how to run it
+fn check_interpreter(shell: &ShellType, declared: &[String]) -> Result<(), String> {
+    let program = shell.name().split_whitespace().next().unwrap_or_default();
+    if crate::registry::is_known(program, declared) {
+        return Ok(());
+    }
+    Err(format!(
+        "unknown interpreter '{program}' in @shell; \
+         declare it with `# @interpreter {program} command=...`"
+    ))
 }
 
 /// Strip surrounding quotes from a string
@@ -82,6 +112,26 @@ fn parse_attribute_line(line: &str) -> Option<Attribute> {
         return Some(Attribute::Noerrexit);
     }
 
+    // Handle @default - the function bare `run` executes
+    if without_hash.trim() == "default" {
+        return Some(Attribute::Default);
+    }
+
+    // Handle @init - runs before any invoked function
+    if without_hash.trim() == "init" {
+        return Some(Attribute::Init);
+    }
+
+    // Handle @trace - record each top-level statement as a step
+    if without_hash.trim() == "trace" {
+        return Some(Attribute::Trace);
+    }
+
+    // Handle bare @sandbox - run in the default sandbox
+    if without_hash.trim() == "sandbox" {
+        return Some(Attribute::Sandbox(crate::sandbox::SandboxPaths::default()));
+    }
+
     // Handle @arg - format: "1:name type description"
     if let Some(arg_text) = without_hash.strip_prefix("arg ") {
         return parse_arg_attribute(arg_text);
@@ -104,19 +154,30 @@ fn parse_attribute_line(line: &str) -> Option<Attribute> {
             };
             Some(Attribute::Os(platform))
         }
-        "shell" => {
-            let shell = match parts[1] {
-                "python" => ShellType::Python,
-                "python3" => ShellType::Python3,
-                "node" => ShellType::Node,
-                "ruby" => ShellType::Ruby,
-                "pwsh" => ShellType::Pwsh,
-                "bash" => ShellType::Bash,
-                "sh" => ShellType::Sh,
+        // An interpreter name or command line such as `uv run --script`, looked up in
+        // the interpreter registry
+        "shell" => Some(Attribute::Shell(ShellType::new(parts[1..].join(" ")))),
+        "exec" => {
+            let mode = match parts[1] {
+                "file" => ExecMode::File,
+                "inline" => ExecMode::Inline,
+                _ => return None,
+            };
+            Some(Attribute::Exec(mode))
+        }
+        "siblings" => {
+            let scope = match parts[1] {
+                "used" => SiblingScope::Used,
+                "all" => SiblingScope::All,
                 _ => return None,
             };
-            Some(Attribute::Shell(shell))
+            Some(Attribute::Siblings(scope))
         }
+        "limit" => parts[1..].join(" ").parse().ok().map(Attribute::Limit),
+        "sandbox" => parts[1..].join(" ").parse().ok().map(Attribute::Sandbox),
+        "sources" => Some(Attribute::Sources(
+            parts[1..].iter().map(|glob| strip_quotes(glob)).collect(),
+        )),
         _ => None,
     }
 }
@@ -3,7 +3,7 @@
 //! Converts raw pest parser errors into structured, human-readable diagnostics
 //! with source context, precise column indicators, and actionable hints.
 
-use std::fmt;
+use std::fmt::{self, Write as _};
 
 use super::Rule;
 
@@ -78,6 +78,63 @@ impl ParseError {
     }
 }
 
+impl ParseError {
+    /// Point the error at the file and line `map` says its line came from, for errors
+    /// reported against merged or `source`-expanded Runfile content.
+    pub fn relocate(&mut self, map: &crate::source_map::SourceMap) {
+        if let Some((file, line)) = map.describe(self.line) {
+            self.filename = Some(file);
+            self.line = line;
+        }
+    }
+}
+
+/// Format the errors from a recovered parse as a warning that the affected items
+/// were skipped, followed by each diagnostic.
+#[must_use]
+pub fn format_skipped_items(errors: &[ParseError]) -> String {
+    let mut out = if errors.len() == 1 {
+        "warning: 1 Runfile item could not be parsed and was skipped\n".to_string()
+    } else {
+        format!(
+            "warning: {} Runfile items could not be parsed and were skipped\n",
+            errors.len()
+        )
+    };
+    for error in errors {
+        out.push('\n');
+        out.push_str(&error.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+/// Say why a Runfile with syntax errors is not run.
+#[must_use]
+pub fn blocking_summary(errors: &[ParseError]) -> String {
+    let count = if errors.len() == 1 {
+        "a syntax error".to_string()
+    } else {
+        format!("{} syntax errors", errors.len())
+    };
+    format!(
+        "the Runfile has {count}; fix it before running a function \
+         (`run --list` still shows the valid ones)"
+    )
+}
+
+/// Format the errors that stop a Runfile from being run: each diagnostic, followed by
+/// the [`blocking_summary`].
+#[must_use]
+pub fn format_blocking_errors(errors: &[ParseError]) -> String {
+    let mut out = String::new();
+    for error in errors {
+        let _ = writeln!(out, "{error}");
+    }
+    let _ = writeln!(out, "error: {}", blocking_summary(errors));
+    out
+}
+
 /// Return a short, user-facing label for a grammar rule, or `None` to omit it.
 ///
 /// Returning `None` suppresses the rule from user-visible messages (e.g. `EOI`
@@ -7,11 +7,12 @@ mod attributes;
 mod block;
 pub mod error;
 mod preprocessing;
+mod recovery;
 mod shebang;
 
 pub use error::ParseError;
 
-use crate::ast::{Expression, Program, Statement};
+use crate::ast::{Attribute, Expression, Program, SourceLocation, Statement};
 use pest::Parser;
 use pest_derive::Parser;
 
@@ -29,8 +30,18 @@ pub struct ScriptParser;
 /// - Malformed attribute directives
 /// - Unmatched braces or parentheses
 /// - Invalid command syntax
+/// - `@shell` naming an unknown interpreter
 pub fn parse_script(input: &str) -> Result<Program, Box<pest::error::Error<Rule>>> {
+    parse_with_interpreters(input, &crate::settings::declared_interpreter_names(input))
+}
+
+/// [`parse_script`], with `@shell` also accepting the `declared` interpreter names
+fn parse_with_interpreters(
+    input: &str,
+    declared: &[String],
+) -> Result<Program, Box<pest::error::Error<Rule>>> {
     let preprocessed = preprocessing::preprocess_escaped_newlines(input);
+    let line_origins = preprocessing::original_line_numbers(input);
     let pairs = ScriptParser::parse(Rule::program, &preprocessed)?;
     let mut statements = Vec::new();
 
@@ -43,15 +54,18 @@ pub fn parse_script(input: &str) -> Result<Program, Box<pest::error::Error<Rule>
             if inner_pair.as_rule() == Rule::item {
                 // Item wraps the actual content
                 if let Some(content) = inner_pair.into_inner().next() {
-                    match content.as_rule() {
-                        Rule::comment => {
-                            // Skip comments - attributes are collected in parse_statement
-                        }
-                        _ => {
-                            if let Some(stmt) = parse_statement(content, &preprocessed) {
-                                statements.push(stmt);
-                            }
+                    let attributes = match content.as_rule() {
+                        // Skip comments - attributes are collected with their function
+                        Rule::comment => continue,
+                        Rule::function_def => {
+                            function_attributes(&content, &preprocessed, declared)?
                         }
+                        _ => Vec::new(),
+                    };
+                    if let Some(stmt) =
+                        parse_statement(content, &preprocessed, &line_origins, attributes)
+                    {
+                        statements.push(stmt);
                     }
                 }
             }
@@ -61,7 +75,104 @@ pub fn parse_script(input: &str) -> Result<Program, Box<pest::error::Error<Rule>
     Ok(Program { statements })
 }
 
-fn parse_statement(pair: pest::iterators::Pair<Rule>, original_input: &str) -> Option<Statement> {
+/// Parse a script, recovering from syntax errors at top-level item boundaries.
+///
+/// If the whole input parses, this is equivalent to [`parse_script`]. Otherwise each
+/// top-level item (a function definition with its attribute comments, an assignment, a
+/// call) is parsed on its own: the items that parse are returned as the program, and
+/// every item that doesn't contributes a [`ParseError`]. Line numbers in both refer to
+/// the full input.
+#[must_use]
+pub fn parse_script_recovering(input: &str, filename: Option<&str>) -> (Program, Vec<ParseError>) {
+    // Declarations anywhere in the input count, not just those in the same item
+    let declared = crate::settings::declared_interpreter_names(input);
+    let first_error = match parse_with_interpreters(input, &declared) {
+        Ok(program) => return (program, Vec::new()),
+        Err(e) => e,
+    };
+
+    let mut statements = Vec::new();
+    let mut errors = Vec::new();
+    for segment in recovery::split_top_level_items(input) {
+        // Pad with blank lines so positions match the full input
+        let padded = format!("{}{}", "\n".repeat(segment.start_line), segment.text);
+        match parse_with_interpreters(&padded, &declared) {
+            Ok(program) => statements.extend(program.statements),
+            Err(e) => errors.push(ParseError::from_pest(&e, &padded, filename)),
+        }
+    }
+    if errors.is_empty() {
+        // Items parse individually but not together; report the original error
+        errors.push(ParseError::from_pest(&first_error, input, filename));
+    }
+
+    (Program { statements }, errors)
+}
+
+/// Parse merged Runfile content with recovery (see [`parse_script_recovering`]),
+/// pointing each error at the original file and line recorded in `map`.
+#[must_use]
+pub fn parse_runfile(
+    content: &str,
+    map: &crate::source_map::SourceMap,
+) -> (Program, Vec<ParseError>) {
+    let (program, mut errors) = parse_script_recovering(content, Some("Runfile"));
+    for error in &mut errors {
+        error.relocate(map);
+    }
+    (program, errors)
+}
+
+/// Translate a byte offset in the preprocessed input to a 1-indexed original line number.
+fn original_line_at(preprocessed: &str, offset: usize, line_origins: &[usize]) -> usize {
+    let line = preprocessed[..offset].matches('\n').count();
+    line_origins.get(line).copied().unwrap_or(line + 1)
+}
+
+/// Original line of the first body line of a `{ ... }` block starting at `start`
+fn block_body_line(
+    block_str: &str,
+    start: usize,
+    preprocessed: &str,
+    line_origins: &[usize],
+) -> usize {
+    // The body starts at the first non-whitespace character after `{`
+    let body_offset = block_str[1..]
+        .find(|c: char| !c.is_whitespace())
+        .map_or(0, |i| i + 1);
+    original_line_at(preprocessed, start + body_offset, line_origins)
+}
+
+/// Attribute comments above the function definition `pair`
+fn function_attributes(
+    pair: &pest::iterators::Pair<Rule>,
+    original_input: &str,
+    declared: &[String],
+) -> Result<Vec<Attribute>, Box<pest::error::Error<Rule>>> {
+    let line_num = original_input[..pair.as_span().start()].lines().count();
+    attributes::parse_attributes_from_lines(original_input, line_num, declared).map_err(|e| {
+        // Point at the start of the offending attribute line
+        let offset = original_input
+            .split_inclusive('\n')
+            .take(e.line)
+            .map(str::len)
+            .sum();
+        let position = pest::Position::new(original_input, offset)
+            .unwrap_or_else(|| pest::Position::from_start(original_input));
+        Box::new(pest::error::Error::new_from_pos(
+            pest::error::ErrorVariant::CustomError { message: e.message },
+            position,
+        ))
+    })
+}
+
+/// Parse a top-level item; a function definition gets the `attributes` above it
+fn parse_statement(
+    pair: pest::iterators::Pair<Rule>,
+    original_input: &str,
+    line_origins: &[usize],
+    attributes: Vec<Attribute>,
+) -> Option<Statement> {
     match pair.as_rule() {
         Rule::assignment => {
             let mut inner = pair.into_inner();
@@ -74,8 +185,7 @@ fn parse_statement(pair: pest::iterators::Pair<Rule>, original_input: &str) -> O
         }
         Rule::function_def => {
             let span = pair.as_span();
-            let line_num = original_input[..span.start()].lines().count();
-            let attributes = attributes::parse_attributes_from_lines(original_input, line_num);
+            let line = original_line_at(original_input, span.start(), line_origins);
 
             let mut inner = pair.into_inner();
             let name = inner.next()?.as_str().to_string();
@@ -98,8 +208,16 @@ fn parse_statement(pair: pest::iterators::Pair<Rule>, original_input: &str) -> O
             // Parse body (block or command)
             match body_pair.as_rule() {
                 Rule::block => {
+                    let block_str = body_pair.as_str();
+                    let body_line = block_body_line(
+                        block_str,
+                        body_pair.as_span().start(),
+                        original_input,
+                        line_origins,
+                    );
+
                     // Parse and dedent block content
-                    let full_content = block::parse_block_content(body_pair.as_str());
+                    let full_content = block::parse_block_content(block_str);
 
                     // Split into commands based on shell type
                     let commands = block::split_block_commands(&full_content, &attributes);
@@ -113,15 +231,19 @@ fn parse_statement(pair: pest::iterators::Pair<Rule>, original_input: &str) -> O
                         commands,
                         attributes,
                         shebang,
+                        location: SourceLocation { line, body_line },
                     })
                 }
                 Rule::command => {
+                    let body_line =
+                        original_line_at(original_input, body_pair.as_span().start(), line_origins);
                     let command_template = parse_command(body_pair);
                     Some(Statement::SimpleFunctionDef {
                         name,
                         params,
                         command_template,
                         attributes,
+                        location: SourceLocation { line, body_line },
                     })
                 }
                 _ => None,
@@ -336,6 +458,114 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_function_locations_use_original_lines() {
+        let input = "# header\n\nbuild() cargo build \\\n    --release\n\ndeploy() {\n    echo one\n    echo two\n}\n";
+        let result = parse_script(input).unwrap();
+
+        let locations: Vec<SourceLocation> = result
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::SimpleFunctionDef { location, .. }
+                | Statement::BlockFunctionDef { location, .. } => Some(*location),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                SourceLocation {
+                    line: 3,
+                    body_line: 3
+                },
+                SourceLocation {
+                    line: 6,
+                    body_line: 7
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_script_recovering_collects_all_errors() {
+        let input =
+            "ok() echo ok\n\nbad() {\n    echo bad\n# @desc Last\nlast() echo last\n\nworse() {\n";
+        let (program, errors) = parse_script_recovering(input, Some("Runfile"));
+
+        let names: Vec<&str> = program
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::SimpleFunctionDef { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(names, vec!["ok", "last"]);
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![3, 8]
+        );
+
+        // Attributes and locations survive recovery
+        if let Statement::SimpleFunctionDef {
+            attributes,
+            location,
+            ..
+        } = &program.statements[1]
+        {
+            assert_eq!(attributes, &vec![Attribute::Desc("Last".to_string())]);
+            assert_eq!(location.line, 6);
+        } else {
+            panic!("Expected SimpleFunctionDef");
+        }
+    }
+
+    #[test]
+    fn test_parse_script_recovering_valid_input_has_no_errors() {
+        let (program, errors) = parse_script_recovering("a() echo a\nb() echo b\n", None);
+        assert!(errors.is_empty());
+        assert_eq!(program.statements.len(), 2);
+    }
+
+    #[test]
+    fn test_unknown_shell_is_an_error_on_its_attribute_line() {
+        let input = "# @interpreter janet command=janet\n\n\
+                     # @shell janet\nok() {\n  (print 1)\n}\n\n\
+                     # @desc Typo\n# @shell pyhton\nbad() {\n  print(1)\n}\n";
+        let error = parse_script(input).expect_err("unknown @shell");
+        assert!(format!("{error}").contains("unknown interpreter 'pyhton'"));
+
+        // With recovery, declarations in other items still count
+        let input = format!("{input}\nbroken() {{\n");
+        let (program, errors) = parse_script_recovering(&input, None);
+        assert_eq!(program.statements.len(), 1);
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![9, 14]
+        );
+        assert!(errors[0].message.contains("'pyhton'"));
+    }
+
+    #[test]
+    fn test_parse_sources_attribute() {
+        let input = "# @sources src/** 'tests/*.rs' Cargo.toml\ntest() cargo test\n";
+        let result = parse_script(input).unwrap();
+
+        if let Statement::SimpleFunctionDef { attributes, .. } = &result.statements[0] {
+            assert_eq!(
+                attributes,
+                &vec![Attribute::Sources(vec![
+                    "src/**".to_string(),
+                    "tests/*.rs".to_string(),
+                    "Cargo.toml".to_string(),
+                ])]
+            );
+        } else {
+            panic!("Expected SimpleFunctionDef");
+        }
+    }
+
     #[test]
     fn test_parse_desc_attribute() {
         let input = r"
@@ -27,3 +27,25 @@ pub(super) fn preprocess_escaped_newlines(input: &str) -> String {
     }
     result
 }
+
+/// Map each line of the preprocessed output back to the original input line it starts on.
+///
+/// Index `i` holds the 1-indexed original line number of preprocessed line `i + 1`,
+/// so locations reported against the preprocessed text can be translated back to
+/// what the user sees in their editor.
+pub(super) fn original_line_numbers(input: &str) -> Vec<usize> {
+    let mut origins = Vec::new();
+    let mut start: Option<usize> = None;
+    for (idx, line) in input.lines().enumerate() {
+        let first = *start.get_or_insert(idx + 1);
+        if line.trim_end().ends_with('\\') {
+            continue;
+        }
+        origins.push(first);
+        start = None;
+    }
+    if let Some(first) = start {
+        origins.push(first);
+    }
+    origins
+}
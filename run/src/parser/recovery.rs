@@ -0,0 +1,133 @@
+//! Error recovery at top-level item boundaries
+//!
+//! When a Runfile fails to parse as a whole, it is split into top-level items
+//! (function definitions, assignments, calls) and each item is parsed on its own,
+//! so one broken function does not hide every other function in the file.
+
+/// A top-level item: its first line (0-indexed) and its text
+pub(super) struct Segment {
+    pub(super) start_line: usize,
+    pub(super) text: String,
+}
+
+/// Whether a line at column 0 looks like the start of a function definition
+fn starts_definition(line: &str) -> bool {
+    if line.starts_with(char::is_whitespace) {
+        return false;
+    }
+    if let Some(rest) = line.strip_prefix("function ") {
+        return !rest.trim().is_empty();
+    }
+    let name_len = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '-')))
+        .unwrap_or(line.len());
+    line.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && line[name_len..].trim_start().starts_with('(')
+}
+
+fn finish(current: &mut Option<Segment>, segments: &mut Vec<Segment>) {
+    if let Some(segment) = current.take() {
+        segments.push(segment);
+    }
+}
+
+/// Split input into top-level items.
+///
+/// Comment lines (including `# @attribute` lines) stay attached to the item that
+/// follows them. An item ends when its braces balance at the end of a line, or
+/// when a new definition (or `# @` attribute) starts at column 0 while a block is
+/// still open, which is how a missing `}` is contained to the function it belongs to.
+pub(super) fn split_top_level_items(input: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut current: Option<Segment> = None;
+    let mut has_item = false;
+    let mut depth: usize = 0;
+    let mut continued = false;
+
+    for (idx, line) in input.lines().enumerate() {
+        let trimmed = line.trim();
+
+        if !continued {
+            if depth == 0 && trimmed.is_empty() {
+                finish(&mut current, &mut segments);
+                has_item = false;
+                continue;
+            }
+            let is_comment = trimmed.starts_with('#');
+            let new_item = if depth == 0 {
+                // A complete item followed directly by another line
+                has_item
+            } else {
+                // An unclosed block running into the next definition or its attributes
+                starts_definition(line) || line.starts_with("# @")
+            };
+            if new_item {
+                finish(&mut current, &mut segments);
+                has_item = false;
+                depth = 0;
+            }
+            if !is_comment || depth > 0 {
+                has_item = true;
+            }
+        }
+
+        let segment = current.get_or_insert_with(|| Segment {
+            start_line: idx,
+            text: String::new(),
+        });
+        segment.text.push_str(line);
+        segment.text.push('\n');
+
+        let (opens, closes) = crate::config::count_unquoted_braces(line);
+        depth = depth.saturating_add(opens).saturating_sub(closes);
+        continued = line.trim_end().ends_with('\\');
+    }
+    finish(&mut current, &mut segments);
+
+    segments
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    fn starts(input: &str) -> Vec<usize> {
+        split_top_level_items(input)
+            .iter()
+            .map(|s| s.start_line)
+            .collect()
+    }
+
+    #[test]
+    fn test_split_keeps_attributes_with_function() {
+        let input = "# @desc Build\nbuild() cargo build\ntest() cargo test\n\nX=1\n";
+        let segments = split_top_level_items(input);
+        assert_eq!(starts(input), vec![0, 2, 4]);
+        assert_eq!(segments[0].text, "# @desc Build\nbuild() cargo build\n");
+    }
+
+    #[test]
+    fn test_split_blocks_with_blank_lines_inside() {
+        let input = "deploy() {\n    echo a\n\n    echo b\n}\nnext() echo n\n";
+        assert_eq!(starts(input), vec![0, 5]);
+    }
+
+    #[test]
+    fn test_split_contains_unclosed_block() {
+        let input = "broken() {\n    echo a\n\nok() {\n    echo ok\n}\n";
+        assert_eq!(starts(input), vec![0, 3]);
+    }
+
+    #[test]
+    fn test_split_unclosed_block_before_attributes() {
+        let input = "broken() {\n    echo a\n# @desc Next\nnext() echo n\n";
+        assert_eq!(starts(input), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_split_follows_line_continuations() {
+        let input = "build() cargo build \\\n--release\nnext() echo n\n";
+        assert_eq!(starts(input), vec![0, 2]);
+    }
+}
@@ -0,0 +1,365 @@
+//! Child process control shared by the commands that supervise other processes.
+//!
+//! A supervised child gets a process group of its own so that stopping it also stops
+//! everything it started (the interpreter running the function, and its children).
+//! A function `run` waits for also takes over the terminal, when `run` has it, so Ctrl+C
+//! and Ctrl+Z still reach it and it can read from it.
+//! While a child runs, `SIGINT` and `SIGTERM` are caught instead of ending `run` at once,
+//! so that the child can be stopped and temporary files removed first.
+
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// How long a child gets to exit after `SIGTERM` before it is killed
+pub const GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// How long a child gets to act on a signal it may have received itself (Ctrl+C
+/// reaches the whole foreground process group) before it is passed on
+const FORWARD_DELAY: Duration = Duration::from_millis(100);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Signal received while an [`Interrupts`] guard is alive, or 0
+static INTERRUPT: AtomicI32 = AtomicI32::new(0);
+/// Number of live [`Interrupts`] guards
+static CATCHING: AtomicUsize = AtomicUsize::new(0);
+
+/// Start `cmd` in a new process group, led by the child. The child no longer receives
+/// Ctrl+C from the terminal, and can't read from it.
+pub fn isolate(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+/// Start `cmd`, to be waited for with [`wait_forwarding`], in a new process group. If
+/// `run` is in the foreground of a terminal, the group takes it over, and
+/// [`wait_forwarding`] takes it back.
+///
+/// When our output goes down a pipe to another program, which may read the terminal
+/// too (`run logs | less`), the child stays in our process group instead.
+pub fn supervise(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let terminal = terminal::owned();
+        if terminal.is_some() && terminal::output_is_piped() {
+            return;
+        }
+        cmd.process_group(0);
+        if let Some(fd) = terminal {
+            // SAFETY: the closure only makes async-signal-safe calls (setpgid, getpid,
+            // signal, tcsetpgrp) and doesn't allocate
+            unsafe {
+                cmd.pre_exec(move || {
+                    // Whichever runs first, this or the `process_group` above
+                    if libc::setpgid(0, 0) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    terminal::give(fd, libc::getpid());
+                    Ok(())
+                });
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+/// Ask a child to exit with `SIGTERM`, along with its process group if it was started
+/// with [`isolate`]
+pub fn stop(child: &Child) {
+    #[cfg(unix)]
+    signal_group(child, libc::SIGTERM);
+    #[cfg(not(unix))]
+    let _ = child;
+}
+
+/// Kill a child with `SIGKILL`, along with its process group if it was started with
+/// [`isolate`]
+pub fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    signal_group(child, libc::SIGKILL);
+    let _ = child.kill();
+}
+
+/// Stop a child and everything in its process group: `SIGTERM` first, then `SIGKILL` for
+/// whatever is still running after `grace`.
+pub fn terminate(child: &mut Child, grace: Duration) -> Option<ExitStatus> {
+    if let Ok(Some(status)) = child.try_wait() {
+        // The leader is gone, but what it started may not be
+        kill(child);
+        return Some(status);
+    }
+    stop(child);
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline {
+        if let Ok(Some(status)) = child.try_wait() {
+            kill(child);
+            return Some(status);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    kill(child);
+    child.wait().ok()
+}
+
+/// Send `signal` to the child's process group, or to the child alone if it doesn't
+/// lead one
+#[cfg(unix)]
+fn signal_group(child: &Child, signal: libc::c_int) {
+    let Ok(pid) = libc::pid_t::try_from(child.id()) else {
+        return;
+    };
+    // SAFETY: kill(2) has no memory-safety preconditions; a negative pid addresses
+    // the process group the child leads
+    unsafe {
+        if libc::kill(-pid, signal) != 0 {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/// While alive, `SIGINT` and `SIGTERM` are noted in [`interrupted`] instead of ending
+/// the process. Dropping the last guard restores the default handling and delivers the
+/// signal caught meanwhile, so the process ends the way it would have, only later.
+///
+/// Create it before anything that needs cleaning up, so that is dropped first.
+#[must_use = "signals are only caught while the guard is alive"]
+pub struct Interrupts {
+    _private: (),
+}
+
+/// Catch `SIGINT` and `SIGTERM` until the returned guard is dropped. On other platforms
+/// this does nothing.
+pub fn catch_interrupts() -> Interrupts {
+    if CATCHING.fetch_add(1, Ordering::SeqCst) == 0 {
+        INTERRUPT.store(0, Ordering::SeqCst);
+        #[cfg(unix)]
+        {
+            extern "C" fn note(signal: libc::c_int) {
+                INTERRUPT.store(signal, Ordering::SeqCst);
+            }
+            let handler = note as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+            unsafe {
+                libc::signal(libc::SIGINT, handler);
+                libc::signal(libc::SIGTERM, handler);
+            }
+        }
+    }
+    Interrupts { _private: () }
+}
+
+impl Drop for Interrupts {
+    fn drop(&mut self) {
+        if CATCHING.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        #[cfg(unix)]
+        // SAFETY: restoring the default disposition and raising a signal have no
+        // memory-safety preconditions
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGTERM, libc::SIG_DFL);
+            let signal = INTERRUPT.swap(0, Ordering::SeqCst);
+            if signal != 0 {
+                libc::raise(signal);
+            }
+        }
+    }
+}
+
+/// The signal caught since [`catch_interrupts`], if any
+#[must_use]
+pub fn interrupted() -> Option<i32> {
+    match INTERRUPT.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal),
+    }
+}
+
+/// Wait for a child started with [`supervise`], passing on a signal caught by an
+/// [`Interrupts`] guard to its process group if the child doesn't exit by itself, and
+/// killing the group if it still hasn't exited [`GRACE_PERIOD`] later.
+///
+/// When the child is stopped (Ctrl+Z), `run` stops too, handing the terminal back to
+/// the shell, and continues the child when it is continued itself.
+///
+/// # Errors
+///
+/// Returns `Err` if waiting for the child fails.
+pub fn wait_forwarding(child: &mut Child) -> std::io::Result<ExitStatus> {
+    let mut signalled: Option<Instant> = None;
+    let mut forwarded = false;
+    loop {
+        if let Some(status) = poll(child)? {
+            return Ok(status);
+        }
+        if let Some(signal) = interrupted() {
+            let since = *signalled.get_or_insert_with(Instant::now);
+            if !forwarded && since.elapsed() >= FORWARD_DELAY {
+                forwarded = true;
+                #[cfg(unix)]
+                signal_group(child, signal);
+                #[cfg(not(unix))]
+                let _ = signal;
+            }
+            if since.elapsed() >= FORWARD_DELAY + GRACE_PERIOD {
+                kill(child);
+                return wait(child);
+            }
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The status of a child that has exited, without blocking. A child that was stopped
+/// is dealt with like the shell would, with `run` stopping along with it.
+#[cfg(unix)]
+fn poll(child: &mut Child) -> std::io::Result<Option<ExitStatus>> {
+    use std::os::unix::process::ExitStatusExt;
+    let Ok(pid) = libc::pid_t::try_from(child.id()) else {
+        return child.try_wait();
+    };
+    let mut status = 0;
+    // SAFETY: waitpid only writes to the status it is given
+    let waited = unsafe { libc::waitpid(pid, &raw mut status, libc::WNOHANG | libc::WUNTRACED) };
+    if waited < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    if waited == 0 {
+        return Ok(None);
+    }
+    let terminal = terminal::owned_by(pid);
+    if let Some(fd) = terminal {
+        // SAFETY: getpgrp has no preconditions
+        terminal::give(fd, unsafe { libc::getpgrp() });
+    }
+    if libc::WIFSTOPPED(status) {
+        // SAFETY: getpgid has no preconditions
+        if unsafe { libc::getpgid(pid) } != pid {
+            // Stopped along with us, in our process group: the shell continues both
+            return Ok(None);
+        }
+        // SAFETY: raising a signal and signalling the child's group have no
+        // memory-safety preconditions
+        unsafe {
+            libc::raise(libc::SIGTSTP);
+            // Continued: back in the foreground, if we were there before
+            if let Some(fd) = terminal
+                && terminal::owned() == Some(fd)
+            {
+                terminal::give(fd, pid);
+            }
+            libc::kill(-pid, libc::SIGCONT);
+        }
+        return Ok(None);
+    }
+    Ok(Some(ExitStatus::from_raw(status)))
+}
+
+#[cfg(not(unix))]
+fn poll(child: &mut Child) -> std::io::Result<Option<ExitStatus>> {
+    child.try_wait()
+}
+
+/// Wait for a child [`poll`] hasn't reaped yet
+fn wait(child: &mut Child) -> std::io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = poll(child)? {
+            return Ok(status);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The controlling terminal, as far as handing it between process groups goes
+#[cfg(unix)]
+mod terminal {
+    /// Standard streams, in the order a terminal is looked for among them
+    const STREAMS: [libc::c_int; 3] =
+        [libc::STDIN_FILENO, libc::STDERR_FILENO, libc::STDOUT_FILENO];
+
+    /// A standard stream that is a terminal with our process group in its foreground
+    pub(super) fn owned() -> Option<libc::c_int> {
+        // SAFETY: getpgrp has no preconditions
+        owned_by(unsafe { libc::getpgrp() })
+    }
+
+    /// A standard stream that is a terminal with process group `group` in its foreground
+    pub(super) fn owned_by(group: libc::pid_t) -> Option<libc::c_int> {
+        // SAFETY: isatty and tcgetpgrp only look at the descriptor
+        STREAMS
+            .into_iter()
+            .find(|&fd| unsafe { libc::isatty(fd) == 1 && libc::tcgetpgrp(fd) == group })
+    }
+
+    /// Whether standard output is a pipe, as it is within a shell pipeline
+    pub(super) fn output_is_piped() -> bool {
+        // SAFETY: fstat only writes to the struct it is given
+        unsafe {
+            let mut stat: libc::stat = std::mem::zeroed();
+            libc::fstat(libc::STDOUT_FILENO, &raw mut stat) == 0
+                && stat.st_mode & libc::S_IFMT == libc::S_IFIFO
+        }
+    }
+
+    /// Put process group `group` in the foreground of the terminal `fd`. Only
+    /// async-signal-safe calls, so it can run between fork and exec.
+    pub(super) fn give(fd: libc::c_int, group: libc::pid_t) {
+        // SAFETY: ignoring SIGTTOU for the call keeps a background process from being
+        // stopped for taking the terminal; the disposition is restored right after
+        unsafe {
+            let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            libc::tcsetpgrp(fd, group);
+            libc::signal(libc::SIGTTOU, previous);
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminate_stops_the_whole_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("grandchild.pid");
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(format!("sleep 30 & echo $! > {}; wait", marker.display()));
+        isolate(&mut cmd);
+        let mut child = cmd.spawn().unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while std::fs::read_to_string(&marker).map_or(true, |s| !s.ends_with('\n')) {
+            assert!(Instant::now() < deadline, "grandchild never started");
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        let grandchild: libc::pid_t = std::fs::read_to_string(&marker)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+
+        let status = terminate(&mut child, GRACE_PERIOD).unwrap();
+        assert!(!status.success());
+        // Once killed, the orphaned grandchild is at most a zombie waiting to be reaped
+        let running = || {
+            std::fs::read_to_string(format!("/proc/{grandchild}/stat"))
+                .is_ok_and(|stat| !stat.contains(") Z "))
+        };
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while running() {
+            assert!(Instant::now() < deadline, "grandchild survived");
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,622 @@
+//! Interpreter registry
+//!
+//! Everything `run` needs to know to execute a function in a given language: which
+//! executable to start, how the code and the function's arguments are handed over,
+//! which preamble dialect turns named parameters into variables, and whose functions
+//! it can inline. Built-in entries cover the common shells and scripting languages;
+//! projects register more (or override built-ins) with top-level `# @interpreter` lines.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock, RwLock};
+
+/// How generated code reaches the interpreter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeDelivery {
+    /// Inline, after these arguments: `python3 -c <code>`, `perl -e <code>`
+    Inline(Vec<String>),
+    /// Through a temporary file with this extension, after these arguments:
+    /// `lua <file>`, `deno run -A <file>`
+    TempFile {
+        args: Vec<String>,
+        extension: String,
+    },
+}
+
+/// Where the function's arguments go on the interpreter's command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgvConvention {
+    /// Right after the code: `python3 -c <code> a b` gives `sys.argv[1:] == [a, b]`
+    Direct,
+    /// After a `$0` placeholder, as `sh -c` expects: `bash -c <code> bash a b`
+    DollarZero,
+}
+
+/// Language of generated preambles: parameter variables and `__RUNFILE_DIR__`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// POSIX-style shells (sh, bash, zsh)
+    Sh,
+    Pwsh,
+    Python,
+    /// JavaScript with `process.argv` (node, bun)
+    Node,
+    /// JavaScript with `Deno.args`
+    Deno,
+    Ruby,
+    Perl,
+    Lua,
+    Php,
+    Fish,
+    /// No preamble: the code reads its arguments itself
+    None,
+}
+
+impl Dialect {
+    /// Shell dialects run the body inside a function, with compatible sibling
+    /// functions defined in front of it
+    #[must_use]
+    pub fn is_shell(self) -> bool {
+        matches!(self, Self::Sh | Self::Pwsh)
+    }
+
+    /// Prefix of a line comment (`#` for unknown languages)
+    #[must_use]
+    pub fn line_comment(self) -> &'static str {
+        match self {
+            Self::Node | Self::Deno | Self::Php => "//",
+            Self::Lua => "--",
+            Self::Sh
+            | Self::Pwsh
+            | Self::Python
+            | Self::Ruby
+            | Self::Perl
+            | Self::Fish
+            | Self::None => "#",
+        }
+    }
+
+    /// `$name` is the language's own variable syntax, so Runfile-style `$1`/`$name`
+    /// substitution has to leave the code alone
+    #[must_use]
+    pub fn has_dollar_variables(self) -> bool {
+        matches!(self, Self::Perl | Self::Php | Self::Fish)
+    }
+
+    /// Extension for script files in this language (without the dot)
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Sh => "sh",
+            Self::Pwsh => "ps1",
+            Self::Python => "py",
+            Self::Node => "js",
+            Self::Deno => "ts",
+            Self::Ruby => "rb",
+            Self::Perl => "pl",
+            Self::Lua => "lua",
+            Self::Php => "php",
+            Self::Fish => "fish",
+            Self::None => "",
+        }
+    }
+
+    /// First line a script file needs that inline code doesn't (`php -r` code has no
+    /// `<?php` tag)
+    #[must_use]
+    pub fn file_header(self) -> Option<&'static str> {
+        match self {
+            Self::Php => Some("<?php"),
+            _ => None,
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "sh" | "bash" | "zsh" => Self::Sh,
+            "pwsh" | "powershell" => Self::Pwsh,
+            "python" => Self::Python,
+            "node" | "bun" | "js" => Self::Node,
+            "deno" => Self::Deno,
+            "ruby" => Self::Ruby,
+            "perl" => Self::Perl,
+            "lua" => Self::Lua,
+            "php" => Self::Php,
+            "fish" => Self::Fish,
+            "none" => Self::None,
+            _ => return None,
+        })
+    }
+}
+
+/// One interpreter `run` can execute functions with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterpreterSpec {
+    /// Name used by `@shell` and in structured output
+    pub name: String,
+    /// Other names recognised in `@shell` and shebangs (`#!/usr/bin/env powershell`)
+    pub aliases: Vec<String>,
+    /// Executables to try in order; the first one on `PATH` is used
+    pub executables: Vec<String>,
+    pub code: CodeDelivery,
+    pub argv: ArgvConvention,
+    pub dialect: Dialect,
+    /// Functions of the same compatibility group are inlined into each other's scripts;
+    /// others are called through a `run` wrapper
+    pub group: String,
+    /// A further group this interpreter can run (bash and zsh run `sh` functions)
+    pub runs_group: Option<String>,
+    /// Script prefix that stops at the first failing command (`set -e`), if any
+    pub errexit: String,
+}
+
+impl InterpreterSpec {
+    fn builtin(
+        name: &str,
+        executables: &[&str],
+        code: CodeDelivery,
+        argv: ArgvConvention,
+        dialect: Dialect,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            aliases: Vec::new(),
+            executables: executables.iter().map(ToString::to_string).collect(),
+            code,
+            argv,
+            dialect,
+            group: name.to_string(),
+            runs_group: None,
+            errexit: String::new(),
+        }
+    }
+
+    /// Spec for an interpreter nobody registered: run `<name> <script file> args...`,
+    /// which is how most interpreters take a script
+    #[must_use]
+    pub fn fallback(name: &str) -> Self {
+        Self::builtin(
+            name,
+            &[name],
+            CodeDelivery::TempFile {
+                args: Vec::new(),
+                extension: String::new(),
+            },
+            ArgvConvention::Direct,
+            Dialect::None,
+        )
+    }
+
+    fn with_group(mut self, group: &str, runs_group: Option<&str>) -> Self {
+        self.group = group.to_string();
+        self.runs_group = runs_group.map(ToString::to_string);
+        self
+    }
+
+    fn with_errexit(mut self, errexit: &str) -> Self {
+        self.errexit = errexit.to_string();
+        self
+    }
+
+    fn with_aliases(mut self, aliases: &[&str]) -> Self {
+        self.aliases = aliases.iter().map(ToString::to_string).collect();
+        self
+    }
+
+    /// Whether `name` refers to this interpreter
+    #[must_use]
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|a| a == name)
+    }
+
+    /// Executable to start: the first candidate found on `PATH`, else the first one
+    #[must_use]
+    pub fn program(&self) -> String {
+        self.executables
+            .iter()
+            .find(|exe| on_path(exe))
+            .or_else(|| self.executables.first())
+            .cloned()
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    /// Extension given to script files run by this interpreter (without the dot)
+    #[must_use]
+    pub fn file_extension(&self) -> &str {
+        match &self.code {
+            CodeDelivery::TempFile { extension, .. } if !extension.is_empty() => extension,
+            _ => self.dialect.extension(),
+        }
+    }
+
+    /// This interpreter taking its code from a script file instead of inline:
+    /// `python3 deploy.py args...`, `pwsh -File deploy.ps1 args...`
+    #[must_use]
+    pub fn run_from_file(&self) -> Self {
+        let mut spec = self.clone();
+        if let CodeDelivery::Inline(_) = self.code {
+            let args = match self.dialect {
+                Dialect::Pwsh => vec!["-File".to_string()],
+                _ => Vec::new(),
+            };
+            spec.code = CodeDelivery::TempFile {
+                args,
+                extension: self.dialect.extension().to_string(),
+            };
+            // The script file takes the `$0` slot
+            spec.argv = ArgvConvention::Direct;
+        }
+        spec
+    }
+
+    /// Whether functions of `other` can be inlined into scripts run by this interpreter
+    #[must_use]
+    pub fn can_run(&self, other: &InterpreterSpec) -> bool {
+        self.group == other.group || self.runs_group.as_deref() == Some(other.group.as_str())
+    }
+
+    /// Parse the settings of a `# @interpreter <name> key=value...` line.
+    ///
+    /// Keys:
+    /// - `command` (required): executable and fixed leading arguments, e.g. `"deno run -A"`
+    /// - `code`: `file` or `file:.ext` for a temporary script file (the default), or the
+    ///   flag that takes inline code, e.g. `-e`
+    /// - `argv`: `direct` (default) or `dollar0`
+    /// - `dialect`: preamble dialect for named parameters (`python`, `node`, `perl`, ...,
+    ///   default `none`)
+    /// - `group`: compatibility group (defaults to the name)
+    /// - `aliases`: comma-separated extra names matched in `@shell` and shebangs
+    ///
+    /// # Errors
+    ///
+    /// Returns a message describing the first invalid or missing setting.
+    pub fn from_directive(name: &str, settings: &str) -> Result<Self, String> {
+        let mut spec = Self::fallback(name);
+        let mut command: Option<Vec<String>> = None;
+        let mut code = "file".to_string();
+
+        for token in split_words(settings) {
+            let (key, value) = token
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got '{token}'"))?;
+            match key {
+                "command" => command = Some(split_words(value)),
+                "code" => code = value.to_string(),
+                "argv" => {
+                    spec.argv = match value {
+                        "direct" => ArgvConvention::Direct,
+                        "dollar0" => ArgvConvention::DollarZero,
+                        _ => return Err(format!("argv must be direct or dollar0, got '{value}'")),
+                    };
+                }
+                "dialect" => {
+                    spec.dialect = Dialect::parse(value)
+                        .ok_or_else(|| format!("unknown dialect '{value}'"))?;
+                }
+                "group" => spec.group = value.to_string(),
+                "aliases" => {
+                    spec.aliases = value
+                        .split(',')
+                        .filter(|a| !a.is_empty())
+                        .map(ToString::to_string)
+                        .collect();
+                }
+                _ => return Err(format!("unknown key '{key}'")),
+            }
+        }
+
+        let mut command = command
+            .filter(|c| !c.is_empty())
+            .ok_or_else(|| "missing command=...".to_string())?;
+        spec.executables = vec![command.remove(0)];
+        spec.code = if let Some(rest) = code.strip_prefix("file") {
+            CodeDelivery::TempFile {
+                args: command,
+                extension: rest.trim_start_matches([':', '.']).to_string(),
+            }
+        } else {
+            command.push(code);
+            CodeDelivery::Inline(command)
+        };
+        Ok(spec)
+    }
+}
+
+/// Split on whitespace, keeping quoted runs (`"deno run -A"`) together without the quotes
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+fn inline(args: &[&str]) -> CodeDelivery {
+    CodeDelivery::Inline(args.iter().map(ToString::to_string).collect())
+}
+
+fn temp_file(args: &[&str], extension: &str) -> CodeDelivery {
+    CodeDelivery::TempFile {
+        args: args.iter().map(ToString::to_string).collect(),
+        extension: extension.to_string(),
+    }
+}
+
+/// Whether `exe` is found on `PATH`, looked up once per process
+fn on_path(exe: &str) -> bool {
+    static FOUND: OnceLock<Mutex<HashMap<String, bool>>> = OnceLock::new();
+    let found = FOUND.get_or_init(|| Mutex::new(HashMap::new()));
+    let Ok(mut found) = found.lock() else {
+        return which::which(exe).is_ok();
+    };
+    *found
+        .entry(exe.to_string())
+        .or_insert_with(|| which::which(exe).is_ok())
+}
+
+/// The interpreters `run` knows about without any configuration
+#[must_use]
+pub fn builtins() -> &'static [InterpreterSpec] {
+    static BUILTINS: OnceLock<Vec<InterpreterSpec>> = OnceLock::new();
+    BUILTINS.get_or_init(builtin_specs)
+}
+
+fn builtin_specs() -> Vec<InterpreterSpec> {
+    use ArgvConvention::{Direct, DollarZero};
+
+    vec![
+        InterpreterSpec::builtin("sh", &["sh"], inline(&["-c"]), DollarZero, Dialect::Sh)
+            .with_errexit("set -e"),
+        InterpreterSpec::builtin("bash", &["bash"], inline(&["-c"]), DollarZero, Dialect::Sh)
+            .with_group("bash", Some("sh"))
+            .with_errexit("set -eo pipefail"),
+        InterpreterSpec::builtin("zsh", &["zsh"], inline(&["-c"]), DollarZero, Dialect::Sh)
+            .with_group("zsh", Some("sh"))
+            .with_errexit("set -eo pipefail"),
+        InterpreterSpec::builtin(
+            "pwsh",
+            &["pwsh"],
+            inline(&["-Command"]),
+            DollarZero,
+            Dialect::Pwsh,
+        )
+        .with_aliases(&["powershell"]),
+        InterpreterSpec::builtin(
+            "python",
+            &["python3", "python"],
+            inline(&["-c"]),
+            Direct,
+            Dialect::Python,
+        ),
+        InterpreterSpec::builtin(
+            "python3",
+            &["python3"],
+            inline(&["-c"]),
+            Direct,
+            Dialect::Python,
+        )
+        .with_group("python", None),
+        InterpreterSpec::builtin("node", &["node"], inline(&["-e"]), Direct, Dialect::Node),
+        InterpreterSpec::builtin("ruby", &["ruby"], inline(&["-e"]), Direct, Dialect::Ruby),
+        InterpreterSpec::builtin(
+            "deno",
+            &["deno"],
+            temp_file(&["run", "-A"], "ts"),
+            Direct,
+            Dialect::Deno,
+        ),
+        InterpreterSpec::builtin(
+            "bun",
+            &["bun"],
+            temp_file(&["run"], "ts"),
+            Direct,
+            Dialect::Node,
+        ),
+        // PEP 723 scripts: `uv run --script` installs the inline dependencies first
+        InterpreterSpec::builtin(
+            "uv",
+            &["uv"],
+            temp_file(&["run", "--script"], "py"),
+            Direct,
+            Dialect::Python,
+        )
+        .with_group("python", None),
+        InterpreterSpec::builtin("perl", &["perl"], inline(&["-e"]), Direct, Dialect::Perl),
+        InterpreterSpec::builtin("lua", &["lua"], temp_file(&[], "lua"), Direct, Dialect::Lua),
+        InterpreterSpec::builtin("php", &["php"], inline(&["-r"]), Direct, Dialect::Php),
+        InterpreterSpec::builtin("fish", &["fish"], inline(&["-c"]), Direct, Dialect::Fish),
+    ]
+}
+
+static USER_INTERPRETERS: OnceLock<RwLock<Vec<InterpreterSpec>>> = OnceLock::new();
+
+fn user_interpreters() -> &'static RwLock<Vec<InterpreterSpec>> {
+    USER_INTERPRETERS.get_or_init(|| RwLock::new(Vec::new()))
+}
+
+/// Register an interpreter for this process, replacing any earlier registration (or
+/// built-in) with the same name
+pub fn register(spec: InterpreterSpec) {
+    if let Ok(mut registered) = user_interpreters().write() {
+        registered.retain(|s| s.name != spec.name);
+        registered.push(spec);
+    }
+}
+
+/// Look up an interpreter by name or alias; registered entries take precedence
+#[must_use]
+pub fn lookup(name: &str) -> Option<InterpreterSpec> {
+    let registered = user_interpreters()
+        .read()
+        .ok()
+        .and_then(|r| r.iter().rev().find(|s| s.is_named(name)).cloned());
+    registered.or_else(|| builtins().iter().find(|s| s.is_named(name)).cloned())
+}
+
+/// Whether `name` is a known interpreter: a built-in, a registered one, or one of
+/// `declared` (names from `# @interpreter` lines not registered yet)
+#[must_use]
+pub fn is_known(name: &str, declared: &[String]) -> bool {
+    declared.iter().any(|d| d == name) || lookup(name).is_some()
+}
+
+/// Spec for `name`, which is either an interpreter name or a whole command line taken
+/// from a shebang (`uv run --script`, `python3 -u`).
+///
+/// A command line runs its program with the given arguments in front of a script file,
+/// as the kernel would run the shebang; the program's own entry (if any) supplies the
+/// dialect and compatibility group. Unknown names get [`InterpreterSpec::fallback`].
+#[must_use]
+pub fn resolve(name: &str) -> InterpreterSpec {
+    let words = split_words(name);
+    let [program, args @ ..] = words.as_slice() else {
+        return InterpreterSpec::fallback(name);
+    };
+    let base = lookup(program).unwrap_or_else(|| InterpreterSpec::fallback(program));
+    if args.is_empty() {
+        return base;
+    }
+
+    let mut spec = base.run_from_file();
+    spec.name = name.to_string();
+    spec.executables = vec![program.clone()];
+    spec.code = CodeDelivery::TempFile {
+        args: args.to_vec(),
+        extension: base.file_extension().to_string(),
+    };
+    spec
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtins_cover_new_languages() {
+        for name in ["deno", "bun", "perl", "lua", "php", "zsh", "fish"] {
+            assert!(lookup(name).is_some(), "missing built-in {name}");
+        }
+        assert_eq!(lookup("powershell").unwrap().name, "pwsh");
+        assert!(lookup("cobol").is_none());
+    }
+
+    #[test]
+    fn test_compatibility_groups() {
+        let sh = lookup("sh").unwrap();
+        let bash = lookup("bash").unwrap();
+        let zsh = lookup("zsh").unwrap();
+        let python = lookup("python").unwrap();
+        let python3 = lookup("python3").unwrap();
+        assert!(bash.can_run(&sh));
+        assert!(zsh.can_run(&sh));
+        assert!(!sh.can_run(&bash));
+        assert!(!bash.can_run(&zsh));
+        assert!(python.can_run(&python3) && python3.can_run(&python));
+    }
+
+    #[test]
+    fn test_from_directive_inline_flag() {
+        let spec =
+            InterpreterSpec::from_directive("janet", "command=janet code=-e dialect=none").unwrap();
+        assert_eq!(spec.executables, vec!["janet"]);
+        assert_eq!(spec.code, inline(&["-e"]));
+        assert_eq!(spec.group, "janet");
+        assert_eq!(spec.argv, ArgvConvention::Direct);
+    }
+
+    #[test]
+    fn test_from_directive_temp_file_with_quoted_command() {
+        let spec = InterpreterSpec::from_directive(
+            "tsx",
+            "command=\"npx tsx\" code=file:.ts dialect=node group=node aliases=tsx,ts-node",
+        )
+        .unwrap();
+        assert_eq!(spec.executables, vec!["npx"]);
+        assert_eq!(spec.code, temp_file(&["tsx"], "ts"));
+        assert_eq!(spec.dialect, Dialect::Node);
+        assert!(spec.is_named("ts-node"));
+        assert!(spec.can_run(&lookup("node").unwrap()));
+    }
+
+    #[test]
+    fn test_from_directive_errors() {
+        assert!(InterpreterSpec::from_directive("x", "code=-e").is_err());
+        assert!(InterpreterSpec::from_directive("x", "command=x argv=sideways").is_err());
+        assert!(InterpreterSpec::from_directive("x", "command=x colour=blue").is_err());
+        assert!(InterpreterSpec::from_directive("x", "command=x dialect=cobol").is_err());
+    }
+
+    #[test]
+    fn test_register_adds_interpreter() {
+        let spec = InterpreterSpec::from_directive("test-override-lua", "command=luajit").unwrap();
+        register(spec);
+        assert_eq!(
+            lookup("test-override-lua").unwrap().executables,
+            vec!["luajit"]
+        );
+    }
+
+    #[test]
+    fn test_run_from_file() {
+        let python = lookup("python3").unwrap().run_from_file();
+        assert_eq!(
+            python.code,
+            CodeDelivery::TempFile {
+                args: vec![],
+                extension: "py".to_string()
+            }
+        );
+        let pwsh = lookup("pwsh").unwrap().run_from_file();
+        assert_eq!(pwsh.argv, ArgvConvention::Direct);
+        assert!(matches!(pwsh.code, CodeDelivery::TempFile { ref args, .. } if args == &["-File"]));
+        // Already file-based: unchanged
+        let lua = lookup("lua").unwrap();
+        assert_eq!(lua.run_from_file(), lua);
+    }
+
+    #[test]
+    fn test_resolve_command_line() {
+        let uv = resolve("uv run --script");
+        assert_eq!(uv.name, "uv run --script");
+        assert_eq!(uv.dialect, Dialect::Python);
+        assert_eq!(uv.file_extension(), "py");
+
+        let python = resolve("python3 -u");
+        assert_eq!(python.executables, ["python3"]);
+        assert_eq!(
+            python.code,
+            CodeDelivery::TempFile {
+                args: vec!["-u".to_string()],
+                extension: "py".to_string()
+            }
+        );
+        assert!(python.can_run(&resolve("python")));
+
+        assert_eq!(resolve("node"), lookup("node").unwrap());
+        assert_eq!(resolve("tclsh").dialect, Dialect::None);
+    }
+}
@@ -1,5 +1,6 @@
 //! Interactive REPL (Read-Eval-Print Loop) for the run scripting language.
 
+use crate::ast::Statement;
 use crate::{config, interpreter, parser};
 use std::env;
 use std::io::{self, Write};
@@ -27,20 +28,19 @@ pub fn run_repl() {
 
     // Load Runfile functions into the REPL
     if let Some(config_content) = config::load_config() {
-        match parser::parse_script(&config_content) {
-            Ok(program) => {
-                if let Err(e) = interpreter.execute(program) {
-                    eprintln!("Warning: Error loading Runfile functions: {e}");
-                }
-            }
-            Err(e) => {
-                eprintln!("Warning: Error parsing Runfile: {e}");
-            }
+        crate::settings::ProjectSettings::from_content(&config_content).register_interpreters();
+        let (program, parse_errors) = parser::parse_script_recovering(&config_content, None);
+        for e in &parse_errors {
+            eprintln!("Warning: Error parsing Runfile: {e}");
         }
+        // Definitions only: top-level commands in the Runfile are not run
+        interpreter.load_definitions(program);
     }
 
     let stdin = io::stdin();
     let mut stdout = io::stdout();
+    // `@init` functions run once, before the first function call
+    let mut initialised = false;
 
     loop {
         // Print prompt
@@ -75,6 +75,17 @@ pub fn run_repl() {
                 // Try to parse and execute the input
                 match parser::parse_script(input) {
                     Ok(program) => {
+                        let first_call = program.statements.iter().find_map(|stmt| match stmt {
+                            Statement::FunctionCall { name, .. } => Some(name.as_str()),
+                            _ => None,
+                        });
+                        if let Some(name) = first_call.filter(|_| !initialised) {
+                            if let Err(e) = interpreter.run_init_functions(name) {
+                                eprintln!("Error: {e}");
+                                continue;
+                            }
+                            initialised = true;
+                        }
                         if let Err(e) = interpreter.execute(program) {
                             eprintln!("Error: {e}");
                         }
@@ -0,0 +1,565 @@
+//! Landlock sandbox for the processes that run a function (`@sandbox`, `--sandbox`).
+//!
+//! The sandbox is entered in the child before the interpreter starts, so it holds for it
+//! and for everything it starts. Writes are only allowed beneath the project directory,
+//! the `rw=` paths, the `.run-output` directory and a private temporary directory that
+//! `TMPDIR` points to. Reads are allowed everywhere, unless `ro=` paths are given: reads
+//! outside the project are then limited to them and to the system directories
+//! interpreters need.
+//!
+//! Landlock denies an operation with `EACCES` and doesn't tell the process why, so a
+//! "Permission denied" error the command prints is only put down to the sandbox when it
+//! names a path the sandbox blocks and that `run` itself could access.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// Denied operations kept for the result
+const MAX_DENIALS: usize = 10;
+
+/// Directories a sandbox with `ro=` paths still lets interpreters read: programs,
+/// libraries, devices and process information
+const SYSTEM_READABLE: &[&str] = &[
+    "/bin",
+    "/sbin",
+    "/usr",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/libx32",
+    "/etc/ld.so.cache",
+    "/dev",
+    "/proc",
+];
+
+/// Devices every sandboxed command may write to
+const WRITABLE_DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/full", "/dev/tty"];
+
+/// Paths a function's sandbox opens up: `# @sandbox rw=./build ro=/etc`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxPaths {
+    /// Writable as well as the project directory
+    pub rw: Vec<String>,
+    /// Readable, instead of everything outside the project directory
+    pub ro: Vec<String>,
+}
+
+impl FromStr for SandboxPaths {
+    type Err = String;
+
+    /// `rw=./build ro=/etc`, with each key repeated or given comma-separated paths, or
+    /// nothing for the default sandbox
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut paths = Self::default();
+        for setting in text.split_whitespace() {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("expected rw=path or ro=path, got '{setting}'"))?;
+            let list = match key {
+                "rw" => &mut paths.rw,
+                "ro" => &mut paths.ro,
+                _ => {
+                    return Err(format!(
+                        "unknown sandbox setting '{key}' (expected rw or ro)"
+                    ));
+                }
+            };
+            let given: Vec<String> = value
+                .split(',')
+                .filter(|path| !path.is_empty())
+                .map(String::from)
+                .collect();
+            if given.is_empty() {
+                return Err(format!("no path given for {key}"));
+            }
+            list.extend(given);
+        }
+        Ok(paths)
+    }
+}
+
+/// Where a sandboxed command may write and read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    project: Option<PathBuf>,
+    writable: Vec<PathBuf>,
+    /// `None` when reads aren't restricted
+    readable: Option<Vec<PathBuf>>,
+}
+
+impl Sandbox {
+    /// The sandbox `paths` describe for a function of the project in `project`, whose
+    /// output files go to `output_dir`. Relative paths are taken from the project.
+    #[must_use]
+    pub fn new(paths: &SandboxPaths, project: Option<&Path>, output_dir: &Path) -> Self {
+        let base = project
+            .map(Path::to_path_buf)
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        let resolve = |path: &String| -> PathBuf {
+            match path.strip_prefix("~/") {
+                Some(rest) => crate::config::get_home_dir().unwrap_or_default().join(rest),
+                None => base.join(path),
+            }
+        };
+        let mut writable: Vec<PathBuf> = project.map(Path::to_path_buf).into_iter().collect();
+        writable.extend(paths.rw.iter().map(resolve));
+        writable.push(output_dir.to_path_buf());
+        let readable = (!paths.ro.is_empty()).then(|| {
+            let mut readable: Vec<PathBuf> = paths.ro.iter().map(resolve).collect();
+            readable.extend(project.map(Path::to_path_buf));
+            readable.extend(SYSTEM_READABLE.iter().map(PathBuf::from));
+            readable
+        });
+        Self {
+            project: project.map(Path::to_path_buf),
+            writable,
+            readable,
+        }
+    }
+
+    /// Also let the command write to `path`
+    pub fn allow_writes(&mut self, path: &Path) {
+        self.writable.push(path.to_path_buf());
+    }
+
+    /// Also let the command read `path`, when reads are restricted
+    pub fn allow_reads(&mut self, path: &Path) {
+        if let Some(readable) = &mut self.readable {
+            readable.push(path.to_path_buf());
+        }
+    }
+
+    /// Run `cmd` in the sandbox, creating the writable directories in the project that
+    /// don't exist yet, and point its `TMPDIR` to a private temporary directory. Fails
+    /// when the sandbox can't be set up, rather than running the command without it.
+    ///
+    /// Call it after anything else that sets up the child between fork and exec, which
+    /// the sandbox would otherwise get in the way of. Keep the returned directory until
+    /// the command has finished.
+    ///
+    /// # Errors
+    ///
+    /// When Landlock isn't available, or a directory or the rules can't be created.
+    pub fn apply(&self, cmd: &mut Command) -> Result<PrivateTemp, Box<dyn std::error::Error>> {
+        // Only existing paths can be opened up; `rw=./build` shouldn't wait for a build
+        for path in &self.writable {
+            if !path.exists()
+                && self
+                    .project
+                    .as_ref()
+                    .is_some_and(|project| path.starts_with(project))
+            {
+                std::fs::create_dir_all(path)?;
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let temp = PrivateTemp::create()?;
+            let mut writable = self.writable.clone();
+            writable.push(temp.path().to_path_buf());
+            landlock::restrict(cmd, &writable, self.readable.as_deref())?;
+            cmd.env("TMPDIR", temp.path());
+            Ok(temp)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = cmd;
+            Err("sandbox: Landlock is only available on Linux".into())
+        }
+    }
+
+    /// The lines of `stderr` that report an operation the sandbox denied: a "Permission
+    /// denied" error about a path the sandbox blocks, which `run` could access itself
+    #[must_use]
+    pub fn denied(&self, stderr: &str) -> Vec<String> {
+        let mut denials: Vec<String> = Vec::new();
+        for line in stderr.lines() {
+            let line = line.trim();
+            if line.to_lowercase().contains("permission denied")
+                && error_paths(line).iter().any(|path| self.blocks(path))
+                && !denials.iter().any(|seen| seen == line)
+            {
+                denials.push(line.to_string());
+                if denials.len() == MAX_DENIALS {
+                    break;
+                }
+            }
+        }
+        denials
+    }
+
+    /// Whether the sandbox keeps the command from accessing `path` where it could
+    /// otherwise: writing outside the writable paths or, when reads are restricted,
+    /// reading outside the readable ones
+    fn blocks(&self, path: &Path) -> bool {
+        let path = match std::env::current_dir() {
+            Ok(cwd) => cwd.join(path),
+            Err(_) => path.to_path_buf(),
+        };
+        let outside = |allowed: &[PathBuf]| !allowed.iter().any(|a| path.starts_with(a));
+        if !outside(&self.writable) || WRITABLE_DEVICES.iter().any(|d| path == Path::new(d)) {
+            return false;
+        }
+        let read_blocked = self.readable.as_deref().is_some_and(outside);
+        (read_blocked && accessible(&path, false)) || accessible(&path, true)
+    }
+}
+
+/// The paths an error line names: quoted ones, ones that contain a `/`, and a single
+/// word before a final ": Permission denied" (`bash: out.txt: Permission denied`)
+fn error_paths(line: &str) -> Vec<PathBuf> {
+    const QUOTES: &[char] = &['\'', '"', '`', '\u{2018}', '\u{2019}'];
+    let mut paths: Vec<PathBuf> = line
+        .split(QUOTES)
+        .skip(1)
+        .step_by(2)
+        .chain(line.split_whitespace().filter(|word| word.contains('/')))
+        .map(|word| word.trim_end_matches([':', ',']).trim_matches(QUOTES))
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    if let Some(rest) = line.strip_suffix(": Permission denied")
+        && let Some(name) = rest.rsplit(": ").next()
+        && !name.is_empty()
+        && !name.contains(char::is_whitespace)
+    {
+        paths.push(PathBuf::from(name));
+    }
+    paths
+}
+
+/// Whether `run` itself may read or write `path`, judging a path that doesn't exist by
+/// the directory it would be created in
+#[cfg(unix)]
+fn accessible(path: &Path, write: bool) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Some(existing) = path.ancestors().find(|p| p.exists()) else {
+        return false;
+    };
+    let Ok(name) = std::ffi::CString::new(existing.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mode = if write { libc::W_OK } else { libc::R_OK };
+    // SAFETY: `name` is NUL-terminated
+    unsafe { libc::access(name.as_ptr(), mode) == 0 }
+}
+
+#[cfg(not(unix))]
+fn accessible(_path: &Path, _write: bool) -> bool {
+    false
+}
+
+/// The private temporary directory a sandboxed command's `TMPDIR` points to, removed
+/// when dropped
+#[derive(Debug)]
+pub struct PrivateTemp {
+    dir: tempfile::TempDir,
+}
+
+impl PrivateTemp {
+    #[cfg(target_os = "linux")]
+    fn create() -> std::io::Result<Self> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::Builder::new()
+            .prefix("run-tmp-")
+            .permissions(std::fs::Permissions::from_mode(0o700))
+            .tempdir()?;
+        Ok(Self { dir })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use super::WRITABLE_DEVICES;
+    use std::ffi::CString;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    const CREATE_RULESET_VERSION: u32 = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    const REMOVE_DIR: u64 = 1 << 4;
+    const REMOVE_FILE: u64 = 1 << 5;
+    const MAKE_CHAR: u64 = 1 << 6;
+    const MAKE_DIR: u64 = 1 << 7;
+    const MAKE_REG: u64 = 1 << 8;
+    const MAKE_SOCK: u64 = 1 << 9;
+    const MAKE_FIFO: u64 = 1 << 10;
+    const MAKE_BLOCK: u64 = 1 << 11;
+    const MAKE_SYM: u64 = 1 << 12;
+    /// Since ABI 2
+    const REFER: u64 = 1 << 13;
+    /// Since ABI 3
+    const TRUNCATE: u64 = 1 << 14;
+
+    const READ: u64 = EXECUTE | READ_FILE | READ_DIR;
+    /// Rights that apply to files, as opposed to directories
+    const FILE_RIGHTS: u64 = EXECUTE | WRITE_FILE | READ_FILE | TRUNCATE;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: libc::c_int,
+    }
+
+    /// Make `cmd` enter a Landlock domain where only `writable` can be written to and,
+    /// when given, only `readable` and `writable` can be read
+    pub(super) fn restrict(
+        cmd: &mut Command,
+        writable: &[PathBuf],
+        readable: Option<&[PathBuf]>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let abi = abi_version()?;
+        let mut write = WRITE_FILE
+            | REMOVE_DIR
+            | REMOVE_FILE
+            | MAKE_CHAR
+            | MAKE_DIR
+            | MAKE_REG
+            | MAKE_SOCK
+            | MAKE_FIFO
+            | MAKE_BLOCK
+            | MAKE_SYM;
+        if abi >= 2 {
+            write |= REFER;
+        }
+        if abi >= 3 {
+            write |= TRUNCATE;
+        }
+        let handled = if readable.is_some() {
+            write | READ
+        } else {
+            write
+        };
+        let ruleset = create_ruleset(handled)?;
+        for path in writable {
+            add_rule(&ruleset, path, handled)?;
+        }
+        for device in WRITABLE_DEVICES {
+            add_rule(&ruleset, Path::new(device), handled)?;
+        }
+        for path in readable.unwrap_or_default() {
+            add_rule(&ruleset, path, handled & READ)?;
+        }
+
+        // SAFETY: the closure only makes async-signal-safe calls (prctl, syscall) and
+        // doesn't allocate
+        unsafe {
+            cmd.pre_exec(move || {
+                // Landlock needs this from a process without CAP_SYS_ADMIN, and it keeps
+                // setuid programs from running outside the sandbox
+                if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+
+    /// The Landlock ABI the kernel supports
+    fn abi_version() -> Result<libc::c_long, Box<dyn std::error::Error>> {
+        // SAFETY: asking for the version takes no attributes
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0usize,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if version < 1 {
+            return Err(format!(
+                "sandbox: Landlock is not available: {}",
+                std::io::Error::last_os_error()
+            )
+            .into());
+        }
+        Ok(version)
+    }
+
+    fn create_ruleset(handled: u64) -> Result<OwnedFd, Box<dyn std::error::Error>> {
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        // SAFETY: the attributes outlive the call, which is given their size
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &raw const attr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        let fd = libc::c_int::try_from(fd)
+            .ok()
+            .filter(|fd| *fd >= 0)
+            .ok_or_else(|| {
+                format!(
+                    "sandbox: cannot create Landlock ruleset: {}",
+                    std::io::Error::last_os_error()
+                )
+            })?;
+        // SAFETY: the fd was just created and nothing else owns it
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// Allow `access` beneath `path`, or to it if it's a file. Paths that don't exist
+    /// are left out.
+    fn add_rule(
+        ruleset: &OwnedFd,
+        path: &Path,
+        access: u64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return Ok(());
+        };
+        let access = if metadata.is_dir() {
+            access
+        } else {
+            access & FILE_RIGHTS
+        };
+        let name = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: `name` is NUL-terminated
+        let fd = unsafe { libc::open(name.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Ok(());
+        }
+        // SAFETY: the fd was just opened and nothing else owns it
+        let parent = unsafe { OwnedFd::from_raw_fd(fd) };
+        let attr = PathBeneathAttr {
+            allowed_access: access,
+            parent_fd: parent.as_raw_fd(),
+        };
+        // SAFETY: the attributes and the fd they name outlive the call
+        let added = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset.as_raw_fd(),
+                RULE_PATH_BENEATH,
+                &raw const attr,
+                0u32,
+            )
+        };
+        if added != 0 {
+            return Err(format!(
+                "sandbox: cannot allow access to {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sandbox_paths() {
+        let paths: SandboxPaths = "rw=./build,./dist ro=/etc rw=~/cache".parse().unwrap();
+        assert_eq!(paths.rw, vec!["./build", "./dist", "~/cache"]);
+        assert_eq!(paths.ro, vec!["/etc"]);
+        assert_eq!("".parse::<SandboxPaths>().unwrap(), SandboxPaths::default());
+    }
+
+    #[test]
+    fn test_parse_sandbox_paths_rejects_mistakes() {
+        assert!("rw".parse::<SandboxPaths>().is_err());
+        assert!("rw=".parse::<SandboxPaths>().is_err());
+        assert!("wo=/tmp".parse::<SandboxPaths>().is_err());
+    }
+
+    #[test]
+    fn test_sandbox_defaults_to_the_project() {
+        let project = Path::new("/work/app");
+        let output = Path::new("/work/app/.run-output");
+        let sandbox = Sandbox::new(&SandboxPaths::default(), Some(project), output);
+        assert_eq!(sandbox.writable, vec![project, output]);
+        assert_eq!(sandbox.readable, None);
+    }
+
+    #[test]
+    fn test_sandbox_paths_add_writes_and_restrict_reads() {
+        let paths: SandboxPaths = "rw=/cache ro=/etc".parse().unwrap();
+        let project = Path::new("/work/app");
+        let output = Path::new("/work/app/.run-output");
+        let mut sandbox = Sandbox::new(&paths, Some(project), output);
+        sandbox.allow_reads(Path::new("/tmp/run-x/f.py"));
+        assert_eq!(sandbox.writable, vec![project, Path::new("/cache"), output]);
+        let readable = sandbox.readable.unwrap();
+        assert_eq!(
+            readable[..2],
+            [PathBuf::from("/etc"), project.to_path_buf()]
+        );
+        assert!(readable.contains(&PathBuf::from("/usr")));
+        assert!(readable.contains(&PathBuf::from("/tmp/run-x/f.py")));
+    }
+
+    #[test]
+    fn test_error_paths() {
+        assert_eq!(
+            error_paths("touch: cannot touch '/etc/x': Permission denied"),
+            vec![PathBuf::from("/etc/x"), PathBuf::from("/etc/x")]
+        );
+        assert_eq!(
+            error_paths("bash: line 3: out.txt: Permission denied"),
+            vec![PathBuf::from("out.txt")]
+        );
+        assert!(error_paths("git@github.com: Permission denied (publickey).").is_empty());
+    }
+
+    #[test]
+    fn test_denied_lines() {
+        let project = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let sandbox = Sandbox::new(
+            &SandboxPaths::default(),
+            Some(project.path()),
+            &project.path().join(".run-output"),
+        );
+        let blocked = outside.path().join("x");
+        let stderr = format!(
+            "touch: cannot touch '{blocked}': Permission denied\nok\n\
+             touch: cannot touch '{blocked}': Permission denied\n\
+             PermissionError: [Errno 13] Permission denied: '{inside}'\n\
+             git@github.com: Permission denied (publickey).\n\
+             ssh: connect to host: Permission denied\n",
+            blocked = blocked.display(),
+            inside = project.path().join("y").display(),
+        );
+        // Only the path the sandbox blocks counts, and only once
+        assert_eq!(
+            sandbox.denied(&stderr),
+            vec![format!(
+                "touch: cannot touch '{}': Permission denied",
+                blocked.display()
+            )]
+        );
+    }
+}
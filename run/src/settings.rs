@@ -0,0 +1,188 @@
+//! Project settings declared in the Runfile
+//!
+//! Settings are top-level `# @set <key> <value>` lines (also `# @set key=value`).
+//! Like `# @instructions`, they are only read at brace-depth 0, and when the global
+//! and project Runfiles are merged the project's value wins because it comes later.
+//!
+//! Extra interpreters are declared the same way with `# @interpreter <name> key=value...`
+//! (see [`InterpreterSpec::from_directive`]).
+
+use crate::config::count_unquoted_braces;
+use crate::registry::{self, InterpreterSpec};
+
+/// What bare `run` (no function, no flags) does when no function is marked `@default`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BareAction {
+    /// Start the interactive shell
+    #[default]
+    Repl,
+    /// Print the available functions, like `run --list`
+    List,
+    /// Show a numbered menu of functions and run the one picked
+    Pick,
+}
+
+impl BareAction {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "repl" => Some(Self::Repl),
+            "list" => Some(Self::List),
+            "pick" | "picker" => Some(Self::Pick),
+            _ => None,
+        }
+    }
+}
+
+/// Settings collected from `# @set` lines
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectSettings {
+    /// `# @set bare repl|list|pick`
+    pub bare: BareAction,
+    /// `# @interpreter <name> ...` declarations, in order
+    pub interpreters: Vec<InterpreterSpec>,
+}
+
+impl ProjectSettings {
+    /// Collect settings from (merged) Runfile content.
+    ///
+    /// Unknown keys and invalid values are reported on stderr and otherwise ignored,
+    /// so a Runfile written for a newer version still loads.
+    #[must_use]
+    pub fn from_content(content: &str) -> Self {
+        let mut settings = Self::default();
+
+        for line in top_level_lines(content) {
+            if let Some((key, value)) = top_level_setting(line)
+                && let Err(e) = settings.apply(key, value)
+            {
+                eprintln!("run: warning: ignoring `# @set {key} {value}`: {e}");
+            }
+            if let Some((name, rest)) = top_level_interpreter(line) {
+                match InterpreterSpec::from_directive(name, rest) {
+                    Ok(spec) => settings.interpreters.push(spec),
+                    Err(e) => eprintln!("run: warning: ignoring `# @interpreter {name}`: {e}"),
+                }
+            }
+        }
+
+        settings
+    }
+
+    fn apply(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "bare" => {
+                self.bare = BareAction::parse(value)
+                    .ok_or_else(|| "expected one of: repl, list, pick".to_string())?;
+            }
+            _ => return Err(format!("unknown setting '{key}'")),
+        }
+        Ok(())
+    }
+
+    /// Make the declared interpreters available to `@shell` and shebangs
+    pub fn register_interpreters(&self) {
+        for spec in &self.interpreters {
+            registry::register(spec.clone());
+        }
+    }
+}
+
+/// Names and aliases of the valid `# @interpreter` declarations in `content`, which
+/// `@shell` may use before they are registered. Invalid ones are left for
+/// [`ProjectSettings::from_content`] to report.
+#[must_use]
+pub fn declared_interpreter_names(content: &str) -> Vec<String> {
+    top_level_lines(content)
+        .filter_map(top_level_interpreter)
+        .filter_map(|(name, rest)| InterpreterSpec::from_directive(name, rest).ok())
+        .flat_map(|spec| std::iter::once(spec.name).chain(spec.aliases))
+        .collect()
+}
+
+/// Lines of `content` outside any function body (at brace-depth 0)
+fn top_level_lines(content: &str) -> impl Iterator<Item = &str> {
+    let mut brace_depth: usize = 0;
+    content.lines().filter(move |line| {
+        let top_level = brace_depth == 0;
+        let (opens, closes) = count_unquoted_braces(line);
+        brace_depth = brace_depth.saturating_add(opens).saturating_sub(closes);
+        top_level
+    })
+}
+
+/// If `line` is a `# @interpreter name settings...` directive, return the name and settings.
+fn top_level_interpreter(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim();
+    let rest = trimmed
+        .strip_prefix("# @interpreter ")
+        .or_else(|| trimmed.strip_prefix("#@interpreter "))?
+        .trim();
+    Some(rest.split_once(char::is_whitespace).unwrap_or((rest, "")))
+}
+
+/// If `line` is a `# @set key value` directive, return the key and value.
+fn top_level_setting(line: &str) -> Option<(&str, &str)> {
+    let trimmed = line.trim();
+    let rest = trimmed
+        .strip_prefix("# @set ")
+        .or_else(|| trimmed.strip_prefix("#@set "))?
+        .trim();
+    let (key, value) = rest
+        .split_once('=')
+        .or_else(|| rest.split_once(char::is_whitespace))?;
+    let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+    Some((key.trim(), value))
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bare_defaults_to_repl() {
+        let settings = ProjectSettings::from_content("build() cargo build\n");
+        assert_eq!(settings.bare, BareAction::Repl);
+    }
+
+    #[test]
+    fn test_bare_setting_forms() {
+        assert_eq!(
+            ProjectSettings::from_content("# @set bare list\n").bare,
+            BareAction::List
+        );
+        assert_eq!(
+            ProjectSettings::from_content("#@set bare = \"pick\"\n").bare,
+            BareAction::Pick
+        );
+    }
+
+    #[test]
+    fn test_later_setting_wins_and_blocks_are_ignored() {
+        let content = "# @set bare list\nf() {\n  # @set bare repl\n}\n# @set bare pick\n";
+        assert_eq!(
+            ProjectSettings::from_content(content).bare,
+            BareAction::Pick
+        );
+    }
+
+    #[test]
+    fn test_invalid_setting_is_ignored() {
+        let content = "# @set bare list\n# @set bare sometimes\n# @set colour on\n";
+        assert_eq!(
+            ProjectSettings::from_content(content).bare,
+            BareAction::List
+        );
+    }
+
+    #[test]
+    fn test_interpreter_declarations() {
+        let content = "# @interpreter mypy command=python3 code=-c dialect=python\n\
+                       # @interpreter broken code=-x\n\
+                       f() {\n  # @interpreter inner command=x\n}\n";
+        let settings = ProjectSettings::from_content(content);
+        assert_eq!(settings.interpreters.len(), 1);
+        assert_eq!(settings.interpreters[0].name, "mypy");
+        assert_eq!(settings.interpreters[0].executables, ["python3"]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Line mapping from merged/expanded Runfile content back to the files it came from.
+//!
+//! `source` directives are inlined and the global `~/.runfile` is concatenated with the
+//! project Runfile before parsing, so line numbers in the parsed text no longer match
+//! any file on disk. A `SourceMap` records, for every line of that combined text, which
+//! file and line it originated from.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Environment variable pointing a `run` subprocess at a JSON-encoded [`SourceMap`]
+/// for its `--runfile`, used when that file is a temporary merge of other Runfiles.
+pub const SOURCE_MAP_ENV: &str = "RUN_SOURCE_MAP";
+
+/// Origin of each line of a combined Runfile text.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceMap {
+    /// Files referenced by `lines`
+    files: Vec<PathBuf>,
+    /// For each combined line (0-indexed): index into `files` and the 1-indexed line
+    /// number in that file. `None` marks synthetic lines such as merge separators.
+    lines: Vec<Option<(usize, usize)>>,
+}
+
+impl SourceMap {
+    /// Map every line of `content` one-to-one onto `file`.
+    #[must_use]
+    pub fn for_file(file: &Path, content: &str) -> Self {
+        let mut map = Self::default();
+        for (idx, _) in content.lines().enumerate() {
+            map.push_line(file, idx + 1);
+        }
+        map
+    }
+
+    /// Record that the next combined line comes from `file` at `line`.
+    pub fn push_line(&mut self, file: &Path, line: usize) {
+        let idx = if let Some(idx) = self.files.iter().position(|f| f == file) {
+            idx
+        } else {
+            self.files.push(file.to_path_buf());
+            self.files.len() - 1
+        };
+        self.lines.push(Some((idx, line)));
+    }
+
+    /// Record a synthetic line that does not exist in any file.
+    pub fn push_synthetic(&mut self) {
+        self.lines.push(None);
+    }
+
+    /// Append another map, as when two texts are concatenated.
+    pub fn append(&mut self, other: &SourceMap) {
+        for entry in &other.lines {
+            match entry {
+                Some((file_idx, line)) => self.push_line(&other.files[*file_idx], *line),
+                None => self.push_synthetic(),
+            }
+        }
+    }
+
+    /// Number of combined lines covered by the map.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Whether the map covers no lines.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Resolve a 1-indexed combined line to its file and 1-indexed line in that file.
+    #[must_use]
+    pub fn resolve(&self, line: usize) -> Option<(&Path, usize)> {
+        let (file_idx, file_line) = (*self.lines.get(line.checked_sub(1)?)?)?;
+        Some((self.files[file_idx].as_path(), file_line))
+    }
+
+    /// Resolve a combined line to a `file:line`-style pair for display, shortening the
+    /// path relative to the current directory or home directory when possible.
+    #[must_use]
+    pub fn describe(&self, line: usize) -> Option<(String, usize)> {
+        self.resolve(line)
+            .map(|(file, file_line)| (display_path(file), file_line))
+    }
+}
+
+/// Shorten a path for display: relative to the current directory when inside it,
+/// `~/`-prefixed when inside the home directory, otherwise unchanged.
+#[must_use]
+pub fn display_path(path: &Path) -> String {
+    if let Ok(cwd) = std::env::current_dir()
+        && let Ok(relative) = path.strip_prefix(&cwd)
+    {
+        return relative.display().to_string();
+    }
+    if let Some(home) = crate::config::get_home_dir()
+        && let Ok(relative) = path.strip_prefix(&home)
+    {
+        return format!("~/{}", relative.display());
+    }
+    path.display().to_string()
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_file_maps_lines_one_to_one() {
+        let map = SourceMap::for_file(Path::new("/p/Runfile"), "a\nb\nc");
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.resolve(2), Some((Path::new("/p/Runfile"), 2)));
+        assert_eq!(map.resolve(0), None);
+        assert_eq!(map.resolve(4), None);
+    }
+
+    #[test]
+    fn test_append_keeps_original_files() {
+        let mut map = SourceMap::for_file(Path::new("/home/u/.runfile"), "a\nb");
+        map.push_synthetic();
+        map.append(&SourceMap::for_file(Path::new("/p/Runfile"), "c"));
+        assert_eq!(map.resolve(2), Some((Path::new("/home/u/.runfile"), 2)));
+        assert_eq!(map.resolve(3), None);
+        assert_eq!(map.resolve(4), Some((Path::new("/p/Runfile"), 1)));
+    }
+}
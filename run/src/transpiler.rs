@@ -1,67 +1,64 @@
 // Transpiler for converting Runfile functions to shell syntax
 use crate::ast::ShellType;
+use crate::registry;
 use which::which;
 
+/// An interpreter from the [`registry`], by name or shebang command line
 #[derive(Debug, Clone, PartialEq)]
-pub enum Interpreter {
-    Sh,
-    Bash,
-    Pwsh,
-    Python,
-    Python3,
-    Node,
-    Ruby,
-}
+pub struct Interpreter(String);
 
 impl Interpreter {
+    #[must_use]
+    pub fn named(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    /// Registry name of this interpreter
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// How to run this interpreter (see [`registry::resolve`])
+    #[must_use]
+    pub fn spec(&self) -> registry::InterpreterSpec {
+        registry::resolve(self.name())
+    }
+
+    /// Whether this is the interpreter called `name` (or one of its aliases)
+    #[must_use]
+    pub fn is(&self, name: &str) -> bool {
+        self.spec().is_named(name)
+    }
+
     /// Check if this interpreter is compatible with another for function composition.
     ///
-    /// Compatibility is asymmetric for `sh`/`bash`: bash is a superset of sh and can
-    /// run sh code, but sh (e.g. dash on Ubuntu) cannot run bash-specific syntax.
+    /// Compatibility comes from the registry's groups and is asymmetric for `sh`/`bash`:
+    /// bash is a superset of sh and can run sh code, but sh (e.g. dash on Ubuntu) cannot
+    /// run bash-specific syntax.
     ///
     /// - `bash.is_compatible_with(sh)` → `true`
     /// - `sh.is_compatible_with(bash)` → `false`
     #[must_use]
     pub fn is_compatible_with(&self, other: &Interpreter) -> bool {
-        matches!(
-            (self, other),
-            // bash is a superset: it can run both bash and sh code
-            (Interpreter::Bash, Interpreter::Sh | Interpreter::Bash)
-                // sh can only run sh code (not bash-specific syntax)
-                | (Interpreter::Sh, Interpreter::Sh)
-                | (Interpreter::Pwsh, Interpreter::Pwsh)
-                | (
-                    Interpreter::Python | Interpreter::Python3,
-                    Interpreter::Python | Interpreter::Python3
-                )
-                | (Interpreter::Node, Interpreter::Node)
-                | (Interpreter::Ruby, Interpreter::Ruby)
-        )
+        self == other || self.spec().can_run(&other.spec())
     }
 
     /// Convert `ShellType` to Interpreter
     #[must_use]
     pub fn from_shell_type(shell_type: &ShellType) -> Self {
-        match shell_type {
-            ShellType::Sh => Interpreter::Sh,
-            ShellType::Bash => Interpreter::Bash,
-            ShellType::Pwsh => Interpreter::Pwsh,
-            ShellType::Python => Interpreter::Python,
-            ShellType::Python3 => Interpreter::Python3,
-            ShellType::Node => Interpreter::Node,
-            ShellType::Ruby => Interpreter::Ruby,
-        }
+        Self::named(shell_type.name())
     }
 }
 
 impl Default for Interpreter {
     fn default() -> Self {
         if cfg!(target_os = "windows") {
-            Interpreter::Pwsh
+            Self::named("pwsh")
         } else if which("bash").is_ok() {
-            Interpreter::Bash
+            Self::named("bash")
         } else {
-            Interpreter::Sh
+            Self::named("sh")
         }
     }
 }
@@ -130,150 +127,459 @@ fn indent(text: &str, prefix: &str) -> String {
     result.join("\n")
 }
 
+/// How shell code starts `run` for a sibling: the binary of the current call (see
+/// [`crate::config::sibling_call_env`]), or the one on `PATH` outside of `run`
+pub const RUN_COMMAND: &str = "\"${RUN_EXE:-run}\"";
+
 /// Rewrite call sites in function body to use sanitised names
 ///
 /// This replaces function names containing colons with their sanitised versions
-/// (colons replaced with double underscores). Only replaces names in command
-/// position — at the start of a line (after optional whitespace) or after shell
-/// command separators (`&&`, `||`, `;`, `|`, `(`). Names appearing as arguments
-/// to other commands (e.g. `pnpm test:unit`) are left untouched.
+/// (colons replaced with double underscores). Only names in command position are
+/// rewritten — at the start of a line, after `&&`, `||`, `;`, `|`, `(`, inside `$(...)`
+/// and backticks, after `if`, `then`, `else`, `do`, `!`, `time` and the like, and at the
+/// start of a `case` branch. Names appearing as arguments to other commands (e.g.
+/// `pnpm test:unit`) or as `case` patterns, in quotes, comments or heredocs are left
+/// untouched.
+///
+/// Commands that run their argument as a program (`exec`, `xargs`, `sudo`, `env`)
+/// can't see shell functions, so a sibling called through them becomes `run <name>`:
+/// `xargs docker:build` → `xargs "${RUN_EXE:-run}" docker build`.
 #[must_use]
 pub fn rewrite_call_sites(body: &str, sibling_names: &[&str]) -> String {
-    let colon_siblings: Vec<&str> = sibling_names
-        .iter()
-        .filter(|s| s.contains(':'))
-        .copied()
-        .collect();
-
-    if colon_siblings.is_empty() {
+    if sibling_names.is_empty() {
         return body.to_string();
     }
+    CallScanner::scan(body, sibling_names, |sibling, kind| match kind {
+        CallKind::Shell => sanitise_name(sibling),
+        CallKind::Program => format!("{RUN_COMMAND} {}", sibling.replace(':', " ")),
+    })
+}
+
+/// Find the siblings a function body calls.
+///
+/// Uses the same command-position rules as [`rewrite_call_sites`], so a name only
+/// counts as a call where it would be rewritten. Each sibling is listed once, in
+/// order of first call.
+#[must_use]
+pub fn called_siblings<'a>(body: &str, sibling_names: &[&'a str]) -> Vec<&'a str> {
+    let mut called = Vec::new();
+    if sibling_names.is_empty() {
+        return called;
+    }
+    CallScanner::scan(body, sibling_names, |sibling, _| {
+        if !called.contains(&sibling) {
+            called.push(sibling);
+        }
+        sibling.to_string()
+    });
+    called
+}
 
-    body.lines()
-        .map(|line| rewrite_line(line, &colon_siblings))
-        .collect::<Vec<_>>()
-        .join("\n")
+/// How a command word is run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CallKind {
+    /// By the shell itself, which can call functions
+    Shell,
+    /// As a program started by another command (`xargs build`), which can't
+    Program,
 }
 
-/// Rewrite sibling call sites within a single line.
+/// What the next word of a command is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WordRole {
+    /// A command name (or a reserved word or assignment that precedes one)
+    Command,
+    /// Options of a prefix command such as `sudo -u root` or `time -p`, followed by the
+    /// command it runs
+    Prefix {
+        command: &'static str,
+        kind: CallKind,
+        /// The previous word was an option that takes an argument
+        option_argument: bool,
+    },
+    /// An argument
+    Argument,
+    /// The word after `case`, up to `in`
+    CaseSubject,
+    /// A `case` pattern, up to its `)`
+    Pattern,
+}
+
+/// Reserved words after which a new command starts
+const RESERVED_WORDS: &[&str] = &[
+    "if", "elif", "then", "else", "while", "until", "do", "!", "{",
+];
+
+/// Commands that run the command given as their argument: `(name, kind)`
+const PREFIX_COMMANDS: &[(&str, CallKind)] = &[
+    ("time", CallKind::Shell),
+    ("exec", CallKind::Program),
+    ("xargs", CallKind::Program),
+    ("sudo", CallKind::Program),
+    ("env", CallKind::Program),
+];
+
+/// Whether `option` of a prefix command consumes the following word
+fn option_takes_argument(command: &str, option: &str) -> bool {
+    let options: &[&str] = match command {
+        "time" => &["-f", "-o"],
+        "exec" => &["-a"],
+        "xargs" => &["-a", "-d", "-E", "-I", "-L", "-n", "-P", "-s"],
+        "sudo" => &[
+            "-C", "-D", "-g", "-h", "-p", "-R", "-r", "-T", "-t", "-U", "-u",
+        ],
+        "env" => &["-C", "-S", "-u"],
+        _ => &[],
+    };
+    options.contains(&option)
+}
+
+/// A `NAME=value` word
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Characters that end a shell word
+fn is_metachar(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ';' | '&' | '|' | '(' | ')' | '<' | '>')
+}
+
+/// A small shell tokenizer that finds sibling calls in command position.
 ///
-/// Scans for command positions (start of line, after `&&`, `||`, `;`, `|`, `(`)
-/// and only rewrites sibling names found there.
-fn rewrite_line(line: &str, colon_siblings: &[&str]) -> String {
-    let mut result = String::new();
-    let chars: Vec<char> = line.chars().collect();
-    let len = chars.len();
-    let mut i = 0;
-
-    while i < len {
-        // Detect command position: start of line or just after a separator
-        if is_command_position(&result) {
-            // Skip whitespace
-            let ws_start = i;
-            while i < len && (chars[i] == ' ' || chars[i] == '\t') {
-                i += 1;
+/// The body is copied through unchanged except for sibling names in command position,
+/// which are replaced by `on_call`. It understands quoting, `$(...)`, backticks,
+/// `${...}`, comments and heredocs well enough to tell commands from arguments; on
+/// anything it doesn't understand it still copies the input verbatim.
+struct CallScanner<'s, 'a, F> {
+    chars: Vec<char>,
+    pos: usize,
+    out: String,
+    siblings: &'s [&'a str],
+    on_call: F,
+    /// Heredoc delimiters whose bodies start after the current line, and whether
+    /// leading tabs are stripped (`<<-`)
+    heredocs: Vec<(String, bool)>,
+}
+
+impl<'a, F: FnMut(&'a str, CallKind) -> String> CallScanner<'_, 'a, F> {
+    fn scan(body: &str, siblings: &[&'a str], on_call: F) -> String {
+        let mut scanner = CallScanner {
+            chars: body.chars().collect(),
+            pos: 0,
+            out: String::with_capacity(body.len()),
+            siblings,
+            on_call,
+            heredocs: Vec::new(),
+        };
+        scanner.commands(None);
+        // Whatever an unbalanced `)` or backtick left behind
+        while scanner.pos < scanner.chars.len() {
+            scanner.copy(1);
+        }
+        scanner.out
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn copy(&mut self, count: usize) {
+        for _ in 0..count {
+            if let Some(c) = self.peek(0) {
+                self.out.push(c);
+                self.pos += 1;
             }
+        }
+    }
 
-            // Try to match a sibling name at this position
-            if let Some((sibling, end)) = match_sibling_at(&chars, i, colon_siblings) {
-                // Check word boundary after the match
-                let after_ok = end >= len || !is_word_char(chars[end]);
-                if after_ok {
-                    // Push the whitespace, then the sanitised name
-                    result.push_str(&chars[ws_start..i].iter().collect::<String>());
-                    result.push_str(&sanitise_name(sibling));
-                    i = end;
-                    continue;
+    /// Scan a command list until `end` (`)` or a backtick), which is consumed
+    fn commands(&mut self, end: Option<char>) {
+        let mut role = WordRole::Command;
+        let mut redirect_target = false;
+        // `case` commands open in this list, whose `;;` lead to another pattern
+        let mut cases = 0;
+
+        while let Some(c) = self.peek(0) {
+            if c == ')' && role == WordRole::Pattern {
+                self.copy(1);
+                role = WordRole::Command;
+                continue;
+            }
+            if Some(c) == end {
+                self.copy(1);
+                return;
+            }
+            match c {
+                '\n' => {
+                    self.copy(1);
+                    self.heredoc_bodies();
+                    if !matches!(role, WordRole::CaseSubject | WordRole::Pattern) {
+                        role = WordRole::Command;
+                    }
+                }
+                c if c.is_whitespace() => self.copy(1),
+                '#' => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.copy(1);
+                    }
+                }
+                '&' if self.peek(1) == Some('>') => {
+                    self.copy(2);
+                    redirect_target = true;
+                }
+                // `;;`, `;&` and `;;&` end a `case` branch
+                ';' if cases > 0 && matches!(self.peek(1), Some(';' | '&')) => {
+                    self.copy(2);
+                    if self.peek(0) == Some('&') {
+                        self.copy(1);
+                    }
+                    role = WordRole::Pattern;
+                }
+                // `(a | b)`: the parenthesis and alternatives of a pattern
+                '(' | '|' if role == WordRole::Pattern => self.copy(1),
+                ';' | '&' | '|' | '(' | ')' => {
+                    self.copy(1);
+                    role = WordRole::Command;
+                }
+                '<' | '>' => redirect_target = self.redirection(),
+                _ => {
+                    let start = self.out.len();
+                    self.word(end);
+                    // `2>file`: the descriptor belongs to the redirection
+                    let word = &self.out[start..];
+                    let is_descriptor = word.chars().all(|c| c.is_ascii_digit())
+                        && matches!(self.peek(0), Some('<' | '>'));
+                    if redirect_target {
+                        redirect_target = false;
+                    } else if !is_descriptor {
+                        role = match (role, word) {
+                            (WordRole::Command, "case") => {
+                                cases += 1;
+                                WordRole::CaseSubject
+                            }
+                            (WordRole::CaseSubject, "in") => WordRole::Pattern,
+                            (WordRole::Command | WordRole::Pattern, "esac") if cases > 0 => {
+                                cases -= 1;
+                                WordRole::Argument
+                            }
+                            (WordRole::CaseSubject | WordRole::Pattern, _) => role,
+                            _ => self.command_word(start, role),
+                        };
+                    }
                 }
             }
-
-            // No match — rewind to before whitespace skip and fall through
-            i = ws_start;
         }
+    }
 
-        // Check for command separators that introduce a new command position
-        if i < len {
-            // Check for two-char separators: && ||
-            if i + 1 < len {
-                let two = [chars[i], chars[i + 1]];
-                if two == ['&', '&'] || two == ['|', '|'] {
-                    result.push(chars[i]);
-                    result.push(chars[i + 1]);
-                    i += 2;
-                    continue;
-                }
+    /// Copy a redirection operator, registering heredocs. Returns whether a file name follows.
+    fn redirection(&mut self) -> bool {
+        if self.peek(0) == Some('<') && self.peek(1) == Some('<') && self.peek(2) != Some('<') {
+            self.copy(2);
+            let strip_tabs = self.peek(0) == Some('-');
+            if strip_tabs {
+                self.copy(1);
             }
-            // Single-char separators: ; | (
-            if chars[i] == ';' || chars[i] == '|' || chars[i] == '(' {
-                result.push(chars[i]);
-                i += 1;
-                continue;
+            while self.peek(0).is_some_and(|c| c == ' ' || c == '\t') {
+                self.copy(1);
             }
-
-            result.push(chars[i]);
-            i += 1;
+            let start = self.out.len();
+            self.word(None);
+            let delimiter: String = self.out[start..]
+                .chars()
+                .filter(|c| !matches!(c, '\'' | '"' | '\\'))
+                .collect();
+            self.heredocs.push((delimiter, strip_tabs));
+            return false;
+        }
+        // `<`, `>`, `>>`, `<<<`, `>&`, `<&`, `>|`, `<>`
+        self.copy(1);
+        while self
+            .peek(0)
+            .is_some_and(|c| matches!(c, '<' | '>' | '&' | '|'))
+        {
+            self.copy(1);
         }
+        true
     }
 
-    result
-}
-
-/// Check whether `result` (the output so far for the current line) indicates
-/// that the next token would be in command position.
-fn is_command_position(result: &str) -> bool {
-    // Empty → start of line
-    if result.is_empty() {
-        return true;
-    }
-    // Check the last non-whitespace character
-    let trimmed = result.trim_end();
-    if trimmed.is_empty() {
-        return true;
+    /// Copy heredoc bodies registered on the line that just ended
+    fn heredoc_bodies(&mut self) {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.chars.len() {
+                let start = self.pos;
+                while self.peek(0).is_some_and(|c| c != '\n') {
+                    self.copy(1);
+                }
+                let line: String = self.chars[start..self.pos].iter().collect();
+                self.copy(1);
+                let line = if strip_tabs {
+                    line.trim_start_matches('\t')
+                } else {
+                    &line
+                };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
     }
-    let Some(last) = trimmed.chars().last() else {
-        return true;
-    };
-    // After these characters the next token is a new command
-    matches!(last, '&' | '|' | ';' | '(')
-}
 
-/// Try to match any sibling name at position `start` in `chars`.
-/// Returns the matched sibling name and the end index if found.
-fn match_sibling_at<'a>(
-    chars: &[char],
-    start: usize,
-    colon_siblings: &[&'a str],
-) -> Option<(&'a str, usize)> {
-    // Try longest match first to avoid prefix conflicts
-    let mut best: Option<(&'a str, usize)> = None;
-
-    for &sibling in colon_siblings {
-        let sib_chars: Vec<char> = sibling.chars().collect();
-        let end = start + sib_chars.len();
-
-        if end > chars.len() {
-            continue;
+    /// Copy one shell word, scanning command substitutions inside it
+    fn word(&mut self, end: Option<char>) {
+        while let Some(c) = self.peek(0) {
+            if is_metachar(c) || Some(c) == end {
+                return;
+            }
+            match c {
+                '\\' => self.copy(2),
+                '\'' => {
+                    self.copy(1);
+                    while self.peek(0).is_some_and(|c| c != '\'') {
+                        self.copy(1);
+                    }
+                    self.copy(1);
+                }
+                '"' => self.double_quoted(),
+                '`' => {
+                    self.copy(1);
+                    self.commands(Some('`'));
+                }
+                '$' if self.peek(1) == Some('(') => {
+                    self.copy(2);
+                    self.commands(Some(')'));
+                }
+                '$' if self.peek(1) == Some('{') => self.parameter_expansion(false),
+                _ => self.copy(1),
+            }
         }
+    }
 
-        if chars[start..end]
-            .iter()
-            .zip(sib_chars.iter())
-            .all(|(a, b)| a == b)
+    /// Copy a `${...}` expansion up to its matching `}`, so `#`, `;` and `|` in it
+    /// aren't taken for comments or separators. Inside double quotes a `'` is literal.
+    fn parameter_expansion(&mut self, in_double_quotes: bool) {
+        self.copy(2);
+        let mut depth = 1;
+        while depth > 0
+            && let Some(c) = self.peek(0)
         {
-            // Check that character before is not a word char (for word boundary)
-            let before_ok = start == 0 || !is_word_char(chars[start - 1]);
-            if before_ok && best.is_none_or(|(prev, _)| sibling.len() > prev.len()) {
-                best = Some((sibling, end));
+            match c {
+                '\\' => self.copy(2),
+                '\'' if !in_double_quotes => {
+                    self.copy(1);
+                    while self.peek(0).is_some_and(|c| c != '\'') {
+                        self.copy(1);
+                    }
+                    self.copy(1);
+                }
+                '"' => self.double_quoted(),
+                '`' => {
+                    self.copy(1);
+                    self.commands(Some('`'));
+                }
+                '$' if self.peek(1) == Some('(') => {
+                    self.copy(2);
+                    self.commands(Some(')'));
+                }
+                '$' if self.peek(1) == Some('{') => self.parameter_expansion(in_double_quotes),
+                '}' => {
+                    depth -= 1;
+                    self.copy(1);
+                }
+                _ => self.copy(1),
             }
         }
     }
 
-    best
-}
+    /// Copy a double-quoted string, scanning the command substitutions inside it
+    fn double_quoted(&mut self) {
+        self.copy(1);
+        while let Some(c) = self.peek(0) {
+            match c {
+                '"' => {
+                    self.copy(1);
+                    return;
+                }
+                '\\' => self.copy(2),
+                '`' => {
+                    self.copy(1);
+                    self.commands(Some('`'));
+                }
+                '$' if self.peek(1) == Some('(') => {
+                    self.copy(2);
+                    self.commands(Some(')'));
+                }
+                '$' if self.peek(1) == Some('{') => self.parameter_expansion(true),
+                _ => self.copy(1),
+            }
+        }
+    }
+
+    /// Handle the word copied to `out[start..]` given its role, returning the role of
+    /// the next word
+    fn command_word(&mut self, start: usize, role: WordRole) -> WordRole {
+        let word = &self.out[start..];
+        let call = |kind| {
+            self.siblings
+                .iter()
+                .find(|&&sibling| sibling == word)
+                .map(|&sibling| (sibling, kind))
+        };
+        let (call, next) = match role {
+            WordRole::Argument | WordRole::CaseSubject | WordRole::Pattern => return role,
+            WordRole::Prefix {
+                command,
+                kind,
+                option_argument,
+            } => {
+                if option_argument {
+                    return WordRole::Prefix {
+                        command,
+                        kind,
+                        option_argument: false,
+                    };
+                }
+                if word.starts_with('-') {
+                    return WordRole::Prefix {
+                        command,
+                        kind,
+                        option_argument: option_takes_argument(command, word),
+                    };
+                }
+                if is_assignment(word) && matches!(command, "env" | "sudo") {
+                    return role;
+                }
+                (call(kind), Self::role_after(word))
+            }
+            WordRole::Command => {
+                if RESERVED_WORDS.contains(&word) || is_assignment(word) {
+                    return WordRole::Command;
+                }
+                (call(CallKind::Shell), Self::role_after(word))
+            }
+        };
+        if let Some((sibling, kind)) = call {
+            let replacement = (self.on_call)(sibling, kind);
+            self.out.truncate(start);
+            self.out.push_str(&replacement);
+        }
+        next
+    }
 
-/// Check if a character is a word character (alphanumeric, underscore, or colon)
-fn is_word_char(c: char) -> bool {
-    c.is_alphanumeric() || c == '_' || c == ':'
+    /// Role of the word following the command name `word`
+    fn role_after(word: &str) -> WordRole {
+        PREFIX_COMMANDS
+            .iter()
+            .find(|(command, _)| *command == word)
+            .map_or(WordRole::Argument, |&(command, kind)| WordRole::Prefix {
+                command,
+                kind,
+                option_argument: false,
+            })
+    }
 }
 
 #[cfg(test)]
@@ -362,8 +668,8 @@ mod tests {
 
     #[test]
     fn test_interpreter_compatibility_sh_bash() {
-        let sh = Interpreter::Sh;
-        let bash = Interpreter::Bash;
+        let sh = Interpreter::named("sh");
+        let bash = Interpreter::named("bash");
 
         // bash is a superset of sh: it can run sh code
         assert!(bash.is_compatible_with(&sh));
@@ -376,8 +682,8 @@ mod tests {
 
     #[test]
     fn test_interpreter_compatibility_pwsh() {
-        let pwsh = Interpreter::Pwsh;
-        let sh = Interpreter::Sh;
+        let pwsh = Interpreter::named("pwsh");
+        let sh = Interpreter::named("sh");
 
         assert!(pwsh.is_compatible_with(&pwsh));
         assert!(!pwsh.is_compatible_with(&sh));
@@ -386,11 +692,11 @@ mod tests {
 
     #[test]
     fn test_interpreter_compatibility_polyglot() {
-        let python = Interpreter::Python;
-        let python3 = Interpreter::Python3;
-        let node = Interpreter::Node;
-        let ruby = Interpreter::Ruby;
-        let sh = Interpreter::Sh;
+        let python = Interpreter::named("python");
+        let python3 = Interpreter::named("python3");
+        let node = Interpreter::named("node");
+        let ruby = Interpreter::named("ruby");
+        let sh = Interpreter::named("sh");
 
         // Python and Python3 can compose with each other
         assert!(python.is_compatible_with(&python3));
@@ -570,6 +876,174 @@ mod tests {
         assert_eq!(rewrite_call_sites("(test:unit)", &siblings), "(test__unit)");
     }
 
+    #[test]
+    fn test_rewrite_call_sites_after_reserved_words() {
+        let siblings = vec!["test:unit", "test:lint"];
+
+        assert_eq!(
+            rewrite_call_sites("if test:unit; then test:lint; fi", &siblings),
+            "if test__unit; then test__lint; fi"
+        );
+        assert_eq!(
+            rewrite_call_sites("while ! test:unit; do sleep 1; done", &siblings),
+            "while ! test__unit; do sleep 1; done"
+        );
+        // A reserved word that is only an argument doesn't start a command
+        assert_eq!(
+            rewrite_call_sites("echo then test:unit", &siblings),
+            "echo then test:unit"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_call_sites_in_command_substitutions() {
+        let siblings = vec!["docker:build", "version:get"];
+
+        assert_eq!(
+            rewrite_call_sites("tag=$(version:get)", &siblings),
+            "tag=$(version__get)"
+        );
+        assert_eq!(
+            rewrite_call_sites("echo \"built $(docker:build --quiet)\"", &siblings),
+            "echo \"built $(docker__build --quiet)\""
+        );
+        assert_eq!(
+            rewrite_call_sites("tag=`version:get`", &siblings),
+            "tag=`version__get`"
+        );
+        assert_eq!(
+            rewrite_call_sites("echo $(echo docker:build)", &siblings),
+            "echo $(echo docker:build)"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_call_sites_after_else_and_time() {
+        let siblings = vec!["test:unit", "test:lint"];
+
+        assert_eq!(
+            rewrite_call_sites("if true\nthen\n  test:unit\nelse test:lint\nfi", &siblings),
+            "if true\nthen\n  test__unit\nelse test__lint\nfi"
+        );
+        assert_eq!(
+            rewrite_call_sites("time test:unit", &siblings),
+            "time test__unit"
+        );
+        assert_eq!(
+            rewrite_call_sites("time -p test:unit && CI=1 test:lint", &siblings),
+            "time -p test__unit && CI=1 test__lint"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_call_sites_through_command_wrappers() {
+        let siblings = vec!["build", "docker:push"];
+
+        assert_eq!(
+            rewrite_call_sites("ls | xargs build", &siblings),
+            "ls | xargs \"${RUN_EXE:-run}\" build"
+        );
+        assert_eq!(
+            rewrite_call_sites("xargs -n 1 docker:push < tags", &siblings),
+            "xargs -n 1 \"${RUN_EXE:-run}\" docker push < tags"
+        );
+        assert_eq!(
+            rewrite_call_sites("sudo -u deploy docker:push", &siblings),
+            "sudo -u deploy \"${RUN_EXE:-run}\" docker push"
+        );
+        assert_eq!(
+            rewrite_call_sites("env A=1 B=2 build", &siblings),
+            "env A=1 B=2 \"${RUN_EXE:-run}\" build"
+        );
+        assert_eq!(
+            rewrite_call_sites("exec build", &siblings),
+            "exec \"${RUN_EXE:-run}\" build"
+        );
+        // Arguments of the wrapped command are still arguments
+        assert_eq!(
+            rewrite_call_sites("sudo echo build", &siblings),
+            "sudo echo build"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_call_sites_ignores_quotes_comments_and_heredocs() {
+        let siblings = vec!["docker:build"];
+
+        assert_eq!(
+            rewrite_call_sites("echo 'x; docker:build' \"; docker:build\"", &siblings),
+            "echo 'x; docker:build' \"; docker:build\""
+        );
+        assert_eq!(
+            rewrite_call_sites("# docker:build\ndocker:build # ; docker:build", &siblings),
+            "# docker:build\ndocker__build # ; docker:build"
+        );
+        let body = "cat <<'EOF' > notes\ndocker:build\n$(docker:build)\nEOF\ndocker:build";
+        assert_eq!(
+            rewrite_call_sites(body, &siblings),
+            "cat <<'EOF' > notes\ndocker:build\n$(docker:build)\nEOF\ndocker__build"
+        );
+        let body = "cat <<-END; docker:build\n\tdocker:build\n\tEND\ndocker:build";
+        assert_eq!(
+            rewrite_call_sites(body, &siblings),
+            "cat <<-END; docker__build\n\tdocker:build\n\tEND\ndocker__build"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_call_sites_skips_parameter_expansions() {
+        let siblings = vec!["docker:build"];
+
+        // `#`, `;` and `|` inside `${...}` neither start a comment nor end the command
+        assert_eq!(
+            rewrite_call_sites("echo ${x:- #} ${y//;/|}; docker:build", &siblings),
+            "echo ${x:- #} ${y//;/|}; docker__build"
+        );
+        assert_eq!(
+            rewrite_call_sites("echo \"${x:-${y:-;}}\" | docker:build", &siblings),
+            "echo \"${x:-${y:-;}}\" | docker__build"
+        );
+        assert_eq!(
+            rewrite_call_sites("v=${x:-$(docker:build)} docker:build", &siblings),
+            "v=${x:-$(docker__build)} docker__build"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_call_sites_in_case_branches() {
+        let siblings = vec!["build", "docker:push"];
+
+        assert_eq!(
+            rewrite_call_sites("case $x in a) build;; esac", &siblings),
+            "case $x in a) build;; esac"
+        );
+        // Patterns are never calls, the command after each `)` is
+        let body = "case $1 in\n  build|docker:push) docker:push ;;\n  (build) build ;&\n  *) echo build ;;&\nesac\ndocker:push";
+        assert_eq!(
+            rewrite_call_sites(body, &siblings),
+            "case $1 in\n  build|docker:push) docker__push ;;\n  (build) build ;&\n  *) echo build ;;&\nesac\ndocker__push"
+        );
+        assert_eq!(
+            rewrite_call_sites("v=$(case $x in docker:push) docker:push;; esac)", &siblings),
+            "v=$(case $x in docker:push) docker__push;; esac)"
+        );
+        let body = "case $x in\n  a) case $y in docker:push) docker:push ;; esac ;;\n  docker:push) true ;;\nesac";
+        assert_eq!(
+            rewrite_call_sites(body, &siblings),
+            "case $x in\n  a) case $y in docker:push) docker__push ;; esac ;;\n  docker:push) true ;;\nesac"
+        );
+    }
+
+    #[test]
+    fn test_called_siblings() {
+        let siblings = vec!["build", "test", "docker:push", "unused"];
+        let body = "build\nif test; then docker:push; fi\necho unused\nbuild --release";
+        assert_eq!(
+            called_siblings(body, &siblings),
+            vec!["build", "test", "docker:push"]
+        );
+    }
+
     #[test]
     fn test_rewrite_call_sites_indented() {
         // Indented calls (e.g. inside if/then blocks) should still be rewritten
@@ -0,0 +1,509 @@
+//! `run --watch <glob> <function>`: re-run a function when files change.
+//!
+//! Files are polled for changes to their size and modification time; a burst of changes
+//! is debounced into one restart. The function runs as a `run` subprocess in a process
+//! group of its own, so a restart stops everything the previous run started. Files
+//! ignored by `.gitignore` are never watched.
+
+use crate::ast::Attribute;
+use crate::cli::OutputFormatArg;
+use crate::{config, parser, process};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long files must stay unchanged before the function restarts
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Directories never watched, whatever `.gitignore` says
+const ALWAYS_IGNORED: &[&str] = &[".git", ".run-output"];
+
+/// A compiled glob
+#[derive(Debug)]
+struct Glob {
+    /// Directory to scan: the part of the pattern before any wildcard
+    root: PathBuf,
+    regex: Regex,
+}
+
+impl Glob {
+    /// Compile `pattern` relative to `base`. A pattern without a `/` matches file names
+    /// at any depth (`*.rs`, but not `./*.rs`); a pattern naming a directory matches
+    /// everything in it.
+    fn new(base: &Path, pattern: &str) -> Result<Self, String> {
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+        let pattern = if anchored {
+            pattern.to_string()
+        } else {
+            format!("**/{pattern}")
+        };
+        let absolute = if Path::new(&pattern).is_absolute() {
+            pattern.clone()
+        } else {
+            format!("{}/{pattern}", base.display())
+        };
+
+        let literal: Vec<&str> = absolute
+            .split('/')
+            .take_while(|part| !part.contains(['*', '?', '[', '{']))
+            .collect();
+        let root = if literal.len() == 1 && literal[0].is_empty() {
+            PathBuf::from("/")
+        } else {
+            PathBuf::from(literal.join("/"))
+        };
+        let regex = Regex::new(&format!("^{}(?:/.*)?$", glob_to_regex(&absolute)))
+            .map_err(|e| format!("invalid glob '{pattern}': {e}"))?;
+        Ok(Self { root, regex })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        path.to_str().is_some_and(|p| self.regex.is_match(p))
+    }
+}
+
+/// Translate a glob to a regular expression: `**` crosses directories, `*` and `?`
+/// don't, `[...]` is a character class and `{a,b}` an alternation.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = glob.chars().peekable();
+    let mut in_braces = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            '{' => {
+                in_braces += 1;
+                regex.push_str("(?:");
+            }
+            ',' if in_braces > 0 => regex.push('|'),
+            '}' if in_braces > 0 => {
+                in_braces -= 1;
+                regex.push(')');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex
+}
+
+/// One `.gitignore` rule
+#[derive(Debug)]
+struct IgnoreRule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// `.gitignore` rules collected while walking, each anchored at its file's directory
+#[derive(Debug, Default)]
+struct Ignores {
+    rules: Vec<IgnoreRule>,
+}
+
+impl Ignores {
+    /// Add the rules of `dir/.gitignore`, if there is one
+    fn load(&mut self, dir: &Path) {
+        let Ok(content) = fs::read_to_string(dir.join(".gitignore")) else {
+            return;
+        };
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let dir_only = pattern.ends_with('/');
+            let pattern = pattern.trim_end_matches('/');
+            // A slash anywhere but the end anchors the pattern to this directory
+            let anchored = pattern.contains('/');
+            let pattern = pattern.trim_start_matches('/');
+            let glob = if anchored {
+                format!("{}/{pattern}", dir.display())
+            } else {
+                format!("{}/**/{pattern}", dir.display())
+            };
+            if let Ok(regex) = Regex::new(&format!("^{}$", glob_to_regex(&glob))) {
+                self.rules.push(IgnoreRule {
+                    regex,
+                    negated,
+                    dir_only,
+                });
+            }
+        }
+    }
+
+    /// Whether `path` is ignored; the last matching rule wins
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| ALWAYS_IGNORED.contains(&n))
+        {
+            return true;
+        }
+        let Some(text) = path.to_str() else {
+            return false;
+        };
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.regex.is_match(text))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+/// Size and modification time of every watched file
+type Snapshot = BTreeMap<PathBuf, (u64, Option<SystemTime>)>;
+
+/// Record the watched files: those matching a glob and not ignored
+fn snapshot(base: &Path, globs: &[Glob]) -> Snapshot {
+    let mut files = Snapshot::new();
+    let mut ignores = Ignores::default();
+    // .gitignore files above the scanned directories, up to the project directory
+    for dir in base.ancestors().collect::<Vec<_>>().into_iter().rev() {
+        ignores.load(dir);
+    }
+    let mut roots: Vec<&Path> = globs.iter().map(|g| g.root.as_path()).collect();
+    roots.sort();
+    roots.dedup_by(|a, b| a.starts_with(*b));
+    for root in roots {
+        if root.is_file() {
+            record(&mut files, root, globs);
+        } else {
+            walk(&mut files, root, base, globs, &mut ignores);
+        }
+    }
+    files
+}
+
+fn walk(files: &mut Snapshot, dir: &Path, base: &Path, globs: &[Glob], ignores: &mut Ignores) {
+    if dir != base && dir.starts_with(base) {
+        ignores.load(dir);
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<_> = entries.flatten().collect();
+    entries.sort_by_key(fs::DirEntry::file_name);
+    for entry in entries {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if ignores.is_ignored(&path, file_type.is_dir()) {
+            continue;
+        }
+        if file_type.is_dir() {
+            walk(files, &path, base, globs, ignores);
+        } else {
+            record(files, &path, globs);
+        }
+    }
+}
+
+fn record(files: &mut Snapshot, path: &Path, globs: &[Glob]) {
+    if globs.iter().any(|g| g.matches(path))
+        && let Ok(metadata) = fs::metadata(path)
+    {
+        files.insert(
+            path.to_path_buf(),
+            (metadata.len(), metadata.modified().ok()),
+        );
+    }
+}
+
+/// A file that was added, removed or modified between two snapshots
+fn changed_file(before: &Snapshot, after: &Snapshot) -> Option<PathBuf> {
+    after
+        .iter()
+        .find(|(path, state)| before.get(*path) != Some(state))
+        .map(|(path, _)| path.clone())
+        .or_else(|| before.keys().find(|p| !after.contains_key(*p)).cloned())
+}
+
+/// The `@sources` globs of `function`, resolved like a call would be
+fn declared_sources(function: &str, args: &[String]) -> Vec<String> {
+    let Some((content, _metadata, source_map)) = config::load_merged_config_mapped() else {
+        return Vec::new();
+    };
+    let (program, _) = parser::parse_runfile(&content, &source_map);
+    let mut interpreter = crate::interpreter::Interpreter::new();
+    interpreter.load_definitions(program);
+    interpreter
+        .function_attributes(function, args)
+        .into_iter()
+        .filter_map(|attr| match attr {
+            Attribute::Sources(globs) => Some(globs),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Watch the files matching `patterns` (and the function's `@sources`), running
+/// `function` now and again after every change, until interrupted.
+pub fn watch(
+    patterns: &[String],
+    function: &str,
+    args: &[String],
+    output_format: OutputFormatArg,
+) -> ! {
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let runfile_dir = config::find_runfile_path()
+        .and_then(|p| p.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| cwd.clone());
+
+    let globs: Result<Vec<Glob>, String> = patterns
+        .iter()
+        .map(|p| Glob::new(&cwd, p))
+        .chain(
+            declared_sources(function, args)
+                .iter()
+                .map(|p| Glob::new(&runfile_dir, p)),
+        )
+        .collect();
+    let globs = match globs {
+        Ok(globs) => globs,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(crate::exit::USAGE);
+        }
+    };
+    // .gitignore files apply from the project root down
+    let base = if runfile_dir.starts_with(&cwd) || cwd.starts_with(&runfile_dir) {
+        runfile_dir.clone()
+    } else {
+        cwd.clone()
+    };
+
+    let _interrupts = process::catch_interrupts();
+    let call = std::iter::once(function)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut files = snapshot(&base, &globs);
+    eprintln!("[watch] running {call}, watching {} file(s)", files.len());
+    let mut run = start(function, args, output_format, &call);
+
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        if let Some(signal) = process::interrupted() {
+            if let Some(child) = run.child.as_mut() {
+                process::terminate(child, process::GRACE_PERIOD);
+            }
+            std::process::exit(crate::exit::SIGNAL_BASE + signal);
+        }
+        run.check_finished(files.len());
+
+        let current = snapshot(&base, &globs);
+        let Some(changed) = changed_file(&files, &current) else {
+            continue;
+        };
+        // Wait for the burst of changes to settle
+        let mut settled = current;
+        let mut quiet_since = Instant::now();
+        while quiet_since.elapsed() < DEBOUNCE && process::interrupted().is_none() {
+            std::thread::sleep(DEBOUNCE / 4);
+            let next = snapshot(&base, &globs);
+            if next != settled {
+                settled = next;
+                quiet_since = Instant::now();
+            }
+        }
+        files = settled;
+
+        let shown = changed.strip_prefix(&cwd).map_or_else(
+            |_| changed.display().to_string(),
+            |p| p.display().to_string(),
+        );
+        if let Some(child) = run.child.as_mut() {
+            process::terminate(child, process::GRACE_PERIOD);
+            eprintln!("[watch] {shown} changed, restarting {call}");
+        } else {
+            eprintln!("[watch] {shown} changed, running {call}");
+        }
+        run = start(function, args, output_format, &call);
+    }
+}
+
+/// The run in progress, if it hasn't finished
+struct Run {
+    child: Option<Child>,
+    call: String,
+    started: Instant,
+}
+
+impl Run {
+    /// Print a status line once the run has finished
+    fn check_finished(&mut self, watched: usize) {
+        let Some(child) = self.child.as_mut() else {
+            return;
+        };
+        let Ok(Some(status)) = child.try_wait() else {
+            return;
+        };
+        // Anything it left running in the background goes with it
+        process::terminate(child, Duration::ZERO);
+        self.child = None;
+        eprintln!(
+            "[watch] {} {} in {:.1}s, watching {watched} file(s)",
+            self.call,
+            describe_status(status),
+            self.started.elapsed().as_secs_f64()
+        );
+    }
+}
+
+fn describe_status(status: ExitStatus) -> String {
+    match status.code() {
+        Some(0) => "succeeded".to_string(),
+        Some(code) => format!("failed (exit code {code})"),
+        None => "was stopped".to_string(),
+    }
+}
+
+/// Start the function in a process group of its own, run the way this `run` runs
+/// functions
+fn start(function: &str, args: &[String], output_format: OutputFormatArg, call: &str) -> Run {
+    let child = std::env::current_exe().and_then(|exe| {
+        let mut cmd = Command::new(exe);
+        if let Some(path) = config::get_custom_runfile_path() {
+            cmd.arg("--working-dir").arg(path);
+        }
+        cmd.arg(format!("--output-format={}", output_format.name()))
+            .args(config::run_flags())
+            .arg(function)
+            .arg("--")
+            .args(args);
+        process::isolate(&mut cmd);
+        cmd.spawn()
+    });
+    match child {
+        Ok(child) => Run {
+            child: Some(child),
+            call: call.to_string(),
+            started: Instant::now(),
+        },
+        Err(e) => {
+            eprintln!("[watch] could not start {call}: {e}");
+            Run {
+                child: None,
+                call: call.to_string(),
+                started: Instant::now(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::expect_used, clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_matching() {
+        let base = Path::new("/p");
+        let src = Glob::new(base, "src/**").unwrap();
+        assert_eq!(src.root, PathBuf::from("/p/src"));
+        assert!(src.matches(Path::new("/p/src/main.rs")));
+        assert!(src.matches(Path::new("/p/src/a/b.rs")));
+        assert!(!src.matches(Path::new("/p/tests/a.rs")));
+
+        let rust = Glob::new(base, "*.rs").unwrap();
+        assert_eq!(rust.root, PathBuf::from("/p"));
+        assert!(rust.matches(Path::new("/p/main.rs")));
+        assert!(rust.matches(Path::new("/p/src/deep/lib.rs")));
+        assert!(!rust.matches(Path::new("/p/src/lib.rsx")));
+
+        let config = Glob::new(base, "./{Cargo,package}.{toml,json}").unwrap();
+        assert!(config.matches(Path::new("/p/Cargo.toml")));
+        assert!(config.matches(Path::new("/p/package.json")));
+        assert!(!config.matches(Path::new("/p/sub/Cargo.toml")));
+
+        // A directory matches everything in it
+        let dir = Glob::new(base, "docs").unwrap();
+        assert!(dir.matches(Path::new("/p/docs/index.md")));
+    }
+
+    #[test]
+    fn test_gitignore_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(".gitignore"),
+            "# build output\ntarget/\n*.log\n/dist\n!keep.log\n",
+        )
+        .unwrap();
+        let mut ignores = Ignores::default();
+        ignores.load(dir.path());
+        let root = dir.path();
+
+        assert!(ignores.is_ignored(&root.join("target"), true));
+        assert!(!ignores.is_ignored(&root.join("target"), false));
+        assert!(ignores.is_ignored(&root.join("a/b/debug.log"), false));
+        assert!(!ignores.is_ignored(&root.join("a/keep.log"), false));
+        assert!(ignores.is_ignored(&root.join("dist"), true));
+        assert!(!ignores.is_ignored(&root.join("src/dist"), true));
+        assert!(ignores.is_ignored(&root.join(".git"), true));
+        assert!(!ignores.is_ignored(&root.join("src/main.rs"), false));
+    }
+
+    #[test]
+    fn test_snapshot_skips_ignored_files_and_detects_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("target/out.rs"), "").unwrap();
+
+        let globs = [Glob::new(root, "*.rs").unwrap()];
+        let before = snapshot(root, &globs);
+        assert_eq!(
+            before.keys().collect::<Vec<_>>(),
+            vec![&root.join("src/main.rs")]
+        );
+
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        let after = snapshot(root, &globs);
+        assert_eq!(changed_file(&before, &after), Some(root.join("src/lib.rs")));
+        assert_eq!(changed_file(&after, &before), Some(root.join("src/lib.rs")));
+        assert_eq!(changed_file(&after, &after), None);
+    }
+}
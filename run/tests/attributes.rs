@@ -1,4 +1,4 @@
-//! Attribute tests (@os, @shell, @desc, @arg)
+//! Attribute tests (@os, @shell, @desc, @arg, @init)
 
 #![allow(clippy::unwrap_used)]
 #![allow(clippy::expect_used)]
@@ -180,3 +180,110 @@ test_bash() echo "Hello from bash"
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("Hello from bash"));
 }
+
+#[test]
+fn test_list_and_inspect_do_not_run_top_level_commands() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(temp_dir.path(), "touch marker\n\nbuild() echo building\n");
+
+    for flag in ["--list", "--inspect"] {
+        let output = test_command(&binary)
+            .arg(flag)
+            .current_dir(temp_dir.path())
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+    }
+    let output = test_command(&binary)
+        .arg("--complete")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    assert!(!temp_dir.path().join("marker").exists());
+}
+
+#[test]
+fn test_top_level_commands_are_not_run_on_call() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(temp_dir.path(), "touch marker\n\nbuild() echo building\n");
+
+    let output = test_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("building"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("were not run"), "stderr: {stderr}");
+    assert!(stderr.contains("touch marker"));
+    assert!(!temp_dir.path().join("marker").exists());
+}
+
+#[test]
+fn test_init_function_runs_before_called_function() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @init
+setup() echo "init ran"
+
+build() echo "building"
+"#,
+    );
+
+    let output = test_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "init ran\nbuilding\n");
+
+    // Calling the init function itself runs it once
+    let output = test_command(&binary)
+        .arg("setup")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "init ran\n");
+
+    // Listing does not run it
+    let output = test_command(&binary)
+        .arg("--list")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("init ran"));
+}
+
+#[test]
+fn test_failing_init_function_stops_call() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        "# @init\ncheck() exit 3\n\nbuild() echo building\n",
+    );
+
+    let output = test_command(&binary)
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("building"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("@init function 'check' failed"));
+}
@@ -132,6 +132,118 @@ docker:shell() echo "Opening Docker shell for $1"
     assert!(stdout.contains("Opening Docker shell for myapp"));
 }
 
+#[test]
+fn test_deeply_nested_function_call() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+docker() echo "docker with $@"
+docker:compose() echo "compose with $@"
+docker:compose:up() echo "compose up with $@"
+"#,
+    );
+
+    let run = |args: &[&str]| {
+        let output = Command::new(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert!(run(&["docker", "compose", "up", "-d"]).contains("compose up with -d"));
+    assert!(run(&["docker", "compose", "logs"]).contains("compose with logs"));
+    assert!(run(&["docker", "ps"]).contains("docker with ps"));
+}
+
+#[test]
+fn test_namespace_without_function_lists_commands() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+docker:compose:up() echo up
+docker:compose:down() echo down
+",
+    );
+
+    let output = Command::new(&binary)
+        .args(["docker", "compose"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is a namespace"), "stderr: {stderr}");
+    assert!(stderr.contains("docker compose down"));
+    assert!(stderr.contains("docker compose up"));
+}
+
+#[test]
+fn test_ambiguous_underscore_name() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+a:b_c() echo first
+a_b:c() echo second
+",
+    );
+
+    let output = Command::new(&binary)
+        .arg("a_b_c")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("ambiguous"), "stderr: {stderr}");
+    assert!(stderr.contains("a:b_c") && stderr.contains("a_b:c"));
+}
+
+#[test]
+fn test_complete_walks_namespaces() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+build() echo build
+docker:ps() echo ps
+docker:compose:up() echo up
+docker:compose:down() echo down
+",
+    );
+
+    let complete = |words: &[&str]| {
+        let output = Command::new(&binary)
+            .arg("--complete")
+            .args(words)
+            .current_dir(temp_dir.path())
+            .env("RUN_NO_GLOBAL_MERGE", "1")
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert_eq!(complete(&[]), "build\ndocker\n");
+    assert_eq!(complete(&["docker"]), "compose\nps\n");
+    assert_eq!(complete(&["docker", "compose"]), "down\nup\n");
+}
+
 #[test]
 fn test_function_not_found() {
     let binary = get_binary_path();
@@ -336,6 +448,100 @@ hello()
     assert!(stdout.contains("Hello from script"));
 }
 
+#[test]
+fn test_script_file_arguments_and_main() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let script_path = temp_dir.path().join("deploy.run");
+    fs::write(
+        &script_path,
+        "#!/usr/bin/env run\necho \"top $1\"\n\nmain() {\n    echo \"main $1 $2\"\n}\n",
+    )
+    .unwrap();
+
+    let output = Command::new(&binary)
+        .args([script_path.to_str().unwrap(), "prod", "v2"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("top prod"), "stdout: {stdout}");
+    assert!(stdout.contains("main prod v2"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_file_as_executable_with_shebang() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let script_path = temp_dir.path().join("hello");
+    fs::write(
+        &script_path,
+        "#!/usr/bin/env run\nmain() echo \"hello $1\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!(
+        "{}:{}",
+        binary.parent().unwrap().display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    // Another test thread may briefly hold the freshly written file open across a fork
+    // ("text file busy"), so retry a few times
+    let mut attempts = 0;
+    let output = loop {
+        match Command::new(&script_path)
+            .arg("world")
+            .env("PATH", &path)
+            .current_dir(temp_dir.path())
+            .output()
+        {
+            Err(e) if e.raw_os_error() == Some(26) && attempts < 10 => {
+                attempts += 1;
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            result => break result.expect("Failed to execute command"),
+        }
+    };
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello world"));
+}
+
+#[test]
+fn test_script_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let mut child = Command::new(&binary)
+        .args(["-", "a", "b"])
+        .current_dir(temp_dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute command");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"main() echo \"args: $@\"\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("args: a b"));
+}
+
 #[test]
 fn test_parse_error_handling() {
     let binary = get_binary_path();
@@ -97,6 +97,122 @@ hello() echo "Hello, World!"
     assert!(stdout.contains("hello"));
 }
 
+const RUNFILE_WITH_BROKEN_ITEMS: &str = r#"
+# @desc Build the project
+build() echo "Building..."
+
+# @desc Deploy
+deploy() {
+    echo "Deploying..."
+
+# @desc Run the tests
+test() echo "Testing..."
+
+lint(a: nope) {
+    echo lint
+}
+"#;
+
+#[test]
+fn test_list_skips_broken_items_with_warnings() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), RUNFILE_WITH_BROKEN_ITEMS);
+
+    let output = test_command_local(&binary)
+        .arg("--list")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains("build"));
+    assert!(stdout.contains("test"));
+    assert!(!stdout.contains("deploy"));
+    assert!(!stdout.contains("lint"));
+    assert!(
+        stderr.contains("2 Runfile items could not be parsed"),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("Runfile:6:"), "stderr: {stderr}");
+    assert!(stderr.contains("Runfile:12:"), "stderr: {stderr}");
+}
+
+#[test]
+fn test_function_call_refused_with_broken_items() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), RUNFILE_WITH_BROKEN_ITEMS);
+
+    let output = test_command_local(&binary)
+        .arg("test")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(65));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stdout.contains("Testing..."), "stdout: {stdout}");
+    assert!(stderr.contains("Runfile:6:"), "stderr: {stderr}");
+    assert!(stderr.contains("Runfile:12:"), "stderr: {stderr}");
+    assert!(
+        stderr.contains("the Runfile has 2 syntax errors"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn test_broken_override_does_not_fall_back_to_global() {
+    let binary = get_binary_path();
+    let home = create_temp_dir();
+    std::fs::write(
+        home.path().join(".runfile"),
+        "deploy() echo global deploy\n",
+    )
+    .unwrap();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "deploy() {\n    echo project deploy\n");
+
+    let output = Command::new(&binary)
+        .arg("deploy")
+        .env("HOME", home.path())
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(65));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("global deploy"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_inspect_reports_skipped_items() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), RUNFILE_WITH_BROKEN_ITEMS);
+
+    let output = test_command_local(&binary)
+        .arg("--inspect")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("valid inspect JSON");
+    let names: Vec<&str> = json["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| t["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["build", "test"]);
+    assert_eq!(json["warnings"].as_array().unwrap().len(), 2);
+}
+
 #[test]
 fn test_generate_completion_bash() {
     let binary = get_binary_path();
@@ -210,3 +326,776 @@ fn test_install_completion_auto_detect_fails_with_unknown_shell() {
     let stderr = String::from_utf8_lossy(&output.stderr);
     assert!(stderr.to_lowercase().contains("could not detect shell"));
 }
+
+fn run_bare_with_stdin(dir: &std::path::Path, args: &[&str], stdin: &str) -> std::process::Output {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let binary = get_binary_path();
+    let mut child = test_command_local(&binary)
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn command");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child
+        .wait_with_output()
+        .expect("Failed to wait for command")
+}
+
+#[test]
+fn test_bare_run_calls_default_function() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "build() echo building\n\n# @default\ndev() echo starting dev server\n",
+    );
+
+    let output = run_bare_with_stdin(temp_dir.path(), &[], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("starting dev server"));
+    assert!(!stdout.contains("Run Shell"));
+}
+
+#[test]
+fn test_bare_run_starts_repl_by_default() {
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), "build() echo building\n");
+
+    let output = run_bare_with_stdin(temp_dir.path(), &[], "exit\n");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Run Shell"));
+}
+
+#[test]
+fn test_bare_run_list_setting() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "# @set bare list\n\nbuild() echo building\ntest() echo testing\n",
+    );
+
+    let output = run_bare_with_stdin(temp_dir.path(), &[], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Available functions"));
+    assert!(stdout.contains("build"));
+    assert!(!stdout.contains("building"));
+}
+
+#[test]
+fn test_bare_run_picker_setting() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "# @set bare pick\n\n# @desc Build it\nbuild() echo building\ngreet() echo hello $1\n",
+    );
+
+    let output = run_bare_with_stdin(temp_dir.path(), &[], "2 world\n");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1) build  Build it"), "stderr: {stderr}");
+    assert!(stderr.contains("2) greet"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hello world"));
+
+    let output = run_bare_with_stdin(temp_dir.path(), &[], "build\n");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("building"));
+}
+
+#[test]
+fn test_repl_flag_skips_default_function() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "# @default\ndev() echo starting dev server\n",
+    );
+
+    let output = run_bare_with_stdin(temp_dir.path(), &["--repl"], "exit\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Run Shell"));
+    assert!(!stdout.contains("starting dev server"));
+}
+
+#[test]
+fn test_repl_runs_init_functions_once_before_the_first_call() {
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "# @init\nsetup() echo setting up\n\nbuild() echo building\n",
+    );
+
+    let output = run_bare_with_stdin(
+        temp_dir.path(),
+        &["--repl"],
+        "echo first\nbuild()\nbuild()\nexit\n",
+    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("setting up").count(), 1, "stdout: {stdout}");
+    let first = stdout.find("first").unwrap();
+    let setup = stdout.find("setting up").unwrap();
+    let build = stdout.find("building").unwrap();
+    assert!(first < setup && setup < build, "stdout: {stdout}");
+    assert_eq!(stdout.matches("building").count(), 2, "stdout: {stdout}");
+}
+
+#[test]
+fn test_show_script_is_deterministic_and_in_source_order() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let names: Vec<String> = (0..30).map(|i| format!("step{i:02}")).collect();
+    let functions = names
+        .iter()
+        .map(|name| format!("{name}() echo {name}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let runfile = format!(
+        "A=\"1\"\nB=\"2\"\nC=\"3\"\n{functions}\nall() {{\n{}\n}}\n",
+        names.join("\n")
+    );
+    create_runfile(temp_dir.path(), &runfile);
+
+    let show_script = || {
+        let output = test_command_local(&binary)
+            .args(["--show-script", "all"])
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let script = show_script();
+    for _ in 0..3 {
+        assert_eq!(show_script(), script);
+    }
+
+    let positions: Vec<usize> = names
+        .iter()
+        .map(|name| script.find(&format!("{name}() {{")).unwrap())
+        .collect();
+    assert!(positions.is_sorted(), "Siblings out of order:\n{script}");
+    assert!(script.find("A=\"1\"").unwrap() < script.find("C=\"3\"").unwrap());
+}
+
+#[test]
+fn test_show_script_annotate() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+compile() echo compile
+
+# @shell python3
+report() print("report")
+
+build() {
+    compile
+    report
+}
+"#,
+    );
+
+    let output = test_command_local(&binary)
+        .args(["--show-script", "--annotate", "build"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let compile = stdout.find("# --- sibling compile (Runfile:2) ---\ncompile() {");
+    let report = stdout.find("# --- wrapper for report (Runfile:5), calls `run report` ---");
+    let build = stdout.find("# --- function build (Runfile:7) ---\n__run__() {");
+    assert!(
+        compile.is_some() && report.is_some() && build.is_some(),
+        "{stdout}"
+    );
+    assert!(compile < report && report < build, "{stdout}");
+
+    // --annotate only makes sense with --show-script
+    let output = test_command_local(&binary)
+        .args(["--annotate", "build"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_explain_reports_resolution_and_composition() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+compile() echo compile
+
+# @shell python3
+report() print("report")
+
+docker:build(env, tag = "latest") {
+    compile
+    report
+}
+"#,
+    );
+
+    let output = test_command_local(&binary)
+        .args(["--explain", "docker", "build", "prod"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        stdout.starts_with("docker:build (block function)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("longest prefix"), "{stdout}");
+    assert!(
+        stdout.contains("defined at:   Runfile:7 (project)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("compile inlined (Runfile:2)"), "{stdout}");
+    assert!(
+        stdout.contains("report wrapped, calls `run report` (Runfile:5)"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("$env = \"prod\""), "{stdout}");
+    assert!(stdout.contains("$tag = \"latest\" (default)"), "{stdout}");
+    // Nothing was run
+    assert!(!stdout.contains("compile\n"), "{stdout}");
+}
+
+#[test]
+fn test_explain_json_lists_overridden_definitions() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let project = temp_dir.path().join("project");
+    std::fs::create_dir(&project).unwrap();
+
+    std::fs::write(temp_dir.path().join(".runfile"), "build() echo global\n").unwrap();
+    create_runfile(
+        &project,
+        "# @shell python3\nbuild() {\n    print(\"project\")\n}\n",
+    );
+
+    let output = Command::new(&binary)
+        .args(["--explain", "--output-format", "json", "build"])
+        .current_dir(&project)
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("--explain should print JSON");
+
+    assert_eq!(json["function"], "build");
+    assert_eq!(json["resolution"]["strategy"], "exact");
+    assert_eq!(json["interpreter"]["name"], "python3");
+    assert_eq!(json["interpreter"]["source"], "attribute");
+    let definitions = json["definitions"].as_array().unwrap();
+    assert_eq!(definitions.len(), 2);
+    assert_eq!(definitions[0]["origin"], "global");
+    assert_eq!(definitions[0]["active"], false);
+    assert_eq!(definitions[1]["origin"], "project");
+    assert_eq!(definitions[1]["active"], true);
+}
+
+#[test]
+fn test_graph_dot_and_mermaid() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    std::fs::write(temp_dir.path().join("lint.run"), "lint() echo lint\n").unwrap();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+source ./lint.run
+
+# @shell python3
+report() print("report")
+
+ci() {
+    lint
+    report
+}
+"#,
+    );
+
+    let graph = |format: &str| {
+        let output = test_command_local(&binary)
+            .args(["--graph", format])
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let dot = graph("dot");
+    assert!(dot.contains("label=\"lint.run\";"), "{dot}");
+    assert!(
+        dot.contains("\"report\" [label=\"report\\npython3\"];"),
+        "{dot}"
+    );
+    assert!(dot.contains("\"ci\" -> \"lint\";"), "{dot}");
+    assert!(
+        dot.contains("\"ci\" -> \"report\" [style=dashed, label=\"run\"];"),
+        "{dot}"
+    );
+
+    let mermaid = graph("mermaid");
+    assert!(mermaid.starts_with("flowchart LR\n"), "{mermaid}");
+    assert!(mermaid.contains("-.->|run|"), "{mermaid}");
+}
+
+#[test]
+fn test_watch_reruns_on_change() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    std::fs::create_dir_all(temp_dir.path().join("src")).unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("build")).unwrap();
+    std::fs::write(temp_dir.path().join(".gitignore"), "build/\n").unwrap();
+    std::fs::write(temp_dir.path().join("src/main.txt"), "one").unwrap();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @sources VERSION
+check() echo "checking $(cat src/main.txt)"
+"#,
+    );
+
+    let log = temp_dir.path().join("watch.log");
+    let mut child = test_command_local(&binary)
+        .args(["--watch", "src/**", "--watch", "build/**", "check"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .stdout(std::fs::File::create(&log).unwrap())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to execute command");
+
+    let pause = || std::thread::sleep(std::time::Duration::from_millis(1200));
+    pause();
+    std::fs::write(temp_dir.path().join("src/main.txt"), "two").unwrap();
+    pause();
+    // Ignored by .gitignore
+    std::fs::write(temp_dir.path().join("build/out.txt"), "x").unwrap();
+    pause();
+    // Declared with @sources
+    std::fs::write(temp_dir.path().join("VERSION"), "1.0").unwrap();
+    pause();
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let stdout = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        vec!["checking one", "checking two", "checking two"]
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_watch_runs_the_function_the_way_run_would() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let outside = create_temp_dir();
+    let escaped = outside.path().join("escaped.txt");
+    create_runfile(
+        temp_dir.path(),
+        &format!(
+            r#"
+w() {{
+    echo "arg $1"
+    echo escaped > "{}"
+}}
+"#,
+            escaped.display()
+        ),
+    );
+
+    let log = temp_dir.path().join("watch.log");
+    let mut child = test_command_local(&binary)
+        .args(["--sandbox", "--output-format", "json", "--watch", "Runfile"])
+        .args(["w", "-x"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .stdout(std::fs::File::create(&log).unwrap())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("Failed to execute command");
+    std::thread::sleep(std::time::Duration::from_millis(1200));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let stdout = std::fs::read_to_string(&log).unwrap();
+    if stdout.contains("Landlock is not available") {
+        return;
+    }
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["outputs"][0]["stdout"], "arg -x\n", "{stdout}");
+    assert!(!escaped.exists(), "the sandbox was dropped: {stdout}");
+}
+
+#[test]
+fn test_history_and_rerun() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r#"
+greet() echo "Hello, $1!"
+fail() exit 1
+"#,
+    );
+    let run = |args: &[&str]| {
+        test_command_local(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    assert!(run(&["greet", "big world"]).status.success());
+    assert!(!run(&["fail"]).status.success());
+
+    let history = run(&["--history"]);
+    assert!(history.status.success());
+    let table = String::from_utf8_lossy(&history.stdout);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 3, "{table}");
+    assert!(lines[0].starts_with("#  WHEN"));
+    assert!(lines[1].starts_with("1 ") && lines[1].ends_with("greet 'big world'"));
+    assert!(lines[2].starts_with("2 ") && lines[2].contains(" 1 ") && lines[2].ends_with("fail"));
+
+    let filtered = run(&["--history", "greet"]);
+    assert_eq!(String::from_utf8_lossy(&filtered.stdout).lines().count(), 2);
+
+    let rerun = run(&["--rerun", "1"]);
+    assert!(rerun.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&rerun.stdout),
+        "Hello, big world!\n"
+    );
+    assert!(
+        String::from_utf8_lossy(&rerun.stderr).contains("Re-running #1: run greet 'big world'")
+    );
+
+    // The rerun is recorded too, so `last` is now the greeting again
+    let last = run(&["--rerun", "last"]);
+    assert_eq!(String::from_utf8_lossy(&last.stdout), "Hello, big world!\n");
+
+    let missing = run(&["--rerun", "42"]);
+    assert_eq!(missing.status.code(), Some(64));
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("no call #42"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rerun_repeats_the_run_flags() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r"
+steps() {
+    echo one
+    echo two
+}
+",
+    );
+    let run = |args: &[&str]| {
+        test_command_local(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    assert!(run(&["--trace", "steps"]).status.success());
+    let rerun = run(&["--rerun"]);
+    assert!(rerun.status.success());
+    let stderr = String::from_utf8_lossy(&rerun.stderr);
+    assert!(
+        stderr.contains("Re-running #1: run --trace steps"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("trace: steps"),
+        "the trace was dropped: {stderr}"
+    );
+}
+
+#[test]
+fn test_exit_code_is_the_function_s_own() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r"
+three() {
+    exit 3
+}
+
+# @shell python3
+py() {
+    import sys
+    sys.exit(5)
+}
+
+killed() {
+    kill -9 $$
+}
+",
+    );
+    let run = |args: &[&str]| {
+        test_command_local(&binary)
+            .args(args)
+            .current_dir(temp_dir.path())
+            .output()
+            .expect("Failed to execute command")
+    };
+
+    assert_eq!(run(&["three"]).status.code(), Some(3));
+    // Polyglot functions stream their output and fail the same way
+    assert_eq!(run(&["py"]).status.code(), Some(5));
+    assert_eq!(run(&["nope"]).status.code(), Some(66));
+    assert_eq!(run(&["--no-such-flag"]).status.code(), Some(64));
+    assert_eq!(run(&["--version"]).status.code(), Some(0));
+    #[cfg(unix)]
+    assert_eq!(run(&["killed"]).status.code(), Some(137));
+
+    let output = run(&["--output-format", "json", "py"]);
+    assert_eq!(output.status.code(), Some(5));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["exit_code"], 5);
+    assert_eq!(json["exit_reason"], "exited");
+
+    let output = run(&["--output-format", "json", "nope"]);
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["success"], false);
+    assert_eq!(json["exit_code"], 66);
+    assert_eq!(json["exit_reason"], "not_found");
+
+    // Nothing runs while the Runfile has a syntax error
+    let runfile = temp_dir.path().join("Runfile");
+    let mut content = std::fs::read_to_string(&runfile).unwrap();
+    content.push_str("broken() {\n    echo \"unterminated\n");
+    std::fs::write(&runfile, content).unwrap();
+    let output = run(&["--output-format", "json", "broken"]);
+    assert_eq!(output.status.code(), Some(65));
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["exit_reason"], "parse_error");
+    assert_eq!(run(&["three"]).status.code(), Some(65));
+}
+
+#[test]
+fn test_trace_flag_prints_a_step_table() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r"
+build() {
+    echo compiling
+    sleep 0.2
+    test -f missing.txt
+}
+",
+    );
+
+    let output = test_command_local(&binary)
+        .args(["--trace", "build"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "compiling\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let rows: Vec<&str> = stderr
+        .lines()
+        .skip_while(|line| *line != "trace: build")
+        .skip(1)
+        .take(3)
+        .collect();
+    assert_eq!(rows.len(), 3, "{stderr}");
+    assert!(rows[0].contains("Runfile:3") && rows[0].ends_with("ok      echo compiling"));
+    assert!(rows[1].contains("Runfile:4") && rows[1].ends_with("ok      sleep 0.2"));
+    assert!(rows[2].contains("exit 1  test -f missing.txt"), "{stderr}");
+
+    // The sleep is the slow step
+    let millis = |row: &str| -> u64 {
+        let field = row.split_whitespace().nth(2).unwrap();
+        field.trim_end_matches("ms").parse().unwrap()
+    };
+    assert!(millis(rows[1]) >= 200, "{stderr}");
+    assert!(millis(rows[0]) < 200, "{stderr}");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sandboxed_function_prints_its_full_output() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @sandbox
+big() {
+    seq 1 20000
+}
+",
+    );
+
+    let output = test_command_local(&binary)
+        .args(["--output-format", "json", "big"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("Landlock is not available") {
+        return;
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let stdout = json["outputs"][0]["stdout"].as_str().unwrap();
+    assert_eq!(stdout.lines().count(), 20000, "{stderr}");
+    assert!(stdout.ends_with("20000\n"));
+    // Nothing is spilled to disk, as it would be for an MCP call
+    let spilled = std::fs::read_dir(temp_dir.path().join(".run-output")).is_ok_and(|entries| {
+        entries
+            .filter_map(Result::ok)
+            .any(|entry| entry.file_name().to_string_lossy().contains("-stdout-"))
+    });
+    assert!(!spilled);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sigterm_stops_everything_the_function_started() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        r"
+srv() {
+    sleep 301 &
+    echo $! > sleep.pid
+    wait
+}
+",
+    );
+
+    let mut child = test_command_local(&binary)
+        .arg("srv")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .spawn()
+        .expect("Failed to execute command");
+    let pid_file = temp_dir.path().join("sleep.pid");
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while std::fs::read_to_string(&pid_file).map_or(true, |pid| !pid.ends_with('\n')) {
+        assert!(std::time::Instant::now() < deadline, "srv never started");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let sleep_pid = std::fs::read_to_string(&pid_file)
+        .unwrap()
+        .trim()
+        .to_string();
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    assert!(!child.wait().unwrap().success());
+
+    // The orphaned sleep is at most a zombie waiting to be reaped
+    let running = || {
+        std::fs::read_to_string(format!("/proc/{sleep_pid}/stat"))
+            .is_ok_and(|stat| !stat.contains(") Z "))
+    };
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while running() {
+        if std::time::Instant::now() > deadline {
+            let _ = Command::new("kill").args(["-9", &sleep_pid]).status();
+            panic!("sleep {sleep_pid} survived");
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sigterm_removes_the_call_temp_files() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let tmp = temp_dir.path().join("tmp");
+    std::fs::create_dir(&tmp).unwrap();
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @shell bash
+srv() {
+    touch started
+    sleep 30
+}
+",
+    );
+
+    let mut child = test_command_local(&binary)
+        .arg("srv")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("TMPDIR", &tmp)
+        .spawn()
+        .expect("Failed to execute command");
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while !temp_dir.path().join("started").exists() {
+        assert!(std::time::Instant::now() < deadline, "srv never started");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(
+        std::fs::read_dir(&tmp).unwrap().next().is_some(),
+        "the call should have a temp dir while it runs"
+    );
+
+    let killed = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(killed.success());
+    assert!(!child.wait().unwrap().success());
+
+    let left: Vec<_> = std::fs::read_dir(&tmp)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert!(left.is_empty(), "temp files left behind: {left:?}");
+}
@@ -69,6 +69,12 @@ pub fn is_ruby_available() -> bool {
     which::which("ruby").is_ok()
 }
 
+/// Helper to check if Perl is available on the system
+#[allow(dead_code)]
+pub fn is_perl_available() -> bool {
+    which::which("perl").is_ok()
+}
+
 /// Package version for testing --version flag
 #[allow(dead_code)]
 pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
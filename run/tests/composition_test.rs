@@ -754,9 +754,8 @@ ci() {
 }
 
 #[test]
-fn test_incompatible_function_without_colon_not_wrapped() {
-    // Functions without colons should NOT be wrapped (they'll fail if called directly)
-    // This test verifies that only colon-named functions get wrappers
+fn test_incompatible_function_without_colon_wrapped() {
+    // Incompatible siblings get `run` wrappers whether or not their names contain colons
     let binary = get_binary_path();
     let temp_dir = create_temp_dir();
 
@@ -764,24 +763,25 @@ fn test_incompatible_function_without_colon_not_wrapped() {
 # Shell function that works fine
 shell_func() echo "shell works"
 
-# Python function without colon - cannot be called from shell directly
+# Python function without colon
 # @shell python
 python_only() {
     print("python only")
 }
 
-# This should work because it only calls shell_func
-safe_caller() {
+caller() {
     shell_func
+    python_only
     echo "done"
 }
 "#;
     create_runfile(temp_dir.path(), runfile);
 
     let output = Command::new(&binary)
-        .arg("safe_caller")
+        .arg("caller")
         .current_dir(temp_dir.path())
         .env("HOME", temp_dir.path())
+        .env("PATH", get_path_with_binary())
         .output()
         .expect("Failed to execute command");
 
@@ -792,9 +792,150 @@ safe_caller() {
         String::from_utf8_lossy(&output.stderr)
     );
     assert!(stdout.contains("shell works"), "Expected shell_func output");
+    assert!(
+        stdout.contains("python only"),
+        "Expected python_only output"
+    );
     assert!(stdout.contains("done"), "Expected done message");
 }
 
+#[test]
+fn test_polyglot_function_calls_sibling_with_run_fn() {
+    // Python and Node functions call any sibling through the injected run_fn helper
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let runfile = r#"
+build() echo "building $@"
+
+# @shell python3
+py_report() print("ignored")
+
+# @shell python3
+report() {
+    code, out = run_fn("build", "--release", 3)
+    print(f"python: rc={code} out={out.strip()}")
+}
+
+# @shell node
+js_report() {
+    const r = run_fn("build", "fast");
+    console.log(`node: rc=${r.code} out=${r.output.trim()}`);
+}
+"#;
+    create_runfile(temp_dir.path(), runfile);
+
+    for (function, expected) in [
+        ("report", "python: rc=0 out=building --release 3"),
+        ("js_report", "node: rc=0 out=building fast"),
+    ] {
+        let output = Command::new(&binary)
+            .arg(function)
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .env("PATH", get_path_with_binary())
+            .output()
+            .expect("Failed to execute command");
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "Command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            stdout.contains(expected),
+            "Expected {expected:?}, got: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn test_sibling_calls_use_the_same_binary_and_runfile() {
+    // Wrappers and run_fn start this `run` with this `--runfile`, not the one on PATH or
+    // the Runfile found from the current directory
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    create_runfile(
+        temp_dir.path(),
+        "build() echo decoy\nbuild_sh() echo decoy\n",
+    );
+    let project = temp_dir.path().join("project");
+    std::fs::create_dir(&project).unwrap();
+    create_runfile(
+        &project,
+        r#"
+# @shell python3
+build() print("python build")
+
+build_sh() echo "shell build $1"
+
+# @shell bash
+deploy() build
+
+# @shell python3
+report() {
+    code, out = run_fn("build_sh", "x")
+    print(f"rc={code} out={out.strip()}")
+}
+"#,
+    );
+
+    for (function, expected) in [
+        ("deploy", "python build"),
+        ("report", "rc=0 out=shell build x"),
+    ] {
+        let output = Command::new(&binary)
+            .args(["--runfile", "project/Runfile", function])
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .env("PATH", "/usr/local/bin:/usr/bin:/bin")
+            .output()
+            .expect("Failed to execute command");
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "{function} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(
+            stdout.contains(expected),
+            "Expected {expected:?}, got: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn test_polyglot_simple_function_with_same_language_siblings() {
+    // Same-language siblings are not inlined as shell functions into a Python script
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let runfile = r#"
+# @shell python3
+first() print("first")
+
+# @shell python3
+second() print("second")
+"#;
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .arg("first")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "Command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "first");
+}
+
 #[test]
 fn test_nested_colon_function_composition_with_polyglot() {
     // Test deeply nested colon functions mixing shell and polyglot
@@ -1163,3 +1304,158 @@ caller() {
         "Expected helper to receive 'beta' as second arg, got: {stdout}"
     );
 }
+
+#[test]
+fn test_preamble_only_includes_called_siblings() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let runfile = r#"
+compile() echo "compiling"
+build() compile
+unrelated() echo "unrelated"
+
+release() {
+    if build; then
+        echo "released"
+    fi
+}
+
+# @siblings all
+each() {
+    for task in "$@"; do
+        "$task"
+    done
+}
+"#;
+    create_runfile(temp_dir.path(), runfile);
+
+    let show_script = |function: &str| {
+        let output = Command::new(&binary)
+            .args(["--show-script", function])
+            .current_dir(temp_dir.path())
+            .env("HOME", temp_dir.path())
+            .output()
+            .expect("Failed to execute command");
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let script = show_script("release");
+    assert!(script.contains("build()"), "Expected build: {script}");
+    assert!(script.contains("compile()"), "Expected compile: {script}");
+    assert!(
+        !script.contains("unrelated()"),
+        "Unexpected sibling: {script}"
+    );
+
+    let script = show_script("each");
+    assert!(
+        script.contains("unrelated()"),
+        "Expected all siblings: {script}"
+    );
+
+    let output = Command::new(&binary)
+        .args(["each", "unrelated", "release"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "Command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(stdout, "unrelated\ncompiling\nreleased\n");
+}
+
+#[test]
+fn test_siblings_called_from_any_command_position_are_included() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let runfile = r#"
+build() echo "built $1"
+unrelated() echo "unrelated"
+
+# @shell bash
+positions() {
+    x=a
+    time build timed
+    VAR=1 build assigned
+    (exec build exec)
+    echo xargs | xargs -n1 build
+    case $x in
+        unrelated) echo "not a call" ;;
+        a) build case ;;
+    esac
+}
+"#;
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .args(["--show-script", "positions"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let script = String::from_utf8_lossy(&output.stdout);
+    assert!(script.contains("build()"), "Expected build: {script}");
+    assert!(
+        !script.contains("unrelated()"),
+        "A case pattern is not a call: {script}"
+    );
+
+    let output = Command::new(&binary)
+        .arg("positions")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "Command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        stdout,
+        "built timed\nbuilt assigned\nbuilt exec\nbuilt xargs\nbuilt case\n"
+    );
+}
+
+#[test]
+fn test_sibling_called_in_command_substitution() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let runfile = r#"
+version:get() echo "1.2.3"
+
+tag() {
+    local version="$(version:get)"
+    echo "tagging v$version"
+    echo "version:get is only mentioned here"
+}
+"#;
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .arg("tag")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("tagging v1.2.3"), "stdout: {stdout}");
+    assert!(
+        stdout.contains("version:get is only mentioned here"),
+        "stdout: {stdout}"
+    );
+}
@@ -324,3 +324,237 @@ wrapper() {
         "Simple function wrapper should have set -e, got: {stdout}"
     );
 }
+
+#[test]
+fn test_failure_reports_runfile_line() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let runfile = r#"# Deployment tasks
+
+deploy() {
+    echo "starting"
+    ls /nonexistent-run-test-dir
+    echo "never"
+}
+"#;
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .arg("deploy")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    if which::which("bash").is_ok() {
+        assert!(
+            stderr.contains("failed at Runfile:5 in deploy: `ls /nonexistent-run-test-dir`"),
+            "Expected failing line location, got: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn test_failure_in_sibling_reports_sibling_line() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let runfile = r#"
+check() {
+    echo "checking"
+    false
+}
+
+release() {
+    check
+    echo "released"
+}
+"#;
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .arg("release")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    if which::which("bash").is_ok() {
+        assert!(
+            stderr.contains("failed at Runfile:4 in check: `false`"),
+            "Expected sibling failure location, got: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn test_failure_in_sourced_file_reports_that_file() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    fs::write(
+        temp_dir.path().join("tasks.run"),
+        "\nbroken() {\n    echo \"about to fail\"\n    false\n}\n",
+    )
+    .unwrap();
+    create_runfile(temp_dir.path(), "source ./tasks.run\n\nok() echo ok\n");
+
+    let output = Command::new(&binary)
+        .arg("broken")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    if which::which("bash").is_ok() {
+        assert!(
+            stderr.contains("failed at tasks.run:4 in broken: `false`"),
+            "Expected sourced file location, got: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn test_failure_line_after_global_merge() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let home = temp_dir.path().join("home");
+    let project = temp_dir.path().join("project");
+    fs::create_dir_all(&home).unwrap();
+    fs::create_dir_all(&project).unwrap();
+
+    fs::write(
+        home.join(".runfile"),
+        "greet() echo hello\n\nother() {\n    echo other\n}\n",
+    )
+    .unwrap();
+    create_runfile(&project, "build() {\n    echo building\n    false\n}\n");
+
+    let output = Command::new(&binary)
+        .arg("build")
+        .current_dir(&project)
+        .env("HOME", &home)
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    if which::which("bash").is_ok() {
+        assert!(
+            stderr.contains("failed at Runfile:3 in build: `false`"),
+            "Expected project Runfile location, got: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn test_python_failure_reported_in_json_output() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    if which::which("python3").is_err() {
+        return;
+    }
+
+    let runfile = r"
+# @shell python3
+calc() {
+    x = 1
+    y = 0
+    print(x / y)
+}
+";
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .args(["--output-format=json", "calc"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let json: serde_json::Value = serde_json::from_str(&stdout).expect("valid JSON output");
+    assert_eq!(json["success"], false);
+    assert_eq!(json["failure"]["line"], 6, "got: {stdout}");
+    assert_eq!(json["failure"]["function"], "calc");
+    assert_eq!(json["failure"]["command"], "print(x / y)");
+}
+
+#[test]
+fn test_python_failure_reported_when_streaming() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    if which::which("python3").is_err() {
+        return;
+    }
+
+    let runfile = r"
+# @shell python3
+calc() {
+    x = 1
+    y = 0
+    print(x / y)
+}
+";
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .arg("calc")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("ZeroDivisionError"),
+        "the traceback should still be shown, got: {stderr}"
+    );
+    assert!(
+        stderr.contains("failed at Runfile:6 in calc: `print(x / y)`"),
+        "Expected Runfile location, got: {stderr}"
+    );
+}
+
+#[test]
+fn test_node_failure_reported_when_streaming() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    if which::which("node").is_err() {
+        return;
+    }
+
+    let runfile = r"
+# @shell node
+check() {
+    const ok = true;
+    throw new Error('broken');
+}
+";
+    create_runfile(temp_dir.path(), runfile);
+
+    let output = Command::new(&binary)
+        .arg("check")
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("failed at Runfile:5 in check: `throw new Error('broken');`"),
+        "Expected Runfile location, got: {stderr}"
+    );
+}
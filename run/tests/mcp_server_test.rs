@@ -562,6 +562,370 @@ greet(name: str) echo "Hello, $name!"
     );
 }
 
+#[test]
+fn test_mcp_tool_arguments_never_start_other_calls() {
+    use std::io::Write;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @desc Echo two words
+# @arg a str First word
+# @arg b str Second word
+pair(a: str, b: str) echo "got $a $b"
+
+secret() echo "leaked"
+"#,
+    );
+
+    let mut child = test_command(&binary)
+        .arg("--serve-mcp")
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn MCP server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    for (id, (a, b)) in [("+", "secret"), ("--all", "secret")]
+        .into_iter()
+        .enumerate()
+    {
+        let call_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "tools/call",
+            "params": { "name": "pair", "arguments": { "a": a, "b": b } }
+        });
+        writeln!(stdin, "{}", serde_json::to_string(&call_request).unwrap()).unwrap();
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("got + secret"), "{stdout}");
+    assert!(stdout.contains("got --all secret"), "{stdout}");
+    assert!(!stdout.contains("leaked"), "{stdout}");
+}
+
+#[test]
+fn test_mcp_merge_streams_keeps_the_order_written() {
+    use std::io::Write;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @desc Fail halfway through
+build() {
+    echo "compiling a"
+    sleep 0.1
+    echo "warning: unused x" >&2
+    sleep 0.1
+    echo "compiling b"
+    sleep 0.1
+    echo "error: mismatch" >&2
+    exit 1
+}
+"#,
+    );
+
+    let mut child = test_command(&binary)
+        .args(["--serve-mcp", "--merge-streams"])
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn MCP server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let call_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "build", "arguments": {} }
+    });
+    writeln!(stdin, "{}", serde_json::to_string(&call_request).unwrap()).unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let response: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("one JSON-RPC response");
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    assert!(
+        text.contains(
+            "compiling a\n[stderr] warning: unused x\ncompiling b\n[stderr] error: mismatch\n"
+        ),
+        "got: {text}"
+    );
+    assert!(!text.contains("**Stderr:**"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_mcp_server_wide_limits() {
+    use std::io::Write;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @desc Spin forever
+spin() {
+    echo started
+    while :; do :; done
+}
+
+# @desc Spin with more time to spare
+# @limit cpu=2s
+patient() {
+    while :; do :; done
+}
+",
+    );
+
+    let mut child = test_command(&binary)
+        .args(["--serve-mcp", "--limit", "cpu=1s"])
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn MCP server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    for (id, name) in ["spin", "patient"].into_iter().enumerate() {
+        let call_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "tools/call",
+            "params": { "name": name, "arguments": {} }
+        });
+        writeln!(stdin, "{}", serde_json::to_string(&call_request).unwrap()).unwrap();
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let texts: Vec<String> = stdout
+        .lines()
+        .map(|line| {
+            let response: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(response["result"]["isError"], true, "{line}");
+            response["result"]["content"][0]["text"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(texts.len(), 2, "{stdout}");
+    assert!(texts[0].contains("started"), "{}", texts[0]);
+    assert!(
+        texts[0].contains("**Limit exceeded:** CPU time limit of 1s exceeded"),
+        "{}",
+        texts[0]
+    );
+    // The function's own limit wins over the server's
+    assert!(
+        texts[1].contains("**Limit exceeded:** CPU time limit of 2s exceeded"),
+        "{}",
+        texts[1]
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_mcp_server_wide_sandbox() {
+    use std::io::Write;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let outside = create_temp_dir();
+    let outside_file = outside.path().join("escaped.txt");
+
+    create_runfile(
+        temp_dir.path(),
+        &format!(
+            r#"
+# @desc Write a file outside the project
+escape() {{
+    echo escaped > "{}"
+}}
+"#,
+            outside_file.display()
+        ),
+    );
+
+    let mut child = test_command(&binary)
+        .args(["--serve-mcp", "--sandbox"])
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn MCP server");
+
+    let mut stdin = child.stdin.take().unwrap();
+    let call_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "escape", "arguments": {} }
+    });
+    writeln!(stdin, "{}", serde_json::to_string(&call_request).unwrap()).unwrap();
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let response: serde_json::Value = serde_json::from_str(stdout.trim()).unwrap();
+    let text = response["result"]["content"][0]["text"].as_str().unwrap();
+    if text.contains("Landlock is not available") {
+        return;
+    }
+    assert_eq!(response["result"]["isError"], true, "{text}");
+    assert!(text.contains("**Sandbox denied:**\n- "), "{text}");
+    assert!(text.contains("Permission denied"), "{text}");
+    assert!(!outside_file.exists());
+}
+
+#[test]
+fn test_mcp_tools_call_is_recorded_in_history() {
+    use std::io::Write;
+    use std::time::Duration;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @desc Greet the user
+greet(name: str) echo "Hello, $name!"
+
+# @desc Take a while
+slow() sleep 10
+"#,
+    );
+
+    let mut child = test_command(&binary)
+        .arg("--serve-mcp")
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn MCP server");
+
+    let stdin = child.stdin.as_mut().unwrap();
+    for (id, (name, arguments)) in [
+        ("greet", serde_json::json!({ "name": "World" })),
+        ("slow", serde_json::json!({ "timeout": 1 })),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let call_request = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "tools/call",
+            "params": { "name": name, "arguments": arguments }
+        });
+        writeln!(stdin, "{}", serde_json::to_string(&call_request).unwrap()).unwrap();
+    }
+    stdin.flush().unwrap();
+
+    std::thread::sleep(Duration::from_secs(3));
+    child.kill().expect("Failed to kill process");
+    child.wait().unwrap();
+
+    let history = fs::read_to_string(temp_dir.path().join(".run-output/history.jsonl")).unwrap();
+    let entries: Vec<serde_json::Value> = history
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 2, "history: {history}");
+    assert_eq!(entries[0]["function"], "greet");
+    assert_eq!(entries[0]["args"], serde_json::json!(["World"]));
+    assert_eq!(entries[0]["source"], "mcp");
+    assert_eq!(entries[0]["exit_code"], 0);
+    assert_eq!(entries[0]["output_format"], "markdown");
+    // Timed out: recorded by the server, with the exit code reserved for timeouts
+    assert_eq!(entries[1]["function"], "slow");
+    assert_eq!(entries[1]["source"], "mcp");
+    assert_eq!(entries[1]["exit_code"], 75);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_mcp_timeout_stops_background_processes() {
+    use std::io::Write;
+    use std::time::{Duration, Instant};
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @desc Start a server and wait
+serve() {
+    sleep 300 &
+    echo $! > server.pid
+    sleep 300
+}
+",
+    );
+
+    let mut child = test_command(&binary)
+        .arg("--serve-mcp")
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn MCP server");
+
+    let call_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": { "name": "serve", "arguments": { "timeout": 1 } }
+    });
+    let stdin = child.stdin.as_mut().unwrap();
+    writeln!(stdin, "{}", serde_json::to_string(&call_request).unwrap()).unwrap();
+    stdin.flush().unwrap();
+
+    std::thread::sleep(Duration::from_secs(3));
+    child.kill().expect("Failed to kill process");
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("timed out"), "got: {stdout}");
+
+    // The background process was in the function's process group, so it was stopped too
+    let pid = fs::read_to_string(temp_dir.path().join("server.pid")).unwrap();
+    let running = || {
+        fs::read_to_string(format!("/proc/{}/stat", pid.trim()))
+            .is_ok_and(|stat| !stat.contains(") Z "))
+    };
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while running() {
+        assert!(
+            Instant::now() < deadline,
+            "background process {pid} survived"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
 #[test]
 fn test_mcp_tools_call_non_zero_exit_sets_is_error() {
     use std::io::Write;
@@ -616,6 +980,71 @@ fail() {
     );
 }
 
+#[test]
+fn test_mcp_tools_call_reports_runfile_syntax_errors() {
+    use std::io::Write;
+    use std::time::Duration;
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @desc Say hello
+hello() echo "hello"
+
+# @desc Broken
+broken() {
+    echo "never closed"
+"#,
+    );
+
+    let mut child = test_command(&binary)
+        .arg("--serve-mcp")
+        .current_dir(temp_dir.path())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn MCP server");
+
+    let stdin = child.stdin.as_mut().unwrap();
+
+    let call_request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "tools/call",
+        "params": {
+            "name": "hello",
+            "arguments": {}
+        }
+    });
+    writeln!(stdin, "{}", serde_json::to_string(&call_request).unwrap()).unwrap();
+    stdin.flush().unwrap();
+
+    std::thread::sleep(Duration::from_millis(500));
+
+    child.kill().expect("Failed to kill process");
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let response: serde_json::Value = stdout
+        .lines()
+        .find_map(|line| serde_json::from_str(line).ok())
+        .unwrap_or_else(|| panic!("no response: {stdout}"));
+
+    let message = response["error"]["message"].as_str().unwrap_or_default();
+    assert!(message.contains("syntax error"), "response: {response}");
+    assert!(message.contains("Runfile:6:"), "response: {response}");
+    assert_eq!(
+        response["error"]["data"]["parse_errors"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
 #[test]
 fn test_mcp_tools_call_unknown_tool_returns_error() {
     use std::io::Write;
@@ -0,0 +1,193 @@
+//! Running several functions in one invocation (`--all`, `+`, `--parallel`, `--keep-going`)
+
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+mod common;
+
+use common::*;
+use std::process::{Command, Output};
+
+const RUNFILE: &str = r#"
+build() {
+    echo "building"
+    sleep 0.3
+    echo "built"
+}
+
+greet(name) echo "hello $name"
+
+broken() {
+    echo "about to fail" >&2
+    exit 3
+}
+
+lint() echo "linted"
+"#;
+
+fn run(args: &[&str]) -> Output {
+    let temp_dir = create_temp_dir();
+    create_runfile(temp_dir.path(), RUNFILE);
+    Command::new(get_binary_path())
+        .args(args)
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .env("RUN_NO_GLOBAL_MERGE", "1")
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("Failed to execute command")
+}
+
+#[test]
+fn test_all_runs_each_function_in_order() {
+    let output = run(&["--all", "build", "lint"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "building\nbuilt\nlinted\n"
+    );
+}
+
+#[test]
+fn test_plus_separates_calls_with_arguments() {
+    let output = run(&["--all", "greet", "ada", "+", "lint"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "hello ada\nlinted\n"
+    );
+}
+
+#[test]
+fn test_fail_fast_stops_later_functions() {
+    let output = run(&["--all", "broken", "+", "lint"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stdout.contains("linted"), "stdout: {stdout}");
+    assert!(
+        stderr.contains("not run after a failure: lint"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn test_parallel_prefixes_output_and_keeps_going() {
+    let output = run(&[
+        "--all",
+        "--parallel",
+        "3",
+        "--keep-going",
+        "build",
+        "+",
+        "broken",
+        "+",
+        "lint",
+    ]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stdout.contains("[build]  building\n"), "stdout: {stdout}");
+    assert!(stdout.contains("[build]  built\n"), "stdout: {stdout}");
+    assert!(stdout.contains("[lint]   linted\n"), "stdout: {stdout}");
+    assert!(
+        stderr.contains("[broken] about to fail\n"),
+        "stderr: {stderr}"
+    );
+    assert!(
+        stderr.contains("error: `broken` failed"),
+        "stderr: {stderr}"
+    );
+    // lint finishes while build is still sleeping
+    assert!(
+        stdout.find("linted").unwrap() < stdout.find("built").unwrap(),
+        "stdout: {stdout}"
+    );
+}
+
+#[test]
+fn test_parallel_fail_fast_stops_running_functions() {
+    let output = run(&["--all", "--parallel", "2", "build", "+", "broken"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stdout.contains("built"), "stdout: {stdout}");
+    assert!(
+        stderr.contains("error: `build` was stopped"),
+        "stderr: {stderr}"
+    );
+}
+
+#[test]
+fn test_json_combines_results_per_function() {
+    let output = run(&[
+        "--output-format",
+        "json",
+        "--all",
+        "--parallel",
+        "2",
+        "--keep-going",
+        "greet",
+        "ada",
+        "+",
+        "broken",
+    ]);
+    assert!(!output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(json["context"]["function_name"], "greet + broken");
+    assert_eq!(json["success"], false);
+    let outputs = json["outputs"].as_array().unwrap();
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(outputs[0]["command"], "greet ada");
+    assert_eq!(outputs[0]["stdout"], "hello ada\n");
+    assert_eq!(outputs[0]["exit_code"], 0);
+    assert_eq!(outputs[1]["command"], "broken");
+    assert!(
+        outputs[1]["stderr"]
+            .as_str()
+            .unwrap()
+            .contains("about to fail")
+    );
+    assert_ne!(outputs[1]["exit_code"], 0);
+}
+
+#[test]
+fn test_dangling_separator_is_an_error() {
+    let output = run(&["--all", "build", "+"]);
+    assert_eq!(output.status.code(), Some(64));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected a function name"));
+}
+
+#[test]
+fn test_plus_is_an_argument_without_all() {
+    let output = run(&["greet", "+", "lint"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello +\n");
+}
+
+#[test]
+fn test_double_dash_ends_the_calls() {
+    let output = run(&["--all", "lint", "+", "greet", "--", "+"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "linted\nhello +\n");
+}
+
+#[test]
+fn test_script_arguments_are_not_split() {
+    let temp_dir = create_temp_dir();
+    std::fs::write(temp_dir.path().join("script.sh"), "echo \"args: $@\"\n").unwrap();
+    let output = Command::new(get_binary_path())
+        .args(["--all", "script.sh", "+"])
+        .current_dir(temp_dir.path())
+        .env("HOME", temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "args: +\n");
+}
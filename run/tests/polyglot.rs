@@ -189,6 +189,42 @@ js() {
     }
 }
 
+#[cfg(unix)]
+#[test]
+fn test_memory_limit_leaves_node_room_to_start() {
+    // V8 reserves far more address space than it uses, so only real memory may count
+    if !is_node_available() {
+        return;
+    }
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @limit mem=256M
+# @shell node
+small() {
+    console.log("started");
+}
+"#,
+    );
+
+    let output = Command::new(&binary)
+        .arg("small")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "started\n");
+}
+
 #[test]
 fn test_shell_attribute_node_with_args() {
     if !is_node_available() {
@@ -406,6 +442,43 @@ greet(name) {
     assert!(stdout.contains("Hello, World!"), "stdout: {stdout}");
 }
 
+#[test]
+fn test_python_sees_runfile_variables() {
+    if !is_python_available() {
+        return;
+    }
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+VERSION="1.2.3"
+MOTTO="say \"hi\""
+
+# @shell python
+show() {
+    print(f"{VERSION} {MOTTO}")
+}
+"#,
+    );
+
+    let output = Command::new(&binary)
+        .arg("show")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.trim(), "1.2.3 say \"hi\"");
+}
+
 #[test]
 fn test_python_named_param_with_default() {
     if !is_python_available() {
@@ -494,6 +567,83 @@ greet(name) {
     assert!(stdout.contains("Hello, World!"), "stdout: {stdout}");
 }
 
+#[test]
+fn test_node_sees_runfile_variables() {
+    if !is_node_available() {
+        return;
+    }
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+VERSION="1.2.3"
+
+# @shell node
+show() {
+    const label = `v${VERSION}`;
+    console.log(label);
+}
+"#,
+    );
+
+    let output = Command::new(&binary)
+        .arg("show")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.trim(), "v1.2.3");
+}
+
+#[test]
+fn test_ruby_methods_see_runfile_variables() {
+    if !is_ruby_available() {
+        return;
+    }
+
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r##"
+VERSION="1.2.3"
+channel="beta"
+
+# @shell ruby
+show() {
+    def label
+      "v#{VERSION}-#{$channel}"
+    end
+    puts "#{label} #{channel}"
+}
+"##,
+    );
+
+    let output = Command::new(&binary)
+        .arg("show")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.trim(), "v1.2.3-beta beta");
+}
+
 #[test]
 fn test_node_named_param_with_default() {
     if !is_node_available() {
@@ -694,3 +844,289 @@ bash_func() {
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("Explicit bash"));
 }
+
+// Interpreter registry tests
+
+#[test]
+fn test_shell_attribute_perl_with_named_params() {
+    if !is_perl_available() {
+        return;
+    }
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @shell perl
+greet(name, greeting = "Hello", ...rest) {
+    print "$greeting, $name! (" . scalar(@rest) . " more)\n";
+}
+"#,
+    );
+
+    let output = Command::new(&binary)
+        .args(["greet", "World"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Hello, World! (0 more)\n"
+    );
+
+    let output = Command::new(&binary)
+        .args(["greet", "World", "Hi", "a", "b"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Hi, World! (2 more)\n"
+    );
+}
+
+#[test]
+fn test_shebang_perl() {
+    if !is_perl_available() {
+        return;
+    }
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+where() {
+    #!/usr/bin/env perl
+    print "dir=$__RUNFILE_DIR__\n";
+}
+"#,
+    );
+
+    let output = Command::new(&binary)
+        .arg("where")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("dir=/"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_project_declared_interpreter() {
+    if !is_perl_available() {
+        return;
+    }
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r#"
+# @interpreter myperl command="perl -w" code=file:pl dialect=perl
+
+# @shell myperl
+script(name) {
+    print "$0 $name\n";
+}
+"#,
+    );
+
+    let output = Command::new(&binary)
+        .args(["--output-format", "json", "script", "x"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout: {stdout}");
+    // The code ran from a temporary .pl file
+    assert!(stdout.contains(".pl x"), "stdout: {stdout}");
+    assert!(
+        stdout.contains("\"interpreter\": \"myperl\""),
+        "stdout: {stdout}"
+    );
+}
+
+#[test]
+fn test_unknown_shell_is_a_parse_error() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @shell cta
+show() {
+    not a shell command
+}
+",
+    );
+
+    let output = test_command(&binary)
+        .arg("show")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("unknown interpreter 'cta'"),
+        "stderr: {stderr}"
+    );
+    assert!(stderr.contains("Runfile:2:"), "stderr: {stderr}");
+}
+
+#[test]
+fn test_declared_interpreter_runs_script_file() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @shell cat
+show() {
+    not a shell command
+}
+
+# @interpreter cat command=cat
+",
+    );
+
+    let output = test_command(&binary)
+        .arg("show")
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("not a shell command"));
+}
+
+// Script file execution tests
+
+#[test]
+fn test_exec_file_names_script_after_function() {
+    if !is_python_available() {
+        return;
+    }
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+    let tmp = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @exec file
+# @shell python3
+deploy(env) {
+    import os, sys
+    print(os.path.basename(sys.argv[0]), env)
+}
+",
+    );
+
+    let output = Command::new(&binary)
+        .args(["deploy", "prod"])
+        .env("TMPDIR", tmp.path())
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "deploy.py prod\n");
+    // The private script directory is removed afterwards
+    assert_eq!(std::fs::read_dir(tmp.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_exec_file_reports_failing_line() {
+    if !is_python_available() {
+        return;
+    }
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+# @exec file
+# @shell python3
+crash() {
+    x = 1
+    x / 0
+}
+",
+    );
+
+    let output = Command::new(&binary)
+        .args(["--output-format", "json", "crash"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("crash.py"), "stdout: {stdout}");
+    assert!(stdout.contains("\"line\": 6"), "stdout: {stdout}");
+}
+
+#[test]
+fn test_shebang_with_interpreter_arguments() {
+    if which::which("python3").is_err() {
+        return;
+    }
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    create_runfile(
+        temp_dir.path(),
+        r"
+pep(name) {
+    #!/usr/bin/env -S python3 -u
+    import os, sys
+    print(os.path.basename(sys.argv[0]), name)
+}
+",
+    );
+
+    let output = Command::new(&binary)
+        .args(["pep", "x"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // `python3 -u` ran the body from a script file
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "pep.py x\n");
+}
+
+#[test]
+fn test_long_script_runs_from_file() {
+    let binary = get_binary_path();
+    let temp_dir = create_temp_dir();
+
+    let padding = "#".repeat(200_000);
+    create_runfile(
+        temp_dir.path(),
+        &format!("big() {{\n    # {padding}\n    echo \"big $1\"\n}}\n"),
+    );
+
+    let output = Command::new(&binary)
+        .args(["big", "ok"])
+        .current_dir(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "big ok\n");
+}
@@ -237,3 +237,245 @@ mixed_output() {
     assert!(!stdout.contains("**Stderr:**"));
     assert!(!stdout.contains("stderr message"));
 }
+
+#[test]
+fn test_structured_output_spills_large_output_to_disk() {
+    let temp_dir = TempDir::new().unwrap();
+    let runfile_path = temp_dir.path().join("Runfile");
+
+    // About 6 MiB, more than is kept in memory
+    let runfile_content = r#"
+# @shell python3
+flood() {
+    import sys
+    for i in range(200000):
+        print(f"line {i:06d} " + "x" * 20)
+    print("done", file=sys.stderr)
+}
+"#;
+
+    fs::write(&runfile_path, runfile_content).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_run"))
+        .arg("--runfile")
+        .arg(&runfile_path)
+        .arg("--output-format=json")
+        .arg("flood")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{stdout}");
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+
+    let captured = json["outputs"][0]["stdout"].as_str().unwrap();
+    assert!(captured.starts_with("[Output truncated: 6600000 bytes"));
+    assert!(captured.ends_with(&format!("line 199999 {}", "x".repeat(20))));
+    assert!(captured.len() < 100 * 1024);
+    assert_eq!(json["outputs"][0]["stderr"], "done\n");
+
+    // The log holds both streams, in order
+    let logs: Vec<_> = fs::read_dir(temp_dir.path().join(".run-output"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+    assert_eq!(logs.len(), 1);
+    let log = fs::read_to_string(&logs[0]).unwrap();
+    assert!(captured.contains(&logs[0].display().to_string()));
+    assert_eq!(log.lines().count(), 200_001);
+    let last = log.lines().last().unwrap();
+    assert!(
+        last.starts_with('+') && last.ends_with("s stderr | done"),
+        "{last}"
+    );
+}
+
+#[test]
+fn test_structured_output_traced_steps() {
+    let temp_dir = TempDir::new().unwrap();
+    let runfile_path = temp_dir.path().join("Runfile");
+
+    let runfile_content = r#"
+# @trace
+deploy() {
+    echo "building"
+    if true; then
+        echo "checking" >&2
+    fi
+    for i in 1 2; do echo "i=$i"; done
+    false
+    echo "never"
+}
+"#;
+
+    fs::write(&runfile_path, runfile_content).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_run"))
+        .arg("--runfile")
+        .arg(&runfile_path)
+        .arg("--output-format=json")
+        .arg("deploy")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{stdout}");
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+
+    let steps = json["outputs"][0]["steps"].as_array().unwrap();
+    let summary: Vec<_> = steps
+        .iter()
+        .map(|step| {
+            (
+                step["line"].as_u64().unwrap(),
+                step["exit_code"].as_i64().unwrap(),
+                step["stdout"].as_str().unwrap_or_default(),
+                step["stderr"].as_str().unwrap_or_default(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (4, 0, "building\n", ""),
+            (5, 0, "", "checking\n"),
+            (8, 0, "i=1\ni=2\n", ""),
+            (9, 1, "", ""),
+        ]
+    );
+    assert_eq!(
+        steps[1]["command"],
+        "if true; then\n    echo \"checking\" >&2\nfi"
+    );
+    assert!(
+        steps[3]["file"].as_str().unwrap().ends_with("Runfile"),
+        "{stdout}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_structured_output_reports_exceeded_limits() {
+    let temp_dir = TempDir::new().unwrap();
+    let runfile_path = temp_dir.path().join("Runfile");
+
+    let runfile_content = r"
+# @limit cpu=1s
+spin() {
+    while :; do :; done
+}
+
+# @limit mem=64M
+# @shell python3
+hog() {
+    data = bytearray(512 * 1024 * 1024)
+    print(len(data))
+}
+
+# @limit nofile=16
+files() {
+    for i in $(seq 1 40); do exec {fd}</dev/null; done
+}
+";
+
+    fs::write(&runfile_path, runfile_content).unwrap();
+
+    let run = |function: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_run"))
+            .arg("--runfile")
+            .arg(&runfile_path)
+            .arg("--output-format=json")
+            .arg(function)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json
+    };
+
+    // The kernel's SIGXCPU says the CPU time limit was reached
+    let json = run("spin");
+    assert_eq!(json["exit_reason"], "limit", "{json}");
+    assert_eq!(
+        json["limit_exceeded"],
+        serde_json::json!({
+            "resource": "cpu",
+            "limit": "1s",
+            "message": "CPU time limit of 1s exceeded",
+        })
+    );
+
+    // Memory is held to the limit, but only a cgroup's events tell that it was hit
+    let json = run("hog");
+    let stderr = json["outputs"][0]["stderr"].as_str().unwrap();
+    if json["exit_reason"] == "limit" {
+        assert_eq!(json["limit_exceeded"]["resource"], "mem", "{json}");
+    } else {
+        assert!(stderr.contains("MemoryError"), "{json}");
+        assert!(json["limit_exceeded"].is_null(), "{json}");
+    }
+
+    // Running out of files is the command's own failure, whatever it prints
+    let json = run("files");
+    let stderr = json["outputs"][0]["stderr"].as_str().unwrap();
+    assert!(
+        stderr.to_lowercase().contains("too many open files"),
+        "{json}"
+    );
+    assert_eq!(json["exit_reason"], "exited", "{json}");
+    assert!(json["limit_exceeded"].is_null(), "{json}");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_structured_output_reports_sandbox_denials() {
+    let temp_dir = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    let runfile_path = temp_dir.path().join("Runfile");
+    let outside_file = outside.path().join("escaped.txt");
+
+    let extra = TempDir::new().unwrap();
+    let runfile_content = format!(
+        r#"
+# @sandbox rw={extra}
+build() {{
+    echo built > inside.txt
+    echo extra > "{extra}/extra.txt"
+    scratch="$(mktemp)" && echo scratch > "$scratch" && echo "temp ok"
+    echo "git@example.com: Permission denied (publickey)." >&2
+    echo escaped > "{outside}" || echo "kept going"
+}}
+"#,
+        extra = extra.path().display(),
+        outside = outside_file.display()
+    );
+
+    fs::write(&runfile_path, runfile_content).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_run"))
+        .arg("--runfile")
+        .arg(&runfile_path)
+        .arg("--output-format=json")
+        .arg("build")
+        .current_dir(temp_dir.path())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("Landlock is not available") {
+        return;
+    }
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(json["success"], true, "{json}");
+    let stdout = json["outputs"][0]["stdout"].as_str().unwrap();
+    assert!(stdout.contains("kept going"), "{json}");
+    assert!(stdout.contains("temp ok"), "{json}");
+    // Only the write the sandbox blocked, not a tool's own "Permission denied"
+    let denied = json["outputs"][0]["sandbox_denied"].as_array().unwrap();
+    assert_eq!(denied.len(), 1, "{json}");
+    assert!(denied[0].as_str().unwrap().contains("escaped.txt"));
+    assert!(temp_dir.path().join("inside.txt").exists());
+    assert!(extra.path().join("extra.txt").exists());
+    assert!(!outside_file.exists());
+}